@@ -0,0 +1,618 @@
+/**
+ * Headless command-line entry point for the calibration pipeline.
+ *
+ * Runs a saved configuration (the configuration.json written by save_config) on one or more
+ * input directories or images without starting the GUI, so that bracket sets can be processed
+ * on machines without a display. Progress is printed to the console instead of being sent as
+ * Tauri events, and the process exits with a non-zero status if any stage fails.
+ *
+ * Usage:
+ *     hdri-pipeline --config <configuration dir or configuration.json> --output <dir>
+ *                   [--radiance <dir>] [--hdrgen <dir>] [--dcraw <dir>] [--filter-images]
+ *                   [--merge-backend <hdrgen|native>] [--align] [--bracket-check <off|warn|fail>]
+ *                   [--max-ev-step <ev>] [--check-circle] [--recursive] [--group-brackets]
+ *                   [--max-gap <secs>] [--stages <stages.json>] [--workers <n>]
+ *                   [--continue-on-error] [--no-cache] <input dir or image>...
+ *     hdri-pipeline --rerun-failed <batch manifest.json> [--workers <n>]
+ */
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{exit, Command},
+    str::FromStr,
+};
+
+use hdri_calibration_interface::get_saved_configs::{process_configuration, Config};
+use hdri_calibration_interface::pipeline::{
+    bundled_sidecar_path, get_images_from_dir, group_brackets, run_pipeline, BatchManifest,
+    BracketCheck, BracketGroup, CancellationToken, LuminanceArgs, MergeBackend, PipelineHost,
+    PipelineParams, PipelineProgress, ProgressEvent, StageEntry, DEFAULT_MAX_EV_STEP,
+    DEFAULT_MAX_GAP_SECS,
+};
+
+const USAGE: &str =
+    "Usage: hdri-pipeline --config <configuration dir or configuration.json> --output <dir>
+                     [--radiance <dir>] [--hdrgen <dir>] [--dcraw <dir>] [--filter-images]
+                     [--merge-backend <hdrgen|native>] [--align] [--bracket-check <off|warn|fail>]
+                     [--max-ev-step <ev>] [--check-circle] [--recursive] [--group-brackets]
+                     [--max-gap <secs>] [--stages <stages.json>] [--workers <n>]
+                     [--continue-on-error] [--no-cache] <input dir or image>...
+       hdri-pipeline --rerun-failed <batch manifest.json> [--workers <n>]
+
+Options:
+    --config <path>     Saved configuration directory, or its configuration.json file
+    --output <dir>      Directory the final HDR images are written to
+    --radiance <dir>    Directory containing the Radiance binaries (default: search PATH)
+    --hdrgen <dir>      Directory containing hdrgen (default: bundled sidecar)
+    --dcraw <dir>       Directory containing dcraw_emu (default: bundled sidecar)
+    --filter-images     Filter out LDR images that do not contribute to the HDR image
+    --merge-backend <hdrgen|native>
+                        Merge the exposures with hdrgen, or natively (without hdrgen; needs the
+                        configuration's camera response function) (default: hdrgen)
+    --align             Align the exposures (median threshold bitmaps) before merging them, for
+                        handheld or bumped brackets
+    --bracket-check <off|warn|fail>
+                        What happens when the images of a bracket mix ISO or aperture, repeat an
+                        exposure, or are further apart than --max-ev-step (default: warn)
+    --max-ev-step <ev>  Largest allowed difference between consecutive exposures (default: 3)
+    --check-circle      Warn when the diameter, xleft and ydown of the configuration differ from the
+                        fisheye circle detected in the images of a scene
+    --recursive         Process every leaf directory with images below the input directories as a
+                        scene; the outputs are written to the same relative paths below --output
+    --group-brackets    Split each input directory into bracket sets by capture time and exposure
+                        sequence (EXIF), and process every bracket set as a scene
+    --max-gap <secs>    Largest time between two exposures of the same bracket, for
+                        --group-brackets (default: 10)
+    --stages <file>     JSON list of the pipeline stages to run, in order (default: standard order)
+    --workers <n>       Number of input directories processed at the same time (default: 1)
+    --continue-on-error Process the remaining input directories when one of them fails
+    --no-cache          Run every stage, even if its outputs are in the stage cache
+    --rerun-failed <manifest>
+                        Run the directories of a batch manifest that failed again, with the
+                        parameters of the original run
+    -h, --help          Print this message";
+
+// Command-line arguments
+struct Args {
+    config_path: PathBuf,
+    output_path: PathBuf,
+    radiance_path: PathBuf,
+    hdrgen_path: PathBuf,
+    dcraw_emu_path: PathBuf,
+    filter_images: bool,
+    merge_backend: MergeBackend,
+    align_exposures: bool,
+    bracket_check: BracketCheck,
+    max_ev_step: Option<f64>,
+    check_fisheye_circle: bool,
+    recursive: bool,
+    group_brackets: bool,
+    max_gap_secs: Option<f64>,
+    stages_path: Option<PathBuf>,
+    workers: Option<usize>,
+    continue_on_error: bool,
+    no_cache: bool,
+    inputs: Vec<String>,
+}
+
+// What the command line asks for
+enum Invocation {
+    // Run a saved configuration on input directories or images
+    Run(Args),
+    // Run the failed entries of a batch manifest again
+    RerunFailed {
+        manifest_path: PathBuf,
+        workers: Option<usize>,
+    },
+}
+
+// Host for running the pipeline from a terminal: prints progress and runs sidecars
+// installed next to this executable
+struct ConsoleHost;
+
+impl PipelineHost for ConsoleHost {
+    fn emit_progress(&self, progress: &PipelineProgress) -> Result<(), String> {
+        // Report failed scenes of a batch as they happen, the run goes on until the
+        // running scenes have stopped
+        if progress.event == ProgressEvent::SceneFailed {
+            if let Some(error) = &progress.error {
+                eprintln!(
+                    "[hdri-pipeline] scene {}/{} ({}) failed: {}",
+                    progress.scene_index, progress.scene_count, progress.scene_name, error
+                );
+            }
+            return Ok(());
+        }
+
+        if progress.event == ProgressEvent::Warning {
+            for warning in &progress.warnings {
+                eprintln!(
+                    "[hdri-pipeline] scene {}/{} warning: {}",
+                    progress.scene_index, progress.scene_count, warning
+                );
+            }
+            return Ok(());
+        }
+
+        // Report the shifts found when the exposures of a scene were aligned
+        if progress.event == ProgressEvent::SceneFinished {
+            for offset in &progress.frame_offsets {
+                println!(
+                    "[hdri-pipeline] scene {}/{} aligned {} by ({}, {}) pixels",
+                    progress.scene_index,
+                    progress.scene_count,
+                    offset.image,
+                    offset.x_offset,
+                    offset.y_offset
+                );
+            }
+            return Ok(());
+        }
+
+        // Only report finished stages, one line each
+        if progress.event != ProgressEvent::StageFinished {
+            return Ok(());
+        }
+
+        let mut line = format!(
+            "[hdri-pipeline] scene {}/{}",
+            progress.scene_index, progress.scene_count
+        );
+        if !progress.scene_name.is_empty() {
+            line.push_str(&format!(" ({})", progress.scene_name));
+        }
+        if progress.cached {
+            line.push_str(&format!(
+                " - {} restored from cache, {}%",
+                progress.stage.as_deref().unwrap_or_default(),
+                progress.percent
+            ));
+        } else {
+            line.push_str(&format!(
+                " - {} done in {:.1}s, {}%",
+                progress.stage.as_deref().unwrap_or_default(),
+                progress.stage_elapsed_secs.unwrap_or_default(),
+                progress.percent
+            ));
+        }
+        if let Some(eta_secs) = progress.eta_secs {
+            line.push_str(&format!(", about {:.0}s left", eta_secs));
+        }
+        println!("{}", line);
+        Ok(())
+    }
+
+    fn sidecar_command(&self, name: &str) -> Result<Command, String> {
+        let path = bundled_sidecar_path(name)?;
+        if !path.exists() {
+            return Err(format!(
+                "Bundled '{}' binary not found at {}. Pass its directory on the command line instead.",
+                name,
+                path.display()
+            ));
+        }
+        Ok(Command::new(path))
+    }
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(Invocation::Run(args)) => args,
+        Ok(Invocation::RerunFailed {
+            manifest_path,
+            workers,
+        }) => {
+            let params = match rerun_params(&manifest_path, workers) {
+                Ok(params) => params,
+                Err(message) => {
+                    eprintln!("hdri-pipeline: {}", message);
+                    exit(2);
+                }
+            };
+            let rerun_count = if params.recursive {
+                params.scene_filter.len()
+            } else {
+                params.input_images.len() + params.bracket_groups.len()
+            };
+            println!(
+                "[hdri-pipeline] re-running {} failed input(s) of {}",
+                rerun_count,
+                manifest_path.display()
+            );
+            run(params);
+        }
+        Err(message) => {
+            eprintln!("hdri-pipeline: {}\n\n{}", message, USAGE);
+            exit(2);
+        }
+    };
+
+    let config = match load_config(&args.config_path) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("hdri-pipeline: {}", message);
+            exit(2);
+        }
+    };
+
+    println!(
+        "[hdri-pipeline] running configuration '{}' on {} input(s)",
+        config.name,
+        args.inputs.len()
+    );
+
+    let params = match params_from_config(config, args) {
+        Ok(params) => params,
+        Err(errors) => {
+            eprintln!(
+                "hdri-pipeline: invalid configuration:\n{}",
+                errors.join("\n")
+            );
+            exit(2);
+        }
+    };
+
+    run(params);
+}
+
+// Runs the pipeline and exits with a non-zero status if it failed
+fn run(params: PipelineParams) -> ! {
+    let result = run_pipeline(&ConsoleHost, params, CancellationToken::new());
+
+    match result {
+        Ok(output) => {
+            println!("[hdri-pipeline] finished, output written to {}", output);
+            exit(0);
+        }
+        Err(message) => {
+            eprintln!("hdri-pipeline: {}", message);
+            exit(1);
+        }
+    }
+}
+
+// Builds the parameters to run the failed entries of a batch manifest again
+fn rerun_params(manifest_path: &Path, workers: Option<usize>) -> Result<PipelineParams, String> {
+    let mut params = BatchManifest::read(manifest_path)?.rerun_params()?;
+    if let Some(workers) = workers {
+        params.workers = workers;
+    }
+    Ok(params)
+}
+
+// Parses the command-line arguments (without the program name)
+fn parse_args(raw_args: Vec<String>) -> Result<Invocation, String> {
+    let mut config_path: Option<PathBuf> = None;
+    let mut output_path: Option<PathBuf> = None;
+    let mut radiance_path = PathBuf::new();
+    let mut hdrgen_path = PathBuf::new();
+    let mut dcraw_emu_path = PathBuf::new();
+    let mut filter_images = false;
+    let mut merge_backend = MergeBackend::default();
+    let mut align_exposures = false;
+    let mut bracket_check = BracketCheck::default();
+    let mut max_ev_step: Option<f64> = None;
+    let mut check_fisheye_circle = false;
+    let mut recursive = false;
+    let mut group_brackets = false;
+    let mut max_gap_secs: Option<f64> = None;
+    let mut stages_path: Option<PathBuf> = None;
+    let mut workers: Option<usize> = None;
+    let mut continue_on_error = false;
+    let mut no_cache = false;
+    let mut rerun_manifest: Option<PathBuf> = None;
+    let mut inputs: Vec<String> = Vec::new();
+
+    let mut iter = raw_args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--config" => config_path = Some(PathBuf::from(next_value(&mut iter, &arg)?)),
+            "--output" => output_path = Some(PathBuf::from(next_value(&mut iter, &arg)?)),
+            "--radiance" => radiance_path = PathBuf::from(next_value(&mut iter, &arg)?),
+            "--hdrgen" => hdrgen_path = PathBuf::from(next_value(&mut iter, &arg)?),
+            "--dcraw" => dcraw_emu_path = PathBuf::from(next_value(&mut iter, &arg)?),
+            "--filter-images" => filter_images = true,
+            "--merge-backend" => {
+                let value = next_value(&mut iter, &arg)?;
+                merge_backend = match value.as_str() {
+                    "hdrgen" => MergeBackend::Hdrgen,
+                    "native" => MergeBackend::Native,
+                    _ => {
+                        return Err(format!(
+                            "option '--merge-backend' must be 'hdrgen' or 'native' (got '{}')",
+                            value
+                        ))
+                    }
+                };
+            }
+            "--align" => align_exposures = true,
+            "--bracket-check" => {
+                let value = next_value(&mut iter, &arg)?;
+                bracket_check = match value.as_str() {
+                    "off" => BracketCheck::Off,
+                    "warn" => BracketCheck::Warn,
+                    "fail" => BracketCheck::Fail,
+                    _ => {
+                        return Err(format!(
+                            "option '--bracket-check' must be 'off', 'warn' or 'fail' (got '{}')",
+                            value
+                        ))
+                    }
+                };
+            }
+            "--max-ev-step" => {
+                let value = next_value(&mut iter, &arg)?;
+                max_ev_step = Some(value.parse().map_err(|_| {
+                    format!("option '--max-ev-step' requires a number (got '{}')", value)
+                })?);
+            }
+            "--check-circle" => check_fisheye_circle = true,
+            "--recursive" => recursive = true,
+            "--group-brackets" => group_brackets = true,
+            "--max-gap" => {
+                let value = next_value(&mut iter, &arg)?;
+                max_gap_secs = Some(value.parse().map_err(|_| {
+                    format!("option '--max-gap' requires a number (got '{}')", value)
+                })?);
+            }
+            "--stages" => stages_path = Some(PathBuf::from(next_value(&mut iter, &arg)?)),
+            "--workers" => {
+                let value = next_value(&mut iter, &arg)?;
+                workers = Some(value.parse().map_err(|_| {
+                    format!("option '--workers' requires a number (got '{}')", value)
+                })?);
+            }
+            "--continue-on-error" => continue_on_error = true,
+            "--no-cache" => no_cache = true,
+            "--rerun-failed" => rerun_manifest = Some(PathBuf::from(next_value(&mut iter, &arg)?)),
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ => inputs.push(arg),
+        }
+    }
+
+    // The manifest holds the configuration and the inputs of the run
+    if let Some(manifest_path) = rerun_manifest {
+        if config_path.is_some() || !inputs.is_empty() {
+            return Err("--rerun-failed cannot be combined with --config or inputs".into());
+        }
+        return Ok(Invocation::RerunFailed {
+            manifest_path,
+            workers,
+        });
+    }
+
+    if inputs.is_empty() {
+        return Err("no input directories or images were given".into());
+    }
+    if recursive && group_brackets {
+        return Err("--recursive cannot be combined with --group-brackets".into());
+    }
+
+    Ok(Invocation::Run(Args {
+        config_path: config_path.ok_or("missing required option --config")?,
+        output_path: output_path.ok_or("missing required option --output")?,
+        radiance_path,
+        hdrgen_path,
+        dcraw_emu_path,
+        filter_images,
+        merge_backend,
+        align_exposures,
+        bracket_check,
+        max_ev_step,
+        check_fisheye_circle,
+        recursive,
+        group_brackets,
+        max_gap_secs,
+        stages_path,
+        workers,
+        continue_on_error,
+        no_cache,
+        inputs,
+    }))
+}
+
+// Returns the value following an option, or an error if it is missing
+fn next_value(iter: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    iter.next()
+        .ok_or_else(|| format!("option '{}' requires a value", option))
+}
+
+// Loads a saved configuration from its directory or its configuration.json file
+fn load_config(path: &Path) -> Result<Config, String> {
+    let dir = if path.is_dir() {
+        path.to_path_buf()
+    } else {
+        path.parent().unwrap_or(Path::new(".")).to_path_buf()
+    };
+
+    process_configuration(dir.clone()).ok_or(format!(
+        "{} is not a valid configuration (configuration.json or a file it references is missing or malformed).",
+        dir.display()
+    ))
+}
+
+// Builds the pipeline parameters from a saved configuration, whose values are stored as strings.
+// Returns every value that could not be parsed.
+fn params_from_config(config: Config, args: Args) -> Result<PipelineParams, Vec<String>> {
+    let mut errors: Vec<String> = Vec::new();
+
+    let diameter = parse_value::<u32>("diameter", &config.diameter, &mut errors);
+    let xleft = parse_value::<i64>("xleft", &config.xleft, &mut errors);
+    let ydown = parse_value::<i64>("ydown", &config.ydown, &mut errors);
+    let target_res = parse_value::<u32>("target_res", &config.target_res, &mut errors);
+    let vertical_angle = parse_value::<f64>("vv", &config.vv, &mut errors);
+    let horizontal_angle = parse_value::<f64>("vh", &config.vh, &mut errors);
+    let scale_limit = parse_optional::<f64>("scale_limit", &config.scale_limit, &mut errors);
+    let scale_levels = parse_optional::<u32>("scale_levels", &config.scale_levels, &mut errors);
+
+    // Legend dimensions are stored as "<width> <height>"
+    let legend: Vec<&str> = config.legend_dimensions.split_whitespace().collect();
+    let (legend_width, legend_height) = match legend.as_slice() {
+        [] => (None, None),
+        [width, height] => (
+            parse_optional::<u32>("legend width", width, &mut errors),
+            parse_optional::<u32>("legend height", height, &mut errors),
+        ),
+        _ => {
+            errors.push(format!(
+                "legend_dimensions must be '<width> <height>' (got '{}').",
+                config.legend_dimensions
+            ));
+            (None, None)
+        }
+    };
+
+    let mut bracket_groups: Vec<BracketGroup> = Vec::new();
+    if args.group_brackets {
+        match find_bracket_groups(&args.inputs, args.max_gap_secs) {
+            Ok(groups) => bracket_groups = groups,
+            Err(message) => errors.push(message),
+        }
+    }
+
+    let mut stages = None;
+    if let Some(path) = &args.stages_path {
+        match load_stages(path) {
+            Ok(description) => stages = Some(description),
+            Err(message) => errors.push(message),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Saved configurations store a single target resolution for both dimensions
+    let target_res = target_res.unwrap_or_default();
+    Ok(PipelineParams {
+        radiance_path: args.radiance_path,
+        hdrgen_path: args.hdrgen_path,
+        dcraw_emu_path: args.dcraw_emu_path,
+        output_path: args.output_path,
+        // Bracket groups are processed instead of the input directories they were found in
+        input_images: if bracket_groups.is_empty() {
+            args.inputs
+        } else {
+            Vec::new()
+        },
+        bracket_groups,
+        recursive: args.recursive,
+        scene_filter: Vec::new(),
+        response_function: optional_path(config.response_paths),
+        fisheye_correction_cal: optional_path(config.fe_correction_paths),
+        vignetting_correction_cal: optional_path(config.v_correction_paths),
+        photometric_adjustment_cal: optional_path(config.cf_correction_paths),
+        neutral_density_cal: optional_path(config.nd_correction_paths),
+        diameter: diameter.unwrap_or_default(),
+        xleft: xleft.unwrap_or_default(),
+        ydown: ydown.unwrap_or_default(),
+        xdim: target_res,
+        ydim: target_res,
+        vertical_angle: vertical_angle.unwrap_or_default(),
+        horizontal_angle: horizontal_angle.unwrap_or_default(),
+        luminance: LuminanceArgs {
+            scale_limit,
+            scale_label: Some(config.scale_label).filter(|label| !label.is_empty()),
+            scale_levels,
+            legend_width,
+            legend_height,
+        },
+        evalglare: config.evalglare,
+        filter_images: args.filter_images,
+        merge_backend: args.merge_backend,
+        align_exposures: args.align_exposures,
+        bracket_check: args.bracket_check,
+        max_ev_step: args.max_ev_step.unwrap_or(DEFAULT_MAX_EV_STEP),
+        check_fisheye_circle: args.check_fisheye_circle,
+        stages,
+        workers: args.workers.unwrap_or(1),
+        continue_on_error: args.continue_on_error,
+        use_cache: !args.no_cache,
+    })
+}
+
+// Splits each input directory into bracket groups, and prints the groups found
+fn find_bracket_groups(
+    inputs: &[String],
+    max_gap_secs: Option<f64>,
+) -> Result<Vec<BracketGroup>, String> {
+    let mut groups: Vec<BracketGroup> = Vec::new();
+    for input_dir in inputs {
+        if !Path::new(input_dir).is_dir() {
+            return Err(format!(
+                "--group-brackets requires input directories ({} is not one).",
+                input_dir
+            ));
+        }
+        let images = get_images_from_dir(input_dir)?;
+        let name_prefix = Path::new(input_dir)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let grouping = group_brackets(
+            &images,
+            &name_prefix,
+            max_gap_secs.unwrap_or(DEFAULT_MAX_GAP_SECS),
+        );
+
+        for warning in &grouping.warnings {
+            println!("[hdri-pipeline] warning: {}", warning);
+        }
+        for proposed in grouping.groups {
+            println!(
+                "[hdri-pipeline] {}: {} image(s){}",
+                proposed.group.name,
+                proposed.group.images.len(),
+                proposed
+                    .split_reason
+                    .map(|reason| format!(" ({})", reason))
+                    .unwrap_or_default()
+            );
+            groups.push(proposed.group);
+        }
+    }
+    Ok(groups)
+}
+
+// Loads a pipeline description: a JSON list of stages such as
+// [{ "stage": "mergeExposures" }, { "stage": "neutralDensity" }, ...]
+fn load_stages(path: &Path) -> Result<Vec<StageEntry>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read stages file {}: {}", path.display(), e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid stages file {}: {}", path.display(), e))
+}
+
+// Parses a required configuration value, recording an error if it is missing or malformed
+fn parse_value<T: FromStr>(name: &str, value: &str, errors: &mut Vec<String>) -> Option<T> {
+    match value.trim().parse::<T>() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            errors.push(format!("{} has an invalid value '{}'.", name, value));
+            None
+        }
+    }
+}
+
+// Parses an optional configuration value, where an empty string means unset
+fn parse_optional<T: FromStr>(name: &str, value: &str, errors: &mut Vec<String>) -> Option<T> {
+    if value.trim().is_empty() {
+        None
+    } else {
+        parse_value(name, value, errors)
+    }
+}
+
+// Converts a configuration path to an optional path, where an empty string means unset
+fn optional_path(path: String) -> Option<PathBuf> {
+    if path.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(path))
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+
+use crate::pipeline::{
+    detect_fisheye_circle as find_circle, get_images_from_dir, CancellationToken, CircleDetection,
+};
+
+// Finds the fisheye image circle in the brightest frame of a bracket, so that the lens mask can
+// be filled in without measuring it by hand. Returns the diameter and the xleft/ydown offsets of
+// the circumscribed square from the bottom left corner (the convention pcompos uses), the circle
+// center from the top left corner (for drawing the mask), and a confidence from 0 to 1.
+// dcraw:
+//      Directory of dcraw_emu, used to convert a raw frame (empty to use the bundled one)
+// images:
+//      Paths to the images of the bracket, or a single directory containing them
+#[tauri::command]
+pub async fn detect_fisheye_circle(
+    app_handle: tauri::AppHandle,
+    dcraw: String,
+    images: Vec<String>,
+) -> Result<CircleDetection, String> {
+    let images = match images.as_slice() {
+        [dir] if Path::new(dir).is_dir() => get_images_from_dir(dir)?,
+        _ => images,
+    };
+    if images.is_empty() {
+        return Err("No images were provided.".into());
+    }
+
+    find_circle(
+        &app_handle,
+        Some(Path::new(&dcraw)).filter(|_| !dcraw.is_empty()),
+        &images,
+        &CancellationToken::new(),
+    )
+}
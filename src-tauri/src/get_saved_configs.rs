@@ -8,24 +8,29 @@ use serde::{Deserialize, Serialize};
 use serde_json::{from_str, to_string};
 use tauri::Manager;
 
+use crate::pipeline::EvalglareArgs;
+
+// A saved configuration, as written to configuration.json by save_config
 #[derive(Serialize, Deserialize)]
-struct Config {
-    name: String,
-    response_paths: String,
-    fe_correction_paths: String,
-    v_correction_paths: String,
-    nd_correction_paths: String,
-    cf_correction_paths: String,
-    diameter: String,
-    xleft: String,
-    ydown: String,
-    target_res: String,
-    vh: String,
-    vv: String,
-    scale_limit: String,
-    scale_label: String,
-    scale_levels: String,
-    legend_dimensions: String,
+pub struct Config {
+    pub name: String,
+    pub response_paths: String,
+    pub fe_correction_paths: String,
+    pub v_correction_paths: String,
+    pub nd_correction_paths: String,
+    pub cf_correction_paths: String,
+    pub diameter: String,
+    pub xleft: String,
+    pub ydown: String,
+    pub target_res: String,
+    pub vh: String,
+    pub vv: String,
+    pub scale_limit: String,
+    pub scale_label: String,
+    pub scale_levels: String,
+    pub legend_dimensions: String,
+    #[serde(default)]
+    pub evalglare: EvalglareArgs,
 }
 
 #[derive(Serialize)]
@@ -97,8 +102,9 @@ pub async fn get_saved_configs(app_handle: tauri::AppHandle) -> Result<String, S
 }
 
 // Processes a directory to determine if it contains a valid configuration.
-// Returns Some(Config) if valid, otherwise None.
-fn process_configuration(dir: PathBuf) -> Option<Config> {
+// Returns Some(Config) if valid, otherwise None. Paths to the response function and
+// calibration files in the returned Config are resolved against the directory.
+pub fn process_configuration(dir: PathBuf) -> Option<Config> {
     // Try to read the configuration.json file. Not a valid configuration dir if fails
     let config_info = match read_to_string(dir.join("configuration.json")) {
         Ok(v) => v,
@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use crate::pipeline::{
+    get_images_from_dir, group_brackets as split_brackets, BracketGrouping, DEFAULT_MAX_GAP_SECS,
+};
+
+// Splits a directory holding the frames of many brackets (e.g. a copied DCIM folder) into
+// bracket sets, by capture time gaps and exposure sequence, using the EXIF metadata of the
+// images. No file is moved: the proposed groups list the paths of their images, and can be
+// passed to the pipeline as bracketGroups once the user has checked them.
+// input_dir:
+//      Directory containing the images
+// max_gap_secs:
+//      Largest time between two exposures of the same bracket, in seconds (default: 10)
+#[tauri::command]
+pub async fn group_brackets(
+    input_dir: String,
+    max_gap_secs: Option<f64>,
+) -> Result<BracketGrouping, String> {
+    let max_gap_secs = max_gap_secs.unwrap_or(DEFAULT_MAX_GAP_SECS);
+    if !(max_gap_secs >= 0.0 && max_gap_secs.is_finite()) {
+        return Err(format!(
+            "The largest gap between exposures must not be negative (got {}).",
+            max_gap_secs
+        ));
+    }
+
+    let images = get_images_from_dir(&input_dir)?;
+    if images.is_empty() {
+        return Err(format!("No images found in {}.", input_dir));
+    }
+
+    // Groups are named after the directory, e.g. DCIM_001, DCIM_002, ...
+    let name_prefix = Path::new(&input_dir)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    Ok(split_brackets(&images, &name_prefix, max_gap_secs))
+}
@@ -0,0 +1,73 @@
+/**
+ * Tauri commands to inspect and manage the image cache from the frontend.
+ *
+ * The stats, limit and clear commands also cover the pipeline's stage cache, which is shown
+ * next to the image cache in the settings page.
+ */
+use super::{
+    cache_stats, clear, evict, get_cache_dir, read_settings, validate, write_settings,
+    CacheSettings, CacheStats, ValidationReport,
+};
+use crate::pipeline::{clear_stage_cache, evict_stage_cache};
+
+/**
+ * Tauri command returning the size, number of entries and size limit of the image cache and of
+ * the stage cache
+ *
+ * @param app_handle - Tauri application handle for accessing app paths
+ */
+#[tauri::command]
+pub async fn get_image_cache_stats(app_handle: tauri::AppHandle) -> Result<CacheStats, String> {
+    let cache_dir = get_cache_dir()?;
+    cache_stats(&cache_dir, &read_settings(&app_handle))
+}
+
+/**
+ * Tauri command setting the size limits of the image cache and of the stage cache. Entries over
+ * the new limits are evicted right away, least recently used first.
+ *
+ * @param app_handle - Tauri application handle for accessing app paths
+ * @param max_size_bytes - New size limit of the image cache, in bytes
+ * @param stage_cache_max_size_bytes - New size limit of the stage cache, in bytes (unchanged if
+ *                                     None)
+ * @returns The cache statistics after eviction
+ */
+#[tauri::command]
+pub async fn set_image_cache_limit(
+    app_handle: tauri::AppHandle,
+    max_size_bytes: u64,
+    stage_cache_max_size_bytes: Option<u64>,
+) -> Result<CacheStats, String> {
+    let settings = CacheSettings {
+        max_size_bytes,
+        stage_cache_max_size_bytes: stage_cache_max_size_bytes
+            .unwrap_or(read_settings(&app_handle).stage_cache_max_size_bytes),
+    };
+    write_settings(&app_handle, &settings)?;
+
+    let cache_dir = get_cache_dir()?;
+    evict(&cache_dir, settings.max_size_bytes, None)?;
+    evict_stage_cache(settings.stage_cache_max_size_bytes)?;
+    cache_stats(&cache_dir, &settings)
+}
+
+/**
+ * Tauri command removing every entry of the image cache and of the stage cache
+ *
+ * @returns The number of removed entries
+ */
+#[tauri::command]
+pub async fn clear_image_cache() -> Result<usize, String> {
+    let cache_dir = get_cache_dir()?;
+    Ok(clear(&cache_dir)? + clear_stage_cache()?)
+}
+
+/**
+ * Tauri command rehashing every entry of the image cache and removing the ones that are
+ * corrupted (content doesn't match the hash stored when it was converted)
+ */
+#[tauri::command]
+pub async fn validate_image_cache() -> Result<ValidationReport, String> {
+    let cache_dir = get_cache_dir()?;
+    validate(&cache_dir)
+}
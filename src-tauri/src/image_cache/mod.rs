@@ -1,13 +1,49 @@
+/**
+ * Module for the cache of raw images converted to TIFF.
+ *
+ * Conversions are stored as <key>.tiff, where the key is the blake3 hash of the raw image and
+ * the dcraw_emu arguments, next to <key>.blake3 holding the hash of the TIFF itself so that
+ * entries can be validated. The cache is kept under a configurable size limit by evicting the
+ * least recently used entries, based on their access time, which is updated on every hit.
+ *
+ * Conversions are used both by the image viewer and by the pipeline's merge_exposures stage,
+ * so they go through the PipelineHost to find the bundled dcraw_emu.
+ *
+ * The settings and the stats and clear commands of this module also cover the pipeline's stage
+ * cache (see pipeline/stage_cache.rs), so both caches are managed from one place in the settings
+ * page. Validation only applies to the image cache.
+ */
 use std::{
     env,
-    fs::{self},
+    fs::{self, File, FileTimes},
     io::Read,
     path::{Path, PathBuf},
-    process::{Command, ExitStatus},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
 };
 
+use serde::{Deserialize, Serialize};
 use tauri::Manager;
-use tauri_plugin_shell::ShellExt;
+
+use crate::pipeline::{
+    run_command, stage_cache_stats, CancellationToken, PipelineError, PipelineHost,
+    DEFAULT_STAGE_CACHE_MAX_SIZE_BYTES,
+};
+
+pub mod commands;
+
+// Size limit of the cache when none was configured (10 GiB)
+pub const DEFAULT_MAX_SIZE_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+// Extension of the file holding the content hash of a cached TIFF
+const HASH_EXTENSION: &str = "blake3";
+
+// Name of the file storing the cache settings in the app's configuration directory
+const SETTINGS_FILE: &str = "image_cache.json";
+
+// Numbers the partial files of conversions running at the same time
+static PARTIAL_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 fn dcraw_base_args() -> &'static [&'static str] {
     &[
@@ -19,9 +55,9 @@ fn dcraw_context() -> String {
     format!("dcraw_emu|{}", dcraw_base_args().join(" "))
 }
 
-pub fn get_cache_dir(_app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
-    // use OS-designated temporary directory so the cache can be evicted by the OS when necessary,
-    // and we don't have to worry about cleanup.
+pub fn get_cache_dir() -> Result<PathBuf, String> {
+    // The cache is bounded by its own size limit (see evict); the pipeline's stage cache lives
+    // next to it, in stage_cache
     let base_dir = env::temp_dir().join(env!("CARGO_PKG_NAME"));
     let cache_dir = base_dir.join("image_cache");
     if fs::create_dir_all(&cache_dir).is_err() {
@@ -80,15 +116,16 @@ fn dcraw_working_dir() -> Option<PathBuf> {
 }
 
 fn run_dcraw_conversion(
-    app_handle: &tauri::AppHandle,
+    host: &dyn PipelineHost,
     dcraw_dir: Option<&Path>,
     input: &Path,
     output: &Path,
-) -> Result<(), String> {
+    cancel_token: &CancellationToken,
+) -> Result<(), PipelineError> {
     let mut cmd: Command;
 
     if dcraw_dir.is_none() || dcraw_dir.unwrap().as_os_str().is_empty() {
-        cmd = app_handle.shell().sidecar("dcraw_emu").unwrap().into();
+        cmd = host.sidecar_command("dcraw_emu")?;
         if let Some(wd) = dcraw_working_dir() {
             cmd.current_dir(wd);
         }
@@ -102,40 +139,425 @@ fn run_dcraw_conversion(
     args.push(input.display().to_string());
     cmd.args(args);
 
-    let stat = cmd.status();
-    if !stat.is_ok() || !stat.unwrap_or(ExitStatus::default()).success() {
-        return Err(
-            "Error, non-zero exit status. dcraw_emu command (converting to tiff images) failed."
-                .to_string(),
-        );
+    let output = run_command("raw_conversion", &mut cmd, cancel_token)?;
+    if !output.status.success() {
+        return Err(PipelineError::command_failed(
+            "raw_conversion",
+            &cmd,
+            &output,
+        ));
     }
 
     Ok(())
 }
 
+/**
+ * Returns the TIFF conversion of a raw image from the cache, converting it with dcraw_emu if
+ * it isn't cached yet. Safe to call from several threads at once.
+ *
+ * @param host - Host used to find the bundled dcraw_emu, and providing the cache size limit
+ * @param dcraw_dir - Directory containing dcraw_emu (None or empty to use the bundled sidecar)
+ * @param input - The raw image
+ * @param cancel_token - Kills the running conversion when cancelled
+ */
 pub fn ensure_tiff_for_raw(
-    app_handle: &tauri::AppHandle,
+    host: &dyn PipelineHost,
     dcraw_dir: Option<&Path>,
     input: &Path,
-) -> Result<PathBuf, String> {
-    let cache_dir = get_cache_dir(app_handle)?;
+    cancel_token: &CancellationToken,
+) -> Result<PathBuf, PipelineError> {
+    let cache_dir = get_cache_dir()?;
     let key = compute_hash_for_file(input, &dcraw_context())?;
     let output_path = cache_dir.join(format!("{}.tiff", key));
 
     // if there is an entry in the cache, return it
-    if output_path.exists() {
+    if output_path.exists() && hash_path(&output_path).exists() {
         println!("cache hit for {}", output_path.display());
         let meta_result = output_path.metadata();
         if meta_result.is_ok() && meta_result.unwrap().len() > 0 {
+            // record the access for least recently used eviction
+            touch(&output_path);
             return Ok(output_path);
         }
     }
 
-    // otherwise perform the conversion
-    let result = run_dcraw_conversion(app_handle, dcraw_dir, input, &output_path);
+    // otherwise perform the conversion into a partial file, which is moved in place once its
+    // hash is stored, so that a conversion running in parallel never uses an incomplete image
+    let partial_path = cache_dir.join(format!(
+        "{}.partial-{}-{}",
+        key,
+        std::process::id(),
+        PARTIAL_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let result = run_dcraw_conversion(host, dcraw_dir, input, &partial_path, cancel_token)
+        .and_then(|_| {
+            // store the content hash, used to validate the entry later
+            let hash = compute_hash_for_file(&partial_path, "")?;
+            fs::write(hash_path(&output_path), hash)
+                .and_then(|_| fs::rename(&partial_path, &output_path))
+                .map_err(|error| {
+                    format!("Error storing converted image in cache: {}", error).into()
+                })
+        });
     if result.is_err() {
-        let _ = fs::remove_file(&output_path);
+        let _ = fs::remove_file(&partial_path);
         return Err(result.err().unwrap());
     }
+
+    // make room for the new entry
+    if let Err(error) = evict(&cache_dir, host.image_cache_limit(), Some(&output_path)) {
+        println!("image cache eviction failed: {}", error);
+    }
+
     Ok(output_path)
 }
+
+/**
+ * Returns a private copy of the TIFF conversion of a raw image, in dest_dir. The copy is a hard
+ * link to the cache entry when possible, so it stays valid when the entry is evicted by another
+ * conversion (e.g. of a scene processed in parallel) while it is in use.
+ *
+ * @param host - Host used to find the bundled dcraw_emu, and providing the cache size limit
+ * @param dcraw_dir - Directory containing dcraw_emu (None or empty to use the bundled sidecar)
+ * @param input - The raw image
+ * @param dest_dir - Directory the copy is placed in (created if needed)
+ * @param cancel_token - Kills the running conversion when cancelled
+ */
+pub fn copy_tiff_for_raw(
+    host: &dyn PipelineHost,
+    dcraw_dir: Option<&Path>,
+    input: &Path,
+    dest_dir: &Path,
+    cancel_token: &CancellationToken,
+) -> Result<PathBuf, PipelineError> {
+    fs::create_dir_all(dest_dir)
+        .map_err(|error| format!("Error creating {}: {}", dest_dir.display(), error))?;
+
+    // the entry may be evicted between its conversion and the copy, in which case it is
+    // converted again
+    let mut last_error = String::new();
+    for _ in 0..2 {
+        let cached = ensure_tiff_for_raw(host, dcraw_dir, input, cancel_token)?;
+        let copy = dest_dir.join(cached.file_name().unwrap_or_default());
+        let _ = fs::remove_file(&copy);
+        match fs::hard_link(&cached, &copy).or_else(|_| fs::copy(&cached, &copy).map(|_| ())) {
+            Ok(()) => return Ok(copy),
+            Err(error) => last_error = error.to_string(),
+        }
+    }
+    Err(format!(
+        "Error copying the converted image of {}: {}",
+        input.display(),
+        last_error
+    )
+    .into())
+}
+
+/**
+ * Settings of the image cache and of the pipeline's stage cache, stored in the app's
+ * configuration directory
+ *
+ * @field max_size_bytes - Size the image cache is kept under by evicting least recently used
+ *                         entries
+ * @field stage_cache_max_size_bytes - Size the stage cache is kept under the same way
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheSettings {
+    pub max_size_bytes: u64,
+    #[serde(default = "default_stage_cache_max_size_bytes")]
+    pub stage_cache_max_size_bytes: u64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            max_size_bytes: DEFAULT_MAX_SIZE_BYTES,
+            stage_cache_max_size_bytes: DEFAULT_STAGE_CACHE_MAX_SIZE_BYTES,
+        }
+    }
+}
+
+// Settings saved before the stage cache had a limit get the default one
+fn default_stage_cache_max_size_bytes() -> u64 {
+    DEFAULT_STAGE_CACHE_MAX_SIZE_BYTES
+}
+
+/**
+ * Size and number of entries of the image cache and of the stage cache
+ *
+ * @field size_bytes - Total size of the cached images and their hashes
+ * @field entry_count - Number of cached images
+ * @field max_size_bytes - Configured size limit
+ * @field stage_cache_size_bytes - Total size of the stage outputs in the stage cache
+ * @field stage_cache_entry_count - Number of cached stage outputs
+ * @field stage_cache_max_size_bytes - Configured size limit of the stage cache
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub size_bytes: u64,
+    pub entry_count: usize,
+    pub max_size_bytes: u64,
+    pub stage_cache_size_bytes: u64,
+    pub stage_cache_entry_count: usize,
+    pub stage_cache_max_size_bytes: u64,
+}
+
+/**
+ * Result of validating the cache
+ *
+ * @field checked - Number of entries that were rehashed
+ * @field removed - Cached images that did not match their stored hash (or had none) and were removed
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    pub checked: usize,
+    pub removed: Vec<String>,
+}
+
+// An entry of a cache (a cached image, or a directory of the stage cache), with the size of its
+// files and when it was last used
+pub(crate) struct CacheEntry {
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) last_access: SystemTime,
+}
+
+/**
+ * Reads the cache settings, or the defaults if none were saved
+ */
+pub fn read_settings(app_handle: &tauri::AppHandle) -> CacheSettings {
+    app_handle
+        .path()
+        .app_config_dir()
+        .ok()
+        .and_then(|dir| fs::read_to_string(dir.join(SETTINGS_FILE)).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/**
+ * Saves the cache settings to the app's configuration directory
+ */
+pub fn write_settings(
+    app_handle: &tauri::AppHandle,
+    settings: &CacheSettings,
+) -> Result<(), String> {
+    let config_dir = app_handle
+        .path()
+        .app_config_dir()
+        .map_err(|_| "Error saving image cache settings".to_string())?;
+    if fs::create_dir_all(&config_dir).is_err() {
+        return Err("Error saving image cache settings".to_string());
+    }
+    let contents = serde_json::to_string(settings)
+        .map_err(|error| format!("Error serializing JSON: {:?}", error))?;
+    fs::write(config_dir.join(SETTINGS_FILE), contents)
+        .map_err(|error| format!("Error writing to file: {:?}", error))
+}
+
+/**
+ * Returns the size and number of entries of the cache
+ */
+pub fn cache_stats(cache_dir: &Path, settings: &CacheSettings) -> Result<CacheStats, String> {
+    let entries = list_entries(cache_dir)?;
+    let (stage_cache_size_bytes, stage_cache_entry_count) = stage_cache_stats()?;
+    Ok(CacheStats {
+        size_bytes: entries.iter().map(|entry| entry.size).sum(),
+        entry_count: entries.len(),
+        max_size_bytes: settings.max_size_bytes,
+        stage_cache_size_bytes,
+        stage_cache_entry_count,
+        stage_cache_max_size_bytes: settings.stage_cache_max_size_bytes,
+    })
+}
+
+/**
+ * Removes the least recently used entries until the cache is no larger than max_size_bytes.
+ * Returns the number of removed entries.
+ *
+ * @param keep - An entry that is never removed (the one just added), even if it alone exceeds
+ *               the limit
+ */
+pub fn evict(cache_dir: &Path, max_size_bytes: u64, keep: Option<&Path>) -> Result<usize, String> {
+    let entries = list_entries(cache_dir)?;
+    Ok(evict_entries(entries, max_size_bytes, keep, remove_entry))
+}
+
+/**
+ * Removes the least recently used of a cache's entries until their total size is no larger than
+ * max_size_bytes. Returns the number of removed entries.
+ *
+ * @param entries - The entries of the cache
+ * @param keep - An entry that is never removed (the one just added)
+ * @param remove - Removes the files of an entry
+ */
+pub(crate) fn evict_entries(
+    mut entries: Vec<CacheEntry>,
+    max_size_bytes: u64,
+    keep: Option<&Path>,
+    remove: impl Fn(&Path),
+) -> usize {
+    let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+
+    // oldest access first
+    entries.sort_by_key(|entry| entry.last_access);
+
+    let mut removed = 0;
+    for entry in entries {
+        if size <= max_size_bytes {
+            break;
+        }
+        if Some(entry.path.as_path()) == keep {
+            continue;
+        }
+        remove(&entry.path);
+        size = size.saturating_sub(entry.size);
+        removed += 1;
+    }
+    removed
+}
+
+/**
+ * Removes every entry of the cache. Returns the number of removed entries.
+ */
+pub fn clear(cache_dir: &Path) -> Result<usize, String> {
+    let entries = list_entries(cache_dir)?;
+    for entry in &entries {
+        remove_entry(&entry.path);
+    }
+    Ok(entries.len())
+}
+
+/**
+ * Rehashes every cached image and removes the ones that don't match their stored hash
+ */
+pub fn validate(cache_dir: &Path) -> Result<ValidationReport, String> {
+    let entries = list_entries(cache_dir)?;
+    let mut report = ValidationReport {
+        checked: entries.len(),
+        removed: Vec::new(),
+    };
+
+    for entry in entries {
+        let stored_hash = fs::read_to_string(hash_path(&entry.path)).ok();
+        let actual_hash = compute_hash_for_file(&entry.path, "").ok();
+        let valid = match (stored_hash, actual_hash) {
+            (Some(stored), Some(actual)) => stored.trim() == actual,
+            _ => false,
+        };
+        if !valid {
+            remove_entry(&entry.path);
+            report.removed.push(entry.path.display().to_string());
+        }
+    }
+
+    Ok(report)
+}
+
+// Lists the cached images (<key>.tiff) with the size of their files and their last access time
+fn list_entries(cache_dir: &Path) -> Result<Vec<CacheEntry>, String> {
+    let read_dir = fs::read_dir(cache_dir).map_err(|_| "Error reading image cache directory")?;
+
+    let mut entries: Vec<CacheEntry> = Vec::new();
+    for dir_entry in read_dir.flatten() {
+        let image = dir_entry.path();
+        if image.extension().and_then(|ext| ext.to_str()) != Some("tiff") {
+            continue;
+        }
+        let Ok(meta) = image.metadata() else {
+            continue;
+        };
+        let hash_size = hash_path(&image).metadata().map_or(0, |meta| meta.len());
+        let last_access = meta
+            .accessed()
+            .or_else(|_| meta.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        entries.push(CacheEntry {
+            path: image,
+            size: meta.len() + hash_size,
+            last_access,
+        });
+    }
+    Ok(entries)
+}
+
+// Path of the file holding the content hash of a cached image
+fn hash_path(image: &Path) -> PathBuf {
+    image.with_extension(HASH_EXTENSION)
+}
+
+// Sets the access time of a cached file to now. The OS doesn't update it on every read
+// (e.g. with noatime or relatime mounts), so hits record it explicitly.
+pub(crate) fn touch(image: &Path) {
+    if let Ok(file) = File::options().write(true).open(image) {
+        let _ = file.set_times(FileTimes::new().set_accessed(SystemTime::now()));
+    }
+}
+
+// Removes a cached image and its hash
+fn remove_entry(image: &Path) {
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(hash_path(image));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, time::Duration};
+
+    use super::*;
+
+    // Entry of the given size, last used the given number of seconds after the epoch
+    fn entry(name: &str, size: u64, last_access_secs: u64) -> CacheEntry {
+        CacheEntry {
+            path: PathBuf::from(name),
+            size,
+            last_access: SystemTime::UNIX_EPOCH + Duration::from_secs(last_access_secs),
+        }
+    }
+
+    // Runs an eviction and returns the removed entries, in removal order
+    fn evict(entries: Vec<CacheEntry>, max_size_bytes: u64, keep: Option<&str>) -> Vec<PathBuf> {
+        let removed = RefCell::new(Vec::new());
+        let count = evict_entries(entries, max_size_bytes, keep.map(Path::new), |path| {
+            removed.borrow_mut().push(path.to_path_buf())
+        });
+        let removed = removed.into_inner();
+        assert_eq!(count, removed.len());
+        removed
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let entries = || {
+            vec![
+                entry("b", 10, 200),
+                entry("a", 10, 100),
+                entry("d", 10, 400),
+                entry("c", 10, 300),
+            ]
+        };
+        assert!(evict(entries(), 40, None).is_empty());
+        assert_eq!(
+            evict(entries(), 25, None),
+            vec![PathBuf::from("a"), "b".into()]
+        );
+        assert_eq!(evict(entries(), 0, None).len(), 4);
+    }
+
+    #[test]
+    fn eviction_keeps_the_new_entry() {
+        let entries = vec![
+            entry("new", 30, 100),
+            entry("a", 10, 200),
+            entry("b", 10, 300),
+        ];
+        // The kept entry is the oldest and larger than the limit on its own
+        assert_eq!(
+            evict(entries, 20, Some("new")),
+            vec![PathBuf::from("a"), "b".into()]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use crate::pipeline::{ResponseCurves, ResponseFunction};
+
+// Number of pixel values sampled when the caller does not ask for a number
+const DEFAULT_SAMPLE_COUNT: usize = 64;
+
+// Largest number of pixel values that can be sampled
+const MAX_SAMPLE_COUNT: usize = 4096;
+
+// Parses a camera response function (.rsp) and samples its red, green and blue curves, so that
+// users can see the response they are loading before saving a configuration. Each curve says
+// whether it increases over the whole range, and whether it diverges from the other channels.
+// path:
+//      Path to the .rsp file
+// sample_count:
+//      Number of evenly spaced pixel values to sample, including 0 and 1 (default: 64)
+#[tauri::command]
+pub async fn inspect_response_function(
+    path: String,
+    sample_count: Option<usize>,
+) -> Result<ResponseCurves, String> {
+    let sample_count = sample_count.unwrap_or(DEFAULT_SAMPLE_COUNT);
+    if !(2..=MAX_SAMPLE_COUNT).contains(&sample_count) {
+        return Err(format!(
+            "The number of samples must be between 2 and {} (got {}).",
+            MAX_SAMPLE_COUNT, sample_count
+        ));
+    }
+
+    let response_function = ResponseFunction::read(Path::new(&path))?;
+    Ok(response_function.sample(sample_count))
+}
@@ -0,0 +1,140 @@
+// Import pipeline module (public so the headless command-line binary can run it)
+pub mod pipeline;
+use pipeline::pipeline;
+use pipeline::PipelineState;
+
+// Command to cancel a running pipeline
+use pipeline::cancel_pipeline;
+
+// Command to re-run the failed entries of a batch manifest
+use pipeline::rerun_failed;
+
+// Command to query operating system from frontend
+mod query_os_platform;
+use query_os_platform::query_os_platform;
+
+// Command to read saved binary paths from config file
+mod read_binary_paths;
+use read_binary_paths::read_binary_paths;
+
+// Command to read the contents of a directory
+mod read_dynamic_dir;
+use read_dynamic_dir::read_dynamic_dir;
+
+// Commands to read the header or a specific header value from an HDR file
+mod read_header;
+use read_header::read_header;
+use read_header::read_header_value;
+
+// Command used to read from a file and return its contents to frontend
+mod read_host_file;
+use read_host_file::read_host_file;
+
+// Command to write new binary paths to config file
+mod write_binary_paths;
+use write_binary_paths::write_binary_paths;
+
+// Command to write to a file given its contents from frontend
+mod write_host_file;
+use write_host_file::write_host_file;
+
+// Command to delete a saved config
+mod delete_config;
+use delete_config::delete_config;
+
+// Command to get app's data directory
+mod get_default_output_path;
+use get_default_output_path::get_default_output_path;
+
+// Command to save configuration
+mod save_config;
+use save_config::save_config;
+
+// Command to retrieve saved configurations (public so the headless command-line binary can load them)
+pub mod get_saved_configs;
+use get_saved_configs::get_saved_configs;
+
+// Command to recover a camera response function (.rsp) from bracket sets
+mod recover_response_function;
+use recover_response_function::recover_response_function;
+
+// Command to sample the response curves of a .rsp file for plotting
+mod inspect_response_function;
+use inspect_response_function::inspect_response_function;
+
+// Command to split a directory of many brackets into bracket sets
+mod group_brackets;
+use group_brackets::group_brackets;
+
+// Command to find the fisheye image circle in a bracket
+mod detect_fisheye_circle;
+use detect_fisheye_circle::detect_fisheye_circle;
+
+// Command to compute luminance statistics over the fisheye view of an HDR image
+mod luminance_statistics;
+use luminance_statistics::luminance_statistics;
+
+// Command to convert raw image into tiff image
+mod raw_image_help;
+use raw_image_help::convert_raw_img;
+
+// Image cache utilities, and commands to report, limit, clear and validate the cache
+mod image_cache;
+use image_cache::commands::{
+    clear_image_cache, get_image_cache_stats, set_image_cache_limit, validate_image_cache,
+};
+
+// Native reader/writer for Radiance RGBE (.hdr) pictures
+pub mod radiance_hdr;
+
+// Command to display HDR image using ximage utility
+mod display_hdr_img;
+use display_hdr_img::display_hdr_img;
+
+use tauri::Manager;
+
+// Builds and runs the Tauri application (called from main.rs)
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_os::init())
+        .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_dialog::init())
+        .manage(PipelineState::default())
+        .invoke_handler(tauri::generate_handler![
+            pipeline,
+            cancel_pipeline,
+            rerun_failed,
+            query_os_platform,
+            read_binary_paths,
+            read_dynamic_dir,
+            read_header_value,
+            read_header,
+            read_host_file,
+            write_binary_paths,
+            write_host_file,
+            delete_config,
+            get_default_output_path,
+            save_config,
+            get_saved_configs,
+            recover_response_function,
+            inspect_response_function,
+            group_brackets,
+            detect_fisheye_circle,
+            luminance_statistics,
+            convert_raw_img,
+            get_image_cache_stats,
+            set_image_cache_limit,
+            clear_image_cache,
+            validate_image_cache,
+            display_hdr_img,
+        ])
+        .setup(|app| {
+            let window = app.get_webview_window("main").unwrap();
+            window.show().unwrap();
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
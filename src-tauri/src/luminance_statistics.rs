@@ -0,0 +1,30 @@
+use std::path::Path;
+
+use crate::pipeline::{
+    luminance_statistics as compute_statistics, LuminanceStatistics, DEFAULT_BINS_PER_DECADE,
+    DEFAULT_PERCENTILES,
+};
+
+// Computes luminance statistics of an HDR image over the circle inscribed in it (the fisheye
+// view of a pipeline output), for the image viewer: min, max, mean, median, percentiles, the
+// mean weighted by the solid angle of each pixel, and a histogram with logarithmic bins.
+// Values are in cd/m².
+// file_path:
+//      Path of the .hdr image
+// percentiles:
+//      Percentiles to compute, from 0 to 100 (default: 1, 5, 25, 50, 75, 95, 99)
+// bins_per_decade:
+//      Number of histogram bins per decade of luminance (default: 10)
+#[tauri::command]
+pub async fn luminance_statistics(
+    file_path: String,
+    percentiles: Option<Vec<f64>>,
+    bins_per_decade: Option<usize>,
+) -> Result<LuminanceStatistics, String> {
+    let percentiles = percentiles.unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec());
+    compute_statistics(
+        Path::new(&file_path),
+        &percentiles,
+        bins_per_decade.unwrap_or(DEFAULT_BINS_PER_DECADE),
+    )
+}
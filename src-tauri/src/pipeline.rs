@@ -1,171 +1,219 @@
+mod alignment;
+mod bracket_grouping;
+mod cancel;
+mod circle_detection;
 mod crop;
+mod error;
 mod evalglare;
+mod glare_table;
 mod header_editing;
+mod host;
+mod image_metadata;
+mod luminance_statistics;
+mod manifest;
 mod merge_exposures;
+mod native_glare;
+mod native_merge;
 mod neutral_density;
 mod nullify_exposure_value;
+mod params;
 mod photometric_adjustment;
+mod progress;
 mod projection_adjustment;
+mod radiance_command;
 mod resize;
+mod response_function;
+mod response_recovery;
+mod stage;
+mod stage_cache;
 mod vignetting_effect_correction;
 
-use tauri::Emitter;
 mod falsecolor;
 
 use std::{
-    fs::{self, copy, create_dir_all},
+    collections::HashSet,
+    fs::{self, copy, create_dir_all, remove_dir_all},
     io,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
 };
 
+pub use alignment::{align_exposures, FrameOffset};
+pub use bracket_grouping::{group_brackets, BracketGrouping, ProposedGroup, DEFAULT_MAX_GAP_SECS};
+pub use cancel::{
+    run_command, run_command_output, CancellationToken, PipelineState, CANCELLED_MESSAGE,
+};
 use chrono::prelude::*;
-use crop::crop;
-use evalglare::evalglare;
-use falsecolor::falsecolor;
-use header_editing::header_editing;
-use merge_exposures::merge_exposures;
-use neutral_density::neutral_density;
-use nullify_exposure_value::nullify_exposure_value;
-use photometric_adjustment::photometric_adjustment;
-use projection_adjustment::projection_adjustment;
-use resize::resize;
-use vignetting_effect_correction::vignetting_effect_correction;
+pub use circle_detection::{
+    check_circle, check_scene_circle, detect_circle, detect_fisheye_circle, CircleDetection,
+    MIN_CONFIDENCE,
+};
+pub use error::PipelineError;
+pub use evalglare::{GlareMetrics, GlareSource};
+pub use glare_table::{scene_capture_time, write_glare_table, GlareRow};
+pub use host::{bundled_sidecar_path, PipelineHost};
+pub use image_metadata::{check_bracket, read_metadata, ImageMetadata};
+pub use luminance_statistics::{
+    luminance_statistics, HistogramBin, LuminanceStatistics, Percentile, DEFAULT_BINS_PER_DECADE,
+    DEFAULT_PERCENTILES,
+};
+pub use manifest::{BatchManifest, ManifestEntry, SceneOutputs, SceneStatus};
+pub use native_glare::{compare_glare, compute_glare, NativeGlare, GLARE_TOLERANCES};
+pub use params::{
+    BracketCheck, BracketGroup, EvalglareArgs, EvalglareOutput, GlareBackend, LuminanceArgs,
+    MergeBackend, PipelineParams, TaskArea, DEFAULT_MAX_EV_STEP,
+};
+pub use progress::{PipelineProgress, ProgressEvent, ProgressTracker};
+use rayon::prelude::*;
+pub use response_function::{ResponseCurve, ResponseCurves, ResponseFunction};
+pub use response_recovery::{recover_response, ChannelFit, ResponseRecovery};
+pub use stage::{
+    build_stages, default_stages, Artifact, Stage, StageContext, StageData, StageEntry, StageKind,
+};
+pub use stage_cache::{
+    clear_stage_cache, evict_stage_cache, stage_cache_stats, CacheInputs, StageCache,
+    DEFAULT_STAGE_CACHE_MAX_SIZE_BYTES,
+};
 
 // Used to print out debug information
 pub const DEBUG: bool = true;
 
 // Struct to hold some configuration settings (e.g. path settings).
 // Used when various stages of the pipeline are called.
+#[derive(Clone)]
 pub struct ConfigSettings {
     radiance_path: PathBuf,
     hdrgen_path: PathBuf,
     dcraw_emu_path: PathBuf,
     output_path: PathBuf,
     temp_path: PathBuf, // used to store temp path in output dir, i.e. "output_path/tmp/"
+    cancel_token: CancellationToken, // cancelled by the cancel_pipeline command
+    stage_cache: Option<StageCache>, // None if stage outputs are not cached
 }
 
-// Helper functon to emit progress events
-fn emit_progress(
-    app: &tauri::AppHandle,
-    current_step: usize,
-    total_steps: usize,
-) -> Result<(), String> {
-    let progress = ((current_step as f64 / total_steps as f64) * 100.0) as i32;
-    app.emit("pipeline-progress", progress)
-        .map_err(|e| format!("Failed to emit progress event: {}", e))
+// One set of LDR images processed into an HDR image
+pub struct Scene {
+    pub index: usize, // position of the scene in the run, starting at 0
+    pub name: String, // name of the input directory (empty for a single scene)
+    pub input_images: Vec<String>,
 }
 
-// Struct to hold argument values for falsecolor2/luminance mapping
-pub struct LuminanceArgs {
-    scale_limit: String,
-    scale_label: String,
-    scale_levels: String,
-    legend_dimensions: String,
+// One scene of a batch: an input directory, or a bracket group
+struct BatchScene<'a> {
+    input: String, // input directory or group name, as listed in the manifest
+    name: String,  // name of the scene's temp directory and output files
+    images: Option<&'a [String]>, // images of a bracket group (None to use the directory's images)
+    output_dir: PathBuf, // subdirectory of the output directory for the outputs (empty if none)
 }
 
 // Runs the radiance and hdrgen pipeline.
-// radiance_path:
-//      The path to radiance binaries
-// hdrgen_path:
-//      The path to the hdrgen binary
-// dcraw_emu_path:
-//      The path to the dcraw_emu binary, used for converting raw images to tiff format
-// output_path:
-//      Place for final HDR image to be stored. Temp dir is created within the ouput dir.
-// input_images:
-//      vector of the paths to the input images, or the input directories if batch processing.
-//      Input images must be in .JPG format or .CR2 format.
-// response_function:
-//      string for the path to the camera response function (.rsp)
-// fisheye_correction_cal:
-//      a string for the path to fisheye correction calibration file (.cal)
-// vignetting_correction_cal:
-//      a string for the path to vignetting correction calibration file (.cal)
-// photometric_adjustment_cal:
-//      a string for the path to photometric adjustment calibration file (.cal)
-// neutral_density_cal:
-//      a string for the path to neutral density adjustment calibration file (.cal)
-// diameter:
-//      the fisheye view diameter in pixels
-// xleft:
-//      The x-coordinate of the bottom left corner of the circumscribed square
-//      of the fisheye view (in pixels)
-// ydown:
-//      The y-coordinate of the bottom left corner of the circumscribed square
-//      of the fisheye view (in pixels)
-// xdim:
-//      The x-dimensional resolution to resize the HDR image to (in pixels)
-// ydim:
-//      The y-dimensional resolution to resize the HDR image to (in pixels)
+// params:
+//      The typed parameters of the run (tool paths, output path, input images or directories,
+//      response function and calibration files, fisheye view, target resolution, view angles
+//      and falsecolor settings). See PipelineParams for details.
+//      Parameters are validated before any external command is run, and all problems
+//      found are returned together.
 #[tauri::command]
 pub async fn pipeline(
     app: tauri::AppHandle,
-    radiance_path: String,
-    hdrgen_path: String,
-    dcraw_emu_path: String,
-    output_path: String,
-    input_images: Vec<String>,
-    response_function: String,
-    fisheye_correction_cal: String,
-    vignetting_correction_cal: String,
-    photometric_adjustment_cal: String,
-    neutral_density_cal: String,
-    diameter: String,
-    xleft: String,
-    ydown: String,
-    mut xdim: String,
-    mut ydim: String,
-    mut vertical_angle: String,
-    mut horizontal_angle: String,
-    scale_limit: String,
-    scale_label: String,
-    scale_levels: String,
-    legend_dimensions: String,
-    filter_images: bool,
-) -> Result<String, String> {
-    // Return error if pipeline was called with no input images
-    if input_images.len() == 0 {
-        return Err("No input images were provided.".into());
+    state: tauri::State<'_, PipelineState>,
+    params: PipelineParams,
+) -> Result<String, PipelineError> {
+    let cancel_token = state.start_run();
+    run_pipeline(&app, params, cancel_token)
+}
+
+// Runs the entries of a batch manifest that failed or were not run again, with the parameters
+// of the original run. A new manifest is written for the re-run entries.
+// manifest_path:
+//      Path to the JSON manifest (batch_<date>.json) written by the batch run
+#[tauri::command]
+pub async fn rerun_failed(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, PipelineState>,
+    manifest_path: String,
+) -> Result<String, PipelineError> {
+    let cancel_token = state.start_run();
+    let params = BatchManifest::read(Path::new(&manifest_path))?.rerun_params()?;
+    run_pipeline(&app, params, cancel_token)
+}
+
+// Cancels the running pipeline. The running external command is killed, the partial outputs
+// of the current image set are removed from the temp directory, and the pipeline command
+// returns a Cancelled error.
+#[tauri::command]
+pub fn cancel_pipeline(state: tauri::State<'_, PipelineState>) -> Result<(), String> {
+    state.cancel();
+    Ok(())
+}
+
+// Runs the pipeline on behalf of a host (the GUI or the headless command-line binary).
+// The run stops early with a Cancelled error if cancel_token is cancelled.
+// Batch runs write a manifest of the outcome of every input directory to the output directory.
+pub fn run_pipeline(
+    host: &dyn PipelineHost,
+    params: PipelineParams,
+    cancel_token: CancellationToken,
+) -> Result<String, PipelineError> {
+    // Reject invalid parameters before any external command runs
+    if let Err(errors) = params.validate() {
+        return Err(PipelineError::InvalidParams { errors });
     }
 
-    let is_directory = if input_images.len() > 0 {
-        Path::new(&input_images[0]).is_dir()
+    // Bracket groups, or directories selected as inputs, are processed as the scenes of a batch
+    let batch_scenes: Option<Vec<BatchScene>> = if !params.bracket_groups.is_empty() {
+        Some(
+            params
+                .bracket_groups
+                .iter()
+                .map(|group| BatchScene {
+                    input: group.name.clone(),
+                    name: group.name.clone(),
+                    images: Some(&group.images),
+                    output_dir: PathBuf::new(),
+                })
+                .collect(),
+        )
+    } else if params.recursive {
+        Some(discover_scenes(&params.input_images)?)
+    } else if Path::new(&params.input_images[0]).is_dir() {
+        Some(
+            params
+                .input_images
+                .iter()
+                .map(|input_dir| BatchScene {
+                    input: input_dir.clone(),
+                    name: dir_name(Path::new(input_dir)),
+                    images: None,
+                    output_dir: PathBuf::new(),
+                })
+                .collect(),
+        )
     } else {
-        false
+        None
     };
 
-    // let the frontend handle defaults
-    // if xdim.len() < 1 {
-    //     xdim = "1000".to_string();
-    //     ydim = "1000".to_string();
-    // }
-    // if vertical_angle.len() < 1 {
-    //     vertical_angle = "180".to_string();
-    // }
-    // if horizontal_angle.len() < 1 {
-    //     horizontal_angle = "180".to_string();
-    // }
+    // When the failed scenes of a run are run again, only those scenes are processed. Names are
+    // made unique first, so that a scene keeps its name when it is run again.
+    let batch_scenes = batch_scenes.map(|mut scenes| {
+        make_scene_names_unique(&mut scenes);
+        if !params.scene_filter.is_empty() {
+            scenes.retain(|scene| params.scene_filter.contains(&scene.input));
+        }
+        scenes
+    });
 
     if DEBUG {
         println!("Pipeline module called...");
-        println!("\tradiance path: {radiance_path}");
-        println!("\thdrgen path: {hdrgen_path}");
-        println!("\tdcraw_emu path: {dcraw_emu_path}");
-        println!("\toutput path: {output_path}");
-        println!("\tinput images: {:?}", input_images);
-        println!("\tresponse function: {response_function}");
-        println!("\tfisheye correction cal: {fisheye_correction_cal}");
-        println!("\tvignetting correction cal: {vignetting_correction_cal}");
-        println!("\tphotometric adjustment cal: {photometric_adjustment_cal}");
-        println!("\tneutral density cal: {neutral_density_cal}");
-        println!("\tdiameter: {diameter}");
-        println!("\txleft: {xleft}");
-        println!("\tydown: {ydown}");
-        println!("\txdim: {xdim}");
-        println!("\tydim: {ydim}");
+        println!("\tparams: {:?}", params);
 
         println!("\n\nPROCESSING MODE");
-        if is_directory {
+        if !params.bracket_groups.is_empty() {
+            println!("\tUser selected bracket groups. (Batch processing)");
+        } else if params.recursive {
+            println!("\tUser selected directory trees. (Recursive batch processing)");
+        } else if batch_scenes.is_some() {
             println!("\tUser selected directories. (Batch processing)");
         } else {
             println!("\tUser selected images. (Single scene)");
@@ -174,202 +222,408 @@ pub async fn pipeline(
 
     // Add paths to radiance, hdrgen, raw2hdr, and output and temp directories to config settings
     let mut config_settings = ConfigSettings {
-        radiance_path: Path::new(&radiance_path).to_owned(),
-        hdrgen_path: Path::new(&hdrgen_path).to_owned(),
-        dcraw_emu_path: Path::new(&dcraw_emu_path).to_owned(),
-        output_path: Path::new(&output_path).to_owned(),
-        temp_path: Path::new(&output_path).join("tmp").to_owned(), // Temp directory is located in output directory
-    };
-
-    // Add arguments for falsecolor2 to luminance arguments struct
-    let luminance_args = LuminanceArgs {
-        scale_limit: scale_limit,
-        scale_label: scale_label,
-        scale_levels: scale_levels,
-        legend_dimensions: legend_dimensions,
+        radiance_path: params.radiance_path.clone(),
+        hdrgen_path: params.hdrgen_path.clone(),
+        dcraw_emu_path: params.dcraw_emu_path.clone(),
+        output_path: params.output_path.clone(),
+        temp_path: params.output_path.join("tmp"), // Temp directory is located in output directory
+        cancel_token,
+        stage_cache: None,
     };
 
     // Creates output directory with /tmp subdirectory
     let create_dirs_result = create_dir_all(&config_settings.temp_path);
 
     if create_dirs_result.is_err() {
-        return Result::Err(("Error creating tmp and output directories.").to_string());
+        return Err("Error creating tmp and output directories.".into());
     }
 
-    //Define total steps for progress bar (adjust this count as needed)
-    let total_steps: usize = if is_directory { 5 } else { 5 };
-
-    let mut current_step: usize = 0;
-    emit_progress(&app, current_step, total_steps)?; // Initial progress (0%)
-
-    let mut return_path: PathBuf = PathBuf::new();
-    if is_directory {
-        // Directories were selected (batch processing)
-
-        // Run pipeline for each directory selected
-        for input_dir in &input_images {
-            // Create a subdirectory inside tmp for this directory with input images (same name as input dir)
-            config_settings.temp_path = Path::new(&config_settings.output_path)
-                .join("tmp")
-                .join(Path::new(input_dir).file_name().unwrap_or_default());
+    // Stages whose inputs did not change since an earlier run are restored from the cache.
+    // The cache only speeds up runs, so the pipeline runs without it if it can't be opened.
+    if params.use_cache {
+        match StageCache::open(host.stage_cache_limit()) {
+            Ok(cache) => config_settings.stage_cache = Some(cache),
+            Err(error) => println!("Stage cache disabled: {}", error),
+        }
+    }
 
-            if create_dir_all(&config_settings.temp_path).is_err() {
-                return Result::Err(
-                    ("Error creating directories for outputs in temp directory.").to_string(),
-                );
+    // Build the stages from the pipeline description (already checked by validate)
+    let stages = build_stages(&params).map_err(|errors| PipelineError::InvalidParams { errors })?;
+
+    // Progress covers every stage of every scene (one scene per directory or group in batch mode)
+    let scene_count = batch_scenes.as_ref().map_or(1, |scenes| scenes.len());
+    let progress = ProgressTracker::start(host, scene_count, stages.len())?; // Initial progress (0%)
+
+    let return_path = if let Some(batch_scenes) = &batch_scenes {
+        // Directories or bracket groups were selected (batch processing)
+
+        // Scenes are processed by a pool of params.workers threads (one after another by default)
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(params.workers)
+            .build()
+            .map_err(|e| format!("Error creating worker threads: {}", e))?;
+
+        // Set when a scene fails, so that no new scenes are started (unless continuing on errors)
+        let failed = AtomicBool::new(false);
+
+        // Run pipeline for each directory or group selected
+        let results: Vec<Option<Result<SceneOutputs, PipelineError>>> = pool.install(|| {
+            batch_scenes
+                .par_iter()
+                .with_max_len(1) // Hand out scenes one at a time, in order
+                .enumerate()
+                .map(|(scene_index, batch_scene)| {
+                    // Don't start the next directory if the run was cancelled or a scene failed
+                    if config_settings.cancel_token.is_cancelled() || failed.load(Ordering::SeqCst)
+                    {
+                        return None;
+                    }
+
+                    let result = process_batch_scene(
+                        host,
+                        &config_settings,
+                        &params,
+                        &stages,
+                        scene_index,
+                        batch_scene,
+                        &progress,
+                    );
+                    if result.is_err() && !params.continue_on_error {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    Some(result)
+                })
+                .collect()
+        });
+
+        // List the outcome of every directory, also when the run stopped early
+        let manifest = BatchManifest {
+            created: Local::now().to_rfc3339(),
+            params: params.clone(),
+            entries: batch_scenes
+                .iter()
+                .zip(&results)
+                .map(|(batch_scene, result)| {
+                    ManifestEntry::new(&batch_scene.input, result.as_ref())
+                })
+                .collect(),
+        };
+        let batch_datetime = Local::now().format("%F_%H-%M-%S");
+        let manifest_name = format!("batch_{}", batch_datetime);
+        let manifest_path = manifest.write(&config_settings.output_path, &manifest_name)?;
+
+        // Table of the glare metrics of the scenes that succeeded, for analysis in R or pandas
+        let glare_rows: Vec<GlareRow> = batch_scenes
+            .iter()
+            .zip(&results)
+            .filter_map(|(batch_scene, result)| match result {
+                Some(Ok(outputs)) => Some(GlareRow {
+                    scene: &batch_scene.name,
+                    input: &batch_scene.input,
+                    outputs,
+                }),
+                _ => None,
+            })
+            .collect();
+        let glare_table_name = format!("glare_metrics_{}", batch_datetime);
+        if let Some(table_path) =
+            write_glare_table(&config_settings.output_path, &glare_table_name, &glare_rows)?
+        {
+            if DEBUG {
+                println!("Glare metrics table written to {}", table_path.display());
             }
+        }
 
-            // Grab all JPG or CR2 images from the directory and ignore all other files
-            let input_images_from_dir_result = get_images_from_dir(&input_dir);
-            if input_images_from_dir_result.is_err() {
-                return Err(input_images_from_dir_result.unwrap_err());
-            }
-            let input_images_from_dir = input_images_from_dir_result.unwrap();
+        if config_settings.cancel_token.is_cancelled() {
+            return Err(PipelineError::Cancelled);
+        }
 
-            if input_images_from_dir.len() == 0 {
-                return Err("All directories must contain at least one LDR image".to_string());
+        // Report the first scene that failed, in input order
+        if !params.continue_on_error {
+            for result in results.into_iter().flatten() {
+                result?;
             }
+        }
 
-            // Run the HDRGen and Radiance pipeline on the input images
-            let result = process_image_set(
-                &app,
-                &config_settings,
-                &luminance_args,
-                input_images_from_dir,
-                response_function.clone(),
-                fisheye_correction_cal.clone(),
-                vignetting_correction_cal.clone(),
-                photometric_adjustment_cal.clone(),
-                neutral_density_cal.clone(),
-                diameter.clone(),
-                xleft.clone(),
-                ydown.clone(),
-                xdim.clone(),
-                ydim.clone(),
-                vertical_angle.clone(),
-                horizontal_angle.clone(),
-                current_step,
-                total_steps,
-                filter_images,
-            );
-            if result.is_err() {
-                return result;
-            }
+        // All scenes ran, but some of them failed
+        let failed_count = manifest.unfinished_count();
+        if failed_count > 0 {
+            progress.finish()?;
+            return Err(PipelineError::ScenesFailed {
+                failed_count,
+                scene_count,
+                manifest: manifest_path.to_string_lossy().to_string(),
+            });
+        }
 
-            // Set output file name to be the same as the input directory name (i.e. <dir_name>.hdr)
-            // Get current local date and time and format output name with it
-            let datetime = format!("{}", Local::now().format("%F_%H-%M-%S"));
-            return_path = config_settings.output_path.join(Path::new(input_dir));
-            let base_name = Path::new(input_dir)
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy();
-
-            let mut output_file_name = config_settings
-                .output_path
-                .join(format!("{}_{}.hdr", base_name, datetime));
-
-            // Copy the final output hdr image to output directory
-            let mut copy_result = copy(
-                &config_settings.temp_path.join("header_editing.hdr"),
-                output_file_name,
-            );
-            if copy_result.is_err() {
-                return Result::Err(
-                    ("Error copying final hdr image to output directory.").to_string(),
-                );
-            }
-            if copy_result.is_err() {
-                return Result::Err(
-                    ("Error copying evalglare value to output directory.").to_string(),
-                );
-            }
-            let base_name2 = Path::new(input_dir)
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy();
-            let luminance_file_name = config_settings
-                .output_path
-                .join(format!("{}_{}_fc.hdr", base_name2, datetime));
-            copy_result = copy(
-                &config_settings.temp_path.join("falsecolor_output.hdr"),
-                luminance_file_name,
-            );
-            if copy_result.is_err() {
-                return Result::Err(
-                    ("Error copying final luminance map hdr image to output directory.")
-                        .to_string(),
-                );
-            }
+        if !params.bracket_groups.is_empty() {
+            config_settings.output_path.clone()
+        } else if params.recursive {
+            // Root of the mirrored tree of the last input directory
+            config_settings.output_path.join(dir_name(Path::new(
+                params.input_images.last().unwrap_or(&String::new()),
+            )))
+        } else {
+            config_settings.output_path.join(Path::new(
+                params.input_images.last().unwrap_or(&String::new()),
+            ))
         }
     } else {
         // Individual images were selected (single scene)
 
         // Ensure images are a supported format
-        for input_image in &input_images {
+        for input_image in &params.input_images {
             if !is_supported_format(&PathBuf::from(input_image)) {
-                return Err("Unsupported image format.".to_string());
+                return Err("Unsupported image format.".into());
             }
         }
 
+        let scene = Scene {
+            index: 0,
+            name: String::new(),
+            input_images: params.input_images.clone(),
+        };
+
         // Run the HDRGen and Radiance pipeline on the images
-        let result = process_image_set(
-            &app,
-            &config_settings,
-            &luminance_args,
+        let data = process_image_set(host, &config_settings, &params, &stages, &scene, &progress)?;
+
+        // Get current local date and time and format output name with it
+        let datetime = format!("{}", Local::now().format("%F_%H-%M-%S"));
+        // Copy the final output hdr image to output directory
+        copy_outputs(&data, &config_settings, &datetime)?;
+        config_settings.output_path.clone()
+    };
+
+    progress.finish()?;
+
+    // If no errors, return Ok
+    return Result::Ok(return_path.to_string_lossy().to_string());
+}
+
+// Runs the pipeline on the images of one directory or bracket group of a batch, in its own
+// subdirectory of tmp, and copies the outputs to <name>_<date>.hdr (in the scene's output
+// subdirectory when recursively discovered). Returns the outputs for the batch manifest.
+// Errors are reported as a SceneFailed progress event and returned wrapped with the name of the
+// scene.
+fn process_batch_scene(
+    host: &dyn PipelineHost,
+    config_settings: &ConfigSettings,
+    params: &PipelineParams,
+    stages: &[Box<dyn Stage>],
+    scene_index: usize,
+    batch_scene: &BatchScene,
+    progress: &ProgressTracker,
+) -> Result<SceneOutputs, PipelineError> {
+    let base_name = batch_scene.name.clone();
+
+    let result = (|| -> Result<SceneOutputs, PipelineError> {
+        let config_settings = scene_config_settings(config_settings, batch_scene)?;
+
+        let input_images = match batch_scene.images {
+            Some(images) => {
+                if !images
+                    .iter()
+                    .all(|image| is_supported_format(&PathBuf::from(image)))
+                {
+                    return Err("Unsupported image format.".into());
+                }
+                images.to_vec()
+            }
+            None => {
+                // Grab all JPG or CR2 images from the directory and ignore all other files
+                let input_images_from_dir = get_images_from_dir(&batch_scene.input)?;
+                if input_images_from_dir.is_empty() {
+                    return Err("All directories must contain at least one LDR image".into());
+                }
+                input_images_from_dir
+            }
+        };
+
+        let scene = Scene {
+            index: scene_index,
+            name: base_name.clone(),
             input_images,
-            response_function.clone(),
-            fisheye_correction_cal.clone(),
-            vignetting_correction_cal.clone(),
-            photometric_adjustment_cal.clone(),
-            neutral_density_cal.clone(),
-            diameter.clone(),
-            xleft.clone(),
-            ydown.clone(),
-            xdim.clone(),
-            ydim.clone(),
-            vertical_angle.clone(),
-            horizontal_angle.clone(),
-            current_step,
-            total_steps,
-            filter_images,
-        );
-        if result.is_err() {
-            return result;
-        }
+        };
+
+        // Run the HDRGen and Radiance pipeline on the input images
+        let data = process_image_set(host, &config_settings, params, stages, &scene, progress)?;
 
+        // Set output file name to be the same as the input directory or group name (i.e. <name>.hdr)
         // Get current local date and time and format output name with it
         let datetime = format!("{}", Local::now().format("%F_%H-%M-%S"));
-        let output_file_name = config_settings
-            .output_path
-            .join(format!("{}.hdr", datetime));
 
         // Copy the final output hdr image to output directory
-        let mut copy_result = copy(
-            &config_settings.temp_path.join("header_editing.hdr"),
-            output_file_name,
-        );
-        if copy_result.is_err() {
-            return Result::Err(("Error copying final hdr image to output directory.").to_string());
+        let mut outputs = copy_outputs(
+            &data,
+            &config_settings,
+            &format!("{}_{}", base_name, datetime),
+        )?;
+        outputs.capture_time = scene_capture_time(&scene.input_images);
+        Ok(outputs)
+    })();
+
+    result.map_err(|error| {
+        let error = error.in_scene(&base_name, scene_index);
+        if let PipelineError::SceneFailed { error: cause, .. } = &error {
+            let _ = progress.scene_failed(scene_index, &base_name, cause);
         }
+        error
+    })
+}
 
-        let luminance_file_name = config_settings
-            .output_path
-            .join(format!("{}_fc.hdr", datetime));
-        copy_result = copy(
-            &config_settings.temp_path.join("falsecolor_output.hdr"),
-            luminance_file_name,
-        );
-        if copy_result.is_err() {
-            return Result::Err(
-                ("Error copying final hdr luminance image to output directory.").to_string(),
+// Returns the settings of a batch scene, with its own subdirectory of tmp (named after the scene,
+// i.e. its input directory or bracket group) and its output subdirectory (mirroring the input
+// tree in recursive mode). Creates both directories.
+fn scene_config_settings(
+    config_settings: &ConfigSettings,
+    batch_scene: &BatchScene,
+) -> Result<ConfigSettings, String> {
+    let mut config_settings = config_settings.clone();
+    config_settings.temp_path = config_settings
+        .output_path
+        .join("tmp")
+        .join(&batch_scene.output_dir)
+        .join(&batch_scene.name);
+    config_settings.output_path = config_settings.output_path.join(&batch_scene.output_dir);
+
+    if create_dir_all(&config_settings.temp_path).is_err() {
+        return Err("Error creating directories for outputs in temp directory.".into());
+    }
+    if create_dir_all(&config_settings.output_path).is_err() {
+        return Err(format!(
+            "Error creating output directory {}.",
+            config_settings.output_path.display()
+        ));
+    }
+    Ok(config_settings)
+}
+
+// Copies the final HDR image (<name>.hdr) and, if they were produced, the falsecolor
+// luminance map (<name>_fc.hdr) and the evalglare check image (<name>_glare_check.hdr) of an
+// image set to the output directory.
+// Returns the paths of the copies.
+fn copy_outputs(
+    data: &StageData,
+    config_settings: &ConfigSettings,
+    name: &str,
+) -> Result<SceneOutputs, String> {
+    let hdr_image = data
+        .hdr_image
+        .as_ref()
+        .ok_or("Pipeline did not produce an hdr image.")?;
+    let mut outputs = SceneOutputs {
+        hdr_image: config_settings.output_path.join(format!("{}.hdr", name)),
+        falsecolor_image: None,
+        glare_check_image: None,
+        glare_metrics: data.glare_metrics.clone(),
+        capture_time: None,
+    };
+    if copy(hdr_image, &outputs.hdr_image).is_err() {
+        return Err("Error copying final hdr image to output directory.".to_string());
+    }
+
+    if let Some(falsecolor_image) = &data.falsecolor_image {
+        let falsecolor_output = config_settings.output_path.join(format!("{}_fc.hdr", name));
+        if copy(falsecolor_image, &falsecolor_output).is_err() {
+            return Err(
+                "Error copying final luminance map hdr image to output directory.".to_string(),
             );
         }
-        return_path = config_settings.output_path;
+        outputs.falsecolor_image = Some(falsecolor_output);
     }
 
-    // If no errors, return Ok
-    return Result::Ok(return_path.to_string_lossy().to_string());
+    if let Some(glare_check_image) = &data.glare_check_image {
+        let check_output = config_settings
+            .output_path
+            .join(format!("{}_glare_check.hdr", name));
+        if copy(glare_check_image, &check_output).is_err() {
+            return Err("Error copying evalglare check image to output directory.".to_string());
+        }
+        outputs.glare_check_image = Some(check_output);
+    }
+
+    Ok(outputs)
+}
+
+// Finds the scenes of a recursive batch: every leaf directory with supported images below the
+// input directories (directories whose subdirectories contain no images), in path order. The
+// outputs of a scene are written to the same relative path below the output directory, starting
+// with the name of its input directory, so that scenes with the same name in different
+// directories (e.g. site1/position1 and site2/position1) do not overwrite each other.
+fn discover_scenes(input_dirs: &[String]) -> Result<Vec<BatchScene<'static>>, String> {
+    let mut scenes: Vec<BatchScene> = Vec::new();
+    for input_dir in input_dirs {
+        let root = Path::new(input_dir);
+        let mut scene_dirs: Vec<PathBuf> = Vec::new();
+        find_scene_dirs(root, &mut scene_dirs)?;
+        if scene_dirs.is_empty() {
+            return Err(format!(
+                "No directories with supported images were found in {}.",
+                input_dir
+            ));
+        }
+
+        for scene_dir in scene_dirs {
+            let relative_dir = scene_dir.strip_prefix(root).unwrap_or(Path::new(""));
+            scenes.push(BatchScene {
+                input: scene_dir.to_string_lossy().to_string(),
+                name: dir_name(&scene_dir),
+                images: None,
+                output_dir: PathBuf::from(dir_name(root)).join(relative_dir),
+            });
+        }
+    }
+    Ok(scenes)
+}
+
+// Suffixes the names of scenes that would share a temp directory and output files with an
+// earlier scene (e.g. the input directories /a/scene and /b/scene) with _2, _3, ..., as the
+// scenes are processed in parallel and each one removes its temp directory.
+fn make_scene_names_unique(scenes: &mut [BatchScene]) {
+    let mut used: HashSet<PathBuf> = HashSet::new();
+    for scene in scenes.iter_mut() {
+        let name = scene.name.clone();
+        let mut suffix = 1;
+        while !used.insert(scene.output_dir.join(&scene.name)) {
+            suffix += 1;
+            scene.name = format!("{}_{}", name, suffix);
+        }
+    }
+}
+
+// Adds the leaf directories with supported images below a directory (or the directory itself)
+// to scene_dirs. Returns whether any image was found.
+fn find_scene_dirs(dir: &Path, scene_dirs: &mut Vec<PathBuf>) -> Result<bool, String> {
+    let entries = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<PathBuf>, io::Error>>()
+        })
+        .map_err(|e| format!("Error reading input directory {}: {}", dir.display(), e))?;
+
+    let mut subdirs: Vec<&PathBuf> = entries.iter().filter(|entry| entry.is_dir()).collect();
+    subdirs.sort();
+    let mut found_below = false;
+    for subdir in subdirs {
+        found_below |= find_scene_dirs(subdir, scene_dirs)?;
+    }
+
+    let has_images = entries
+        .iter()
+        .any(|entry| entry.is_file() && is_supported_format(entry));
+    if has_images && !found_below {
+        scene_dirs.push(dir.to_path_buf());
+    }
+    Ok(has_images || found_below)
+}
+
+// Name of a directory, used as the name of its scene
+fn dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
 }
 
 /*
@@ -412,310 +666,119 @@ pub fn get_images_from_dir(input_dir: &String) -> Result<Vec<String>, String> {
 
 /*
  * Run the HDRGen and Radiance pipeline on one set of LDR images
- * Returns a Result<String, String> either indicating images processed successfully
- * or representing an error, which is passed to the frontend in the pipeline function.
+ * The stages run in the order given, each one reading the artifacts produced by earlier stages.
+ * Returns the artifacts of the image set (final HDR image, falsecolor map, ...) or an error
+ * naming the failed stage, which is passed to the frontend in the pipeline function.
  */
 pub fn process_image_set(
-    app: &tauri::AppHandle,
+    host: &dyn PipelineHost,
     config_settings: &ConfigSettings,
-    luminance_args: &LuminanceArgs,
-    input_images: Vec<String>,
-    response_function: String,
-    fisheye_correction_cal: String,
-    vignetting_correction_cal: String,
-    photometric_adjustment_cal: String,
-    neutral_density_cal: String,
-    diameter: String,
-    xleft: String,
-    ydown: String,
-    xdim: String,
-    ydim: String,
-    vertical_angle: String,
-    horizontal_angle: String,
-    mut current_step: usize,
-    total_steps: usize,
-    filter_images: bool,
-) -> Result<String, String> {
-    // Merge exposures
-    // TODO: Examine a safer way to convert paths to strings that works for non utf-8?
-    let merge_exposures_result = merge_exposures(
-        app,
-        &config_settings,
-        input_images,
-        response_function,
-        config_settings
-            .temp_path
-            .join("merge_exposures.hdr")
-            .display()
-            .to_string(),
-        diameter.clone(),
-        xleft.clone(),
-        ydown.clone(),
-        xdim.clone(),
-        ydim.clone(),
-        filter_images,
-    );
-
-    // If the command to merge exposures encountered an error, abort pipeline
-    if merge_exposures_result.is_err() {
-        return merge_exposures_result;
+    params: &PipelineParams,
+    stages: &[Box<dyn Stage>],
+    scene: &Scene,
+    progress: &ProgressTracker,
+) -> Result<StageData, PipelineError> {
+    let context = StageContext {
+        host,
+        config_settings,
+        params,
     };
-
-    current_step += 1;
-    emit_progress(app, current_step, total_steps)?;
-
-    // Nullify the exposure value
-    let nullify_exposure_result = nullify_exposure_value(
-        &config_settings,
-        config_settings
-            .temp_path
-            .join("merge_exposures.hdr")
-            .display()
-            .to_string(),
-        config_settings
-            .temp_path
-            .join("nullify_exposure_value.hdr")
-            .display()
-            .to_string(),
-    );
-
-    // If the command to nullify the exposure value encountered an error, abort pipeline
-    if nullify_exposure_result.is_err() {
-        return nullify_exposure_result;
-    }
-
-    current_step += 1;
-    emit_progress(app, current_step, total_steps)?;
-
-    // Crop the HDR image to a square fitting the fisheye view
-    let crop_result = crop(
-        &config_settings,
-        config_settings
-            .temp_path
-            .join("nullify_exposure_value.hdr")
-            .display()
-            .to_string(),
-        config_settings
-            .temp_path
-            .join("crop.hdr")
-            .display()
-            .to_string(),
-        diameter.clone(),
-        xleft,
-        ydown,
-    );
-
-    // If the cropping command encountered an error, abort pipeline
-    if crop_result.is_err() {
-        return crop_result;
-    }
-
-    let mut next_path = "crop.hdr";
-
-    current_step += 1;
-    emit_progress(app, current_step, total_steps)?;
-
-    // Check diameter instead of ydim or xdim in case user wanted image smaller than 1000
-    if diameter.parse::<u32>().unwrap() > 1000 {
-        // Resize the HDR image
-        let resize_result = resize(
-            &config_settings,
-            config_settings
-                .temp_path
-                .join("crop.hdr")
-                .display()
-                .to_string(),
-            config_settings
-                .temp_path
-                .join("resize.hdr")
-                .display()
-                .to_string(),
-            xdim,
-            ydim,
-        );
-
-        // If the resizing command encountered an error, abort pipeline
-        if resize_result.is_err() {
-            return resize_result;
+    let mut data = StageData::new(scene.input_images.clone());
+
+    // Check that the images form a consistent bracket before anything is merged
+    if params.bracket_check != BracketCheck::Off {
+        let problems = check_bracket(&data.ldr_images, params.max_ev_step);
+        if !problems.is_empty() {
+            if params.bracket_check == BracketCheck::Fail {
+                return Err(PipelineError::InvalidBracket { problems });
+            }
+            progress.warning(scene, problems)?;
         }
-
-        next_path = "resize.hdr";
     }
 
-    /* Start Calibration Files - able to be skipped in some instances */
-
-    if fisheye_correction_cal.len() > 0 {
-        // Apply the projection adjustment to the HDR image
-        let projection_adjustment_result = projection_adjustment(
-            &config_settings,
-            config_settings
-                .temp_path
-                .join(next_path)
-                .display()
-                .to_string(),
-            config_settings
-                .temp_path
-                .join("projection_adjustment.hdr")
-                .display()
-                .to_string(),
-            fisheye_correction_cal,
+    // Check the fisheye circle entered by the user against the one found in the images
+    if params.check_fisheye_circle {
+        let dcraw_dir = Some(config_settings.dcraw_emu_path.as_path())
+            .filter(|path| !path.as_os_str().is_empty());
+        let problems = check_scene_circle(
+            host,
+            dcraw_dir,
+            &data.ldr_images,
+            params.diameter,
+            params.xleft,
+            params.ydown,
+            &config_settings.cancel_token,
         );
-
-        // If the command to apply projection adjustment encountered an error, abort pipeline
-        if projection_adjustment_result.is_err() {
-            return projection_adjustment_result;
+        if !problems.is_empty() {
+            progress.warning(scene, problems)?;
         }
-
-        next_path = "projection_adjustment.hdr"
     }
 
-    if vignetting_correction_cal.len() > 0 {
-        // Correct for the vignetting effect
-        let vignetting_effect_correction_result = vignetting_effect_correction(
-            &config_settings,
-            config_settings
-                .temp_path
-                .join(next_path)
-                .display()
-                .to_string(),
-            config_settings
-                .temp_path
-                .join("vignetting_correction.hdr")
-                .display()
-                .to_string(),
-            vignetting_correction_cal,
-        );
+    // Key of the current input in the stage cache. None once a stage can't be cached, as the
+    // stages after it can't be identified by their inputs either.
+    let cache = config_settings.stage_cache.as_ref();
+    let mut cache_key = cache.and_then(|cache| cache.input_key(&data.ldr_images).ok());
 
-        // If the command encountered an error, abort pipeline
-        if vignetting_effect_correction_result.is_err() {
-            return vignetting_effect_correction_result;
+    for (stage_index, stage) in stages.iter().enumerate() {
+        if DEBUG {
+            println!("Running pipeline stage '{}'", stage.name());
         }
 
-        next_path = "vignetting_correction.hdr";
-    }
-
-    if neutral_density_cal.len() > 0 {
-        // Apply the neutral density filter.
-        let neutral_density_result: Result<String, String> = neutral_density(
-            &config_settings,
-            config_settings
-                .temp_path
-                .join(next_path)
-                .display()
-                .to_string(),
-            config_settings
-                .temp_path
-                .join("neutral_density.hdr")
-                .display()
-                .to_string(),
-            neutral_density_cal,
-        );
-
-        // If the command encountered an error, abort pipeline
-        if neutral_density_result.is_err() {
-            return neutral_density_result;
+        progress.stage_started(scene, stage_index, stage.name())?;
+        let stage_start = Instant::now();
+
+        let stage_key = match (cache, &cache_key, stage.cache_inputs(&context)) {
+            (Some(cache), Some(input_key), Some(inputs)) => cache
+                .stage_key(input_key, stage.name(), &inputs, config_settings)
+                .ok(),
+            _ => None,
+        };
+        let cached = match (cache, &stage_key) {
+            (Some(cache), Some(key)) => cache.restore(key, stage.as_ref(), &context, &mut data),
+            _ => false,
+        };
+
+        let result = if config_settings.cancel_token.is_cancelled() || cached {
+            Ok(())
+        } else {
+            stage.run(&context, &mut data)
+        };
+
+        // If the run was cancelled (before or during the stage), remove the partial outputs
+        // of this image set and abort pipeline
+        if config_settings.cancel_token.is_cancelled() {
+            let _ = remove_dir_all(&config_settings.temp_path);
+            return Err(PipelineError::Cancelled);
         }
 
-        next_path = "neutral_density.hdr";
-    }
-
-    if photometric_adjustment_cal.len() > 0 {
-        // Correct for photometric adjustments
-        let photometric_adjustment_result = photometric_adjustment(
-            &config_settings,
-            config_settings
-                .temp_path
-                .join(next_path)
-                .display()
-                .to_string(),
-            config_settings
-                .temp_path
-                .join("photometric_adjustment.hdr")
-                .display()
-                .to_string(),
-            photometric_adjustment_cal,
-        );
+        // If a stage encountered an error, abort pipeline
+        result.map_err(|error| error.in_stage(stage.name()))?;
 
-        // If the command encountered an error, abort pipeline
-        if photometric_adjustment_result.is_err() {
-            return photometric_adjustment_result;
+        // Report the problems the stage found without failing (e.g. a metric cross-check)
+        if !data.warnings.is_empty() {
+            progress.warning(scene, std::mem::take(&mut data.warnings))?;
         }
 
-        next_path = "photometric_adjustment.hdr";
-    }
-
-    /* End Calibration Files */
-
-    // Evalglare
-    let evalglare_result = evalglare(
-        &config_settings,
-        config_settings
-            .temp_path
-            .join(next_path)
-            .display()
-            .to_string(),
-        vertical_angle.clone(),
-        horizontal_angle.clone(),
-    );
-
-    // If the command encountered an error, abort the pipeline
-    if evalglare_result.is_err() {
-        return evalglare_result;
-    }
-    let evalglare_value = evalglare_result.unwrap();
-
-    current_step += 1;
-    emit_progress(app, current_step, total_steps)?;
-
-    // Edit the header
-    let header_editing_result = header_editing(
-        &config_settings,
-        config_settings
-            .temp_path
-            .join(next_path)
-            .display()
-            .to_string(),
-        config_settings
-            .temp_path
-            .join("header_editing.hdr")
-            .display()
-            .to_string(),
-        vertical_angle,
-        horizontal_angle,
-        evalglare_value,
-    );
-
-    // If the command encountered an error, abort pipeline
-    if header_editing_result.is_err() {
-        return header_editing_result;
-    }
-
-    current_step += 1;
-    emit_progress(app, current_step, total_steps)?;
-
-    // Create luminance map
-    let falsecolor_result = falsecolor(
-        &config_settings,
-        config_settings
-            .temp_path
-            .join("header_editing.hdr")
-            .display()
-            .to_string(),
-        config_settings
-            .temp_path
-            .join("falsecolor_output.hdr")
-            .display()
-            .to_string(),
-        luminance_args,
-    );
-
-    // If the command encountered an error, abort pipeline
-    if falsecolor_result.is_err() {
-        return falsecolor_result;
+        // Store the outputs for later runs. A failure only means the stage runs again next time.
+        if let (Some(cache), Some(key), false) = (cache, &stage_key, cached) {
+            if let Err(error) = cache.store(key, stage.as_ref(), &data) {
+                println!("{}", error);
+            }
+        }
+        cache_key = stage_key;
+
+        progress.stage_finished(
+            scene,
+            stage_index,
+            stage.name(),
+            stage_start.elapsed(),
+            cached,
+        )?;
     }
 
-    // Pipeline has completed successfully. Return Ok
-    return Result::Ok(("Image set processed.").to_string());
+    // Pipeline has completed successfully. Report the glare metrics and return the artifacts
+    progress.scene_finished(scene, data.glare_metrics.as_ref(), &data.frame_offsets)?;
+    Ok(data)
 }
 
 fn is_supported_format(entry: &PathBuf) -> bool {
@@ -735,3 +798,135 @@ fn is_supported_format(entry: &PathBuf) -> bool {
 
     return false;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Temporary directory with empty files at the given relative paths
+    fn tree(name: &str, files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir()
+            .join(format!("pipeline_test_{}", std::process::id()))
+            .join(name);
+        for file in files {
+            let path = root.join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        root
+    }
+
+    fn batch_scene(name: &str, output_dir: &str) -> BatchScene<'static> {
+        BatchScene {
+            input: name.to_string(),
+            name: name.to_string(),
+            images: None,
+            output_dir: PathBuf::from(output_dir),
+        }
+    }
+
+    #[test]
+    fn finds_leaf_directories_with_images() {
+        let root = tree(
+            "leaves",
+            &[
+                "site1/position1/a.jpg",
+                "site1/position2/b.CR2",
+                "site2/position1/c.jpg",
+                "site2/notes.txt",
+                "empty/readme.md",
+            ],
+        );
+        let mut scene_dirs = Vec::new();
+        assert!(find_scene_dirs(&root, &mut scene_dirs).unwrap());
+        assert_eq!(
+            scene_dirs,
+            vec![
+                root.join("site1/position1"),
+                root.join("site1/position2"),
+                root.join("site2/position1"),
+            ]
+        );
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn parent_with_images_and_an_image_subdirectory_is_not_a_scene() {
+        let root = tree("parent", &["scene/a.jpg", "scene/bracket/b.jpg"]);
+        let mut scene_dirs = Vec::new();
+        find_scene_dirs(&root, &mut scene_dirs).unwrap();
+        assert_eq!(scene_dirs, vec![root.join("scene/bracket")]);
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn discovered_scenes_mirror_the_input_tree() {
+        let root = tree(
+            "survey",
+            &["site1/position1/a.jpg", "site2/position1/b.jpg"],
+        );
+        let scenes = discover_scenes(&[root.display().to_string()]).unwrap();
+        let found: Vec<(&str, &Path)> = scenes
+            .iter()
+            .map(|scene| (scene.name.as_str(), scene.output_dir.as_path()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("position1", Path::new("survey/site1/position1")),
+                ("position1", Path::new("survey/site2/position1")),
+            ]
+        );
+
+        let empty = tree("no_images", &["notes.txt"]);
+        assert!(discover_scenes(&[empty.display().to_string()]).is_err());
+        remove_dir_all(&root).unwrap();
+        remove_dir_all(&empty).unwrap();
+    }
+
+    #[test]
+    fn scene_names_are_unique_per_output_directory() {
+        // Same name in different output directories is fine, in the same one it is suffixed
+        let mut scenes = vec![
+            batch_scene("scene", ""),
+            batch_scene("scene", ""),
+            batch_scene("scene", "survey/site1"),
+            batch_scene("scene", ""),
+            batch_scene("scene_2", ""),
+        ];
+        make_scene_names_unique(&mut scenes);
+        let names: Vec<&str> = scenes.iter().map(|scene| scene.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["scene", "scene_2", "scene", "scene_3", "scene_2_2"]
+        );
+    }
+
+    #[test]
+    fn scene_output_directory_is_created() {
+        let output = tree("output", &[]);
+        let config_settings = ConfigSettings {
+            radiance_path: PathBuf::new(),
+            hdrgen_path: PathBuf::new(),
+            dcraw_emu_path: PathBuf::new(),
+            output_path: output.clone(),
+            temp_path: output.join("tmp"),
+            cancel_token: CancellationToken::new(),
+            stage_cache: None,
+        };
+
+        let scene = batch_scene("position1", "survey/site1/position1");
+        let scene_settings = scene_config_settings(&config_settings, &scene).unwrap();
+        assert_eq!(
+            scene_settings.output_path,
+            output.join("survey/site1/position1")
+        );
+        assert!(scene_settings.output_path.is_dir());
+        assert_eq!(
+            scene_settings.temp_path,
+            output.join("tmp/survey/site1/position1/position1")
+        );
+        assert!(scene_settings.temp_path.is_dir());
+        remove_dir_all(&output).unwrap();
+    }
+}
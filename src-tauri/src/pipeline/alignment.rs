@@ -0,0 +1,514 @@
+/**
+ * Module aligning the exposures of a bracket before they are merged.
+ *
+ * Uses median threshold bitmaps (Ward, "Fast, Robust Image Registration for Compositing High
+ * Dynamic Range Photographs from Handheld Exposures", 2003): every frame is turned into a bitmap
+ * of the pixels brighter than its median, which looks the same at every exposure. Translations
+ * are searched on an image pyramid, from the smallest level (where one pixel covers a large
+ * shift) to the full resolution, keeping at each level the shift of -1, 0 or +1 pixel that
+ * gives the fewest differing bits. Pixels close to the median (noise) and pixels outside the
+ * fisheye circle (the black border) are ignored.
+ *
+ * Frames are sorted by brightness, and each one is aligned to its neighbor towards the middle
+ * frame, so that frames are always compared with a frame of similar exposure. When a frame has
+ * to move, every frame is written to the temp directory as a lossless TIFF (whatever the input
+ * format, so that the merger gets one format) with the exposure metadata of the original, so
+ * that hdrgen can still read the exposure times.
+ */
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel};
+use rayon::prelude::*;
+use serde::Serialize;
+use tiff::{
+    encoder::{colortype, Rational, TiffEncoder},
+    tags::{Tag, Type},
+};
+
+use super::{
+    image_metadata::{read_metadata, ImageMetadata},
+    merge_exposures::fisheye_mask,
+};
+
+// Largest shift searched, as a power of two: shifts up to 2^MAX_SHIFT_BITS - 1 pixels
+const MAX_SHIFT_BITS: u32 = 6;
+
+// Smallest side of the smallest pyramid level, in pixels
+const MIN_LEVEL_SIZE: u32 = 16;
+
+// Pixels within this many gray levels of the median are left out of the comparison (noise)
+const EXCLUSION_RANGE: u8 = 4;
+
+// Bits of a bitmap pixel
+const ABOVE_MEDIAN: u8 = 1;
+const COMPARED: u8 = 2;
+
+// Tags for the exposure metadata of the aligned TIFF frames. They are written both to the
+// image directory (TIFF/EP) and to an EXIF directory, where most EXIF readers look for them.
+const TAG_EXPOSURE_TIME: u16 = 33434;
+const TAG_F_NUMBER: u16 = 33437;
+const TAG_ISO: u16 = 34855;
+const TAG_EXIF_DIRECTORY: u16 = 34665;
+
+/**
+ * Translation found for a frame of the bracket
+ *
+ * @field image - The frame
+ * @field aligned_image - The aligned frame in the temp directory, or the frame itself if no frame
+ *                        of the bracket moved
+ * @field x_offset - Shift applied to the frame, in pixels (positive to the right)
+ * @field y_offset - Shift applied to the frame, in pixels (positive downwards)
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameOffset {
+    pub image: String,
+    pub aligned_image: String,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+// One level of the image pyramid of a frame, as a median threshold bitmap
+struct Bitmap {
+    width: u32,
+    height: u32,
+    bits: Vec<u8>,
+}
+
+/**
+ * Aligns the frames of a bracket to its middle exposure. If any frame moves, writes every frame
+ * to a directory as TIFF.
+ *
+ * @param images - The frames (JPG or TIFF), all of the same size
+ * @param exposure_sources - The images the exposure metadata of each frame is read from (the
+ *                           original raw images when the frames were converted to TIFF)
+ * @param diameter - The fisheye view diameter in pixels
+ * @param xleft - The x-coordinate of the bottom left corner of the circumscribed square
+ * @param ydown - The y-coordinate of the bottom left corner of the circumscribed square, from
+ *                the bottom of the image (see PipelineParams)
+ * @param output_dir - Directory the aligned frames are written to
+ * @returns The offset of every frame, in input order
+ */
+pub fn align_exposures(
+    images: &[String],
+    exposure_sources: &[String],
+    diameter: u32,
+    xleft: i64,
+    ydown: i64,
+    output_dir: &Path,
+) -> Result<Vec<FrameOffset>, String> {
+    let frames: Vec<DynamicImage> = images
+        .par_iter()
+        .map(|path| image::open(path).map_err(|e| format!("Failed to open image {}: {}", path, e)))
+        .collect::<Result<Vec<DynamicImage>, String>>()?;
+    let (width, height) = frames.first().ok_or("No images to align.")?.dimensions();
+    if let Some(index) = frames
+        .iter()
+        .position(|frame| frame.dimensions() != (width, height))
+    {
+        return Err(format!(
+            "Image {} does not have the same size as the other images of the bracket.",
+            images[index]
+        ));
+    }
+
+    let mask = fisheye_mask(width, height, diameter as f32, xleft as f32, ydown as f32);
+
+    let pyramids: Vec<(f64, Vec<Bitmap>)> = frames
+        .par_iter()
+        .map(|frame| bitmap_pyramid(frame, &mask))
+        .collect();
+
+    // Align every frame to its neighbor towards the middle exposure, accumulating the shifts
+    let mut order: Vec<usize> = (0..frames.len()).collect();
+    order.sort_by(|a, b| pyramids[*a].0.total_cmp(&pyramids[*b].0));
+    let middle = order.len() / 2;
+    let mut offsets = vec![(0, 0); frames.len()];
+    for position in (0..middle).rev().chain(middle + 1..order.len()) {
+        let neighbor = if position < middle {
+            order[position + 1]
+        } else {
+            order[position - 1]
+        };
+        let frame = order[position];
+        let (dx, dy) = find_shift(&pyramids[neighbor].1, &pyramids[frame].1);
+        offsets[frame] = (offsets[neighbor].0 + dx, offsets[neighbor].1 + dy);
+    }
+
+    fs::create_dir_all(output_dir)
+        .map_err(|e| format!("Failed to create directory {}: {}", output_dir.display(), e))?;
+
+    let moved = offsets.iter().any(|offset| *offset != (0, 0));
+    images
+        .par_iter()
+        .zip(exposure_sources.par_iter())
+        .zip(frames.par_iter().zip(offsets.par_iter()))
+        .enumerate()
+        .map(|(index, ((image, exposure_source), (frame, &(dx, dy))))| {
+            let aligned_image = if !moved {
+                image.clone()
+            } else {
+                let path = Path::new(image);
+                let file_name = format!(
+                    "aligned_{}_{}.tiff",
+                    index,
+                    path.file_stem().unwrap_or_default().to_string_lossy()
+                );
+                let output = output_dir.join(file_name);
+                let metadata = read_metadata(Path::new(exposure_source)).ok();
+                write_aligned(&shift_frame(frame, dx, dy), metadata.as_ref(), &output)?;
+                output.display().to_string()
+            };
+            Ok(FrameOffset {
+                image: image.clone(),
+                aligned_image,
+                x_offset: dx,
+                y_offset: dy,
+            })
+        })
+        .collect()
+}
+
+// Builds the median threshold bitmaps of a frame, from the full resolution (index 0) to the
+// smallest level. Also returns the mean gray level inside the mask, used to sort the frames by
+// exposure (the median of the darkest and brightest frames is often clipped).
+fn bitmap_pyramid(frame: &DynamicImage, mask: &[bool]) -> (f64, Vec<Bitmap>) {
+    let (mut width, mut height) = frame.dimensions();
+    let mut gray: Vec<u8> = frame.to_luma8().into_raw();
+    let mut mask: Vec<bool> = mask.to_vec();
+
+    let (sum, count) = gray
+        .iter()
+        .zip(&mask)
+        .filter(|(_, &inside)| inside)
+        .fold((0u64, 0u64), |(sum, count), (&value, _)| {
+            (sum + value as u64, count + 1)
+        });
+    let mean = sum as f64 / count.max(1) as f64;
+
+    let mut levels: Vec<Bitmap> = Vec::new();
+    loop {
+        let median = median_gray(&gray, &mask);
+        let bits = gray
+            .iter()
+            .zip(&mask)
+            .map(|(&value, &inside)| {
+                let mut bit = if value > median { ABOVE_MEDIAN } else { 0 };
+                if inside && value.abs_diff(median) > EXCLUSION_RANGE {
+                    bit |= COMPARED;
+                }
+                bit
+            })
+            .collect();
+        levels.push(Bitmap {
+            width,
+            height,
+            bits,
+        });
+
+        if levels.len() as u32 >= MAX_SHIFT_BITS
+            || width / 2 < MIN_LEVEL_SIZE
+            || height / 2 < MIN_LEVEL_SIZE
+        {
+            break;
+        }
+
+        // Halve the level: average 2x2 blocks, keep pixels whose block is inside the circle
+        let (half_width, half_height) = (width / 2, height / 2);
+        let mut half_gray = Vec::with_capacity((half_width * half_height) as usize);
+        let mut half_mask = Vec::with_capacity((half_width * half_height) as usize);
+        for y in 0..half_height {
+            for x in 0..half_width {
+                let indices = [
+                    (2 * y * width + 2 * x) as usize,
+                    (2 * y * width + 2 * x + 1) as usize,
+                    ((2 * y + 1) * width + 2 * x) as usize,
+                    ((2 * y + 1) * width + 2 * x + 1) as usize,
+                ];
+                let sum: u32 = indices.iter().map(|&i| gray[i] as u32).sum();
+                half_gray.push((sum / 4) as u8);
+                half_mask.push(indices.iter().all(|&i| mask[i]));
+            }
+        }
+        gray = half_gray;
+        mask = half_mask;
+        width = half_width;
+        height = half_height;
+    }
+
+    (mean, levels)
+}
+
+// Median gray level of the pixels inside the mask (of all pixels if none is inside)
+fn median_gray(gray: &[u8], mask: &[bool]) -> u8 {
+    let mut histogram = [0usize; 256];
+    let mut count = 0;
+    for (&value, &inside) in gray.iter().zip(mask) {
+        if inside {
+            histogram[value as usize] += 1;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return median_gray(gray, &vec![true; gray.len()]);
+    }
+
+    let mut seen = 0;
+    for (value, &frequency) in histogram.iter().enumerate() {
+        seen += frequency;
+        if seen * 2 >= count {
+            return value as u8;
+        }
+    }
+    255
+}
+
+// Finds the shift to apply to a frame to align it with a reference frame, refining the shift
+// found on each smaller level
+fn find_shift(reference: &[Bitmap], frame: &[Bitmap]) -> (i32, i32) {
+    let mut shift = (0, 0);
+    for level in (0..reference.len().min(frame.len())).rev() {
+        let candidate = (shift.0 * 2, shift.1 * 2);
+        let mut best = (candidate, usize::MAX);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let trial = (candidate.0 + dx, candidate.1 + dy);
+                let errors = count_differences(&reference[level], &frame[level], trial);
+                if errors < best.1 {
+                    best = (trial, errors);
+                }
+            }
+        }
+        shift = best.0;
+    }
+    shift
+}
+
+// Counts the compared pixels whose bit differs between the reference and the shifted frame
+fn count_differences(reference: &Bitmap, frame: &Bitmap, (dx, dy): (i32, i32)) -> usize {
+    let (width, height) = (reference.width as i32, reference.height as i32);
+    (0..height)
+        .into_par_iter()
+        .map(|y| {
+            let source_y = y - dy;
+            if source_y < 0 || source_y >= height {
+                return 0;
+            }
+            let mut errors = 0;
+            for x in 0..width {
+                let source_x = x - dx;
+                if source_x < 0 || source_x >= width {
+                    continue;
+                }
+                let a = reference.bits[(y * width + x) as usize];
+                let b = frame.bits[(source_y * width + source_x) as usize];
+                if a & b & COMPARED != 0 && (a ^ b) & ABOVE_MEDIAN != 0 {
+                    errors += 1;
+                }
+            }
+            errors
+        })
+        .sum()
+}
+
+// Moves a frame by (dx, dy) pixels, filling the uncovered border with black
+fn shift_frame(frame: &DynamicImage, dx: i32, dy: i32) -> DynamicImage {
+    fn shift<P: Pixel>(
+        image: &ImageBuffer<P, Vec<P::Subpixel>>,
+        dx: i32,
+        dy: i32,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        let (width, height) = image.dimensions();
+        let mut shifted = ImageBuffer::new(width, height);
+        for (x, y, pixel) in shifted.enumerate_pixels_mut() {
+            let (source_x, source_y) = (x as i32 - dx, y as i32 - dy);
+            if source_x >= 0
+                && source_y >= 0
+                && (source_x as u32) < width
+                && (source_y as u32) < height
+            {
+                *pixel = *image.get_pixel(source_x as u32, source_y as u32);
+            }
+        }
+        shifted
+    }
+
+    // 16-bit frames (TIFFs converted from raw images) keep their precision
+    match frame {
+        DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_)
+        | DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_) => {
+            DynamicImage::ImageRgb16(shift(&frame.to_rgb16(), dx, dy))
+        }
+        frame => DynamicImage::ImageRgb8(shift(&frame.to_rgb8(), dx, dy)),
+    }
+}
+
+// Writes an aligned frame as an uncompressed TIFF (16 bits per channel for frames converted
+// from raw images, 8 otherwise), with the exposure metadata of its exposure source
+pub(super) fn write_aligned(
+    frame: &DynamicImage,
+    metadata: Option<&ImageMetadata>,
+    output: &Path,
+) -> Result<(), String> {
+    let write_error =
+        |e: &dyn std::fmt::Display| format!("Failed to write {}: {}", output.display(), e);
+
+    let exposure_time = metadata.and_then(|m| m.exposure_time).map(|time| Rational {
+        n: (time * 1_000_000.0).round() as u32,
+        d: 1_000_000,
+    });
+    let f_number = metadata.and_then(|m| m.f_number).map(|f_number| Rational {
+        n: (f_number * 10.0).round() as u32,
+        d: 10,
+    });
+    let iso = metadata
+        .and_then(|m| m.iso)
+        .map(|iso| iso.min(u16::MAX as u32) as u16);
+
+    let file = File::create(output).map_err(|e| write_error(&e))?;
+    let mut encoder = TiffEncoder::new(BufWriter::new(file)).map_err(|e| write_error(&e))?;
+    let (width, height) = frame.dimensions();
+    macro_rules! write_tiff {
+        ($color:ty, $data:expr) => {{
+            let mut image = encoder
+                .new_image::<$color>(width, height)
+                .map_err(|e| write_error(&e))?;
+            let directory = image.encoder();
+            if let Some(exposure_time) = &exposure_time {
+                directory
+                    .write_tag(Tag::Unknown(TAG_EXPOSURE_TIME), exposure_time)
+                    .map_err(|e| write_error(&e))?;
+            }
+            if let Some(f_number) = &f_number {
+                directory
+                    .write_tag(Tag::Unknown(TAG_F_NUMBER), f_number)
+                    .map_err(|e| write_error(&e))?;
+            }
+            if let Some(iso) = iso {
+                directory
+                    .write_tag(Tag::Unknown(TAG_ISO), iso)
+                    .map_err(|e| write_error(&e))?;
+            }
+            if exposure_time.is_some() || f_number.is_some() || iso.is_some() {
+                // Directories start on a word boundary
+                let mut offset = directory
+                    .write_data(&[] as &[u8])
+                    .map_err(|e| write_error(&e))?;
+                if offset % 2 == 1 {
+                    offset = directory.write_data(0u8).map_err(|e| write_error(&e))? + 1;
+                }
+                let exif = exif_directory(offset, exposure_time.as_ref(), f_number.as_ref(), iso);
+                directory
+                    .write_data(exif.as_slice())
+                    .map_err(|e| write_error(&e))?;
+                directory
+                    .write_tag(Tag::Unknown(TAG_EXIF_DIRECTORY), offset as u32)
+                    .map_err(|e| write_error(&e))?;
+            }
+            image.write_data($data).map_err(|e| write_error(&e))
+        }};
+    }
+    match frame {
+        DynamicImage::ImageRgb16(image) => write_tiff!(colortype::RGB16, image.as_raw()),
+        frame => write_tiff!(colortype::RGB8, frame.to_rgb8().as_raw()),
+    }
+}
+
+// Encodes an EXIF directory holding the exposure metadata, to be written at offset in the file
+// (which must be even). Values use the byte order of the TIFF encoder (native).
+fn exif_directory(
+    offset: u64,
+    exposure_time: Option<&Rational>,
+    f_number: Option<&Rational>,
+    iso: Option<u16>,
+) -> Vec<u8> {
+    let rationals: Vec<(u16, &Rational)> =
+        [(TAG_EXPOSURE_TIME, exposure_time), (TAG_F_NUMBER, f_number)]
+            .into_iter()
+            .filter_map(|(tag, value)| Some((tag, value?)))
+            .collect();
+    let entry_count = rationals.len() + iso.is_some() as usize;
+
+    // Entries (tag, type, count, value or offset), sorted by tag, then the next directory
+    // offset, then the rational values
+    let mut value_offset = offset as u32 + 2 + 12 * entry_count as u32 + 4;
+    let mut exif: Vec<u8> = (entry_count as u16).to_ne_bytes().to_vec();
+    let mut values: Vec<u8> = Vec::new();
+    for (tag, rational) in &rationals {
+        exif.extend(tag.to_ne_bytes());
+        exif.extend(Type::RATIONAL.to_u16().to_ne_bytes());
+        exif.extend(1u32.to_ne_bytes());
+        exif.extend(value_offset.to_ne_bytes());
+        values.extend(rational.n.to_ne_bytes());
+        values.extend(rational.d.to_ne_bytes());
+        value_offset += 8;
+    }
+    if let Some(iso) = iso {
+        exif.extend(TAG_ISO.to_ne_bytes());
+        exif.extend(Type::SHORT.to_u16().to_ne_bytes());
+        exif.extend(1u32.to_ne_bytes());
+        exif.extend(iso.to_ne_bytes());
+        exif.extend([0, 0]);
+    }
+    exif.extend(0u32.to_ne_bytes());
+    exif.extend(values);
+    exif
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frame with blocks of different brightness, so that the bitmaps have edges to match
+    fn test_frame(width: u32, height: u32, scale: f32) -> DynamicImage {
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+            let value = (((x / 8 + y / 12) % 3) as f32 * 100.0 * scale).min(255.0) as u8;
+            image::Rgb([value, value, value])
+        }))
+    }
+
+    #[test]
+    fn finds_shift_between_exposures() {
+        let (width, height) = (128, 128);
+        let mask = vec![true; (width * height) as usize];
+        let reference = test_frame(width, height, 1.0);
+        let frame = shift_frame(&test_frame(width, height, 0.5), 3, -2);
+
+        let (_, reference_bitmaps) = bitmap_pyramid(&reference, &mask);
+        let (_, frame_bitmaps) = bitmap_pyramid(&frame, &mask);
+        assert_eq!(find_shift(&reference_bitmaps, &frame_bitmaps), (-3, 2));
+    }
+
+    #[test]
+    fn writes_lossless_tiff_with_exposure_metadata() {
+        let dir = std::env::temp_dir().join(format!("alignment_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("aligned.tiff");
+
+        let frame = test_frame(40, 30, 1.0);
+        let metadata = ImageMetadata {
+            exposure_time: Some(1.0 / 125.0),
+            f_number: Some(8.0),
+            iso: Some(200),
+            ..Default::default()
+        };
+        write_aligned(&frame, Some(&metadata), &output).unwrap();
+
+        let written = read_metadata(&output).unwrap();
+        assert_eq!(written.exposure_time, Some(0.008));
+        assert_eq!(written.f_number, Some(8.0));
+        assert_eq!(written.iso, Some(200));
+        assert_eq!(image::open(&output).unwrap().to_rgb8(), frame.to_rgb8());
+
+        // Without metadata the frame is still written
+        write_aligned(&frame, None, &output).unwrap();
+        assert_eq!(image::open(&output).unwrap().to_rgb8(), frame.to_rgb8());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
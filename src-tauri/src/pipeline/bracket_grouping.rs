@@ -0,0 +1,360 @@
+/**
+ * Module splitting a flat folder of photos into bracket sets, using their EXIF metadata.
+ *
+ * Field teams often copy a whole memory card into one directory, instead of sorting each scene
+ * into its own directory for batch processing. The images are put in capture order (file name
+ * order if some images have no capture time), and a new bracket starts when:
+ * - the time between the end of an exposure and the start of the next one is larger than the
+ *   allowed gap (the camera was moved to the next position),
+ * - an exposure time already seen in the current bracket comes back (the camera started the
+ *   same bracketing sequence again), or
+ * - the camera, ISO or aperture changes.
+ *
+ * The groups only list the paths of their images, no file is moved. They can be checked by the
+ * user and given to the pipeline as PipelineParams::bracket_groups.
+ */
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use super::{image_metadata::read_metadata, BracketGroup, ImageMetadata};
+
+// Largest time between two exposures of the same bracket by default (in seconds)
+pub const DEFAULT_MAX_GAP_SECS: f64 = 10.0;
+
+// Exposures closer than this (in EV) are considered the same exposure time
+const SAME_EXPOSURE_EV: f64 = 0.1;
+
+// Format of the EXIF DateTimeOriginal field
+const EXIF_DATE_TIME_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+/**
+ * A bracket set proposed by group_brackets()
+ *
+ * @field group - Name and images of the bracket set, in capture order
+ * @field exposure_times - Exposure time of each image in seconds (None if not in the metadata)
+ * @field capture_time - Capture time of the first image, e.g. "2024:06:21 12:00:00"
+ * @field split_reason - Why this bracket set was split from the previous one (None for the first)
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProposedGroup {
+    #[serde(flatten)]
+    pub group: BracketGroup,
+    pub exposure_times: Vec<Option<f64>>,
+    pub capture_time: Option<String>,
+    pub split_reason: Option<String>,
+}
+
+/**
+ * Bracket sets proposed for a folder of images
+ *
+ * @field groups - The bracket sets, in capture order
+ * @field warnings - Images whose metadata could not be read. They are grouped by capture order
+ *                   only.
+ */
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BracketGrouping {
+    pub groups: Vec<ProposedGroup>,
+    pub warnings: Vec<String>,
+}
+
+/**
+ * Splits images into bracket sets by capture time gaps and exposure sequence
+ *
+ * @param images - Paths to the images, in any order
+ * @param name_prefix - Prefix of the group names, which are <prefix>_001, <prefix>_002, ...
+ * @param max_gap_secs - Largest time between the end of an exposure and the start of the next
+ *                       one of the same bracket, in seconds
+ */
+pub fn group_brackets(images: &[String], name_prefix: &str, max_gap_secs: f64) -> BracketGrouping {
+    let mut warnings: Vec<String> = Vec::new();
+    let frames: Vec<ImageMetadata> = images
+        .iter()
+        .map(|image| {
+            read_metadata(Path::new(image)).unwrap_or_else(|error| {
+                warnings.push(error);
+                ImageMetadata {
+                    path: image.clone(),
+                    ..Default::default()
+                }
+            })
+        })
+        .collect();
+
+    BracketGrouping {
+        warnings,
+        ..group_frames(frames, name_prefix, max_gap_secs)
+    }
+}
+
+// Splits images into bracket sets, given their metadata
+fn group_frames(
+    mut frames: Vec<ImageMetadata>,
+    name_prefix: &str,
+    max_gap_secs: f64,
+) -> BracketGrouping {
+    let mut grouping = BracketGrouping::default();
+
+    // File names give the capture order of a camera, so they are used when capture times are
+    // missing and to order images taken in the same second
+    frames.sort_by(|a, b| a.path.cmp(&b.path));
+    if frames.iter().all(|frame| capture_time(frame).is_some()) {
+        frames.sort_by_key(capture_time);
+    }
+
+    let mut current: Vec<ImageMetadata> = Vec::new();
+    let mut split_reason: Option<String> = None;
+    for frame in frames {
+        if let Some(reason) = split_before(&current, &frame, max_gap_secs) {
+            push_group(
+                &mut grouping,
+                name_prefix,
+                &mut current,
+                split_reason.take(),
+            );
+            split_reason = Some(reason);
+        }
+        current.push(frame);
+    }
+    if !current.is_empty() {
+        push_group(&mut grouping, name_prefix, &mut current, split_reason);
+    }
+
+    grouping
+}
+
+// Returns why a frame starts a new bracket after the frames of the current one, or None if it
+// belongs to the current bracket
+fn split_before(
+    current: &[ImageMetadata],
+    frame: &ImageMetadata,
+    max_gap_secs: f64,
+) -> Option<String> {
+    let previous = current.last()?;
+
+    if let (Some(previous_time), Some(time)) = (capture_time(previous), capture_time(frame)) {
+        let gap = (time - previous_time).num_milliseconds() as f64 / 1000.0
+            - previous.exposure_time.unwrap_or(0.0);
+        if gap > max_gap_secs {
+            return Some(format!("{:.0} s after the previous image", gap));
+        }
+    }
+
+    if let Some(exposure_time) = frame.exposure_time {
+        let repeated = current
+            .iter()
+            .filter_map(|image| image.exposure_time)
+            .any(|seen| (exposure_time / seen).log2().abs() < SAME_EXPOSURE_EV);
+        if repeated {
+            return Some(format!(
+                "Exposure time {} s repeats the bracketing sequence",
+                exposure_time
+            ));
+        }
+    }
+
+    if changed(&previous.camera_model, &frame.camera_model) {
+        return Some("Different camera".into());
+    }
+    if changed(&previous.iso, &frame.iso) {
+        return Some("ISO changed".into());
+    }
+    if changed(
+        &previous.f_number.map(|f_number| format!("{:.1}", f_number)),
+        &frame.f_number.map(|f_number| format!("{:.1}", f_number)),
+    ) {
+        return Some("Aperture changed".into());
+    }
+
+    None
+}
+
+// Whether a setting differs between two images. Missing values are not compared.
+fn changed<T: PartialEq>(previous: &Option<T>, value: &Option<T>) -> bool {
+    matches!((previous, value), (Some(previous), Some(value)) if previous != value)
+}
+
+// Moves the frames of the current bracket into a new group
+fn push_group(
+    grouping: &mut BracketGrouping,
+    name_prefix: &str,
+    frames: &mut Vec<ImageMetadata>,
+    split_reason: Option<String>,
+) {
+    let frames = std::mem::take(frames);
+    grouping.groups.push(ProposedGroup {
+        group: BracketGroup {
+            name: format!("{}_{:03}", name_prefix, grouping.groups.len() + 1),
+            images: frames.iter().map(|frame| frame.path.clone()).collect(),
+        },
+        exposure_times: frames.iter().map(|frame| frame.exposure_time).collect(),
+        capture_time: frames.first().and_then(|frame| frame.capture_time.clone()),
+        split_reason,
+    });
+}
+
+// Parses the capture time of an image
+fn capture_time(frame: &ImageMetadata) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(frame.capture_time.as_ref()?, EXIF_DATE_TIME_FORMAT).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Metadata of an image taken at the given time of day, e.g. "12:00:00"
+    fn frame(path: &str, time: Option<&str>, exposure_time: f64) -> ImageMetadata {
+        ImageMetadata {
+            path: path.into(),
+            exposure_time: Some(exposure_time),
+            f_number: Some(8.0),
+            iso: Some(100),
+            camera_model: Some("Canon EOS 5D".into()),
+            capture_time: time.map(|time| format!("2024:06:21 {}", time)),
+            ..Default::default()
+        }
+    }
+
+    fn names(grouping: &BracketGrouping) -> Vec<Vec<&str>> {
+        grouping
+            .groups
+            .iter()
+            .map(|group| group.group.images.iter().map(String::as_str).collect())
+            .collect()
+    }
+
+    #[test]
+    fn splits_on_capture_time_gap() {
+        let frames = vec![
+            frame("IMG_0001.JPG", Some("12:00:00"), 0.01),
+            frame("IMG_0002.JPG", Some("12:00:01"), 0.1),
+            // 20 s after the end of the 1 s exposure: the camera was moved
+            frame("IMG_0004.JPG", Some("12:00:23"), 0.01),
+            frame("IMG_0003.JPG", Some("12:00:02"), 1.0),
+            frame("IMG_0005.JPG", Some("12:00:24"), 0.1),
+        ];
+        let grouping = group_frames(frames, "DCIM", DEFAULT_MAX_GAP_SECS);
+        assert_eq!(
+            names(&grouping),
+            vec![
+                vec!["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"],
+                vec!["IMG_0004.JPG", "IMG_0005.JPG"]
+            ]
+        );
+        assert_eq!(grouping.groups[0].group.name, "DCIM_001");
+        assert_eq!(grouping.groups[0].split_reason, None);
+        assert_eq!(grouping.groups[1].group.name, "DCIM_002");
+        assert_eq!(
+            grouping.groups[1].split_reason.as_deref(),
+            Some("20 s after the previous image")
+        );
+        assert_eq!(
+            grouping.groups[1].capture_time.as_deref(),
+            Some("2024:06:21 12:00:23")
+        );
+    }
+
+    #[test]
+    fn long_exposure_does_not_count_as_a_gap() {
+        let frames = vec![
+            frame("IMG_0001.JPG", Some("12:00:00"), 0.5),
+            frame("IMG_0002.JPG", Some("12:00:01"), 30.0),
+            frame("IMG_0003.JPG", Some("12:00:35"), 4.0),
+        ];
+        let grouping = group_frames(frames, "DCIM", DEFAULT_MAX_GAP_SECS);
+        assert_eq!(grouping.groups.len(), 1);
+    }
+
+    #[test]
+    fn splits_on_repeated_exposure() {
+        // The same sequence taken twice within the gap, with a 1/60 s and a 1/64 s exposure
+        // being the same stop
+        let frames = vec![
+            frame("IMG_0001.JPG", Some("12:00:00"), 1.0 / 60.0),
+            frame("IMG_0002.JPG", Some("12:00:01"), 1.0 / 15.0),
+            frame("IMG_0003.JPG", Some("12:00:03"), 1.0 / 64.0),
+            frame("IMG_0004.JPG", Some("12:00:04"), 1.0 / 15.0),
+        ];
+        let grouping = group_frames(frames, "DCIM", DEFAULT_MAX_GAP_SECS);
+        assert_eq!(
+            names(&grouping),
+            vec![
+                vec!["IMG_0001.JPG", "IMG_0002.JPG"],
+                vec!["IMG_0003.JPG", "IMG_0004.JPG"]
+            ]
+        );
+        assert!(grouping.groups[1]
+            .split_reason
+            .as_ref()
+            .unwrap()
+            .contains("repeats the bracketing sequence"));
+    }
+
+    #[test]
+    fn splits_on_changed_settings() {
+        let mut other_iso = frame("IMG_0002.JPG", Some("12:00:01"), 0.1);
+        other_iso.iso = Some(400);
+        let frames = vec![frame("IMG_0001.JPG", Some("12:00:00"), 0.01), other_iso];
+        let grouping = group_frames(frames, "DCIM", DEFAULT_MAX_GAP_SECS);
+        assert_eq!(
+            grouping.groups[1].split_reason.as_deref(),
+            Some("ISO changed")
+        );
+    }
+
+    #[test]
+    fn images_taken_in_the_same_second_keep_file_name_order() {
+        let frames = vec![
+            frame("IMG_0003.JPG", Some("12:00:00"), 0.1),
+            frame("IMG_0001.JPG", Some("12:00:00"), 0.001),
+            frame("IMG_0002.JPG", Some("12:00:00"), 0.01),
+        ];
+        let grouping = group_frames(frames, "DCIM", DEFAULT_MAX_GAP_SECS);
+        assert_eq!(
+            names(&grouping),
+            vec![vec!["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"]]
+        );
+        assert_eq!(
+            grouping.groups[0].exposure_times,
+            vec![Some(0.001), Some(0.01), Some(0.1)]
+        );
+    }
+
+    #[test]
+    fn missing_capture_times_fall_back_to_file_name_order() {
+        // Without a capture time for every image, the images are put in file name order, and no
+        // time gap is measured to or from an image without one
+        let frames = vec![
+            frame("IMG_0004.JPG", None, 0.1),
+            frame("IMG_0002.JPG", Some("12:05:00"), 0.1),
+            frame("IMG_0003.JPG", None, 1.0),
+            frame("IMG_0001.JPG", Some("12:00:00"), 0.01),
+        ];
+        let grouping = group_frames(frames, "DCIM", DEFAULT_MAX_GAP_SECS);
+        assert_eq!(
+            names(&grouping),
+            vec![
+                vec!["IMG_0001.JPG"],
+                vec!["IMG_0002.JPG", "IMG_0003.JPG"],
+                vec!["IMG_0004.JPG"]
+            ]
+        );
+        assert_eq!(
+            grouping.groups[1].split_reason.as_deref(),
+            Some("300 s after the previous image")
+        );
+    }
+
+    #[test]
+    fn unreadable_images_are_grouped_with_a_warning() {
+        let missing = std::env::temp_dir()
+            .join(format!("bracket_grouping_test_{}", std::process::id()))
+            .join("IMG_0001.JPG");
+        let grouping = group_brackets(&[missing.display().to_string()], "DCIM", 10.0);
+        assert_eq!(grouping.groups.len(), 1);
+        assert_eq!(grouping.warnings.len(), 1);
+    }
+}
@@ -0,0 +1,191 @@
+/**
+ * Module for cancelling a running pipeline.
+ *
+ * A CancellationToken is created for every run and shared with the stages through
+ * ConfigSettings. The pipeline checks it between stages and between directories in batch mode,
+ * and external commands are run through run_command()/run_command_output(), which poll the
+ * child process and kill it as soon as the token is cancelled, so that a long hdrgen or pcomb
+ * call does not have to finish first. They also capture the command's stderr for PipelineError.
+ */
+use std::{
+    io::{self, Read},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::PipelineError;
+
+// Error message returned by the pipeline when a run was cancelled
+pub const CANCELLED_MESSAGE: &str = "Pipeline was cancelled.";
+
+// How often a running child process is checked for completion or cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/**
+ * Flag shared between a pipeline run and whoever can cancel it. Clones refer to the same flag.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /**
+     * Requests the run using this token to stop
+     */
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /**
+     * Returns whether the run using this token has been cancelled
+     */
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/**
+ * Tauri managed state holding the token of the current (or last) pipeline run
+ */
+#[derive(Default)]
+pub struct PipelineState {
+    cancel_token: Mutex<CancellationToken>,
+}
+
+impl PipelineState {
+    /**
+     * Creates the token for a new run, replacing the token of the previous run
+     */
+    pub fn start_run(&self) -> CancellationToken {
+        let token = CancellationToken::new();
+        if let Ok(mut current) = self.cancel_token.lock() {
+            *current = token.clone();
+        }
+        token
+    }
+
+    /**
+     * Cancels the current run. Does nothing if no run is in progress.
+     */
+    pub fn cancel(&self) {
+        if let Ok(current) = self.cancel_token.lock() {
+            current.cancel();
+        }
+    }
+}
+
+/**
+ * Runs a command to completion, like Command::status(), but kills it if the token is cancelled.
+ * The command's stderr is captured for error reports; its stdout is left as configured
+ * (usually redirected to the stage's output file).
+ *
+ * @param stage - Name of the stage running the command, for error reports
+ * @param command - The command to run
+ * @param cancel_token - Token of the current pipeline run
+ * @returns The exit status and captured stderr (stdout is empty), or an error if the command
+ *          could not be started or the run was cancelled
+ */
+pub fn run_command(
+    stage: &str,
+    command: &mut Command,
+    cancel_token: &CancellationToken,
+) -> Result<Output, PipelineError> {
+    run(stage, command, cancel_token, false)
+}
+
+/**
+ * Runs a command to completion capturing its output, like Command::output(), but kills it if
+ * the token is cancelled.
+ *
+ * @param stage - Name of the stage running the command, for error reports
+ * @param command - The command to run. Its stdout and stderr are captured.
+ * @param cancel_token - Token of the current pipeline run
+ * @returns The exit status and output, or an error if the command could not be started or
+ *          the run was cancelled
+ */
+pub fn run_command_output(
+    stage: &str,
+    command: &mut Command,
+    cancel_token: &CancellationToken,
+) -> Result<Output, PipelineError> {
+    run(stage, command, cancel_token, true)
+}
+
+fn run(
+    stage: &str,
+    command: &mut Command,
+    cancel_token: &CancellationToken,
+    capture_stdout: bool,
+) -> Result<Output, PipelineError> {
+    if capture_stdout {
+        command.stdout(Stdio::piped());
+    }
+    command.stderr(Stdio::piped());
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| PipelineError::command_not_started(stage, command, &e))?;
+
+    // Read the pipes in the background so the child never blocks on a full pipe
+    let stdout_reader = child.stdout.take().map(read_in_background);
+    let stderr_reader = child.stderr.take().map(read_in_background);
+
+    let status = wait_for_child(&mut child, cancel_token).map_err(|e| {
+        if cancel_token.is_cancelled() {
+            PipelineError::Cancelled
+        } else {
+            PipelineError::command_not_started(stage, command, &e)
+        }
+    })?;
+
+    let stdout = stdout_reader
+        .map(|reader| reader.join().unwrap_or_default())
+        .unwrap_or_default();
+    let stderr = stderr_reader
+        .map(|reader| reader.join().unwrap_or_default())
+        .unwrap_or_default();
+
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+// Polls a child process until it exits, killing it if the token is cancelled
+fn wait_for_child(child: &mut Child, cancel_token: &CancellationToken) -> io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if cancel_token.is_cancelled() {
+            // The child may exit on its own between the checks, so errors are ignored
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                CANCELLED_MESSAGE,
+            ));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Reads a pipe to its end on a separate thread
+fn read_in_background<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = pipe.read_to_end(&mut buffer);
+        buffer
+    })
+}
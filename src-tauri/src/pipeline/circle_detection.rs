@@ -0,0 +1,624 @@
+/**
+ * Module finding the fisheye image circle in the LDR images of a bracket.
+ *
+ * The brightest frame (the longest exposure) is used, as it shows the clearest edge between the
+ * fisheye view and the black border around it. The frame is downsampled, a threshold just above
+ * the level of the border is chosen from the image corners and Otsu's method, and the first
+ * bright pixels seen from each side of every row and column are taken as edge points. A circle
+ * is fitted to the edge points by least squares (Kåsa's method), the points far from it (dark
+ * scene content at the edge of the view, lens flare) are dropped and the circle is fitted again.
+ *
+ * The result is given as the diameter and bottom-left offsets pcompos uses (see
+ * PipelineParams), with a confidence score. check_circle() compares it with the values entered
+ * by the user.
+ */
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::{merge_exposures::is_raw, read_metadata, CancellationToken, PipelineHost};
+use crate::image_cache::ensure_tiff_for_raw;
+
+// Largest side of the downsampled frame the circle is detected in (in pixels)
+const DETECTION_SIZE: u32 = 1024;
+
+// Smallest side of an image the circle is detected in (in pixels)
+const MIN_IMAGE_SIZE: u32 = 64;
+
+// Number of consecutive pixels above the threshold needed for an edge point, to skip noise
+const EDGE_RUN: usize = 3;
+
+// Number of refits after dropping the edge points far from the circle
+const REFITS: usize = 4;
+
+// Number of angular sectors used to measure how much of the circle edge was found
+const SECTORS: usize = 72;
+
+// Detections with a lower confidence are not used to check the user's values
+pub const MIN_CONFIDENCE: f64 = 0.6;
+
+// Largest allowed difference between the user's values and the detected circle, as a fraction
+// of the detected diameter (and at least MIN_TOLERANCE_PIXELS)
+const TOLERANCE: f64 = 0.02;
+const MIN_TOLERANCE_PIXELS: f64 = 4.0;
+
+/**
+ * A fisheye image circle found by detect_circle()
+ *
+ * @field image - Frame the circle was detected in
+ * @field diameter - Diameter of the circle, in pixels
+ * @field xleft - x-coordinate of the bottom left corner of the circumscribed square (in pixels)
+ * @field ydown - y-coordinate of the bottom left corner of the circumscribed square, from the
+ *                bottom of the image (in pixels). Negative if the circle is cut by the image.
+ * @field x_center - x-coordinate of the circle center, from the left of the image (in pixels)
+ * @field y_center - y-coordinate of the circle center, from the top of the image (in pixels)
+ * @field confidence - From 0 to 1: the fraction of edge points on the circle, times the fraction
+ *                     of the visible circle edge where points were found
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CircleDetection {
+    pub image: String,
+    pub diameter: u32,
+    pub xleft: i64,
+    pub ydown: i64,
+    pub x_center: f64,
+    pub y_center: f64,
+    pub confidence: f64,
+}
+
+/**
+ * Finds the fisheye image circle in the brightest frame of a bracket. Raw frames are converted
+ * to TIFF through the image cache first.
+ *
+ * @param host - Host the dcraw_emu conversion runs through
+ * @param dcraw_dir - Directory of dcraw_emu (None to use the bundled one)
+ * @param images - Paths to the LDR images of the bracket
+ * @param cancel_token - Token cancelling a raw conversion
+ */
+pub fn detect_fisheye_circle(
+    host: &dyn PipelineHost,
+    dcraw_dir: Option<&Path>,
+    images: &[String],
+    cancel_token: &CancellationToken,
+) -> Result<CircleDetection, String> {
+    let brightest = brightest_frame(images)?;
+    let mut detection = if is_raw(&brightest) {
+        let tiff = ensure_tiff_for_raw(host, dcraw_dir, Path::new(&brightest), cancel_token)
+            .map_err(|error| error.to_string())?;
+        detect_circle(&tiff)?
+    } else {
+        detect_circle(Path::new(&brightest))?
+    };
+    detection.image = brightest;
+    Ok(detection)
+}
+
+/**
+ * Finds the fisheye image circle in one frame
+ *
+ * @param image - Path to a JPEG or TIFF image
+ */
+pub fn detect_circle(image: &Path) -> Result<CircleDetection, String> {
+    let frame = image::open(image)
+        .map_err(|e| format!("Failed to open {}: {}", image.display(), e))?
+        .to_luma32f();
+    let (width, height) = frame.dimensions();
+    if width < MIN_IMAGE_SIZE || height < MIN_IMAGE_SIZE {
+        return Err(format!(
+            "{} is too small ({}x{}) to detect the fisheye circle.",
+            image.display(),
+            width,
+            height
+        ));
+    }
+
+    // Average blocks of pixels, which also smooths out noise along the edge
+    let scale = width.max(height).div_ceil(DETECTION_SIZE).max(1);
+    let (small_width, small_height) = ((width / scale) as usize, (height / scale) as usize);
+    let mut small = vec![0.0f32; small_width * small_height];
+    for y in 0..small_height * scale as usize {
+        for x in 0..small_width * scale as usize {
+            small[(y / scale as usize) * small_width + x / scale as usize] +=
+                frame.get_pixel(x as u32, y as u32)[0];
+        }
+    }
+    let block = (scale * scale) as f32;
+    small.iter_mut().for_each(|value| *value /= block);
+
+    let threshold = border_threshold(&small, small_width, small_height);
+    let points = edge_points(&small, small_width, small_height, threshold);
+    if points.len() < 3 * SECTORS / 4 {
+        return Err(format!(
+            "No fisheye circle edge found in {} (is the image border dark?).",
+            image.display()
+        ));
+    }
+
+    // Fit the circle, then refit on the points close to it
+    let mut inliers: Vec<bool> = vec![true; points.len()];
+    let mut circle = fit_circle(&points, &inliers)?;
+    for _ in 0..REFITS {
+        let residuals: Vec<f64> = points
+            .iter()
+            .map(|&(x, y)| ((x - circle.0).hypot(y - circle.1) - circle.2).abs())
+            .collect();
+        let mut sorted = residuals.clone();
+        sorted.sort_by(f64::total_cmp);
+        let limit = (3.0 * sorted[sorted.len() / 2]).max(1.5);
+        let kept: Vec<bool> = residuals
+            .iter()
+            .map(|&residual| residual <= limit)
+            .collect();
+        if kept == inliers {
+            break;
+        }
+        inliers = kept;
+        circle = fit_circle(&points, &inliers)?;
+    }
+    let (x_center, y_center, radius) = circle;
+    if radius * 4.0 < small_width.min(small_height) as f64 {
+        return Err(format!(
+            "No fisheye circle found in {} (the edge found is too small to be the fisheye view).",
+            image.display()
+        ));
+    }
+
+    let confidence = inlier_fraction(&inliers)
+        * edge_coverage(&points, &inliers, circle, small_width, small_height);
+
+    // Back to full resolution. Coordinates are continuous, pixel i spanning [i, i + 1).
+    let scale = scale as f64;
+    let (x_center, y_center, radius) = (x_center * scale, y_center * scale, radius * scale);
+    Ok(CircleDetection {
+        image: image.display().to_string(),
+        diameter: (2.0 * radius).round() as u32,
+        xleft: (x_center - radius).round() as i64,
+        ydown: (height as f64 - y_center - radius).round() as i64,
+        x_center,
+        y_center,
+        confidence,
+    })
+}
+
+/**
+ * Compares the fisheye circle entered by the user with a detected one
+ *
+ * @param detection - The detected circle
+ * @param diameter - The fisheye view diameter entered by the user (in pixels)
+ * @param xleft - The xleft offset entered by the user (in pixels)
+ * @param ydown - The ydown offset entered by the user (in pixels)
+ * @returns A message for each value too far from the detected one, or a single message if the
+ *          detection is not reliable enough to check them
+ */
+pub fn check_circle(
+    detection: &CircleDetection,
+    diameter: u32,
+    xleft: i64,
+    ydown: i64,
+) -> Vec<String> {
+    let image_name = Path::new(&detection.image)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    if detection.confidence < MIN_CONFIDENCE {
+        return vec![format!(
+            "The fisheye circle could not be detected reliably in {} (confidence {:.2}), the lens mask was not checked.",
+            image_name, detection.confidence
+        )];
+    }
+
+    let tolerance = (TOLERANCE * detection.diameter as f64).max(MIN_TOLERANCE_PIXELS);
+    [
+        ("diameter", diameter as i64, detection.diameter as i64),
+        ("xleft", xleft, detection.xleft),
+        ("ydown", ydown, detection.ydown),
+    ]
+    .into_iter()
+    .filter(|(_, value, detected)| (value - detected).abs() as f64 > tolerance)
+    .map(|(name, value, detected)| {
+        format!(
+            "The fisheye {} {} differs from the {} detected in {} (confidence {:.2}).",
+            name, value, detected, image_name, detection.confidence
+        )
+    })
+    .collect()
+}
+
+/**
+ * Checks the fisheye circle entered by the user against the one detected in the images of a
+ * scene. Detection errors are returned as problems too, as the user's values are not checked.
+ *
+ * @param host - Host the dcraw_emu conversion runs through
+ * @param dcraw_dir - Directory of dcraw_emu (None to use the bundled one)
+ * @param images - Paths to the LDR images of the scene
+ * @param diameter - The fisheye view diameter entered by the user (in pixels)
+ * @param xleft - The xleft offset entered by the user (in pixels)
+ * @param ydown - The ydown offset entered by the user (in pixels)
+ * @param cancel_token - Token cancelling a raw conversion
+ * @returns A message for each problem found, empty if the values match the detected circle
+ */
+pub fn check_scene_circle(
+    host: &dyn PipelineHost,
+    dcraw_dir: Option<&Path>,
+    images: &[String],
+    diameter: u32,
+    xleft: i64,
+    ydown: i64,
+    cancel_token: &CancellationToken,
+) -> Vec<String> {
+    match detect_fisheye_circle(host, dcraw_dir, images, cancel_token) {
+        Ok(detection) => check_circle(&detection, diameter, xleft, ydown),
+        Err(error) => vec![format!("The fisheye circle was not checked: {}", error)],
+    }
+}
+
+// Returns the frame with the longest exposure, or the frame with the highest mean gray level if
+// some exposure times are missing
+fn brightest_frame(images: &[String]) -> Result<String, String> {
+    let exposure_times: Option<Vec<f64>> = images
+        .iter()
+        .map(|image| read_metadata(Path::new(image)).ok()?.exposure_time)
+        .collect();
+    if let Some(exposure_times) = exposure_times {
+        return images
+            .iter()
+            .zip(exposure_times)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(image, _)| image.clone())
+            .ok_or("No images were provided.".into());
+    }
+
+    let mut brightest: Option<(f64, &String)> = None;
+    for image in images.iter().filter(|image| !is_raw(image)) {
+        let frame = image::open(image)
+            .map_err(|e| format!("Failed to open {}: {}", image, e))?
+            .to_luma32f();
+        let mean = frame.pixels().map(|pixel| pixel[0] as f64).sum::<f64>()
+            / (frame.width() as f64 * frame.height() as f64).max(1.0);
+        if brightest.is_none_or(|(brightest_mean, _)| mean > brightest_mean) {
+            brightest = Some((mean, image));
+        }
+    }
+    brightest
+        .map(|(_, image)| image.clone())
+        .or(images.last().cloned())
+        .ok_or("No images were provided.".into())
+}
+
+// Returns a gray level just above the black border around the fisheye view. The border level is
+// taken from the image corners, which are outside the view of a circular fisheye.
+fn border_threshold(gray: &[f32], width: usize, height: usize) -> f32 {
+    let corner = (width.min(height) / 20).max(1);
+    let mut corner_levels: Vec<f32> = [(0, 0), (width - corner, 0), (0, height - corner)]
+        .into_iter()
+        .chain([(width - corner, height - corner)])
+        .map(|(left, top)| {
+            let mut sum = 0.0;
+            for y in top..top + corner {
+                for x in left..left + corner {
+                    sum += gray[y * width + x];
+                }
+            }
+            sum / (corner * corner) as f32
+        })
+        .collect();
+    corner_levels.sort_by(f32::total_cmp);
+    let border = (corner_levels[1] + corner_levels[2]) / 2.0;
+
+    // Otsu's threshold separates the border from the scene. Dark parts of the scene can be
+    // below it, so a level between the two, closer to the border, is used.
+    let otsu = otsu_threshold(gray);
+    border + ((otsu - border) / 4.0).max(0.02)
+}
+
+// Returns the threshold maximizing the variance between the two classes of gray levels (in 0-1)
+fn otsu_threshold(gray: &[f32]) -> f32 {
+    const BINS: usize = 256;
+    let mut histogram = [0u64; BINS];
+    for &value in gray {
+        histogram[((value.clamp(0.0, 1.0) * (BINS - 1) as f32).round()) as usize] += 1;
+    }
+
+    let total = gray.len() as f64;
+    let total_sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(bin, &count)| bin as f64 * count as f64)
+        .sum();
+    let (mut below_count, mut below_sum) = (0.0, 0.0);
+    let (mut best_bin, mut best_variance) = (0, 0.0);
+    for (bin, &count) in histogram.iter().enumerate() {
+        below_count += count as f64;
+        below_sum += bin as f64 * count as f64;
+        let above_count = total - below_count;
+        if below_count == 0.0 || above_count == 0.0 {
+            continue;
+        }
+        let mean_difference = below_sum / below_count - (total_sum - below_sum) / above_count;
+        let variance = below_count * above_count * mean_difference * mean_difference;
+        if variance > best_variance {
+            best_variance = variance;
+            best_bin = bin;
+        }
+    }
+    (best_bin as f32 + 0.5) / (BINS - 1) as f32
+}
+
+// Returns the edge points of the bright area: the first run of bright pixels seen from each
+// side of every row and column. Runs touching the image border are skipped, as the circle is cut
+// there.
+fn edge_points(gray: &[f32], width: usize, height: usize, threshold: f32) -> Vec<(f64, f64)> {
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    let bright = |x: usize, y: usize| gray[y * width + x] > threshold;
+
+    // First index of a run of EDGE_RUN bright pixels along a line, in the given order
+    let first_run = |indices: &mut dyn Iterator<Item = usize>,
+                     is_bright: &dyn Fn(usize) -> bool| {
+        let mut run = 0;
+        for index in indices {
+            run = if is_bright(index) { run + 1 } else { 0 };
+            if run == EDGE_RUN {
+                return Some(index);
+            }
+        }
+        None
+    };
+
+    for y in 0..height {
+        let row = |x: usize| bright(x, y);
+        let center = y as f64 + 0.5;
+        if let Some(x) = first_run(&mut (0..width), &row) {
+            let left = x + 1 - EDGE_RUN;
+            if left > 0 {
+                points.push((left as f64, center));
+            }
+        }
+        if let Some(x) = first_run(&mut (0..width).rev(), &row) {
+            let right = x + EDGE_RUN;
+            if right < width {
+                points.push((right as f64, center));
+            }
+        }
+    }
+    for x in 0..width {
+        let column = |y: usize| bright(x, y);
+        let center = x as f64 + 0.5;
+        if let Some(y) = first_run(&mut (0..height), &column) {
+            let top = y + 1 - EDGE_RUN;
+            if top > 0 {
+                points.push((center, top as f64));
+            }
+        }
+        if let Some(y) = first_run(&mut (0..height).rev(), &column) {
+            let bottom = y + EDGE_RUN;
+            if bottom < height {
+                points.push((center, bottom as f64));
+            }
+        }
+    }
+    points
+}
+
+// Fits a circle to the selected points by algebraic least squares (Kåsa's method). Returns the
+// center and radius.
+fn fit_circle(points: &[(f64, f64)], selected: &[bool]) -> Result<(f64, f64, f64), String> {
+    let chosen: Vec<(f64, f64)> = points
+        .iter()
+        .zip(selected)
+        .filter(|(_, &selected)| selected)
+        .map(|(&point, _)| point)
+        .collect();
+    let count = chosen.len() as f64;
+    if chosen.len() < 3 {
+        return Err("Too few fisheye circle edge points were found.".into());
+    }
+
+    // Points relative to their mean, for a better conditioned system
+    let mean_x = chosen.iter().map(|point| point.0).sum::<f64>() / count;
+    let mean_y = chosen.iter().map(|point| point.1).sum::<f64>() / count;
+
+    // Solves x² + y² + D x + E y + F = 0 with centered coordinates: D, E from a 2x2 system
+    let (mut xx, mut xy, mut yy, mut xz, mut yz, mut z_sum) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    for &(x, y) in &chosen {
+        let (x, y) = (x - mean_x, y - mean_y);
+        let z = x * x + y * y;
+        xx += x * x;
+        xy += x * y;
+        yy += y * y;
+        xz += x * z;
+        yz += y * z;
+        z_sum += z;
+    }
+    let determinant = xx * yy - xy * xy;
+    if determinant.abs() < f64::EPSILON {
+        return Err("The fisheye circle edge points are on a line.".into());
+    }
+    let d = (-xz * yy + yz * xy) / determinant;
+    let e = (-yz * xx + xz * xy) / determinant;
+    let f = -z_sum / count;
+
+    let (x_center, y_center) = (-d / 2.0, -e / 2.0);
+    let radius = (x_center * x_center + y_center * y_center - f).sqrt();
+    if !radius.is_finite() {
+        return Err("No circle fits the fisheye circle edge points.".into());
+    }
+    Ok((x_center + mean_x, y_center + mean_y, radius))
+}
+
+// Fraction of the edge points kept by the fit
+fn inlier_fraction(inliers: &[bool]) -> f64 {
+    inliers.iter().filter(|&&inlier| inlier).count() as f64 / inliers.len().max(1) as f64
+}
+
+// Fraction of the circle edge inside the image where edge points were kept, by angular sector
+fn edge_coverage(
+    points: &[(f64, f64)],
+    inliers: &[bool],
+    (x_center, y_center, radius): (f64, f64, f64),
+    width: usize,
+    height: usize,
+) -> f64 {
+    let sector_of = |x: f64, y: f64| {
+        let angle = (y - y_center).atan2(x - x_center) + std::f64::consts::PI;
+        ((angle / std::f64::consts::TAU * SECTORS as f64) as usize).min(SECTORS - 1)
+    };
+
+    let mut covered = [false; SECTORS];
+    for (&(x, y), _) in points.iter().zip(inliers).filter(|(_, &inlier)| inlier) {
+        covered[sector_of(x, y)] = true;
+    }
+
+    // Sectors whose part of the edge is inside the image (with a margin for the skipped runs)
+    let margin = EDGE_RUN as f64;
+    let visible: Vec<usize> = (0..SECTORS)
+        .filter(|sector| {
+            let angle = (*sector as f64 + 0.5) / SECTORS as f64 * std::f64::consts::TAU
+                - std::f64::consts::PI;
+            let (x, y) = (
+                x_center + radius * angle.cos(),
+                y_center + radius * angle.sin(),
+            );
+            x > margin && x < width as f64 - margin && y > margin && y < height as f64 - margin
+        })
+        .collect();
+    if visible.len() < SECTORS / 4 {
+        return 0.0;
+    }
+    visible.iter().filter(|&&sector| covered[sector]).count() as f64 / visible.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::merge_exposures::fisheye_mask;
+
+    // Bright disc on a dark border, center given from the top left of the image
+    fn disc(width: u32, height: u32, x_center: f64, y_center: f64, radius: f64) -> Vec<f32> {
+        (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| {
+                    let distance = (x as f64 + 0.5 - x_center).hypot(y as f64 + 0.5 - y_center);
+                    if distance <= radius {
+                        0.7
+                    } else {
+                        0.02
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn detection(diameter: u32, xleft: i64, ydown: i64, confidence: f64) -> CircleDetection {
+        CircleDetection {
+            image: "frame.jpg".into(),
+            diameter,
+            xleft,
+            ydown,
+            x_center: 0.0,
+            y_center: 0.0,
+            confidence,
+        }
+    }
+
+    #[test]
+    fn kasa_fit_recovers_circle() {
+        let points: Vec<(f64, f64)> = (0..36)
+            .map(|i| {
+                let angle = i as f64 / 36.0 * std::f64::consts::TAU;
+                (310.0 + 200.0 * angle.cos(), 240.0 + 200.0 * angle.sin())
+            })
+            .collect();
+        let (x_center, y_center, radius) = fit_circle(&points, &[true; 36]).unwrap();
+        assert!((x_center - 310.0).abs() < 1e-9);
+        assert!((y_center - 240.0).abs() < 1e-9);
+        assert!((radius - 200.0).abs() < 1e-9);
+
+        // Points left out of the fit don't move the circle
+        let mut outliers = points.clone();
+        outliers[0] = (0.0, 0.0);
+        let mut selected = [true; 36];
+        selected[0] = false;
+        let (x_center, _, _) = fit_circle(&outliers, &selected).unwrap();
+        assert!((x_center - 310.0).abs() < 1e-9);
+
+        let line: Vec<(f64, f64)> = (0..5).map(|i| (i as f64, 2.0 * i as f64)).collect();
+        assert!(fit_circle(&line, &[true; 5]).is_err());
+        assert!(fit_circle(&points[..2], &[true; 2]).is_err());
+    }
+
+    #[test]
+    fn otsu_threshold_separates_border_from_view() {
+        let gray = disc(200, 160, 100.0, 80.0, 60.0);
+        let threshold = otsu_threshold(&gray);
+        assert!(threshold > 0.02 && threshold < 0.7, "{}", threshold);
+
+        // The edge threshold is just above the border level, well below the view
+        let border = border_threshold(&gray, 200, 160);
+        assert!(border > 0.02 && border < 0.1, "{}", border);
+    }
+
+    #[test]
+    fn detects_synthetic_disc_with_ydown_from_the_bottom() {
+        let (width, height) = (400, 300);
+        // Disc closer to the top, so that ydown from the top or the bottom would differ
+        let (x_center, y_center, radius) = (210.0, 130.0, 110.0);
+        let gray = disc(width, height, x_center, y_center, radius);
+        let path = std::env::temp_dir().join(format!("disc_{}.png", std::process::id()));
+        image::GrayImage::from_fn(width, height, |x, y| {
+            image::Luma([(gray[(y * width + x) as usize] * 255.0).round() as u8])
+        })
+        .save(&path)
+        .unwrap();
+
+        let detected = detect_circle(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!((detected.x_center - x_center).abs() < 1.0);
+        assert!((detected.y_center - y_center).abs() < 1.0);
+        assert!(
+            detected.diameter.abs_diff(220) <= 1,
+            "{}",
+            detected.diameter
+        );
+        assert!((detected.xleft - 100).abs() <= 1, "{}", detected.xleft);
+        assert!((detected.ydown - 60).abs() <= 1, "{}", detected.ydown);
+        assert!(detected.confidence > MIN_CONFIDENCE);
+
+        // The mask built from the detected offsets covers the disc and nothing else
+        let mask = fisheye_mask(
+            width,
+            height,
+            detected.diameter as f32,
+            detected.xleft as f32,
+            detected.ydown as f32,
+        );
+        let mismatches = mask
+            .iter()
+            .zip(&gray)
+            .filter(|(inside, value)| **inside != (**value > 0.5))
+            .count();
+        assert!(mismatches < 2 * 220 * 2, "{} mismatched pixels", mismatches);
+    }
+
+    #[test]
+    fn check_circle_allows_two_percent_of_the_diameter() {
+        // Tolerance is 2% of 1000 = 20 pixels
+        let detected = detection(1000, 100, 50, 0.9);
+        assert!(check_circle(&detected, 1000, 100, 50).is_empty());
+        assert!(check_circle(&detected, 1020, 80, 70).is_empty());
+        let problems = check_circle(&detected, 1021, 100, 29);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("diameter 1021"));
+        assert!(problems[1].contains("ydown 29"));
+
+        // Small circles still allow MIN_TOLERANCE_PIXELS
+        let detected = detection(100, 10, 10, 0.9);
+        assert!(check_circle(&detected, 104, 6, 14).is_empty());
+        assert_eq!(check_circle(&detected, 105, 10, 10).len(), 1);
+
+        // Unreliable detections don't check the values
+        let problems = check_circle(&detection(1000, 0, 0, 0.5), 10, 500, 500);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("could not be detected reliably"));
+    }
+}
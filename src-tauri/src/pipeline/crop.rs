@@ -10,7 +10,7 @@ use std::fs::File;
 use std::process::Command;
 use std::process::Stdio;
 
-use super::ConfigSettings;
+use super::{run_command, ConfigSettings, PipelineError};
 
 /**
  * Crops a fisheye view HDR image to a square which circumscribes the circular fisheye view.
@@ -31,10 +31,10 @@ pub fn crop(
     config_settings: &ConfigSettings,
     input_file: String,
     output_file: String,
-    diameter: String,
-    xleft: String,
-    ydown: String,
-) -> Result<String, String> {
+    diameter: u32,
+    xleft: i64,
+    ydown: i64,
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!("crop() was called with parameters:");
         println!("\tdiameter: {diameter}");
@@ -46,6 +46,7 @@ pub fn crop(
     let mut command = Command::new(config_settings.radiance_path.join("pcompos"));
 
     // Add arguments to pcompos command
+    let diameter = diameter.to_string();
     command.args([
         "-x",
         diameter.as_str(),
@@ -69,11 +70,8 @@ pub fn crop(
     command.stdout(stdio);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: crop: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command("crop", &mut command, &config_settings.cancel_token)?;
+    let status = output.status;
 
     if DEBUG {
         println!("\n'pcompos' command exit status: {:?}\n", status);
@@ -85,6 +83,6 @@ pub fn crop(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'pcompos' failed.".into())
+        Err(PipelineError::command_failed("crop", &command, &output))
     }
 }
@@ -0,0 +1,253 @@
+/**
+ * Module defining the error type of the pipeline.
+ *
+ * When an external command fails, the error keeps the stage it ran in, the exact command line,
+ * the exit code and the captured stderr/stdout, so a rejected .cal file or a failing hdrgen
+ * call can be diagnosed without re-running the command by hand. Errors are serialized to the
+ * frontend as tagged objects, e.g. `{ "kind": "commandFailed", "stage": "crop", ... }`.
+ */
+use std::{fmt, process::Command, process::Output};
+
+use serde::Serialize;
+
+use super::CANCELLED_MESSAGE;
+
+/**
+ * Error of a pipeline run
+ */
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum PipelineError {
+    // The parameters of the run failed validation
+    InvalidParams {
+        errors: Vec<String>,
+    },
+    // The images of a scene failed the bracket checks (mixed ISO or aperture, repeated
+    // exposures, gaps larger than the allowed EV step)
+    InvalidBracket {
+        problems: Vec<String>,
+    },
+    // An external command could not be started (e.g. the program was not found)
+    #[serde(rename_all = "camelCase")]
+    CommandNotStarted {
+        stage: String,
+        command: String,
+        message: String,
+    },
+    // An external command exited with a failure status
+    #[serde(rename_all = "camelCase")]
+    CommandFailed {
+        stage: String,
+        command: String,
+        exit_code: Option<i32>,
+        stderr: String,
+        stdout: String,
+    },
+    // A scene of a batch failed; error is the reason it failed
+    #[serde(rename_all = "camelCase")]
+    SceneFailed {
+        scene: String,
+        scene_index: usize,
+        error: Box<PipelineError>,
+    },
+    // Some scenes of a batch run with continue_on_error failed, the others succeeded.
+    // manifest is the path of the batch manifest listing the error of each failed scene.
+    #[serde(rename_all = "camelCase")]
+    ScenesFailed {
+        failed_count: usize,
+        scene_count: usize,
+        manifest: String,
+    },
+    // The run was cancelled with the cancel_pipeline command
+    Cancelled,
+    // Any other failure (creating files, reading images, ...)
+    Other {
+        stage: Option<String>,
+        message: String,
+    },
+}
+
+impl PipelineError {
+    /**
+     * Creates the error for a command that ran but exited with a failure status
+     *
+     * @param stage - Name of the stage that ran the command
+     * @param command - The command that failed
+     * @param output - Exit status and captured output of the command
+     */
+    pub fn command_failed(stage: &str, command: &Command, output: &Output) -> PipelineError {
+        PipelineError::CommandFailed {
+            stage: stage.to_string(),
+            command: command_line(command),
+            exit_code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        }
+    }
+
+    /**
+     * Creates the error for a command that could not be started
+     *
+     * @param stage - Name of the stage that ran the command
+     * @param command - The command that could not be started
+     * @param error - Error returned when spawning the command
+     */
+    pub fn command_not_started(
+        stage: &str,
+        command: &Command,
+        error: &std::io::Error,
+    ) -> PipelineError {
+        PipelineError::CommandNotStarted {
+            stage: stage.to_string(),
+            command: command_line(command),
+            message: error.to_string(),
+        }
+    }
+
+    /**
+     * Returns the error wrapped to identify the scene of a batch it happened in
+     *
+     * @param scene - Name of the scene (input directory name)
+     * @param scene_index - Index of the scene in the batch, starting at 0
+     */
+    pub fn in_scene(self, scene: &str, scene_index: usize) -> PipelineError {
+        match self {
+            // Cancelling stops the whole run, not a single scene
+            PipelineError::Cancelled => PipelineError::Cancelled,
+            error => PipelineError::SceneFailed {
+                scene: scene.to_string(),
+                scene_index,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /**
+     * Returns the error with its stage set to the given pipeline stage
+     *
+     * @param stage_name - Name of the pipeline stage the error happened in
+     */
+    pub fn in_stage(self, stage_name: &str) -> PipelineError {
+        let stage_name = stage_name.to_string();
+        match self {
+            PipelineError::CommandNotStarted {
+                command, message, ..
+            } => PipelineError::CommandNotStarted {
+                stage: stage_name,
+                command,
+                message,
+            },
+            PipelineError::CommandFailed {
+                command,
+                exit_code,
+                stderr,
+                stdout,
+                ..
+            } => PipelineError::CommandFailed {
+                stage: stage_name,
+                command,
+                exit_code,
+                stderr,
+                stdout,
+            },
+            PipelineError::Other { message, .. } => PipelineError::Other {
+                stage: Some(stage_name),
+                message,
+            },
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::InvalidParams { errors } => {
+                write!(f, "Invalid pipeline parameters:\n{}", errors.join("\n"))
+            }
+            PipelineError::InvalidBracket { problems } => {
+                write!(f, "Invalid exposure bracket:\n{}", problems.join("\n"))
+            }
+            PipelineError::CommandNotStarted {
+                stage,
+                command,
+                message,
+            } => write!(
+                f,
+                "pipeline: {}: failed to start command ({}): {}",
+                stage, message, command
+            ),
+            PipelineError::CommandFailed {
+                stage,
+                command,
+                exit_code,
+                stderr,
+                ..
+            } => {
+                match exit_code {
+                    Some(code) => write!(
+                        f,
+                        "pipeline: {}: command failed with exit code {}: {}",
+                        stage, code, command
+                    )?,
+                    None => write!(
+                        f,
+                        "pipeline: {}: command was terminated: {}",
+                        stage, command
+                    )?,
+                }
+                if !stderr.trim().is_empty() {
+                    write!(f, "\n{}", stderr.trim_end())?;
+                }
+                Ok(())
+            }
+            PipelineError::SceneFailed { scene, error, .. } => {
+                write!(f, "Scene '{}' failed: {}", scene, error)
+            }
+            PipelineError::ScenesFailed {
+                failed_count,
+                scene_count,
+                manifest,
+            } => write!(
+                f,
+                "{} of {} scenes failed, see the batch manifest for details: {}",
+                failed_count, scene_count, manifest
+            ),
+            PipelineError::Cancelled => write!(f, "{}", CANCELLED_MESSAGE),
+            PipelineError::Other { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for PipelineError {
+    fn from(message: String) -> PipelineError {
+        PipelineError::Other {
+            stage: None,
+            message,
+        }
+    }
+}
+
+impl From<&str> for PipelineError {
+    fn from(message: &str) -> PipelineError {
+        PipelineError::from(message.to_string())
+    }
+}
+
+/**
+ * Formats a command as it would be typed in a shell, quoting arguments that contain spaces
+ */
+pub fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|part| {
+            let part = part.to_string_lossy();
+            if part.is_empty() || part.contains(char::is_whitespace) {
+                format!("\"{}\"", part)
+            } else {
+                part.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
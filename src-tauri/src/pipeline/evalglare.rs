@@ -5,14 +5,238 @@
  * suite, which analyzes glare sources in HDR images. The output contains information
  * about glare sources, daylight glare probability (DGP), and other metrics useful
  * for evaluating visual comfort.
+ *
+ * The output is parsed into GlareMetrics, so it can be written to the header as separate keys
+ * and analyzed without re-parsing text. Both output formats of evalglare are understood:
+ * - with -V, only the vertical illuminance is printed, as a single number;
+ * - otherwise, a summary line names the metrics before their values, e.g.
+ *   "dgp,av_lum,E_v,lum_backg,E_v_dir,dgi,ugr,vcp,cgi,lum_sources,omega_sources,Lveil: 0.31 ...".
+ *   With -d, it follows a table of the detected glare sources ("No pixels x-pos y-pos L_s
+ *   Omega_s Posindx ..."), which is parsed into GlareSource entries.
+ *
+ * The options of the command (output, glare source threshold, task zone, peak extraction, check
+ * image) come from the evalglare block of the pipeline parameters.
  */
 use crate::pipeline::DEBUG;
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::process::Stdio;
 
-use super::ConfigSettings;
+use serde::{Deserialize, Serialize};
+
+use super::{run_command_output, ConfigSettings, EvalglareArgs, EvalglareOutput, PipelineError};
+
+/**
+ * Glare metrics of an HDR image, as computed by evalglare. Metrics evalglare did not print
+ * (e.g. everything but the vertical illuminance with -V) are None.
+ *
+ * @field dgp - Daylight glare probability (dgp)
+ * @field average_luminance - Average luminance of the image, in cd/m² (av_lum)
+ * @field vertical_illuminance - Vertical illuminance at the eye, in lux (E_v)
+ * @field background_luminance - Luminance of the background without the glare sources, in
+ *                               cd/m² (lum_backg)
+ * @field dgi - Daylight glare index (dgi)
+ * @field ugr - Unified glare rating (ugr)
+ * @field vcp - Visual comfort probability (vcp)
+ * @field cgi - CIE glare index (cgi)
+ * @field other - The other metrics of the summary line, by evalglare name (e.g. E_v_dir,
+ *                lum_sources, omega_sources, Lveil)
+ * @field glare_sources - The detected glare sources, listed with -d
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GlareMetrics {
+    pub dgp: Option<f64>,
+    pub average_luminance: Option<f64>,
+    pub vertical_illuminance: f64,
+    pub background_luminance: Option<f64>,
+    pub dgi: Option<f64>,
+    pub ugr: Option<f64>,
+    pub vcp: Option<f64>,
+    pub cgi: Option<f64>,
+    #[serde(default)]
+    pub other: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub glare_sources: Vec<GlareSource>,
+}
+
+/**
+ * A glare source detected by evalglare (a row of the -d table)
+ *
+ * @field pixels - Number of pixels of the source
+ * @field x - x-coordinate of the source center in the image, in pixels (x-pos)
+ * @field y - y-coordinate of the source center in the image, in pixels (y-pos)
+ * @field luminance - Average luminance of the source, in cd/m² (L_s)
+ * @field solid_angle - Solid angle of the source, in sr (Omega_s)
+ * @field position_index - Guth position index of the source (Posindx)
+ * @field vertical_illuminance - Vertical illuminance caused by the source, in lux (E_vert)
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GlareSource {
+    pub pixels: f64,
+    pub x: f64,
+    pub y: f64,
+    pub luminance: f64,
+    pub solid_angle: f64,
+    pub position_index: f64,
+    pub vertical_illuminance: Option<f64>,
+}
+
+// Header keys of the metrics, with the evalglare name of each. The vertical illuminance is also
+// kept in EVALGLARE=, which earlier versions wrote and the image viewer reads.
+const HEADER_KEYS: [(&str, &str); 8] = [
+    ("GLARE_DGP", "dgp"),
+    ("GLARE_AV_LUM", "av_lum"),
+    ("GLARE_EV", "E_v"),
+    ("GLARE_LUM_BACKG", "lum_backg"),
+    ("GLARE_DGI", "dgi"),
+    ("GLARE_UGR", "ugr"),
+    ("GLARE_VCP", "vcp"),
+    ("GLARE_CGI", "cgi"),
+];
+
+impl GlareMetrics {
+    /**
+     * Parses the standard output of evalglare
+     *
+     * @param output - What evalglare printed
+     * @returns The metrics, or an error if the output has no vertical illuminance
+     */
+    pub fn parse(output: &str) -> Result<GlareMetrics, String> {
+        let mut values: BTreeMap<String, f64> = BTreeMap::new();
+        let mut glare_sources: Vec<GlareSource> = Vec::new();
+
+        let mut lines = output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        while let Some(line) = lines.next() {
+            // -V: the vertical illuminance alone
+            if let Ok(value) = line.parse::<f64>() {
+                values.insert("E_v".into(), value);
+                continue;
+            }
+
+            // -d: table of glare sources, one row per source after the column names
+            if let Some(start) = line.find("No pixels") {
+                let columns: Vec<&str> = line[start..].split_whitespace().collect();
+                let source_count = line[..start].trim().parse::<usize>().ok();
+                let mut rows = 0;
+                while source_count.is_none_or(|count| rows < count) {
+                    let Some(row) = lines.clone().next().and_then(numbers) else {
+                        break;
+                    };
+                    lines.next();
+                    rows += 1;
+                    if row.len() == columns.len() {
+                        glare_sources.push(GlareSource::from_row(&columns, &row));
+                    }
+                }
+                continue;
+            }
+
+            // Summary line: "names: values", with the image name in front in some versions
+            if let Some((names, numbers_part)) = line.rsplit_once(':') {
+                let names: Vec<&str> = names
+                    .split(|c: char| c.is_whitespace() || c == ':')
+                    .next_back()
+                    .unwrap_or_default()
+                    .split(',')
+                    .collect();
+                if let Some(row) = numbers(numbers_part) {
+                    if row.len() == names.len() {
+                        values.extend(names.into_iter().map(String::from).zip(row));
+                    }
+                }
+            }
+        }
+
+        let vertical_illuminance = values.remove("E_v").ok_or(format!(
+            "No vertical illuminance found in the output of evalglare: {:?}",
+            output.trim()
+        ))?;
+        Ok(GlareMetrics {
+            dgp: values.remove("dgp"),
+            average_luminance: values.remove("av_lum"),
+            vertical_illuminance,
+            background_luminance: values.remove("lum_backg"),
+            dgi: values.remove("dgi"),
+            ugr: values.remove("ugr"),
+            vcp: values.remove("vcp"),
+            cgi: values.remove("cgi"),
+            other: values,
+            glare_sources,
+        })
+    }
+
+    /**
+     * Returns the header lines of the metrics: EVALGLARE= with the vertical illuminance, and a
+     * GLARE_<metric>= line for each metric evalglare printed
+     */
+    pub fn header_lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("EVALGLARE={}", self.vertical_illuminance)];
+        for (key, name) in HEADER_KEYS {
+            if let Some(value) = self.value(name) {
+                lines.push(format!("{}={}", key, value));
+            }
+        }
+        lines
+    }
+
+    /**
+     * Returns a metric by its evalglare name (e.g. "dgp", "E_v", "Lveil")
+     *
+     * @param name - Name of the metric in the evalglare output
+     */
+    pub fn value(&self, name: &str) -> Option<f64> {
+        match name {
+            "dgp" => self.dgp,
+            "av_lum" => self.average_luminance,
+            "E_v" => Some(self.vertical_illuminance),
+            "lum_backg" => self.background_luminance,
+            "dgi" => self.dgi,
+            "ugr" => self.ugr,
+            "vcp" => self.vcp,
+            "cgi" => self.cgi,
+            name => self.other.get(name).copied(),
+        }
+    }
+}
+
+impl GlareSource {
+    // Creates a source from a row of the -d table and its column names
+    fn from_row(columns: &[&str], row: &[f64]) -> GlareSource {
+        let column = |name: &str| {
+            columns
+                .iter()
+                .position(|column| *column == name)
+                .map(|index| row[index])
+        };
+        GlareSource {
+            pixels: column("pixels").unwrap_or_default(),
+            x: column("x-pos").unwrap_or_default(),
+            y: column("y-pos").unwrap_or_default(),
+            luminance: column("L_s").unwrap_or_default(),
+            solid_angle: column("Omega_s").unwrap_or_default(),
+            position_index: column("Posindx").unwrap_or_default(),
+            vertical_illuminance: column("E_vert"),
+        }
+    }
+}
+
+// Parses a line of whitespace separated numbers, None if any of them is not a number
+fn numbers(line: &str) -> Option<Vec<f64>> {
+    let row: Vec<f64> = line
+        .split_whitespace()
+        .map(|value| value.parse().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    (!row.is_empty()).then_some(row)
+}
 
 /**
  * Runs the evalglare command on an HDR image and saves the output
@@ -25,14 +249,16 @@ use super::ConfigSettings;
  * @param input_file - Path to the input HDR image (must be in .hdr format)
  * @param vertical_angle - The fov, in degrees, of the image vertically. Found within the camera settings
  * @param horizontal_angle - The fov, in degrees, of the image horizontally. Found within the camera settings
- * @returns Result containing the output value on success or an error message on failure
+ * @returns Result containing the parsed metrics on success or an error message on failure
  */
 pub fn evalglare(
     config_settings: &ConfigSettings,
     input_file: String,
-    vertical_angle: String,
-    horizontal_angle: String,
-) -> Result<String, String> {
+    vertical_angle: f64,
+    horizontal_angle: f64,
+    args: &EvalglareArgs,
+    check_file: Option<&Path>,
+) -> Result<GlareMetrics, PipelineError> {
     // Print debug message if in debug mode
     if DEBUG {
         println!("evalglare() was called.");
@@ -42,25 +268,18 @@ pub fn evalglare(
     let mut command = Command::new(config_settings.radiance_path.join("evalglare"));
 
     // Add arguments:
-    // -V: Verbose output with detailed information about glare sources
     // -vta: View type
     // -vv/vh: Vertical and horizontal view angles
     command.args([
         "-vta",
         "-vv",
-        vertical_angle.as_str(),
+        vertical_angle.to_string().as_str(),
         "-vh",
-        horizontal_angle.as_str(),
-        "-V",
-        input_file.as_str(),
+        horizontal_angle.to_string().as_str(),
     ]);
-
+    command.args(option_args(args, check_file, &input_file));
     // Execute command and capture output
-    let output_result = command.output();
-    if output_result.is_err() {
-        return Err("pipeline: evalglare: failed to start command.".into());
-    }
-    let output = output_result.unwrap();
+    let output = run_command_output("evalglare", &mut command, &config_settings.cancel_token)?;
 
     // Print debug info about command execution status
     if DEBUG {
@@ -72,11 +291,275 @@ pub fn evalglare(
     // Convert command output from bytes to string
     let stdout = String::from_utf8_lossy(&output.stdout);
 
-    // Return a Result object with the command output
-    if output.status.success() || !stdout.is_empty() {
-        Ok(stdout.to_string())
-    } else {
-        // On error, return an error message
-        Err("PIPELINE ERROR: command 'evalglare' failed.".into())
+    // A failed run may still print partial metrics, which are not used
+    if !output.status.success() {
+        return Err(PipelineError::command_failed(
+            "evalglare",
+            &command,
+            &output,
+        ));
+    }
+
+    // Return the parsed command output, with what evalglare reported if it can't be parsed
+    GlareMetrics::parse(&stdout).map_err(|error| {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.trim().is_empty() {
+            error.into()
+        } else {
+            format!("{} (stderr: {})", error, stderr.trim()).into()
+        }
+    })
+}
+
+/**
+ * Finds the evalglare binary the way its command is resolved: in the Radiance directory, or on
+ * PATH when the directory is empty
+ *
+ * @param radiance_path - Directory containing the Radiance binaries (empty to search PATH)
+ * @returns The path of evalglare, or None if it is not installed there
+ */
+pub fn find_evalglare(radiance_path: &Path) -> Option<PathBuf> {
+    let file_name = format!("evalglare{}", env::consts::EXE_SUFFIX);
+    if !radiance_path.as_os_str().is_empty() {
+        return Some(radiance_path.join(&file_name)).filter(|path| path.is_file());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
+}
+
+// Options of the evalglare command for the configured arguments, followed by the input file,
+// which must come last:
+// -V: Only the vertical illuminance
+// -d: List the detected glare sources
+// -b: Glare source threshold
+// -t/-T: Task zone (-T also draws it in the check image)
+// -s: Smoothing of the glare sources
+// -x: No peak extraction
+// -Y: Peak extraction threshold
+// -c: Check image with the glare sources
+fn option_args(args: &EvalglareArgs, check_file: Option<&Path>, input_file: &str) -> Vec<String> {
+    let mut options: Vec<String> = Vec::new();
+    match args.output {
+        EvalglareOutput::VerticalIlluminance => options.push("-V".into()),
+        EvalglareOutput::Metrics => {}
+        EvalglareOutput::Detailed => options.push("-d".into()),
+    }
+    if let Some(threshold) = args.threshold {
+        options.extend(["-b".into(), threshold.to_string()]);
+    }
+    if let Some(task_area) = &args.task_area {
+        options.push(if task_area.mark { "-T" } else { "-t" }.into());
+        options.extend([
+            task_area.x.to_string(),
+            task_area.y.to_string(),
+            task_area.angle.to_string(),
+        ]);
+    }
+    if args.smoothing {
+        options.push("-s".into());
+    }
+    if !args.peak_extraction {
+        options.push("-x".into());
+    } else if let Some(peak_threshold) = args.peak_threshold {
+        options.extend(["-Y".into(), peak_threshold.to_string()]);
+    }
+    options.extend(args.extra_args.iter().cloned());
+    if let Some(check_file) = check_file {
+        options.extend(["-c".into(), check_file.to_string_lossy().into_owned()]);
+    }
+    options.push(input_file.into());
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs in the layout evalglare 2.x prints them. The numbers are not from a single run.
+
+    // Output of evalglare -V: the vertical illuminance alone
+    const VERTICAL_ILLUMINANCE_OUTPUT: &str = "1573.960000\n";
+
+    // Output of evalglare without -V or -d: the summary line
+    const SUMMARY_OUTPUT: &str = "dgp,av_lum,E_v,lum_backg,E_v_dir,dgi,ugr,vcp,cgi,lum_sources,\
+omega_sources,Lveil,Lveil_cie,dgr,ugp,ugr_exp,dgi_mod,av_lum_pos,av_lum_pos2,med_lum,\
+med_lum_pos,med_lum_pos2: 0.337520 472.603546 3283.147949 385.271515 1264.881836 19.934311 \
+20.637167 55.143410 22.866011 12853.520508 0.085331 15.317781 13.262445 1.000000 0.409522 \
+20.637167 19.934311 361.893585 302.011932 165.212463 182.402527 173.889633\n";
+
+    // Output of evalglare -d: the glare source table, then the summary line with the image name
+    const DETAILED_OUTPUT: &str = "2 No pixels x-pos y-pos L_s Omega_s Posindx L_b L_t E_vert \
+Edir Max_Lum Sigma xdir ydir zdir Eglare_cie Lveil_cie teta glare_zone\n\
+1 1014.000000 493.720000 659.000000 15385.190000 0.069817 1.240000 385.271515 472.603546 \
+1100.470000 1074.160000 47395.210000 0.160000 0.130000 0.780000 0.610000 0.620000 10.840000 \
+38.970000 0\n\
+2 213.000000 402.150000 371.480000 1107.890000 0.015514 3.870000 385.271515 472.603546 \
+9.210000 4.020000 1822.500000 0.070000 -0.180000 0.950000 0.260000 0.010000 0.400000 \
+18.210000 1\n\
+scene.hdr: dgp,av_lum,E_v,lum_backg,E_v_dir,dgi,ugr,vcp,cgi,lum_sources,omega_sources,Lveil: \
+0.337520 472.603546 3283.147949 385.271515 1264.881836 19.934311 20.637167 55.143410 \
+22.866011 12853.520508 0.085331 15.317781\n";
+
+    #[test]
+    fn parses_vertical_illuminance_only() {
+        let metrics = GlareMetrics::parse(VERTICAL_ILLUMINANCE_OUTPUT).unwrap();
+        assert_eq!(
+            metrics,
+            GlareMetrics {
+                vertical_illuminance: 1573.96,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            metrics.header_lines(),
+            vec!["EVALGLARE=1573.96", "GLARE_EV=1573.96"]
+        );
+    }
+
+    #[test]
+    fn parses_summary_line() {
+        let metrics = GlareMetrics::parse(SUMMARY_OUTPUT).unwrap();
+        assert_eq!(metrics.dgp, Some(0.33752));
+        assert_eq!(metrics.average_luminance, Some(472.603546));
+        assert_eq!(metrics.vertical_illuminance, 3283.147949);
+        assert_eq!(metrics.background_luminance, Some(385.271515));
+        assert_eq!(metrics.dgi, Some(19.934311));
+        assert_eq!(metrics.ugr, Some(20.637167));
+        assert_eq!(metrics.vcp, Some(55.14341));
+        assert_eq!(metrics.cgi, Some(22.866011));
+        assert_eq!(metrics.value("Lveil"), Some(15.317781));
+        assert_eq!(metrics.value("med_lum_pos2"), Some(173.889633));
+        assert_eq!(metrics.other.len(), 14);
+        assert!(metrics.glare_sources.is_empty());
+
+        assert_eq!(metrics.header_lines().len(), 9);
+        assert_eq!(metrics.header_lines()[1], "GLARE_DGP=0.33752");
+    }
+
+    #[test]
+    fn parses_glare_source_table() {
+        let metrics = GlareMetrics::parse(DETAILED_OUTPUT).unwrap();
+        assert_eq!(metrics.dgp, Some(0.33752));
+        assert_eq!(metrics.vertical_illuminance, 3283.147949);
+        assert_eq!(metrics.value("Lveil"), Some(15.317781));
+        assert_eq!(
+            metrics.glare_sources,
+            vec![
+                GlareSource {
+                    pixels: 1014.0,
+                    x: 493.72,
+                    y: 659.0,
+                    luminance: 15385.19,
+                    solid_angle: 0.069817,
+                    position_index: 1.24,
+                    vertical_illuminance: Some(1100.47),
+                },
+                GlareSource {
+                    pixels: 213.0,
+                    x: 402.15,
+                    y: 371.48,
+                    luminance: 1107.89,
+                    solid_angle: 0.015514,
+                    position_index: 3.87,
+                    vertical_illuminance: Some(9.21),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_empty_glare_source_table() {
+        let output = DETAILED_OUTPUT.replacen("2 No", "0 No", 1);
+        let output: Vec<&str> = output.lines().collect();
+        let output = format!("{}\n{}\n", output[0], output[3]);
+        let metrics = GlareMetrics::parse(&output).unwrap();
+        assert!(metrics.glare_sources.is_empty());
+        assert_eq!(metrics.dgp, Some(0.33752));
+    }
+
+    #[test]
+    fn output_without_vertical_illuminance_is_an_error() {
+        assert!(GlareMetrics::parse("").is_err());
+        assert!(GlareMetrics::parse("error: cannot open scene.hdr\n").is_err());
+        assert!(GlareMetrics::parse("dgp,av_lum: 0.3 400\n").is_err());
+    }
+
+    #[test]
+    fn metrics_survive_json() {
+        let metrics = GlareMetrics::parse(DETAILED_OUTPUT).unwrap();
+        let json = serde_json::to_string(&metrics).unwrap();
+        assert_eq!(
+            serde_json::from_str::<GlareMetrics>(&json).unwrap(),
+            metrics
+        );
+    }
+
+    #[test]
+    fn options_follow_the_arguments() {
+        let args =
+            |json: serde_json::Value| -> EvalglareArgs { serde_json::from_value(json).unwrap() };
+
+        assert_eq!(
+            option_args(&EvalglareArgs::default(), None, "scene.hdr"),
+            vec!["-V", "scene.hdr"]
+        );
+
+        // Peak extraction turned off wins over its threshold
+        let no_peaks = args(serde_json::json!({
+            "output": "metrics",
+            "peakExtraction": false,
+            "peakThreshold": 50000.0,
+        }));
+        assert_eq!(
+            option_args(&no_peaks, None, "scene.hdr"),
+            vec!["-x", "scene.hdr"]
+        );
+
+        let detailed = args(serde_json::json!({
+            "output": "detailed",
+            "threshold": 2000.0,
+            "taskArea": { "x": 500.0, "y": 400.0, "angle": 0.6, "mark": true },
+            "smoothing": true,
+            "peakThreshold": 50000.0,
+            "extraArgs": ["-q", "1"],
+        }));
+        assert_eq!(
+            option_args(&detailed, Some(Path::new("/tmp/check.hdr")), "scene.hdr"),
+            vec![
+                "-d",
+                "-b",
+                "2000",
+                "-T",
+                "500",
+                "400",
+                "0.6",
+                "-s",
+                "-Y",
+                "50000",
+                "-q",
+                "1",
+                "-c",
+                "/tmp/check.hdr",
+                "scene.hdr"
+            ]
+        );
+
+        let unmarked = args(serde_json::json!({
+            "taskArea": { "x": 500.0, "y": 400.0, "angle": 0.6 },
+        }));
+        assert_eq!(
+            option_args(&unmarked, Some(Path::new("/tmp/check.hdr")), "scene.hdr"),
+            vec![
+                "-V",
+                "-t",
+                "500",
+                "400",
+                "0.6",
+                "-c",
+                "/tmp/check.hdr",
+                "scene.hdr"
+            ]
+        );
     }
 }
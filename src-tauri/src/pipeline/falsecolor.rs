@@ -12,8 +12,8 @@ use std::fs::File;
 use std::process::Command;
 use std::process::Stdio;
 
-use super::ConfigSettings;
 use super::LuminanceArgs;
+use super::{run_command, ConfigSettings, PipelineError};
 
 // Allow radiance to access helvet.fnt
 #[cfg(target_os = "windows")]
@@ -45,7 +45,7 @@ pub fn falsecolor(
     input_file: String,
     output_file: String,
     luminance_args: &LuminanceArgs,
-) -> Result<String, String> {
+) -> Result<String, PipelineError> {
     // Print debug information about the function call parameters
     set_env_vars(
         config_settings
@@ -58,11 +58,8 @@ pub fn falsecolor(
 
     if DEBUG {
         println!(
-            "falsecolor() was called with parameters:\n\t {},\n\t {},\n\t {},\n\t {}\n",
-            luminance_args.scale_limit,
-            luminance_args.scale_label,
-            luminance_args.scale_levels,
-            luminance_args.legend_dimensions,
+            "falsecolor() was called with parameters:\n\t {:?}\n",
+            luminance_args
         );
     }
 
@@ -89,26 +86,25 @@ pub fn falsecolor(
         ),
     );
 
-    // Add arguments
-    if luminance_args.scale_label != "" {
-        command.args([
-            "-s",
-            &luminance_args.scale_limit,
-            "-l",
-            &luminance_args.scale_label,
-            "-n",
-            &luminance_args.scale_levels,
-            "-e",
-            "-lw/-lh",
-            &luminance_args.legend_dimensions,
-            "-i",
-            input_file.as_str(),
-        ]);
-    } else {
-        // NOTE: Make sure that the RAYPATH enviornment variable is set to find helvet.fnt
-        // ex: export RAYPATH=/usr/local/radiance/lib
-        command.args(["-e", "-i", input_file.as_str()]);
+    // Add arguments, leaving out the ones the user didn't set so falsecolor uses its defaults
+    // NOTE: Make sure that the RAYPATH enviornment variable is set to find helvet.fnt
+    // ex: export RAYPATH=/usr/local/radiance/lib
+    if let Some(scale_limit) = luminance_args.scale_limit {
+        command.args(["-s", scale_limit.to_string().as_str()]);
+    }
+    if let Some(scale_label) = &luminance_args.scale_label {
+        command.args(["-l", scale_label.as_str()]);
+    }
+    if let Some(scale_levels) = luminance_args.scale_levels {
+        command.args(["-n", scale_levels.to_string().as_str()]);
     }
+    if let Some(legend_width) = luminance_args.legend_width {
+        command.args(["-lw", legend_width.to_string().as_str()]);
+    }
+    if let Some(legend_height) = luminance_args.legend_height {
+        command.args(["-lh", legend_height.to_string().as_str()]);
+    }
+    command.args(["-e", "-i", input_file.as_str()]);
 
     // Set up piping of output to file
     let file_result = File::create(&output_file);
@@ -124,11 +120,8 @@ pub fn falsecolor(
     command.stdout(stdio);
 
     // Run the commnand
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: falsecolor: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command("falsecolor", &mut command, &config_settings.cancel_token)?;
+    let status = output.status;
 
     if DEBUG {
         println!("\nFalsecolor command exit status: {:?}\n", status);
@@ -140,7 +133,11 @@ pub fn falsecolor(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'falsecolor' failed.".into())
+        Err(PipelineError::command_failed(
+            "falsecolor",
+            &command,
+            &output,
+        ))
     }
 }
 
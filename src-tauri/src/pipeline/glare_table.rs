@@ -0,0 +1,342 @@
+/**
+ * Module for the glare metrics table of a batch run.
+ *
+ * After a batch run, the glare metrics of every scene that succeeded are written to the output
+ * directory as one table, glare_metrics_<date>.csv and glare_metrics_<date>.json, so they can be
+ * analyzed without reading the header of each HDR image. Both files have one row per scene with
+ * the same columns: scene name, input, capture time, output paths, then the metrics. The CSV file
+ * has a header row and empty cells for missing values; the JSON file is a list of records with
+ * null for missing values, e.g. for pandas.read_json() or jsonlite::fromJSON().
+ */
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDateTime;
+use serde_json::Value;
+
+use super::{manifest::csv_field, read_metadata, GlareMetrics, SceneOutputs};
+
+// Format of the EXIF DateTimeOriginal field, and of the capture time column
+const EXIF_DATE_TIME_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+const CAPTURE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// Columns of the typed metrics, with the evalglare name of each
+const METRIC_COLUMNS: [(&str, &str); 8] = [
+    ("dgp", "dgp"),
+    ("average_luminance", "av_lum"),
+    ("vertical_illuminance", "E_v"),
+    ("background_luminance", "lum_backg"),
+    ("dgi", "dgi"),
+    ("ugr", "ugr"),
+    ("vcp", "vcp"),
+    ("cgi", "cgi"),
+];
+
+/**
+ * Row of the table for one scene
+ *
+ * @field scene - Name of the scene (input directory or bracket group name)
+ * @field input - The input directory, or the name of the bracket group
+ * @field outputs - Final images, capture time and glare metrics of the scene
+ */
+pub struct GlareRow<'a> {
+    pub scene: &'a str,
+    pub input: &'a str,
+    pub outputs: &'a SceneOutputs,
+}
+
+/**
+ * Writes the glare metrics of the scenes as <name>.csv and <name>.json in a directory. Scenes
+ * without glare metrics (evalglare did not run) are left out, and nothing is written if no
+ * scene has any.
+ *
+ * @param dir - Directory to write the table to (the output directory of the run)
+ * @param name - File name of the table without extension
+ * @param rows - The scenes, in input order
+ * @returns The path of the CSV table, or None if no scene has glare metrics
+ */
+pub fn write_glare_table(
+    dir: &Path,
+    name: &str,
+    rows: &[GlareRow],
+) -> Result<Option<PathBuf>, String> {
+    let rows: Vec<(&GlareRow, &GlareMetrics)> = rows
+        .iter()
+        .filter_map(|row| Some((row, row.outputs.glare_metrics.as_ref()?)))
+        .collect();
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    // The other metrics depend on the evalglare version and options, so every name found in any
+    // scene gets a column
+    let other_names: BTreeSet<&String> = rows
+        .iter()
+        .flat_map(|(_, metrics)| metrics.other.keys())
+        .collect();
+
+    let mut columns: Vec<String> = [
+        "scene",
+        "input",
+        "capture_time",
+        "hdr_image",
+        "falsecolor_image",
+        "glare_check_image",
+    ]
+    .iter()
+    .chain(METRIC_COLUMNS.iter().map(|(column, _)| column))
+    .map(|column| column.to_string())
+    .collect();
+    columns.extend(other_names.iter().map(|name| column_name(name)));
+    columns.push("glare_source_count".into());
+
+    let records: Vec<Vec<Value>> = rows
+        .iter()
+        .map(|(row, metrics)| {
+            let path = |path: Option<&PathBuf>| {
+                path.map_or(Value::Null, |path| path.to_string_lossy().into())
+            };
+            let number = |value: Option<f64>| value.map_or(Value::Null, Value::from);
+            let mut record: Vec<Value> = vec![
+                row.scene.into(),
+                row.input.into(),
+                row.outputs
+                    .capture_time
+                    .as_ref()
+                    .map_or(Value::Null, |time| time.as_str().into()),
+                path(Some(&row.outputs.hdr_image)),
+                path(row.outputs.falsecolor_image.as_ref()),
+                path(row.outputs.glare_check_image.as_ref()),
+            ];
+            record.extend(
+                METRIC_COLUMNS
+                    .iter()
+                    .map(|(_, metric)| number(metrics.value(metric))),
+            );
+            record.extend(
+                other_names
+                    .iter()
+                    .map(|name| number(metrics.other.get(*name).copied())),
+            );
+            record.push(metrics.glare_sources.len().into());
+            record
+        })
+        .collect();
+
+    // Records are written by hand to keep the keys in column order
+    let json_records: Vec<String> = records
+        .iter()
+        .map(|record| {
+            let fields: Vec<String> = columns
+                .iter()
+                .zip(record)
+                .map(|(column, value)| format!("    {}: {}", Value::from(column.as_str()), value))
+                .collect();
+            format!("  {{\n{}\n  }}", fields.join(",\n"))
+        })
+        .collect();
+    let json = format!("[\n{}\n]\n", json_records.join(",\n"));
+    let json_path = dir.join(format!("{}.json", name));
+    fs::write(&json_path, json)
+        .map_err(|e| format!("Error writing {}: {}", json_path.display(), e))?;
+
+    let mut csv = columns.join(",");
+    csv.push('\n');
+    for record in &records {
+        let row: Vec<String> = record
+            .iter()
+            .map(|value| match value {
+                Value::Null => String::new(),
+                Value::String(text) => csv_field(text),
+                value => value.to_string(),
+            })
+            .collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    let csv_path = dir.join(format!("{}.csv", name));
+    fs::write(&csv_path, csv)
+        .map_err(|e| format!("Error writing {}: {}", csv_path.display(), e))?;
+
+    Ok(Some(csv_path))
+}
+
+/**
+ * Returns when a scene was captured: the earliest EXIF capture time of its images, as
+ * "2024-06-21 12:00:00" (None if no image has one)
+ *
+ * @param images - Paths to the LDR images of the scene
+ */
+pub fn scene_capture_time(images: &[String]) -> Option<String> {
+    images
+        .iter()
+        .filter_map(|image| read_metadata(Path::new(image)).ok()?.capture_time)
+        .filter_map(|time| NaiveDateTime::parse_from_str(&time, EXIF_DATE_TIME_FORMAT).ok())
+        .min()
+        .map(|time| time.format(CAPTURE_TIME_FORMAT).to_string())
+}
+
+// Column name of an evalglare metric, in snake case for R and pandas (e.g. Lveil -> lveil)
+fn column_name(metric: &str) -> String {
+    metric
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::Map;
+
+    use super::*;
+    use crate::pipeline::GlareSource;
+
+    fn outputs(name: &str, other: &[(&str, f64)]) -> SceneOutputs {
+        SceneOutputs {
+            hdr_image: PathBuf::from(format!("/output/{}.hdr", name)),
+            glare_metrics: Some(GlareMetrics {
+                dgp: Some(0.35),
+                vertical_illuminance: 2500.0,
+                ugr: Some(18.5),
+                other: other
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), *value))
+                    .collect::<BTreeMap<String, f64>>(),
+                glare_sources: vec![GlareSource::default(); 2],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn writes_one_row_per_scene_with_metrics() {
+        let dir = std::env::temp_dir().join(format!("glare_table_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let office = SceneOutputs {
+            falsecolor_image: Some(PathBuf::from("/output/office, east_fc.hdr")),
+            capture_time: Some("2024-06-21 12:00:00".into()),
+            ..outputs("office, east", &[("Lveil", 12.5)])
+        };
+        let not_evaluated = SceneOutputs {
+            hdr_image: PathBuf::from("/output/lobby.hdr"),
+            ..Default::default()
+        };
+        let corridor = outputs("corridor", &[("DGR", 3.0)]);
+        let rows = [
+            GlareRow {
+                scene: "office, east",
+                input: "/input/office, east",
+                outputs: &office,
+            },
+            GlareRow {
+                scene: "lobby",
+                input: "/input/lobby",
+                outputs: &not_evaluated,
+            },
+            GlareRow {
+                scene: "corridor \"north\"",
+                input: "/input/corridor",
+                outputs: &corridor,
+            },
+        ];
+        let csv_path = write_glare_table(&dir, "glare_metrics", &rows)
+            .unwrap()
+            .unwrap();
+
+        // Fixed columns, the typed metrics, then the other metrics of every scene
+        let columns = [
+            "scene",
+            "input",
+            "capture_time",
+            "hdr_image",
+            "falsecolor_image",
+            "glare_check_image",
+            "dgp",
+            "average_luminance",
+            "vertical_illuminance",
+            "background_luminance",
+            "dgi",
+            "ugr",
+            "vcp",
+            "cgi",
+            "dgr",
+            "lveil",
+            "glare_source_count",
+        ];
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", csv);
+        assert_eq!(lines[0], columns.join(","));
+        assert_eq!(
+            lines[1],
+            "\"office, east\",\"/input/office, east\",2024-06-21 12:00:00,\
+             \"/output/office, east.hdr\",\"/output/office, east_fc.hdr\",,0.35,,2500.0,,,18.5,,,,\
+             12.5,2"
+        );
+        assert_eq!(
+            lines[2],
+            "\"corridor \"\"north\"\"\",/input/corridor,,/output/corridor.hdr,,,0.35,,2500.0,,,18.5,\
+             ,,3.0,,2"
+        );
+
+        let json = fs::read_to_string(dir.join("glare_metrics.json")).unwrap();
+        let records: Vec<Map<String, Value>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["scene"], "office, east");
+        assert_eq!(records[0]["capture_time"], "2024-06-21 12:00:00");
+        assert_eq!(records[0]["glare_check_image"], Value::Null);
+        assert_eq!(records[0]["lveil"], 12.5);
+        assert_eq!(records[0]["dgr"], Value::Null);
+        assert_eq!(records[1]["scene"], "corridor \"north\"");
+        assert_eq!(records[1]["dgr"], 3.0);
+        assert_eq!(records[1]["glare_source_count"], 2);
+        for record in &records {
+            assert_eq!(record.len(), columns.len());
+        }
+
+        // serde_json sorts the keys it parses, so the order is checked in the text
+        let first_record = &json[..json.find('}').unwrap()];
+        let positions: Vec<usize> = columns
+            .iter()
+            .map(|column| first_record.find(&format!("\"{}\":", column)).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_nothing_without_metrics() {
+        let dir = std::env::temp_dir().join(format!("glare_table_empty_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let outputs = SceneOutputs::default();
+        let rows = [GlareRow {
+            scene: "lobby",
+            input: "/input/lobby",
+            outputs: &outputs,
+        }];
+        assert_eq!(write_glare_table(&dir, "glare_metrics", &rows), Ok(None));
+        assert!(!dir.join("glare_metrics.csv").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn column_names_are_snake_case() {
+        assert_eq!(column_name("Lveil"), "lveil");
+        assert_eq!(column_name("E_v_dir"), "e_v_dir");
+        assert_eq!(column_name("lum-sources"), "lum_sources");
+    }
+}
@@ -4,7 +4,7 @@ use std::process::Command;
 use std::process::Stdio;
 // use regex::Regex;
 
-use super::ConfigSettings;
+use super::{evalglare::GlareMetrics, run_command, ConfigSettings, PipelineError};
 
 // Header Editing
 // config_settings:
@@ -17,15 +17,18 @@ use super::ConfigSettings;
 //      The fov, in degrees, of the image vertically. Found within the camera settings.
 // horizontal_angle:
 //      The fov, in degrees, of the image horizontally. Found within the camera settings.
+// glare_metrics:
+//      The evalglare results, written as EVALGLARE= (vertical illuminance) and one
+//      GLARE_<metric>= line per metric.
 
 pub fn header_editing(
     config_settings: &ConfigSettings,
     input_file: String,
     output_file: String,
-    vertical_angle: String,
-    horizontal_angle: String,
-    evalglare_value: String,
-) -> Result<String, String> {
+    vertical_angle: f64,
+    horizontal_angle: f64,
+    glare_metrics: &GlareMetrics,
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!("header_editing() was called with parameters:\n\tvertical_angle: {vertical_angle}\n\thorizontal_angle: {horizontal_angle}");
     }
@@ -38,8 +41,8 @@ pub fn header_editing(
         "-a",
         format!("VIEW= -vta -vv {} -vh {}", vertical_angle, horizontal_angle).as_str(),
         "-c",
-        format!("EVALGLARE={}", evalglare_value).as_str(),
     ]);
+    command.args(glare_metrics.header_lines());
 
     // Set up piping of the input and output file
     let file_output_result = File::create(&output_file);
@@ -66,11 +69,12 @@ pub fn header_editing(
     command.stdin(stdio_in);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: header_editing: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command(
+        "header_editing",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
 
     if DEBUG {
         println!("\n'getinfo' command exit status: {:?}\n", status);
@@ -82,6 +86,10 @@ pub fn header_editing(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'getinfo'".into())
+        Err(PipelineError::command_failed(
+            "header_editing",
+            &command,
+            &output,
+        ))
     }
 }
@@ -0,0 +1,86 @@
+/**
+ * Module abstracting the environment the pipeline is running in.
+ *
+ * The pipeline can be launched from the GUI, where progress is sent to the frontend as
+ * Tauri events and sidecars are resolved by the shell plugin, or from the headless
+ * command-line binary, where there is no AppHandle. Stages only talk to the host through
+ * the PipelineHost trait so that they work in both cases.
+ */
+use std::{env, path::PathBuf, process::Command};
+
+use tauri::Emitter;
+use tauri_plugin_shell::ShellExt;
+
+use super::{PipelineProgress, DEFAULT_STAGE_CACHE_MAX_SIZE_BYTES};
+use crate::image_cache::{self, DEFAULT_MAX_SIZE_BYTES};
+
+/**
+ * Operations the pipeline needs from whatever launched it.
+ * Shared between the workers when scenes are processed in parallel.
+ */
+pub trait PipelineHost: Sync {
+    /**
+     * Reports progress of the pipeline run
+     *
+     * @param progress - The current stage and scene, timings and percentage of the run
+     */
+    fn emit_progress(&self, progress: &PipelineProgress) -> Result<(), String>;
+
+    /**
+     * Creates a command for a sidecar binary bundled with the application
+     *
+     * @param name - Name of the sidecar without target triple or extension (e.g. "hdrgen")
+     */
+    fn sidecar_command(&self, name: &str) -> Result<Command, String>;
+
+    /**
+     * Returns the size limit of the image cache holding raw images converted to TIFF, in bytes
+     */
+    fn image_cache_limit(&self) -> u64 {
+        DEFAULT_MAX_SIZE_BYTES
+    }
+
+    /**
+     * Returns the size limit of the stage cache holding the outputs of earlier runs, in bytes
+     */
+    fn stage_cache_limit(&self) -> u64 {
+        DEFAULT_STAGE_CACHE_MAX_SIZE_BYTES
+    }
+}
+
+impl PipelineHost for tauri::AppHandle {
+    fn emit_progress(&self, progress: &PipelineProgress) -> Result<(), String> {
+        self.emit("pipeline-progress", progress)
+            .map_err(|e| format!("Failed to emit progress event: {}", e))
+    }
+
+    fn sidecar_command(&self, name: &str) -> Result<Command, String> {
+        match self.shell().sidecar(name) {
+            Ok(command) => Ok(command.into()),
+            Err(e) => Err(format!("Failed to find bundled '{}' binary: {}", name, e)),
+        }
+    }
+
+    fn image_cache_limit(&self) -> u64 {
+        image_cache::read_settings(self).max_size_bytes
+    }
+
+    fn stage_cache_limit(&self) -> u64 {
+        image_cache::read_settings(self).stage_cache_max_size_bytes
+    }
+}
+
+/**
+ * Returns the path a bundled sidecar binary is installed at, next to the current executable.
+ * This is the same location the shell plugin resolves sidecars from.
+ *
+ * @param name - Name of the sidecar without target triple or extension (e.g. "hdrgen")
+ */
+pub fn bundled_sidecar_path(name: &str) -> Result<PathBuf, String> {
+    let cur_exe =
+        env::current_exe().map_err(|e| format!("Failed to locate current executable: {}", e))?;
+    let exe_dir = cur_exe
+        .parent()
+        .ok_or("Failed to locate directory of current executable.")?;
+    Ok(exe_dir.join(format!("{}{}", name, env::consts::EXE_SUFFIX)))
+}
@@ -0,0 +1,375 @@
+/**
+ * Module reading the EXIF metadata of the input images, and checking brackets with it.
+ *
+ * JPEG, TIFF and the TIFF-based raw formats (CR2, NEF, ARW, DNG, ...) are supported. Raw
+ * images converted to TIFF by dcraw_emu lose their metadata, so it is read from the original
+ * files.
+ *
+ * Before the images of a scene are merged, check_bracket() looks for brackets that would give
+ * a poor HDR image: mixed ISO or aperture (only the exposure time should change), repeated
+ * exposures, and gaps between exposures larger than the allowed EV step.
+ */
+use std::{collections::BTreeSet, fs::File, io::BufReader, path::Path};
+
+use exif::{Exif, In, Reader, Tag, Value};
+use serde::Serialize;
+
+// Exposures closer than this (in EV) are considered repeated
+const REPEATED_EXPOSURE_EV: f64 = 0.1;
+
+/**
+ * EXIF metadata of an image. Values missing from the metadata are None.
+ *
+ * @field path - Path to the image
+ * @field exposure_time - Exposure time, in seconds
+ * @field f_number - Aperture (f-number)
+ * @field iso - ISO sensitivity
+ * @field white_balance - White balance mode, e.g. "auto" or "manual"
+ * @field camera_model - Camera model
+ * @field lens_model - Lens model
+ * @field capture_time - Date and time the image was taken, e.g. "2024:06:21 12:00:00"
+ */
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageMetadata {
+    pub path: String,
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    pub white_balance: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    pub capture_time: Option<String>,
+}
+
+/**
+ * Reads the EXIF metadata of an image
+ *
+ * @param path - Path to the image
+ */
+pub fn read_metadata(path: &Path) -> Result<ImageMetadata, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let exif = Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to read EXIF metadata of {}: {}", path.display(), e))?;
+
+    Ok(ImageMetadata {
+        path: path.display().to_string(),
+        exposure_time: rational_field(&exif, Tag::ExposureTime),
+        f_number: rational_field(&exif, Tag::FNumber),
+        iso: exif
+            .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .filter(|iso| *iso > 0),
+        white_balance: exif
+            .get_field(Tag::WhiteBalance, In::PRIMARY)
+            .map(|field| field.display_value().to_string()),
+        camera_model: ascii_field(&exif, Tag::Model),
+        lens_model: ascii_field(&exif, Tag::LensModel),
+        capture_time: ascii_field(&exif, Tag::DateTimeOriginal),
+    })
+}
+
+/**
+ * Returns the exposure of each image of a bracket, t * ISO / N^2, relative to the ISO and
+ * aperture of the first image. When the whole bracket shares the same ISO and aperture this is
+ * the exposure time in seconds, so radiances keep the scale hdrgen gives them. ISO and aperture
+ * are left out when no image has them, but an image missing a value the others have is an
+ * error, as its exposure can't be compared with theirs.
+ *
+ * @param metadata - EXIF metadata of the images of the bracket
+ */
+pub fn relative_exposures(metadata: &[ImageMetadata]) -> Result<Vec<f64>, String> {
+    let reference = match metadata.first() {
+        Some(reference) => reference,
+        None => return Ok(Vec::new()),
+    };
+
+    metadata
+        .iter()
+        .map(|image| {
+            let exposure_time = image.exposure_time.ok_or(format!(
+                "{} has no valid exposure time in its EXIF metadata.",
+                image.path
+            ))?;
+            let iso_ratio = match (image.iso, reference.iso) {
+                (Some(iso), Some(reference_iso)) => iso as f64 / reference_iso as f64,
+                (None, None) => 1.0,
+                _ => return Err(missing_value(metadata, "ISO", |m| m.iso.is_some())),
+            };
+            let aperture_ratio = match (image.f_number, reference.f_number) {
+                (Some(f_number), Some(reference_f_number)) => {
+                    (reference_f_number / f_number).powi(2)
+                }
+                (None, None) => 1.0,
+                _ => {
+                    return Err(missing_value(metadata, "aperture", |m| {
+                        m.f_number.is_some()
+                    }))
+                }
+            };
+            Ok(exposure_time * iso_ratio * aperture_ratio)
+        })
+        .collect()
+}
+
+// Error for a bracket where only some images have a value in their metadata
+fn missing_value(
+    metadata: &[ImageMetadata],
+    name: &str,
+    has_value: impl Fn(&ImageMetadata) -> bool,
+) -> String {
+    let missing: Vec<&str> = metadata
+        .iter()
+        .filter(|m| !has_value(m))
+        .map(|m| m.path.as_str())
+        .collect();
+    format!(
+        "{} has no {} in its EXIF metadata while other images of the bracket do, so its exposure can't be compared with theirs.",
+        missing.join(", "),
+        name
+    )
+}
+
+/**
+ * Checks that images form a usable exposure bracket: same ISO and aperture, no repeated
+ * exposure, and no gap between consecutive exposures larger than max_ev_step. Values missing
+ * from the metadata are not checked.
+ *
+ * @param images - Paths to the images of the bracket
+ * @param max_ev_step - Largest allowed difference between consecutive exposures, in EV
+ * @returns The problems found, empty if the bracket is fine
+ */
+pub fn check_bracket(images: &[String], max_ev_step: f64) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+    let mut metadata: Vec<ImageMetadata> = Vec::new();
+    for image in images {
+        match read_metadata(Path::new(image)) {
+            Ok(image_metadata) => metadata.push(image_metadata),
+            Err(error) => problems.push(error),
+        }
+    }
+
+    let isos: BTreeSet<u32> = metadata.iter().filter_map(|m| m.iso).collect();
+    if isos.len() > 1 {
+        let isos: Vec<String> = isos.iter().map(|iso| iso.to_string()).collect();
+        problems.push(format!(
+            "The bracket mixes ISO values ({}), only the exposure time should change between images.",
+            isos.join(", ")
+        ));
+    }
+
+    let f_numbers: BTreeSet<String> = metadata
+        .iter()
+        .filter_map(|m| m.f_number)
+        .map(|f_number| format!("f/{:.1}", f_number))
+        .collect();
+    if f_numbers.len() > 1 {
+        problems.push(format!(
+            "The bracket mixes apertures ({}), only the exposure time should change between images.",
+            f_numbers.into_iter().collect::<Vec<String>>().join(", ")
+        ));
+    }
+
+    let mut exposures: Vec<(f64, &str)> = metadata
+        .iter()
+        .filter_map(|m| m.exposure_time.map(|time| (time, m.path.as_str())))
+        .collect();
+    exposures.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for pair in exposures.windows(2) {
+        let ((short_time, short_path), (long_time, long_path)) = (pair[0], pair[1]);
+        let step = (long_time / short_time).log2();
+        if step < REPEATED_EXPOSURE_EV {
+            problems.push(format!(
+                "{} and {} have the same exposure time ({}).",
+                short_path,
+                long_path,
+                format_exposure_time(short_time)
+            ));
+        } else if step > max_ev_step {
+            problems.push(format!(
+                "The bracket has a gap of {:.1} EV between {} ({}) and {} ({}), more than the allowed {} EV.",
+                step,
+                short_path,
+                format_exposure_time(short_time),
+                long_path,
+                format_exposure_time(long_time),
+                max_ev_step
+            ));
+        }
+    }
+
+    problems
+}
+
+// Formats an exposure time the way cameras show it, e.g. "1/250 s" or "2 s"
+fn format_exposure_time(seconds: f64) -> String {
+    if seconds < 1.0 {
+        format!("1/{} s", (1.0 / seconds).round())
+    } else {
+        format!("{} s", seconds)
+    }
+}
+
+// Returns a positive rational field as a number
+fn rational_field(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values
+            .first()
+            .filter(|value| value.num > 0 && value.denom > 0)
+            .map(|value| value.to_f64()),
+        _ => None,
+    }
+}
+
+// Returns a text field, without its padding
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| {
+                String::from_utf8_lossy(value)
+                    .trim_end_matches('\0')
+                    .trim()
+                    .to_string()
+            })
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use image::{DynamicImage, RgbImage};
+
+    use super::*;
+    use crate::pipeline::alignment::write_aligned;
+
+    // Writes a small TIFF with the given EXIF exposure time, aperture and ISO
+    fn write_image(dir: &Path, name: &str, exposure_time: f64, f_number: f64, iso: u32) -> String {
+        let path = dir.join(name);
+        let metadata = ImageMetadata {
+            exposure_time: Some(exposure_time),
+            f_number: Some(f_number),
+            iso: Some(iso),
+            ..Default::default()
+        };
+        write_aligned(
+            &DynamicImage::ImageRgb8(RgbImage::new(2, 2)),
+            Some(&metadata),
+            &path,
+        )
+        .unwrap();
+        path.display().to_string()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_exposure_settings() {
+        let dir = test_dir("image_metadata_read");
+        let image = write_image(&dir, "a.tiff", 1.0 / 250.0, 5.6, 400);
+        let metadata = read_metadata(Path::new(&image)).unwrap();
+        assert_eq!(metadata.exposure_time, Some(0.004));
+        assert_eq!(metadata.f_number, Some(5.6));
+        assert_eq!(metadata.iso, Some(400));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn accepts_a_regular_bracket() {
+        let dir = test_dir("image_metadata_regular");
+        let images: Vec<String> = [0.004, 0.016, 0.0625, 0.25]
+            .iter()
+            .enumerate()
+            .map(|(i, time)| write_image(&dir, &format!("{}.tiff", i), *time, 8.0, 100))
+            .collect();
+        assert_eq!(check_bracket(&images, 2.0), Vec::<String>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_mixed_iso_and_aperture() {
+        let dir = test_dir("image_metadata_mixed");
+        let images = vec![
+            write_image(&dir, "a.tiff", 0.01, 8.0, 100),
+            write_image(&dir, "b.tiff", 0.04, 8.0, 400),
+            write_image(&dir, "c.tiff", 0.16, 5.6, 100),
+        ];
+        let problems = check_bracket(&images, 3.0);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("mixes ISO values (100, 400)"));
+        assert!(problems[1].contains("mixes apertures (f/5.6, f/8.0)"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_repeated_exposure() {
+        let dir = test_dir("image_metadata_repeated");
+        let images = vec![
+            write_image(&dir, "a.tiff", 1.0 / 60.0, 8.0, 100),
+            write_image(&dir, "b.tiff", 1.0 / 15.0, 8.0, 100),
+            write_image(&dir, "c.tiff", 1.0 / 64.0, 8.0, 100),
+        ];
+        let problems = check_bracket(&images, 3.0);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].ends_with("have the same exposure time (1/64 s)."));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_gap_larger_than_the_ev_step() {
+        let dir = test_dir("image_metadata_gap");
+        let images = vec![
+            write_image(&dir, "a.tiff", 1.0 / 1000.0, 8.0, 100),
+            write_image(&dir, "b.tiff", 1.0 / 250.0, 8.0, 100),
+            write_image(&dir, "c.tiff", 1.0 / 8.0, 8.0, 100),
+        ];
+        // 2 EV between the first two, 5 EV between the last two
+        let problems = check_bracket(&images, 3.0);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(
+            problems[0].starts_with("The bracket has a gap of 5.0 EV"),
+            "{}",
+            problems[0]
+        );
+        assert!(problems[0].contains("(1/250 s)") && problems[0].contains("(1/8 s)"));
+        assert_eq!(check_bracket(&images, 5.5), Vec::<String>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_unreadable_images() {
+        let dir = test_dir("image_metadata_unreadable");
+        let missing = dir.join("missing.tiff").display().to_string();
+        let problems = check_bracket(&[missing], 3.0);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("Failed to open"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relative_exposures_reject_partial_metadata() {
+        let metadata = [Some(100), None].map(|iso| ImageMetadata {
+            path: format!("{:?}.jpg", iso),
+            exposure_time: Some(0.01),
+            iso,
+            ..Default::default()
+        });
+        let error = relative_exposures(&metadata).unwrap_err();
+        assert!(error.contains("None.jpg has no ISO"), "{}", error);
+
+        // Without ISO and aperture in any image, the exposure time is used alone
+        let metadata = [0.01, 0.04].map(|exposure_time| ImageMetadata {
+            exposure_time: Some(exposure_time),
+            ..Default::default()
+        });
+        assert_eq!(relative_exposures(&metadata).unwrap(), vec![0.01, 0.04]);
+    }
+}
@@ -0,0 +1,338 @@
+/**
+ * Module computing luminance statistics over the fisheye view of an HDR image.
+ *
+ * The luminance of every pixel inside the circle inscribed in the image (the view of a cropped
+ * pipeline output) is computed as 179·(0.265R + 0.670G + 0.065B), divided by the EXPOSURE of
+ * the header. Pixels outside the circle are left out, so the black corners of the image do not
+ * pull the statistics down.
+ *
+ * Besides the statistics of the pixels, a mean weighted by the solid angle of each pixel is
+ * computed for angular fisheye images (-vta), using the view angles from the VIEW line of the
+ * header (180° if it has none): pixels near the edge of the circle cover less of the scene than
+ * pixels near the center. A VIEW line that can't be read gives a warning instead of this mean.
+ */
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::{
+    merge_exposures::compute_circle_mask,
+    native_glare::{luminance, pixel_direction},
+};
+use crate::radiance_hdr::read_hdr;
+
+/**
+ * Percentiles computed when none are requested
+ */
+pub const DEFAULT_PERCENTILES: [f64; 7] = [1.0, 5.0, 25.0, 50.0, 75.0, 95.0, 99.0];
+
+/**
+ * Histogram bins per decade of luminance when none is requested
+ */
+pub const DEFAULT_BINS_PER_DECADE: usize = 10;
+
+// View angle of images without a VIEW line in their header, in degrees
+const DEFAULT_VIEW_ANGLE: f64 = 180.0;
+
+/**
+ * Luminance of a percentile of the pixels
+ *
+ * @field percentile - The percentile, from 0 to 100
+ * @field luminance - Luminance below which this percentage of the pixels are, in cd/m²
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Percentile {
+    pub percentile: f64,
+    pub luminance: f64,
+}
+
+/**
+ * Bin of the luminance histogram
+ *
+ * @field lower - Lowest luminance of the bin, in cd/m²
+ * @field upper - Luminance above the bin (the lowest of the next one), in cd/m²
+ * @field count - Number of pixels in the bin
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramBin {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+}
+
+/**
+ * Luminance statistics of the pixels inside the fisheye view, in cd/m²
+ *
+ * @field pixel_count - Number of pixels inside the view
+ * @field min - Lowest luminance
+ * @field max - Highest luminance
+ * @field mean - Mean luminance of the pixels
+ * @field median - Median luminance
+ * @field percentiles - Luminance of the requested percentiles
+ * @field solid_angle_weighted_mean - Mean luminance weighted by the solid angle of each pixel
+ *                                    (None if the image is not an angular fisheye)
+ * @field view - VIEW line of the header, if any
+ * @field histogram - Pixel counts in logarithmic bins from the lowest to the highest positive
+ *                    luminance
+ * @field non_positive_count - Number of pixels with no luminance (not in the histogram)
+ * @field warnings - Problems found while computing the statistics, e.g. a VIEW line that can't
+ *                   be read (the weighted mean is then left out)
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LuminanceStatistics {
+    pub pixel_count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub percentiles: Vec<Percentile>,
+    pub solid_angle_weighted_mean: Option<f64>,
+    pub view: Option<String>,
+    pub histogram: Vec<HistogramBin>,
+    pub non_positive_count: usize,
+    pub warnings: Vec<String>,
+}
+
+/**
+ * Computes the luminance statistics of an HDR image over its fisheye view
+ *
+ * @param path - Path of the .hdr image
+ * @param percentiles - Percentiles to compute, from 0 to 100
+ * @param bins_per_decade - Number of histogram bins per decade of luminance
+ */
+pub fn luminance_statistics(
+    path: &Path,
+    percentiles: &[f64],
+    bins_per_decade: usize,
+) -> Result<LuminanceStatistics, String> {
+    if let Some(percentile) = percentiles
+        .iter()
+        .find(|percentile| !(0.0..=100.0).contains(*percentile))
+    {
+        return Err(format!(
+            "Percentiles must be between 0 and 100 (got {}).",
+            percentile
+        ));
+    }
+    if bins_per_decade == 0 {
+        return Err("The histogram needs at least one bin per decade.".into());
+    }
+
+    let image = read_hdr(path)?;
+    let exposure = image.header.exposure();
+    let view = image.header.view();
+    let mut warnings: Vec<String> = Vec::new();
+    let view_angles = fisheye_view_angles(view.as_deref()).unwrap_or_else(|error| {
+        warnings.push(format!(
+            "The solid-angle-weighted mean was not computed: {}",
+            error
+        ));
+        None
+    });
+
+    let radius = image.width.min(image.height) as f32 / 2.0;
+    let mask = compute_circle_mask(
+        image.height,
+        image.width,
+        image.width as f32 / 2.0,
+        image.height as f32 / 2.0,
+        radius,
+    );
+
+    let mut values: Vec<f64> = Vec::new();
+    let (mut weighted_sum, mut solid_angle) = (0.0, 0.0);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            if !mask[y * image.width + x] {
+                continue;
+            }
+            let value = luminance(image.get_pixel(x, y), exposure);
+            if let Some((vertical_angle, horizontal_angle)) = view_angles {
+                let (_, pixel_solid_angle) =
+                    pixel_direction(&image, x, y, vertical_angle, horizontal_angle);
+                weighted_sum += value * pixel_solid_angle;
+                solid_angle += pixel_solid_angle;
+            }
+            values.push(value);
+        }
+    }
+    if values.is_empty() {
+        return Err(format!("{} has no pixel inside the view.", path.display()));
+    }
+    values.sort_by(f64::total_cmp);
+
+    let (histogram, non_positive_count) = histogram(&values, bins_per_decade);
+    Ok(LuminanceStatistics {
+        pixel_count: values.len(),
+        min: values[0],
+        max: values[values.len() - 1],
+        mean: values.iter().sum::<f64>() / values.len() as f64,
+        median: percentile(&values, 50.0),
+        percentiles: percentiles
+            .iter()
+            .map(|&p| Percentile {
+                percentile: p,
+                luminance: percentile(&values, p),
+            })
+            .collect(),
+        solid_angle_weighted_mean: (solid_angle > 0.0).then(|| weighted_sum / solid_angle),
+        view,
+        histogram,
+        non_positive_count,
+        warnings,
+    })
+}
+
+// Vertical and horizontal view angles of an angular fisheye image from its VIEW line, None for
+// other view types. Fails when a view angle is missing or not a number.
+fn fisheye_view_angles(view: Option<&str>) -> Result<Option<(f64, f64)>, String> {
+    let Some(view) = view else {
+        return Ok(Some((DEFAULT_VIEW_ANGLE, DEFAULT_VIEW_ANGLE)));
+    };
+
+    // Later options override earlier ones, as in Radiance
+    let (mut view_type, mut vertical_angle, mut horizontal_angle) =
+        (None, DEFAULT_VIEW_ANGLE, DEFAULT_VIEW_ANGLE);
+    let mut tokens = view.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "-vv" => vertical_angle = view_angle(token, tokens.next(), view)?,
+            "-vh" => horizontal_angle = view_angle(token, tokens.next(), view)?,
+            token if token.starts_with("-vt") => view_type = Some(token.to_string()),
+            _ => {}
+        }
+    }
+    Ok(match view_type.as_deref() {
+        None | Some("-vta") => Some((vertical_angle, horizontal_angle)),
+        Some(_) => None,
+    })
+}
+
+// Parses the value of a view angle option of a VIEW line, in degrees
+fn view_angle(option: &str, value: Option<&str>, view: &str) -> Result<f64, String> {
+    value
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|angle| angle.is_finite() && *angle > 0.0)
+        .ok_or(format!(
+            "the {} angle of the VIEW line '{}' is not a positive number.",
+            option,
+            view.trim()
+        ))
+}
+
+// Linearly interpolated percentile of sorted values
+fn percentile(sorted: &[f64], percentile: f64) -> f64 {
+    let rank = percentile / 100.0 * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+// Counts sorted values in logarithmic bins aligned on decades, from the lowest to the highest
+// positive value. Returns the bins and the number of values that are not positive.
+fn histogram(sorted: &[f64], bins_per_decade: usize) -> (Vec<HistogramBin>, usize) {
+    let non_positive_count = sorted.iter().take_while(|value| **value <= 0.0).count();
+    let positive = &sorted[non_positive_count..];
+    let (Some(first), Some(last)) = (positive.first(), positive.last()) else {
+        return (Vec::new(), non_positive_count);
+    };
+
+    let bins_per_decade = bins_per_decade as f64;
+    let bin_of = |value: f64| (value.log10() * bins_per_decade).floor() as i64;
+    let (first_bin, last_bin) = (bin_of(*first), bin_of(*last));
+    let mut bins: Vec<HistogramBin> = (first_bin..=last_bin)
+        .map(|bin| HistogramBin {
+            lower: 10f64.powf(bin as f64 / bins_per_decade),
+            upper: 10f64.powf((bin + 1) as f64 / bins_per_decade),
+            count: 0,
+        })
+        .collect();
+    for value in positive {
+        let bin = (bin_of(*value) - first_bin).clamp(0, last_bin - first_bin) as usize;
+        bins[bin].count += 1;
+    }
+    (bins, non_positive_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::radiance_hdr::{rgb_to_rgbe, rgbe_to_rgb, write_hdr, HdrImage};
+
+    // Writes a picture of uniform RGB to a temporary file, with the given VIEW line if any
+    fn uniform_picture(
+        name: &str,
+        size: usize,
+        rgb: f32,
+        view: Option<&str>,
+    ) -> std::path::PathBuf {
+        let mut image = HdrImage::new(size, size);
+        image.pixels = vec![[rgb; 3]; size * size];
+        image.header.push("EXPOSURE", "0.5");
+        if let Some(view) = view {
+            image.header.push("VIEW", view);
+        }
+        let path = std::env::temp_dir().join(format!("{}_{}.hdr", name, std::process::id()));
+        write_hdr(&path, &image).unwrap();
+        path
+    }
+
+    #[test]
+    fn uniform_fisheye_statistics_equal_the_luminance() {
+        let path = uniform_picture("uniform_fisheye", 64, 2.0, Some("-vta -vv 180 -vh 180"));
+        let statistics = luminance_statistics(&path, &DEFAULT_PERCENTILES, 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // 179 * 2 / EXPOSURE 0.5, with 2 as RGBE stores it (at the middle of its mantissa step)
+        let stored = rgbe_to_rgb(rgb_to_rgbe([2.0; 3]))[0] as f64;
+        let expected = 179.0 * stored / 0.5;
+        let weighted_mean = statistics.solid_angle_weighted_mean.unwrap();
+        let values = [
+            statistics.min,
+            statistics.max,
+            statistics.mean,
+            statistics.median,
+            weighted_mean,
+        ]
+        .into_iter()
+        .chain(statistics.percentiles.iter().map(|p| p.luminance));
+        for value in values {
+            assert!((value - expected).abs() < 1e-6 * expected, "{}", value);
+        }
+        assert_eq!(statistics.histogram.len(), 1);
+        assert_eq!(statistics.histogram[0].count, statistics.pixel_count);
+        assert!(statistics.warnings.is_empty());
+    }
+
+    #[test]
+    fn mask_uses_pixel_centers() {
+        // Centers within 2 pixels of the middle of a 4x4 image: all but the 4 corners
+        let path = uniform_picture("pixel_centers", 4, 1.0, None);
+        let statistics = luminance_statistics(&path, &[], 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(statistics.pixel_count, 12);
+    }
+
+    #[test]
+    fn unreadable_view_gives_a_warning() {
+        let path = uniform_picture("bad_view", 16, 1.0, Some("-vta -vv wide -vh 180"));
+        let statistics = luminance_statistics(&path, &[], 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(statistics.solid_angle_weighted_mean, None);
+        assert_eq!(statistics.warnings.len(), 1);
+        assert!(
+            statistics.warnings[0].contains("-vv"),
+            "{}",
+            statistics.warnings[0]
+        );
+
+        // Other view types have no weighted mean, but nothing is wrong with them
+        let path = uniform_picture("perspective_view", 16, 1.0, Some("-vtv -vv 45 -vh 45"));
+        let statistics = luminance_statistics(&path, &[], 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(statistics.solid_angle_weighted_mean, None);
+        assert!(statistics.warnings.is_empty());
+    }
+}
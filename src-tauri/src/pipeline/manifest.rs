@@ -0,0 +1,428 @@
+/**
+ * Module for the manifest of a batch run.
+ *
+ * After every batch run, a manifest listing each input directory (or bracket group) with its
+ * status, output HDR and falsecolor paths, glare metrics and error message is written to the
+ * output directory, as batch_<date>.json and batch_<date>.csv. The JSON manifest also records
+ * the parameters of the run, so that the entries that failed can be run again with the
+ * rerun_failed command.
+ */
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{GlareMetrics, PipelineError, PipelineParams};
+
+/**
+ * Outcome of one input directory of a batch
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SceneStatus {
+    // The final images were written to the output directory
+    Succeeded,
+    // A stage failed, see the error of the entry
+    Failed,
+    // The directory was not processed because the run stopped (cancelled or another scene failed)
+    NotRun,
+}
+
+impl SceneStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SceneStatus::Succeeded => "succeeded",
+            SceneStatus::Failed => "failed",
+            SceneStatus::NotRun => "notRun",
+        }
+    }
+}
+
+/**
+ * Final images and results of a scene, as copied to the output directory
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SceneOutputs {
+    pub hdr_image: PathBuf,
+    pub falsecolor_image: Option<PathBuf>,
+    pub glare_check_image: Option<PathBuf>,
+    pub glare_metrics: Option<GlareMetrics>,
+    pub capture_time: Option<String>, // earliest capture time of the scene's images
+}
+
+/**
+ * Entry of the manifest for one input directory
+ *
+ * @field input_dir - The input directory, or the name of the bracket group
+ * @field status - Whether the directory was processed successfully
+ * @field hdr_image - The final HDR image in the output directory
+ * @field falsecolor_image - The falsecolor luminance map in the output directory, if one was made
+ * @field glare_check_image - The evalglare check image in the output directory, if one was made
+ * @field glare - Glare metrics computed by evalglare, if the evalglare stage ran
+ * @field error - Why the directory failed
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub input_dir: String,
+    pub status: SceneStatus,
+    pub hdr_image: Option<PathBuf>,
+    pub falsecolor_image: Option<PathBuf>,
+    #[serde(default)]
+    pub glare_check_image: Option<PathBuf>,
+    #[serde(default)]
+    pub glare: Option<GlareMetrics>,
+    pub error: Option<String>,
+}
+
+impl ManifestEntry {
+    /**
+     * Creates the entry of a directory from the result of processing it
+     *
+     * @param input_dir - The input directory, or the name of the bracket group
+     * @param result - Outputs or error of the directory, None if it was not processed
+     */
+    pub fn new(
+        input_dir: &str,
+        result: Option<&Result<SceneOutputs, PipelineError>>,
+    ) -> ManifestEntry {
+        let mut entry = ManifestEntry {
+            input_dir: input_dir.to_string(),
+            status: SceneStatus::NotRun,
+            hdr_image: None,
+            falsecolor_image: None,
+            glare_check_image: None,
+            glare: None,
+            error: None,
+        };
+        match result {
+            Some(Ok(outputs)) => {
+                entry.status = SceneStatus::Succeeded;
+                entry.hdr_image = Some(outputs.hdr_image.clone());
+                entry.falsecolor_image = outputs.falsecolor_image.clone();
+                entry.glare_check_image = outputs.glare_check_image.clone();
+                entry.glare = outputs.glare_metrics.clone();
+            }
+            Some(Err(PipelineError::Cancelled)) => {}
+            Some(Err(error)) => {
+                // The entry already names the directory, so keep only the reason it failed
+                let error = match error {
+                    PipelineError::SceneFailed { error, .. } => error.as_ref(),
+                    error => error,
+                };
+                entry.status = SceneStatus::Failed;
+                entry.error = Some(error.to_string());
+            }
+            None => {}
+        }
+        entry
+    }
+}
+
+/**
+ * Manifest of a batch run
+ *
+ * @field created - Local date and time the manifest was written
+ * @field params - Parameters of the run, used to run failed entries again
+ * @field entries - One entry per input directory, in input order
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchManifest {
+    pub created: String,
+    pub params: PipelineParams,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl BatchManifest {
+    /**
+     * Number of entries that did not succeed (failed or not run)
+     */
+    pub fn unfinished_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.status != SceneStatus::Succeeded)
+            .count()
+    }
+
+    /**
+     * Returns the parameters to run the entries that failed or were not run again
+     */
+    pub fn rerun_params(&self) -> Result<PipelineParams, String> {
+        let unfinished: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.status != SceneStatus::Succeeded)
+            .map(|entry| entry.input_dir.clone())
+            .collect();
+        if unfinished.is_empty() {
+            return Err("Every entry of the batch manifest succeeded, nothing to re-run.".into());
+        }
+
+        let mut params = self.params.clone();
+        if params.recursive {
+            // The scenes are discovered again from the input directories, so that their outputs
+            // are written to the same place in the mirrored output tree
+            params.scene_filter = unfinished;
+        } else if params.bracket_groups.is_empty() {
+            params.input_images = unfinished;
+        } else {
+            // Entries of bracket groups are named after the group
+            params
+                .bracket_groups
+                .retain(|group| unfinished.contains(&group.name));
+        }
+        Ok(params)
+    }
+
+    /**
+     * Writes the manifest as <name>.json and <name>.csv in a directory. Returns the path of the
+     * JSON manifest.
+     *
+     * @param dir - Directory to write the manifest to (the output directory of the run)
+     * @param name - File name of the manifest without extension
+     */
+    pub fn write(&self, dir: &Path, name: &str) -> Result<PathBuf, String> {
+        let json_path = dir.join(format!("{}.json", name));
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing batch manifest: {}", e))?;
+        fs::write(&json_path, json)
+            .map_err(|e| format!("Error writing {}: {}", json_path.display(), e))?;
+
+        let csv_path = dir.join(format!("{}.csv", name));
+        fs::write(&csv_path, self.to_csv())
+            .map_err(|e| format!("Error writing {}: {}", csv_path.display(), e))?;
+
+        Ok(json_path)
+    }
+
+    /**
+     * Reads a manifest written by write()
+     *
+     * @param path - Path to the JSON manifest
+     */
+    pub fn read(path: &Path) -> Result<BatchManifest, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Error reading batch manifest {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid batch manifest {}: {}", path.display(), e))
+    }
+
+    // One row per entry, with a header row
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("input_dir,status,hdr_image,falsecolor_image,evalglare,error\n");
+        for entry in &self.entries {
+            let path_field = |path: &Option<PathBuf>| {
+                path.as_ref()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            };
+            let fields = [
+                entry.input_dir.clone(),
+                entry.status.as_str().to_string(),
+                path_field(&entry.hdr_image),
+                path_field(&entry.falsecolor_image),
+                entry
+                    .glare
+                    .as_ref()
+                    .map(|glare| glare.vertical_illuminance.to_string())
+                    .unwrap_or_default(),
+                entry.error.clone().unwrap_or_default(),
+            ];
+            let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+// Quotes a CSV field if it contains a separator, quote or line break
+pub(super) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::BracketGroup;
+
+    // Parameters of a batch over the given input directories
+    fn params(input_images: &[&str]) -> PipelineParams {
+        serde_json::from_value(serde_json::json!({
+            "outputPath": "/output",
+            "inputImages": input_images,
+            "diameter": 100,
+            "xleft": 0,
+            "ydown": 0,
+            "xdim": 100,
+            "ydim": 100,
+            "verticalAngle": 180.0,
+            "horizontalAngle": 180.0,
+        }))
+        .unwrap()
+    }
+
+    fn manifest(
+        params: PipelineParams,
+        results: &[(&str, Option<Result<(), &str>>)],
+    ) -> BatchManifest {
+        let entries = results
+            .iter()
+            .map(|(input, result)| {
+                let result = result.map(|result| {
+                    result
+                        .map(|_| SceneOutputs {
+                            hdr_image: PathBuf::from(format!("/output/{}.hdr", input)),
+                            ..Default::default()
+                        })
+                        .map_err(|error| PipelineError::from(error).in_scene(input, 0))
+                });
+                ManifestEntry::new(input, result.as_ref())
+            })
+            .collect();
+        BatchManifest {
+            created: "2024-06-21 12:00:00".into(),
+            params,
+            entries,
+        }
+    }
+
+    #[test]
+    fn entries_record_the_outcome_of_each_scene() {
+        let outputs = SceneOutputs {
+            hdr_image: PathBuf::from("/output/a.hdr"),
+            falsecolor_image: Some(PathBuf::from("/output/a_fc.hdr")),
+            glare_metrics: Some(GlareMetrics {
+                vertical_illuminance: 1234.5,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let entry = ManifestEntry::new("/input/a", Some(&Ok(outputs)));
+        assert_eq!(entry.status, SceneStatus::Succeeded);
+        assert_eq!(entry.hdr_image, Some(PathBuf::from("/output/a.hdr")));
+        assert_eq!(entry.glare.unwrap().vertical_illuminance, 1234.5);
+
+        // The scene name is dropped from the error, as the entry already names the directory
+        let error = PipelineError::from("evalglare failed").in_scene("a", 0);
+        let entry = ManifestEntry::new("/input/a", Some(&Err(error)));
+        assert_eq!(entry.status, SceneStatus::Failed);
+        assert_eq!(entry.error.as_deref(), Some("evalglare failed"));
+
+        let entry = ManifestEntry::new("/input/a", Some(&Err(PipelineError::Cancelled)));
+        assert_eq!(entry.status, SceneStatus::NotRun);
+        assert_eq!(entry.error, None);
+        assert_eq!(
+            ManifestEntry::new("/input/a", None).status,
+            SceneStatus::NotRun
+        );
+    }
+
+    #[test]
+    fn rerun_keeps_the_unfinished_input_directories() {
+        let manifest = manifest(
+            params(&["/input/a", "/input/b", "/input/c"]),
+            &[
+                ("/input/a", Some(Ok(()))),
+                ("/input/b", Some(Err("failed"))),
+                ("/input/c", None),
+            ],
+        );
+        assert_eq!(manifest.unfinished_count(), 2);
+        let rerun = manifest.rerun_params().unwrap();
+        assert_eq!(rerun.input_images, vec!["/input/b", "/input/c"]);
+        assert!(rerun.scene_filter.is_empty());
+    }
+
+    #[test]
+    fn rerun_of_a_recursive_batch_filters_the_scenes() {
+        let mut params = params(&["/survey"]);
+        params.recursive = true;
+        let manifest = manifest(
+            params,
+            &[
+                ("/survey/site1/position1", Some(Ok(()))),
+                ("/survey/site2/position1", Some(Err("failed"))),
+            ],
+        );
+        let rerun = manifest.rerun_params().unwrap();
+        // The input directories stay, so that the scenes are found again below them
+        assert_eq!(rerun.input_images, vec!["/survey"]);
+        assert_eq!(rerun.scene_filter, vec!["/survey/site2/position1"]);
+    }
+
+    #[test]
+    fn rerun_of_bracket_groups_keeps_the_unfinished_groups() {
+        let mut params = params(&[]);
+        params.bracket_groups = ["DCIM_001", "DCIM_002", "DCIM_003"]
+            .into_iter()
+            .map(|name| BracketGroup {
+                name: name.into(),
+                images: vec![format!("/DCIM/{}.jpg", name)],
+            })
+            .collect();
+        let manifest = manifest(
+            params,
+            &[
+                ("DCIM_001", Some(Err("failed"))),
+                ("DCIM_002", Some(Ok(()))),
+                ("DCIM_003", None),
+            ],
+        );
+        let rerun = manifest.rerun_params().unwrap();
+        let names: Vec<&str> = rerun
+            .bracket_groups
+            .iter()
+            .map(|group| group.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["DCIM_001", "DCIM_003"]);
+    }
+
+    #[test]
+    fn rerun_of_a_finished_batch_is_an_error() {
+        let manifest = manifest(params(&["/input/a"]), &[("/input/a", Some(Ok(())))]);
+        assert!(manifest.rerun_params().is_err());
+    }
+
+    #[test]
+    fn csv_quotes_fields_with_separators() {
+        let manifest = manifest(
+            params(&["/input/a,b", "/input/c"]),
+            &[
+                ("/input/a,b", Some(Ok(()))),
+                ("/input/c", Some(Err("evalglare said \"no\"\non two lines"))),
+            ],
+        );
+        assert_eq!(
+            manifest.to_csv(),
+            "input_dir,status,hdr_image,falsecolor_image,evalglare,error\n\
+             \"/input/a,b\",succeeded,\"/output//input/a,b.hdr\",,,\n\
+             /input/c,failed,,,,\"evalglare said \"\"no\"\"\non two lines\"\n"
+        );
+
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a\rb"), "\"a\rb\"");
+    }
+
+    #[test]
+    fn write_and_read_round_trip() {
+        let dir = std::env::temp_dir().join(format!("manifest_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = manifest(params(&["/input/a"]), &[("/input/a", Some(Err("failed")))]);
+        let path = manifest.write(&dir, "batch").unwrap();
+        assert!(dir.join("batch.csv").is_file());
+
+        let read = BatchManifest::read(&path).unwrap();
+        assert_eq!(read.entries[0].status, SceneStatus::Failed);
+        assert_eq!(read.entries[0].error.as_deref(), Some("failed"));
+        assert_eq!(read.params.input_images, vec!["/input/a"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
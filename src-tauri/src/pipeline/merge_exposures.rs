@@ -3,38 +3,56 @@ use anyhow::{Context, Result};
 use image::{GenericImageView, Pixel};
 use rayon::prelude::*;
 use std::env;
-use std::{
-    path::Path,
-    process::{Command, ExitStatus},
+use std::{fs, path::Path, process::Command, thread};
+
+use super::{
+    alignment::{align_exposures, FrameOffset},
+    image_metadata::{read_metadata, relative_exposures},
+    native_merge::merge_exposures_native,
+    response_function::ResponseFunction,
+    run_command, ConfigSettings, MergeBackend, PipelineError, PipelineHost,
 };
+use crate::image_cache::copy_tiff_for_raw;
 
-use super::ConfigSettings;
-use tauri_plugin_shell::ShellExt;
+// Largest number of raw images converted to TIFF at the same time
+const MAX_RAW_CONVERSIONS: usize = 4;
 
-// Merges multiple LDR images into an HDR image using hdrgen. If images are in JPG or TIFF format,
-// runs hdrgen command regularly. If images are not in JPG or TIFF format, converts the inputs
-// to TIFF raw images first using dcraw_emu, then runs hdrgen.
+// Merges multiple LDR images into an HDR image using hdrgen, or the native merge. If images are
+// in JPG or TIFF format, merges them directly. If images are not in JPG or TIFF format, converts
+// the inputs to TIFF raw images first using dcraw_emu (through the image cache).
 //
 // input_images:
 //    vector of the paths to the input images. Input images must be in .JPG or .CR2 format.
 // response_function:
-//    string for the path to the camera response function, must be a .rsp file
+//    optional path to the camera response function, must be a .rsp file
 // output_path:
 //    a string for the path and filename where the resulting HDR image will be saved.
-#[tauri::command]
+// diameter, xleft, ydown:
+//    the fisheye view, used to filter images
+// xdim, ydim:
+//    the target resolution of the HDR image
+// merge_backend:
+//    whether the images are merged by hdrgen or natively (needs the response function)
+// align:
+//    whether the images are aligned (median threshold bitmaps) before they are merged. The
+//    aligned images are written to <temp>/aligned, and the offsets to <temp>/alignment.json.
+// Returns the path of the HDR image, and the offsets found by the alignment (empty if the
+// images were not aligned).
 pub fn merge_exposures(
-    app: &tauri::AppHandle,
+    host: &dyn PipelineHost,
     config_settings: &ConfigSettings,
     mut input_images: Vec<String>,
-    response_function: String,
+    response_function: Option<&Path>,
     output_path: String,
-    diameter: String,
-    xleft: String,
-    ydown: String,
-    xdim: String,
-    ydim: String,
+    diameter: u32,
+    xleft: i64,
+    ydown: i64,
+    xdim: u32,
+    ydim: u32,
     filter_images_flag: bool,
-) -> Result<String, String> {
+    merge_backend: MergeBackend,
+    align: bool,
+) -> Result<(String, Vec<FrameOffset>), PipelineError> {
     if DEBUG {
         println!("merge_exposures Tauri command was called!");
     }
@@ -53,156 +71,139 @@ pub fn merge_exposures(
         );
     }
 
-    let mut command: Command;
+    // Images the exposure times are read from, as the converted TIFFs have no EXIF metadata
+    let mut exposure_sources = input_images.clone();
 
     // If raw image format other than TIFF, need to first convert them to TIFF to be used by hdrgen
     if convert_to_tiff {
-        // Get working directory of libraw.dll (only needed for windows)
-        let cur_exe = env::current_exe().unwrap().parent().unwrap().to_path_buf();
-
-        let dcraw_emu_build_working_directory = if cfg!(target_os = "macos") {
-            if cfg!(debug_assertions) {
-                // macOS dev mode
-                cur_exe.join("binaries")
-            } else {
-                // macOS release mode (inside .app bundle)
-                cur_exe.join("../Resources/binaries")
-            }
-        } else {
-            // Linux and Windows
-            cur_exe.join("binaries")
-        };
-
-        let mut index = 1;
-        for input_image in &input_images {
-            // Add command arguments
-            let output_arg = config_settings
-                .temp_path
-                .join(format!("input{}.tiff", index))
-                .display()
-                .to_string();
-            let input_arg = format!("{}", input_image);
-            let args = [
-                "-T",
-                "-o",
-                "1",
-                "-W",
-                "-j",
-                "-q",
-                "3",
-                "-g",
-                "2",
-                "0",
-                "-t",
-                "0",
-                "-b",
-                "1.1",
-                "-Z",
-                &output_arg,
-                &input_arg,
-            ];
-
-            if config_settings.dcraw_emu_path.as_os_str().is_empty() {
-                command = app.shell().sidecar("dcraw_emu").unwrap().into();
-
-                command.current_dir(&dcraw_emu_build_working_directory); // Set the working directory to find libraw.dll
-
-                if DEBUG {
-                    let sidecar_path = command.get_program();
-                    println!("Executing bundled sidecar at: {:?}\n", sidecar_path);
-                }
-            } else {
-                if DEBUG {
-                    println!(
-                        "Overwriting bundled sidecar, running command at: {:?}\n",
-                        config_settings.dcraw_emu_path.join("dcraw_emu")
-                    );
-                }
-                command = Command::new(config_settings.dcraw_emu_path.join("dcraw_emu"));
-            }
-
-            command.args(args);
-            let status: Result<ExitStatus, std::io::Error> = command.status();
-
-            if !status.is_ok() || !status.unwrap_or(ExitStatus::default()).success() {
-                return Err("Error, non-zero exit status. dcraw_emu command (converting to tiff images) failed.".into());
-            }
-
-            index += 1;
-        }
-
-        // Update input images vector to contain the newly converted tiff images instead
-        let mut new_inputs = Vec::new();
-        for i in 1..input_images.len() + 1 {
-            new_inputs.push(
-                config_settings
-                    .temp_path
-                    .join(format!("input{}.tiff", i))
-                    .display()
-                    .to_string(),
-            );
-        }
+        // Convert through the shared image cache, so that images already converted by the image
+        // viewer or an earlier run are reused. Conversions run in parallel on a bounded pool,
+        // as every dcraw_emu process holds a full-resolution image in memory.
+        let dcraw_dir = Some(config_settings.dcraw_emu_path.as_path())
+            .filter(|path| !path.as_os_str().is_empty());
+        let workers = thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(MAX_RAW_CONVERSIONS);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(workers)
+            .build()
+            .map_err(|e| {
+                format!(
+                    "pipeline: merge_exposures: failed to create worker threads: {}",
+                    e
+                )
+            })?;
 
-        input_images = new_inputs;
+        // Update input images vector to contain the converted tiff images instead. They are
+        // copied (hard linked when possible) out of the cache into the scene's temporary
+        // directory, as a scene processed in parallel may evict them while they are merged.
+        let converted_dir = config_settings.temp_path.join("converted");
+        input_images = pool.install(|| {
+            input_images
+                .par_iter()
+                .map(|input_image| {
+                    copy_tiff_for_raw(
+                        host,
+                        dcraw_dir,
+                        Path::new(input_image),
+                        &converted_dir,
+                        &config_settings.cancel_token,
+                    )
+                    .map(|tiff| tiff.display().to_string())
+                })
+                .collect::<Result<Vec<String>, PipelineError>>()
+        })?;
     } else if filter_images_flag {
         // images might include jpeg, so try to filter them if allowed
-        // convert float strings to f32
-        let diameter_f32 = diameter.parse::<f32>().map_err(|error| {
-            format!(
-                "pipeline: merge_exposures: failed to parse diameter as float - {}",
-                error
-            )
-        })?;
-        let xleft_f32 = xleft.parse::<f32>().map_err(|error| {
-            format!(
-                "pipeline: merge_exposures: failed to parse xleft as float - {}",
-                error
-            )
-        })?;
-        let ydown_f32 = ydown.parse::<f32>().map_err(|error| {
-            format!(
-                "pipeline: merge_exposures: failed to parse ydown as float - {}",
-                error
-            )
-        })?;
-        let xdim_f32 = xdim.parse::<f32>().map_err(|error| {
-            format!(
-                "pipeline: merge_exposures: failed to parse xdim as float - {}",
-                error
-            )
-        })?;
-        let ydim_f32 = ydim.parse::<f32>().map_err(|error| {
-            format!(
-                "pipeline: merge_exposures: failed to parse ydim as float - {}",
-                error
-            )
-        })?;
-
         // try and filter images, updating input images if successful
         if is_jpeg(&input_images[0]) {
             let filtered_images = match filter_images(
                 input_images,
-                diameter_f32,
-                xleft_f32,
-                ydown_f32,
-                xdim_f32,
-                ydim_f32,
+                diameter as f32,
+                xleft as f32,
+                ydown as f32,
+                xdim as f32,
+                ydim as f32,
             ) {
                 Ok(image_vec) => image_vec,
                 Err(error) => {
                     return Err(format!(
                         "pipeline: merge_exposures: failed to filter images - {}",
                         error
-                    ))
+                    )
+                    .into())
                 }
             };
             input_images = filtered_images;
+            exposure_sources = input_images.clone();
         }
     }
 
+    let mut frame_offsets: Vec<FrameOffset> = Vec::new();
+    if align {
+        let offsets = align_exposures(
+            &input_images,
+            &exposure_sources,
+            diameter,
+            xleft,
+            ydown,
+            &config_settings.temp_path.join("aligned"),
+        )
+        .map_err(|e| format!("pipeline: merge_exposures: failed to align images - {}", e))?;
+
+        // Report the offsets found next to the other outputs of the image set
+        let report_path = config_settings.temp_path.join("alignment.json");
+        let report = serde_json::to_string_pretty(&offsets)
+            .map_err(|e| format!("pipeline: merge_exposures: {}", e))?;
+        fs::write(&report_path, report).map_err(|e| {
+            format!(
+                "pipeline: merge_exposures: failed to write {}: {}",
+                report_path.display(),
+                e
+            )
+        })?;
+        if DEBUG {
+            for offset in &offsets {
+                println!(
+                    "Aligned {} by ({}, {}) pixels",
+                    offset.image, offset.x_offset, offset.y_offset
+                );
+            }
+        }
+
+        input_images = offsets
+            .iter()
+            .map(|offset| offset.aligned_image.clone())
+            .collect();
+        frame_offsets = offsets;
+    }
+
+    if merge_backend == MergeBackend::Native {
+        let response_function = ResponseFunction::read(response_function.ok_or(
+            "pipeline: merge_exposures: the native merge requires a camera response function.",
+        )?)?;
+        let metadata = exposure_sources
+            .iter()
+            .map(|image| read_metadata(Path::new(image)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let exposures = relative_exposures(&metadata)
+            .map_err(|e| format!("pipeline: merge_exposures: {}", e))?;
+
+        merge_exposures_native(
+            &input_images,
+            &exposures,
+            &response_function,
+            Path::new(&output_path),
+        )
+        .map_err(|e| format!("pipeline: merge_exposures: {}", e))?;
+        return Ok((output_path, frame_offsets));
+    }
+
+    let mut command: Command;
+
     // Create a new command for hdrgen
     if config_settings.hdrgen_path.as_os_str().is_empty() {
-        command = app.shell().sidecar("hdrgen").unwrap().into();
+        command = host.sidecar_command("hdrgen")?;
 
         // Get working directory for libraries (same approach as dcraw_emu)
         let cur_exe = env::current_exe().unwrap().parent().unwrap().to_path_buf();
@@ -248,12 +249,12 @@ pub fn merge_exposures(
     command.arg(format!("{}", output_path));
 
     // Add camera response function if user provided one
-    if response_function != "" {
+    if let Some(response_function) = response_function {
         command.arg("-r");
-        command.arg(format!("{}", response_function));
+        command.arg(response_function);
     }
 
-    // Add remaining flags for hdrgen step
+    // Add remaining flags for hdrgen step (-a turns off hdrgen's alignment, see the align option)
     command.args(["-a", "-e", "-f", "-g", "-F"]);
 
     if DEBUG {
@@ -281,11 +282,12 @@ pub fn merge_exposures(
     // STOP
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: merge_exposures: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command(
+        "merge_exposures",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
 
     if DEBUG {
         println!("\nCommand exit status: {:?}\n", status);
@@ -294,10 +296,14 @@ pub fn merge_exposures(
     // Return a Result object to indicate whether hdrgen command was successful
     if !status.success() {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'hdrgen' failed.".into())
+        Err(PipelineError::command_failed(
+            "merge_exposures",
+            &command,
+            &output,
+        ))
     } else {
         // On success, return output path of HDR image
-        Ok(output_path.into())
+        Ok((output_path, frame_offsets))
     }
 }
 
@@ -306,6 +312,7 @@ pub fn merge_exposures(
 // Pixels with luminance values either below 27 or above 228 are counted respectively
 // Once all images have had their pixel counts resolved, the input array is filtered by starting at the first brighter image that doesn't have \
 // any pixel below 27; and ending at the first darker image that doesn't have any pixel above 228
+// The fisheye view is given by diameter, xleft and ydown, with ydown measured from the bottom of the image (see PipelineParams)
 fn filter_images(
     input_images: Vec<String>,
     diameter: f32,
@@ -317,9 +324,6 @@ fn filter_images(
     if DEBUG {
         println!("filter_images was called...");
     }
-    let radius = diameter / 2.0;
-    let xcenter = xleft + radius;
-    let ycenter = ydown + radius;
     let mut filtered_images = Vec::new();
 
     // If the first image isn't a jpeg, don't bother trying to filter
@@ -335,7 +339,7 @@ fn filter_images(
         )
     })?;
     let (width, height) = image.dimensions();
-    let mask = compute_circle_mask(height as usize, width as usize, xcenter, ycenter, radius);
+    let mask = fisheye_mask(width, height, diameter, xleft, ydown);
 
     // Iterate through every image in parallel and count how many pixels of each is either below 27 or above 228 luminance
     let pixel_counts: Result<Vec<(usize, u32, u32, f32)>, anyhow::Error> = input_images
@@ -443,8 +447,29 @@ fn filter_images(
     Ok(filtered_images)
 }
 
-// Returns an index mask for the pixels that fall inside the fisheye view
-fn compute_circle_mask(
+// Returns an index mask for the pixels inside the fisheye view given by the diameter and the
+// bottom-left offsets of its circumscribed square. ydown is measured from the bottom of the
+// image, like the pcompos offsets (see PipelineParams), while mask rows go from the top.
+pub(super) fn fisheye_mask(
+    width: u32,
+    height: u32,
+    diameter: f32,
+    xleft: f32,
+    ydown: f32,
+) -> Vec<bool> {
+    let radius = diameter / 2.0;
+    compute_circle_mask(
+        height as usize,
+        width as usize,
+        xleft + radius,
+        height as f32 - (ydown + radius),
+        radius,
+    )
+}
+
+// Returns an index mask for the pixels whose center falls inside the fisheye view. Coordinates
+// are continuous, pixel i spanning [i, i + 1), as in the circle detection.
+pub(super) fn compute_circle_mask(
     height: usize,
     width: usize,
     xcenter: f32,
@@ -456,8 +481,8 @@ fn compute_circle_mask(
 
     for y in 0..height {
         for x in 0..width {
-            let dx = x as f32 - xcenter;
-            let dy = y as f32 - ycenter;
+            let dx = x as f32 + 0.5 - xcenter;
+            let dy = y as f32 + 0.5 - ycenter;
             if dx * dx + dy * dy <= rsquare {
                 mask[y * width + x] = true;
             }
@@ -481,7 +506,7 @@ fn is_jpeg(file_name: &String) -> bool {
 }
 
 // Returns a boolean representing whether the image file is in raw format. Returns false if JPG or TIF.
-fn is_raw(file_name: &String) -> bool {
+pub(super) fn is_raw(file_name: &String) -> bool {
     let image_ext = Path::new(file_name)
         .extension()
         .unwrap_or_default()
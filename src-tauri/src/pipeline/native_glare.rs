@@ -0,0 +1,781 @@
+/**
+ * Module computing glare metrics from a calibrated HDR image without evalglare.
+ *
+ * evalglare is an external Radiance binary, so its results depend on the Radiance build
+ * installed, and it is missing from some installs. This module computes the same metrics in
+ * process, for angular fisheye images (-vta) of the given view angles:
+ * - every pixel inside the view ellipse gets its direction and solid angle from the equidistant
+ *   projection, and its luminance from 179·(0.265R + 0.670G + 0.065B) divided by the EXPOSURE
+ *   of the header;
+ * - pixels above the glare source threshold (a luminance in cd/m², or a factor of the average or
+ *   task luminance, as evalglare's -b) are grouped into sources: connected pixels form a source,
+ *   and sources whose centers are closer than the search radius are merged. With peak
+ *   extraction, pixels above the peak threshold (e.g. the sun) are grouped on their own;
+ * - each source is weighted by the Guth position index above the line of sight and the Iwata
+ *   position index below it, as evalglare does, limited to 16;
+ * - DGP (with the low light correction below 1000 lux), UGR, DGI and CGI are computed from the
+ *   sources, the vertical illuminance and the luminance of the background (the pixels outside
+ *   the sources). VCP is not computed.
+ *
+ * The results are comparable with evalglare's, not identical: evalglare grows its sources from
+ * pixel to pixel and smooths them (-s), which this module does not. compare_glare() reports the
+ * metrics that differ by more than GLARE_TOLERANCES when evalglare is cross-checked.
+ */
+use super::{evalglare::GlareMetrics, evalglare::GlareSource, EvalglareArgs};
+use crate::radiance_hdr::HdrImage;
+
+// Luminous efficacy and luminance coefficients of Radiance RGB
+const LUMINOUS_EFFICACY: f64 = 179.0;
+const LUMINANCE_COEFFICIENTS: [f64; 3] = [0.265, 0.670, 0.065];
+
+// Glare source threshold when none is set (evalglare's default), and the largest value of -b
+// that is a factor of the average or task luminance instead of a luminance
+const DEFAULT_THRESHOLD: f64 = 2000.0;
+const MAX_THRESHOLD_FACTOR: f64 = 100.0;
+
+// Sources whose centers are closer than this are merged (evalglare's -r default, in radians)
+const SEARCH_RADIUS: f64 = 0.2;
+
+// Pixels above this luminance are extracted as peak sources when no threshold is set (-Y)
+const DEFAULT_PEAK_THRESHOLD: f64 = 50000.0;
+
+// Largest position index, for sources far from the line of sight
+const MAX_POSITION_INDEX: f64 = 16.0;
+
+// Vertical illuminance below which the low light correction of DGP applies, in lux
+const LOW_LIGHT_ILLUMINANCE: f64 = 1000.0;
+
+/**
+ * Largest differences between the native metrics and evalglare's on the same image that are
+ * accepted by the cross-check: relative for the vertical illuminance and average luminance,
+ * absolute for the others.
+ *
+ * The vertical illuminance and average luminance do not depend on source detection. On
+ * synthetic fisheyes of 100 pixels or more they are within 0.4% of their analytic values (see
+ * the tests), so 2% only leaves room for evalglare's own pixel solid angles. The other metrics
+ * depend on the sum of L²·ω/P² over the sources, which changes when evalglare grows or smooths
+ * a source over different pixels. The tolerances accept a difference of that sum up to:
+ * - dgp: 0.02, a factor 1.65 (0.0918·log10 of the sum);
+ * - ugr and cgi: 1, a factor 1.33 (8·log10 of the sum);
+ * - dgi: 1, a factor 1.26 (10·log10 of its sum).
+ *
+ * They were not fitted to evalglare runs on real scenes.
+ */
+pub const GLARE_TOLERANCES: [(&str, f64, bool); 6] = [
+    // (evalglare name, tolerance, relative)
+    ("E_v", 0.02, true),
+    ("av_lum", 0.02, true),
+    ("dgp", 0.02, false),
+    ("ugr", 1.0, false),
+    ("dgi", 1.0, false),
+    ("cgi", 1.0, false),
+];
+
+// Colors of the sources in the check image, repeated when there are more sources
+const CHECK_COLORS: [[f32; 3]; 6] = [
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [0.0, 1.0, 1.0],
+];
+
+/**
+ * Glare metrics computed natively, with the source each pixel belongs to
+ *
+ * @field metrics - The metrics and glare sources, as evalglare would report them with -d
+ * @field source_of_pixel - Index of the glare source of each pixel (None for the background
+ *                          and for pixels outside the view), row by row from the top
+ */
+#[derive(Debug, Clone)]
+pub struct NativeGlare {
+    pub metrics: GlareMetrics,
+    pub source_of_pixel: Vec<Option<usize>>,
+}
+
+// Direction and size of a pixel of the view
+struct ViewPixel {
+    index: usize,
+    direction: [f64; 3], // x to the right, y up, z along the view direction
+    solid_angle: f64,
+    luminance: f64,
+}
+
+// Sums over the pixels of a glare source
+#[derive(Default)]
+struct SourceSums {
+    pixels: usize,
+    solid_angle: f64,
+    luminance: f64,       // sum of luminance × solid angle
+    illuminance: f64,     // sum of luminance × cosine × solid angle
+    direction: [f64; 3],  // sum of direction × solid angle
+    position: (f64, f64), // sum of pixel coordinates × solid angle
+    members: Vec<usize>,  // indices of the pixels in the view pixel list
+}
+
+/**
+ * Computes the glare metrics of an angular fisheye image
+ *
+ * @param image - The calibrated HDR image
+ * @param vertical_angle - Vertical view angle of the image, in degrees
+ * @param horizontal_angle - Horizontal view angle of the image, in degrees
+ * @param args - The evalglare options. The threshold, task zone and peak extraction are used,
+ *               smoothing and extra arguments are ignored.
+ */
+pub fn compute_glare(
+    image: &HdrImage,
+    vertical_angle: f64,
+    horizontal_angle: f64,
+    args: &EvalglareArgs,
+) -> Result<NativeGlare, String> {
+    if image.width == 0 || image.height == 0 {
+        return Err("The image is empty.".into());
+    }
+    if !(vertical_angle > 0.0 && horizontal_angle > 0.0) {
+        return Err(format!(
+            "Invalid view angles {} x {} for the glare metrics.",
+            horizontal_angle, vertical_angle
+        ));
+    }
+
+    let pixels = view_pixels(image, vertical_angle, horizontal_angle);
+    let view_solid_angle: f64 = pixels.iter().map(|pixel| pixel.solid_angle).sum();
+    if view_solid_angle <= 0.0 {
+        return Err("The image has no pixel inside the view.".into());
+    }
+    let average_luminance = pixels
+        .iter()
+        .map(|pixel| pixel.luminance * pixel.solid_angle)
+        .sum::<f64>()
+        / view_solid_angle;
+    let vertical_illuminance: f64 = pixels.iter().map(illuminance).sum();
+
+    // Glare source threshold, a factor of the task luminance when there is a task zone
+    let threshold = match args.threshold {
+        Some(threshold) if threshold > MAX_THRESHOLD_FACTOR => threshold,
+        Some(factor) => {
+            let reference = match &args.task_area {
+                Some(task_area) => {
+                    task_luminance(image, &pixels, vertical_angle, horizontal_angle, task_area)
+                        .unwrap_or(average_luminance)
+                }
+                None => average_luminance,
+            };
+            factor * reference
+        }
+        None => DEFAULT_THRESHOLD,
+    };
+    let peak_threshold = args
+        .peak_extraction
+        .then(|| args.peak_threshold.unwrap_or(DEFAULT_PEAK_THRESHOLD));
+
+    // Class of each image pixel: 0 for the background, 1 above the threshold, 2 above the peak
+    // threshold. Pixels of different classes are never grouped together.
+    let mut class: Vec<u8> = vec![0; image.width * image.height];
+    let mut position_of: Vec<usize> = vec![0; image.width * image.height];
+    for (position, pixel) in pixels.iter().enumerate() {
+        if pixel.luminance > threshold {
+            let peak = peak_threshold.is_some_and(|peak| pixel.luminance > peak);
+            class[pixel.index] = if peak { 2 } else { 1 };
+            position_of[pixel.index] = position;
+        }
+    }
+
+    let sources = connected_sources(image, &pixels, &class, &position_of);
+    let sources = merge_close_sources(sources, &class, &pixels);
+
+    // Metrics of each source
+    let mut source_of_pixel: Vec<Option<usize>> = vec![None; image.width * image.height];
+    let mut glare_sources: Vec<GlareSource> = Vec::new();
+    let mut source_solid_angle = 0.0;
+    let mut source_luminance = 0.0;
+    for sums in &sources {
+        for &member in &sums.members {
+            source_of_pixel[pixels[member].index] = Some(glare_sources.len());
+        }
+        source_solid_angle += sums.solid_angle;
+        source_luminance += sums.luminance;
+        glare_sources.push(GlareSource {
+            pixels: sums.pixels as f64,
+            x: sums.position.0 / sums.solid_angle,
+            y: sums.position.1 / sums.solid_angle,
+            luminance: sums.luminance / sums.solid_angle,
+            solid_angle: sums.solid_angle,
+            position_index: position_index(normalize(sums.direction)),
+            vertical_illuminance: Some(sums.illuminance),
+        });
+    }
+
+    // Background: everything in the view that is not a glare source
+    let background_solid_angle = view_solid_angle - source_solid_angle;
+    let background_luminance = if background_solid_angle > 0.0 {
+        (average_luminance * view_solid_angle - source_luminance) / background_solid_angle
+    } else {
+        0.0
+    };
+
+    let metrics = glare_metrics(
+        &glare_sources,
+        average_luminance,
+        vertical_illuminance,
+        background_luminance,
+    );
+    Ok(NativeGlare {
+        metrics: GlareMetrics {
+            glare_sources,
+            ..metrics
+        },
+        source_of_pixel,
+    })
+}
+
+impl NativeGlare {
+    /**
+     * Creates a check image like evalglare's -c: the image with the pixels of each glare source
+     * painted in a color, at the luminance of the source
+     *
+     * @param image - The image the metrics were computed from
+     */
+    pub fn check_image(&self, image: &HdrImage) -> HdrImage {
+        let mut check = image.clone();
+        let exposure = image.header.exposure() as f32;
+        for (pixel, source) in check.pixels.iter_mut().zip(&self.source_of_pixel) {
+            if let Some(source) = source {
+                let luminance = self.metrics.glare_sources[*source].luminance as f32;
+                let value = luminance / LUMINOUS_EFFICACY as f32 * exposure;
+                *pixel = CHECK_COLORS[source % CHECK_COLORS.len()].map(|c| c * value);
+            }
+        }
+        check
+    }
+}
+
+/**
+ * Compares glare metrics computed natively with evalglare's. Only the metrics both computed are
+ * compared.
+ *
+ * @param native - Metrics from compute_glare()
+ * @param evalglare - Metrics from evalglare on the same image
+ * @returns A message for every metric differing by more than its tolerance
+ */
+pub fn compare_glare(native: &GlareMetrics, evalglare: &GlareMetrics) -> Vec<String> {
+    GLARE_TOLERANCES
+        .iter()
+        .filter_map(|(name, tolerance, relative)| {
+            let (native, evalglare) = (native.value(name)?, evalglare.value(name)?);
+            let difference = (native - evalglare).abs();
+            let allowed = if *relative {
+                tolerance * evalglare.abs()
+            } else {
+                *tolerance
+            };
+            (difference > allowed).then(|| {
+                format!(
+                    "Glare metric {} differs between evalglare ({:.3}) and the built-in computation ({:.3}).",
+                    name, evalglare, native
+                )
+            })
+        })
+        .collect()
+}
+
+// Returns the pixels inside the view ellipse of an angular fisheye image, with their direction,
+// solid angle and luminance
+fn view_pixels(image: &HdrImage, vertical_angle: f64, horizontal_angle: f64) -> Vec<ViewPixel> {
+    let exposure = image.header.exposure();
+    let (half_h, half_v) = (
+        horizontal_angle.to_radians() / 2.0,
+        vertical_angle.to_radians() / 2.0,
+    );
+
+    let mut pixels = Vec::new();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (dx, dy) = pixel_angles(image, x as f64 + 0.5, y as f64 + 0.5, half_h, half_v);
+            if (dx / half_h).powi(2) + (dy / half_v).powi(2) > 1.0 {
+                continue;
+            }
+            let (direction, solid_angle) =
+                pixel_direction(image, x, y, vertical_angle, horizontal_angle);
+            pixels.push(ViewPixel {
+                index: y * image.width + x,
+                direction,
+                solid_angle,
+                luminance: luminance(image.get_pixel(x, y), exposure).max(0.0),
+            });
+        }
+    }
+    pixels
+}
+
+/**
+ * Returns the luminance of a Radiance RGB value, in cd/m²
+ *
+ * @param rgb - The value as stored in the image
+ * @param exposure - EXPOSURE of the image header, which the value is divided by
+ */
+pub(super) fn luminance(rgb: [f32; 3], exposure: f64) -> f64 {
+    let exposure = if exposure > 0.0 { exposure } else { 1.0 };
+    LUMINOUS_EFFICACY
+        * rgb
+            .iter()
+            .zip(LUMINANCE_COEFFICIENTS)
+            .map(|(value, coefficient)| *value as f64 * coefficient)
+            .sum::<f64>()
+        / exposure
+}
+
+/**
+ * Returns the direction of the center of a pixel of an angular fisheye image (x to the right,
+ * y up, z along the view direction), and the solid angle of the pixel in sr
+ *
+ * @param image - The image
+ * @param x - Column of the pixel
+ * @param y - Row of the pixel, from the top
+ * @param vertical_angle - Vertical view angle of the image, in degrees
+ * @param horizontal_angle - Horizontal view angle of the image, in degrees
+ */
+pub(super) fn pixel_direction(
+    image: &HdrImage,
+    x: usize,
+    y: usize,
+    vertical_angle: f64,
+    horizontal_angle: f64,
+) -> ([f64; 3], f64) {
+    let (half_h, half_v) = (
+        horizontal_angle.to_radians() / 2.0,
+        vertical_angle.to_radians() / 2.0,
+    );
+    let (dx, dy) = pixel_angles(image, x as f64 + 0.5, y as f64 + 0.5, half_h, half_v);
+    let pixel_solid_angle = horizontal_angle.to_radians() / image.width as f64
+        * vertical_angle.to_radians()
+        / image.height as f64;
+
+    // The equidistant projection stretches areas by theta / sin(theta) away from the center
+    let theta = (dx * dx + dy * dy).sqrt();
+    if theta > 0.0 {
+        let sin = theta.sin();
+        (
+            [sin * dx / theta, sin * dy / theta, theta.cos()],
+            pixel_solid_angle * sin / theta,
+        )
+    } else {
+        ([0.0, 0.0, 1.0], pixel_solid_angle)
+    }
+}
+
+// Angles of an image position from the view direction, to the right and up, in radians
+fn pixel_angles(image: &HdrImage, x: f64, y: f64, half_h: f64, half_v: f64) -> (f64, f64) {
+    (
+        (x / image.width as f64 - 0.5) * 2.0 * half_h,
+        (0.5 - y / image.height as f64) * 2.0 * half_v,
+    )
+}
+
+// Illuminance a pixel causes on a vertical plane facing the view direction
+fn illuminance(pixel: &ViewPixel) -> f64 {
+    pixel.luminance * pixel.direction[2].max(0.0) * pixel.solid_angle
+}
+
+// Average luminance of the task zone, None if no pixel of the view is inside it
+fn task_luminance(
+    image: &HdrImage,
+    pixels: &[ViewPixel],
+    vertical_angle: f64,
+    horizontal_angle: f64,
+    task_area: &super::TaskArea,
+) -> Option<f64> {
+    let (dx, dy) = pixel_angles(
+        image,
+        task_area.x,
+        task_area.y,
+        horizontal_angle.to_radians() / 2.0,
+        vertical_angle.to_radians() / 2.0,
+    );
+    let theta = (dx * dx + dy * dy).sqrt();
+    let center = if theta > 0.0 {
+        [
+            theta.sin() * dx / theta,
+            theta.sin() * dy / theta,
+            theta.cos(),
+        ]
+    } else {
+        [0.0, 0.0, 1.0]
+    };
+    let max_cos = (task_area.angle.to_radians() / 2.0).cos();
+
+    let (sum, solid_angle) = pixels
+        .iter()
+        .filter(|pixel| dot(pixel.direction, center) >= max_cos)
+        .fold((0.0, 0.0), |(sum, solid_angle), pixel| {
+            (
+                sum + pixel.luminance * pixel.solid_angle,
+                solid_angle + pixel.solid_angle,
+            )
+        });
+    (solid_angle > 0.0).then(|| sum / solid_angle)
+}
+
+// Groups glare pixels of the same class that touch (including diagonally) into sources
+fn connected_sources(
+    image: &HdrImage,
+    pixels: &[ViewPixel],
+    class: &[u8],
+    position_of: &[usize],
+) -> Vec<SourceSums> {
+    let mut visited = vec![false; class.len()];
+    let mut sources = Vec::new();
+
+    for start in 0..class.len() {
+        if class[start] == 0 || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut sums = SourceSums::default();
+        let mut stack = vec![start];
+        while let Some(index) = stack.pop() {
+            let position = position_of[index];
+            add_pixel(&mut sums, &pixels[position], position, image.width);
+
+            let (x, y) = ((index % image.width) as i64, (index / image.width) as i64);
+            for (nx, ny) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))) {
+                if nx < 0 || ny < 0 || nx >= image.width as i64 || ny >= image.height as i64 {
+                    continue;
+                }
+                let neighbour = ny as usize * image.width + nx as usize;
+                if class[neighbour] == class[index] && !visited[neighbour] {
+                    visited[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        sources.push(sums);
+    }
+    sources
+}
+
+// Merges sources of the same class whose centers are closer than the search radius, including
+// chains of close sources
+fn merge_close_sources(
+    sources: Vec<SourceSums>,
+    class: &[u8],
+    pixels: &[ViewPixel],
+) -> Vec<SourceSums> {
+    let min_cos = SEARCH_RADIUS.cos();
+    let centers: Vec<([f64; 3], u8)> = sources
+        .iter()
+        .map(|sums| {
+            (
+                normalize(sums.direction),
+                class[pixels[sums.members[0]].index],
+            )
+        })
+        .collect();
+
+    // Union-find of the sources, each pointing towards the first source of its group
+    let mut parent: Vec<usize> = (0..sources.len()).collect();
+    fn root(parent: &mut [usize], mut source: usize) -> usize {
+        while parent[source] != source {
+            parent[source] = parent[parent[source]];
+            source = parent[source];
+        }
+        source
+    }
+    for a in 0..centers.len() {
+        for b in a + 1..centers.len() {
+            if centers[a].1 == centers[b].1 && dot(centers[a].0, centers[b].0) >= min_cos {
+                let (root_a, root_b) = (root(&mut parent, a), root(&mut parent, b));
+                parent[root_a.max(root_b)] = root_a.min(root_b);
+            }
+        }
+    }
+
+    let mut groups: Vec<Option<SourceSums>> = Vec::new();
+    let mut group_of: Vec<Option<usize>> = vec![None; sources.len()];
+    for (source, sums) in sources.into_iter().enumerate() {
+        let group = root(&mut parent, source);
+        match group_of[group] {
+            None => {
+                group_of[group] = Some(groups.len());
+                groups.push(Some(sums));
+            }
+            Some(index) => {
+                let target = groups[index]
+                    .as_mut()
+                    .expect("groups are only taken at the end");
+                target.pixels += sums.pixels;
+                target.solid_angle += sums.solid_angle;
+                target.luminance += sums.luminance;
+                target.illuminance += sums.illuminance;
+                for axis in 0..3 {
+                    target.direction[axis] += sums.direction[axis];
+                }
+                target.position.0 += sums.position.0;
+                target.position.1 += sums.position.1;
+                target.members.extend(sums.members);
+            }
+        }
+    }
+    groups.into_iter().flatten().collect()
+}
+
+// Adds a pixel to the sums of a source
+fn add_pixel(sums: &mut SourceSums, pixel: &ViewPixel, position: usize, width: usize) {
+    let weight = pixel.solid_angle;
+    sums.pixels += 1;
+    sums.solid_angle += weight;
+    sums.luminance += pixel.luminance * weight;
+    sums.illuminance += illuminance(pixel);
+    for axis in 0..3 {
+        sums.direction[axis] += pixel.direction[axis] * weight;
+    }
+    sums.position.0 += ((pixel.index % width) as f64 + 0.5) * weight;
+    sums.position.1 += ((pixel.index / width) as f64 + 0.5) * weight;
+    sums.members.push(position);
+}
+
+// Position index of a source in a direction: Guth above the line of sight, Iwata below it
+fn position_index(direction: [f64; 3]) -> f64 {
+    let [x, y, z] = direction;
+    if z <= 0.0 {
+        return MAX_POSITION_INDEX;
+    }
+    let index = if y >= 0.0 {
+        // Guth: sigma is the angle from the line of sight, tau the angle from the vertical plane
+        let sigma = z.clamp(-1.0, 1.0).acos().to_degrees();
+        let tau = x.abs().atan2(y).to_degrees();
+        ((35.2 - 0.31889 * tau - 1.22 * (-2.0 * tau / 9.0).exp()) * 1e-3 * sigma
+            + (21.0 + 0.26667 * tau - 0.002963 * tau * tau) * 1e-5 * sigma * sigma)
+            .exp()
+    } else {
+        // Iwata: r is the distance from the line of sight on a plane at unit distance
+        let r = ((x / z).powi(2) + (y / z).powi(2)).sqrt();
+        if r < 0.6 {
+            1.0 + 0.8 * r
+        } else {
+            1.0 + 1.2 * r
+        }
+    };
+    index.clamp(1.0, MAX_POSITION_INDEX)
+}
+
+// Computes DGP, UGR, DGI and CGI from the glare sources. Indices are 0 without sources.
+fn glare_metrics(
+    sources: &[GlareSource],
+    average_luminance: f64,
+    vertical_illuminance: f64,
+    background_luminance: f64,
+) -> GlareMetrics {
+    // Sum of L²·ω/P², shared by DGP, UGR and CGI
+    let weighted: f64 = sources
+        .iter()
+        .map(|source| source.luminance.powi(2) * source.solid_angle / source.position_index.powi(2))
+        .sum();
+    let direct_illuminance: f64 = sources
+        .iter()
+        .filter_map(|source| source.vertical_illuminance)
+        .sum();
+
+    let mut dgp = if vertical_illuminance > 0.0 {
+        5.87e-5 * vertical_illuminance
+            + 9.18e-2 * (1.0 + weighted / vertical_illuminance.powf(1.87)).log10()
+            + 0.16
+    } else {
+        0.0
+    };
+    if vertical_illuminance < LOW_LIGHT_ILLUMINANCE {
+        let exponent = (0.024 * vertical_illuminance - 4.0).exp();
+        dgp *= exponent / (1.0 + exponent);
+    }
+
+    let index = |value: f64, scale: f64| {
+        if value > 0.0 && value.is_finite() {
+            scale * value.log10()
+        } else {
+            0.0
+        }
+    };
+    let ugr = if background_luminance > 0.0 {
+        index(0.25 / background_luminance * weighted, 8.0)
+    } else {
+        0.0
+    };
+    let dgi_sum: f64 = sources
+        .iter()
+        .map(|source| {
+            let modified_solid_angle = source.solid_angle / source.position_index.powi(2);
+            0.478 * source.luminance.powf(1.6) * modified_solid_angle.powf(0.8)
+                / (background_luminance + 0.07 * source.solid_angle.sqrt() * source.luminance)
+        })
+        .sum();
+    let cgi = if vertical_illuminance > 0.0 {
+        index(
+            2.0 * (1.0 + direct_illuminance / 500.0) / vertical_illuminance * weighted,
+            8.0,
+        )
+    } else {
+        0.0
+    };
+
+    GlareMetrics {
+        dgp: Some(dgp.clamp(0.0, 1.0)),
+        average_luminance: Some(average_luminance),
+        vertical_illuminance,
+        background_luminance: Some(background_luminance),
+        dgi: Some(index(dgi_sum, 10.0)),
+        ugr: Some(ugr),
+        vcp: None,
+        cgi: Some(cgi),
+        ..Default::default()
+    }
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = dot(v, v).sqrt();
+    if length > 0.0 {
+        v.map(|c| c / length)
+    } else {
+        [0.0, 0.0, 1.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: f64 = 1000.0;
+    const SOURCE: f64 = 20000.0;
+
+    // 180° angular fisheye of a uniform background, with a disc of the source luminance of the
+    // given angular radius (in degrees) in the view direction
+    fn fisheye(size: usize, source_radius: f64) -> HdrImage {
+        let mut image = HdrImage::new(size, size);
+        let half = 90f64.to_radians();
+        for y in 0..size {
+            for x in 0..size {
+                let (dx, dy) = pixel_angles(&image, x as f64 + 0.5, y as f64 + 0.5, half, half);
+                let theta = (dx * dx + dy * dy).sqrt().to_degrees();
+                let luminance = if theta < source_radius {
+                    SOURCE
+                } else {
+                    BACKGROUND
+                };
+                image.set_pixel(x, y, [(luminance / LUMINOUS_EFFICACY) as f32; 3]);
+            }
+        }
+        image
+    }
+
+    // Checks a metric against its expected value, within the tolerance of the cross-check
+    fn assert_within_tolerance(metrics: &GlareMetrics, name: &str, expected: f64) {
+        let (_, tolerance, relative) = GLARE_TOLERANCES
+            .iter()
+            .find(|(metric, _, _)| *metric == name)
+            .unwrap();
+        let allowed = if *relative {
+            tolerance * expected.abs()
+        } else {
+            *tolerance
+        };
+        let value = metrics.value(name).unwrap();
+        assert!(
+            (value - expected).abs() <= allowed,
+            "{} is {}, expected {} ± {}",
+            name,
+            value,
+            expected,
+            allowed
+        );
+    }
+
+    #[test]
+    fn uniform_hemisphere() {
+        // A uniform hemisphere of luminance L gives an illuminance of π·L, and no glare source
+        let glare = compute_glare(&fisheye(200, 0.0), 180.0, 180.0, &Default::default()).unwrap();
+        let metrics = &glare.metrics;
+        assert!(metrics.glare_sources.is_empty());
+        assert_within_tolerance(metrics, "E_v", std::f64::consts::PI * BACKGROUND);
+        assert_within_tolerance(metrics, "av_lum", BACKGROUND);
+        assert_eq!(metrics.ugr, Some(0.0));
+
+        let dgp = 5.87e-5 * std::f64::consts::PI * BACKGROUND + 0.16;
+        assert_within_tolerance(metrics, "dgp", dgp);
+    }
+
+    #[test]
+    fn source_in_view_direction() {
+        let radius = 10f64.to_radians();
+        let glare = compute_glare(&fisheye(400, 10.0), 180.0, 180.0, &Default::default()).unwrap();
+        let metrics = &glare.metrics;
+        assert_eq!(metrics.glare_sources.len(), 1);
+
+        // Analytic values for a spherical cap of angular radius α: solid angle 2π(1 - cos α),
+        // illuminance π·sin²α per unit of luminance, and a position index of 1
+        let pi = std::f64::consts::PI;
+        let solid_angle = 2.0 * pi * (1.0 - radius.cos());
+        let illuminance = pi * BACKGROUND + (SOURCE - BACKGROUND) * pi * radius.sin().powi(2);
+        let average = BACKGROUND + (SOURCE - BACKGROUND) * solid_angle / (2.0 * pi);
+        let weighted = SOURCE * SOURCE * solid_angle;
+
+        let source = &metrics.glare_sources[0];
+        assert!((source.luminance - SOURCE).abs() < 1.0);
+        assert!((source.solid_angle - solid_angle).abs() < 0.02 * solid_angle);
+        assert!((source.position_index - 1.0).abs() < 1e-3);
+        assert!((source.x - 200.0).abs() < 0.5 && (source.y - 200.0).abs() < 0.5);
+
+        assert_within_tolerance(metrics, "E_v", illuminance);
+        assert_within_tolerance(metrics, "av_lum", average);
+        assert!((metrics.vertical_illuminance / illuminance - 1.0).abs() < 0.004);
+        assert!((metrics.average_luminance.unwrap() / average - 1.0).abs() < 0.004);
+        assert_within_tolerance(
+            metrics,
+            "dgp",
+            5.87e-5 * illuminance
+                + 9.18e-2 * (1.0 + weighted / illuminance.powf(1.87)).log10()
+                + 0.16,
+        );
+        assert_within_tolerance(metrics, "ugr", 8.0 * (0.25 / BACKGROUND * weighted).log10());
+        assert_within_tolerance(
+            metrics,
+            "cgi",
+            8.0 * (2.0 * (1.0 + SOURCE * pi * radius.sin().powi(2) / 500.0) / illuminance
+                * weighted)
+                .log10(),
+        );
+        let dgi_term = 0.478 * SOURCE.powf(1.6) * solid_angle.powf(0.8)
+            / (BACKGROUND + 0.07 * solid_angle.sqrt() * SOURCE);
+        assert_within_tolerance(metrics, "dgi", 10.0 * dgi_term.log10());
+    }
+
+    #[test]
+    fn compare_glare_reports_differences_over_tolerance() {
+        let glare = compute_glare(&fisheye(100, 10.0), 180.0, 180.0, &Default::default()).unwrap();
+        let native = glare.metrics;
+        assert!(compare_glare(&native, &native).is_empty());
+
+        let evalglare = GlareMetrics {
+            vertical_illuminance: native.vertical_illuminance * 1.05,
+            ugr: native.ugr.map(|ugr| ugr + 0.5),
+            dgp: None,
+            ..native.clone()
+        };
+        let differences = compare_glare(&native, &evalglare);
+        assert_eq!(differences.len(), 1);
+        assert!(differences[0].contains("E_v"));
+    }
+
+    #[test]
+    fn check_image_paints_sources() {
+        let image = fisheye(100, 10.0);
+        let glare = compute_glare(&image, 180.0, 180.0, &Default::default()).unwrap();
+        let check = glare.check_image(&image);
+        assert_eq!(check.get_pixel(50, 50)[1], 0.0);
+        assert!(check.get_pixel(50, 50)[0] > 0.0);
+        assert_eq!(check.get_pixel(10, 50), image.get_pixel(10, 50));
+    }
+}
@@ -0,0 +1,203 @@
+/**
+ * Module merging an aligned bracket of LDR exposures into an HDR image without hdrgen.
+ *
+ * hdrgen is a prebuilt binary that is not available for every platform (aarch64 Linux, for
+ * example). This merge combines the exposures the way hdrgen does with alignment, exposure
+ * adjustment, flare and ghost removal turned off (-a -e -f -g): every pixel value is linearized
+ * with the response curve of its channel from the .rsp file and divided by the exposure of its
+ * image (t * ISO / N^2, see relative_exposures()), then the exposures are averaged with a
+ * hat-shaped weight that trusts mid-range values most and ignores under and over-exposed ones.
+ */
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use super::response_function::{ResponseFunction, CHANNEL_COUNT};
+use crate::radiance_hdr::{write_hdr, HdrImage};
+
+/**
+ * Merges LDR exposures of the same size into a Radiance HDR image
+ *
+ * @param images - Paths to the LDR images (JPG or TIFF)
+ * @param exposures - Exposure of each image, from relative_exposures(): the exposure time in
+ *                    seconds, scaled by the ISO and aperture relative to the first image
+ * @param response_function - Camera response curves used to linearize the pixel values
+ * @param output_path - Path of the HDR image to write
+ */
+pub fn merge_exposures_native(
+    images: &[String],
+    exposures: &[f64],
+    response_function: &ResponseFunction,
+    output_path: &Path,
+) -> Result<(), String> {
+    if images.is_empty() {
+        return Err("No images to merge.".into());
+    }
+    if images.len() != exposures.len() {
+        return Err(format!(
+            "Got {} exposures for {} images.",
+            exposures.len(),
+            images.len()
+        ));
+    }
+
+    // Process the images from the shortest to the longest exposure, see fallback below
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by(|a, b| exposures[*a].total_cmp(&exposures[*b]));
+
+    let mut dimensions: Option<(u32, u32)> = None;
+    let mut sums: Vec<f32> = Vec::new();
+    let mut weights: Vec<f32> = Vec::new();
+    // Value of pixels without a usable sample in any exposure: taken from the shortest exposure
+    // if it is over-exposed there, otherwise from the longest (under-exposed everywhere)
+    let mut fallback: Vec<f32> = Vec::new();
+
+    for (rank, &index) in order.iter().enumerate() {
+        let image = image::open(&images[index])
+            .map_err(|e| format!("Failed to open image {}: {}", images[index], e))?
+            .into_rgb32f();
+
+        match dimensions {
+            None => {
+                dimensions = Some(image.dimensions());
+                let sample_count = image.as_raw().len();
+                sums = vec![0.0; sample_count];
+                weights = vec![0.0; sample_count];
+                fallback = vec![f32::NAN; sample_count];
+            }
+            Some(expected) if expected != image.dimensions() => {
+                return Err(format!(
+                    "Image {} is {}x{}, but the other images of the bracket are {}x{}.",
+                    images[index],
+                    image.width(),
+                    image.height(),
+                    expected.0,
+                    expected.1
+                ));
+            }
+            _ => {}
+        }
+
+        let exposure = exposures[index];
+        let shortest = rank == 0;
+        let longest = rank == order.len() - 1;
+
+        sums.par_iter_mut()
+            .zip(weights.par_iter_mut())
+            .zip(fallback.par_iter_mut())
+            .zip(image.as_raw().par_iter())
+            .enumerate()
+            .for_each(|(sample, (((sum, weight), fallback), value))| {
+                let value = value.clamp(0.0, 1.0);
+                let radiance = (response_function.evaluate(sample % CHANNEL_COUNT, value as f64)
+                    / exposure)
+                    .max(0.0) as f32;
+                let value_weight = hat_weight(value);
+
+                *sum += value_weight * radiance;
+                *weight += value_weight;
+                if (shortest && value >= 0.5) || (longest && fallback.is_nan()) {
+                    *fallback = radiance;
+                }
+            });
+    }
+
+    let (width, height) = dimensions.unwrap_or_default();
+    let mut hdr_image = HdrImage::new(width as usize, height as usize);
+    hdr_image.pixels = sums
+        .par_chunks(CHANNEL_COUNT)
+        .zip(weights.par_chunks(CHANNEL_COUNT))
+        .zip(fallback.par_chunks(CHANNEL_COUNT))
+        .map(|((sum, weight), fallback)| {
+            std::array::from_fn(|channel| {
+                if weight[channel] > 0.0 {
+                    sum[channel] / weight[channel]
+                } else {
+                    fallback[channel]
+                }
+            })
+        })
+        .collect();
+
+    write_hdr(output_path, &hdr_image)
+}
+
+// Weight of a pixel value (normalized to 0..1): 1 in the middle of the range, falling linearly
+// to 0 for black and saturated values
+fn hat_weight(value: f32) -> f32 {
+    1.0 - (2.0 * value - 1.0).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        pipeline::image_metadata::{relative_exposures, ImageMetadata},
+        radiance_hdr::read_hdr,
+    };
+
+    #[test]
+    fn merge_recovers_radiance_of_exposures_with_mixed_iso_and_aperture() {
+        let dir = std::env::temp_dir().join(format!("native_merge_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // Response f(v) = v^2 for every channel, so a pixel value is the square root of the
+        // light it received
+        let response_function = ResponseFunction {
+            channels: vec![vec![1.0, 0.0, 0.0]; CHANNEL_COUNT],
+        };
+        let (width, height) = (64_u32, 4_u32);
+        let radiance = |x: u32, channel: usize| {
+            0.02 * 150_f64.powf(x as f64 / (width - 1) as f64) * (1.0 - 0.2 * channel as f64)
+        };
+
+        // Same exposure time, but ISO and aperture give exposures of 1/4, 1/2 and 1
+        let metadata = [(100, 4.0), (200, 4.0), (100, 2.0)].map(|(iso, f_number)| ImageMetadata {
+            exposure_time: Some(0.25),
+            f_number: Some(f_number),
+            iso: Some(iso),
+            ..Default::default()
+        });
+        let exposures = relative_exposures(&metadata).unwrap();
+        assert_eq!(exposures, vec![0.25, 0.5, 1.0]);
+
+        let images: Vec<String> = exposures
+            .iter()
+            .enumerate()
+            .map(|(i, exposure)| {
+                let image = image::ImageBuffer::from_fn(width, height, |x, _| {
+                    image::Rgb(std::array::from_fn(|channel| {
+                        let light = (radiance(x, channel) * exposure).min(1.0);
+                        (light.sqrt() * u16::MAX as f64).round() as u16
+                    }))
+                });
+                let path = dir.join(format!("exposure_{}.tiff", i));
+                image.save(&path).unwrap();
+                path.display().to_string()
+            })
+            .collect();
+
+        let output = dir.join("merged.hdr");
+        merge_exposures_native(&images, &exposures, &response_function, &output).unwrap();
+
+        let merged = read_hdr(&output).unwrap();
+        for (i, pixel) in merged.pixels.iter().enumerate() {
+            let x = (i % width as usize) as u32;
+            for (channel, value) in pixel.iter().enumerate() {
+                let expected = radiance(x, channel);
+                // RGBE keeps about 8 bits of mantissa
+                assert!(
+                    ((*value as f64 - expected) / expected).abs() < 0.01,
+                    "pixel {} channel {}: {} instead of {}",
+                    x,
+                    channel,
+                    value,
+                    expected
+                );
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
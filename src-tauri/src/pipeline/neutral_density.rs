@@ -3,7 +3,7 @@ use std::fs::File;
 use std::process::Command;
 use std::process::Stdio;
 
-use super::ConfigSettings;
+use super::{run_command, ConfigSettings, PipelineError};
 
 // Neutral Density Filter
 // config_settings:
@@ -20,7 +20,7 @@ pub fn neutral_density(
     input_file: String,
     output_file: String,
     neutral_density: String,
-) -> Result<String, String> {
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!(
             "neutral_density() was called with parameters:\n\t neutral_density: {neutral_density}"
@@ -45,11 +45,12 @@ pub fn neutral_density(
     command.stdout(stdio);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: neutral_density: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command(
+        "neutral_density",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
 
     if DEBUG {
         println!(
@@ -64,6 +65,10 @@ pub fn neutral_density(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'pcomb' (neutral density filter) failed.".into())
+        Err(PipelineError::command_failed(
+            "neutral_density",
+            &command,
+            &output,
+        ))
     }
 }
@@ -1,7 +1,7 @@
 use crate::pipeline::DEBUG;
 use std::process::Command;
 
-use super::ConfigSettings;
+use super::{run_command, ConfigSettings, PipelineError};
 
 // Nullifies the exposure value of an HDR image using ra_xyze.
 // config_settings:
@@ -15,7 +15,7 @@ pub fn nullify_exposure_value(
     config_settings: &ConfigSettings,
     input_file: String,
     output_file: String,
-) -> Result<String, String> {
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!("nullify_exposure_value was called!");
     }
@@ -27,11 +27,12 @@ pub fn nullify_exposure_value(
     command.args(["-r", "-o", input_file.as_str(), output_file.as_str()]);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: nullify_exposure: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command(
+        "nullify_exposure_value",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
 
     if DEBUG {
         println!(
@@ -46,6 +47,10 @@ pub fn nullify_exposure_value(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'ra_xyze' (nullify exposure value) failed.".into())
+        Err(PipelineError::command_failed(
+            "nullify_exposure_value",
+            &command,
+            &output,
+        ))
     }
 }
@@ -0,0 +1,577 @@
+/**
+ * Module defining the typed parameters of a pipeline run.
+ *
+ * The frontend and the headless command-line binary both describe a run with a
+ * PipelineParams value. Parameters are checked by validate() before any external command is
+ * started, so that bad input is reported up front (all problems at once) instead of failing
+ * deep inside a stage.
+ */
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::stage::{build_stages, StageEntry};
+
+// Largest resolution the HDR image can be resized to (in pixels)
+const MAX_TARGET_RESOLUTION: u32 = 20000;
+
+// Largest view angle of an angular fisheye (-vta) view (in degrees)
+const MAX_VIEW_ANGLE: f64 = 360.0;
+
+// Largest allowed difference between consecutive exposures of a bracket (in EV) by default
+pub const DEFAULT_MAX_EV_STEP: f64 = 3.0;
+
+// Largest number of scenes processed at the same time. Each scene runs its own external
+// commands, so more workers than cores only adds memory pressure.
+const MAX_WORKERS: usize = 64;
+
+/**
+ * Arguments for falsecolor luminance mapping. Unset values use falsecolor's defaults.
+ *
+ * @field scale_limit - Upper limit of the luminance scale (-s)
+ * @field scale_label - Label of the legend (-l)
+ * @field scale_levels - Number of contours on the scale (-n)
+ * @field legend_width - Width of the legend in pixels (-lw)
+ * @field legend_height - Height of the legend in pixels (-lh)
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LuminanceArgs {
+    pub scale_limit: Option<f64>,
+    pub scale_label: Option<String>,
+    pub scale_levels: Option<u32>,
+    pub legend_width: Option<u32>,
+    pub legend_height: Option<u32>,
+}
+
+/**
+ * What evalglare computes and prints
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EvalglareOutput {
+    // Only the vertical illuminance (-V)
+    #[default]
+    VerticalIlluminance,
+    // The glare metrics (DGP, DGI, UGR, VCP, CGI, ...)
+    Metrics,
+    // The glare metrics and the list of detected glare sources (-d)
+    Detailed,
+}
+
+/**
+ * Program computing the glare metrics
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GlareBackend {
+    // evalglare from the Radiance path, or the built-in computation if it is missing
+    #[default]
+    Evalglare,
+    // The built-in computation, independent of the Radiance build
+    Native,
+}
+
+/**
+ * Task zone of the evalglare analysis. The glare source threshold is then a factor of the task
+ * luminance instead of the average luminance.
+ *
+ * @field x - x-coordinate of the task center in the image, in pixels
+ * @field y - y-coordinate of the task center in the image, in pixels
+ * @field angle - Opening angle of the task zone, in degrees
+ * @field mark - Whether the task zone is also drawn in the check image (-T instead of -t)
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskArea {
+    pub x: f64,
+    pub y: f64,
+    pub angle: f64,
+    #[serde(default)]
+    pub mark: bool,
+}
+
+/**
+ * Arguments for evalglare. Unset values use evalglare's defaults.
+ *
+ * @field output - What evalglare computes (-V for the vertical illuminance only, -d for the
+ *                 glare sources)
+ * @field threshold - Glare source threshold (-b): a factor of the average (or task) luminance,
+ *                    or a luminance in cd/m² if larger than 100
+ * @field task_area - Task zone (-t or -T)
+ * @field smoothing - Whether the glare sources are smoothed (-s)
+ * @field peak_extraction - Whether luminance peaks (e.g. the sun) are extracted as separate glare
+ *                          sources (-x disables it)
+ * @field peak_threshold - Luminance above which pixels are extracted as peaks, in cd/m² (-Y)
+ * @field extra_args - Other evalglare options, passed as given (e.g. the disability glare
+ *                     options, which depend on the evalglare version)
+ * @field check_image - Whether the check image showing the detected glare sources is written (-c)
+ *                      and copied next to the final HDR image
+ * @field backend - Program computing the glare metrics
+ * @field cross_check - Whether the metrics of evalglare are compared with the built-in
+ *                      computation, with a warning for every metric that differs by more than
+ *                      the expected tolerance
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EvalglareArgs {
+    pub output: EvalglareOutput,
+    pub threshold: Option<f64>,
+    pub task_area: Option<TaskArea>,
+    pub smoothing: bool,
+    pub peak_extraction: bool,
+    pub peak_threshold: Option<f64>,
+    pub extra_args: Vec<String>,
+    pub check_image: bool,
+    pub backend: GlareBackend,
+    pub cross_check: bool,
+}
+
+impl Default for EvalglareArgs {
+    fn default() -> EvalglareArgs {
+        EvalglareArgs {
+            output: EvalglareOutput::default(),
+            threshold: None,
+            task_area: None,
+            smoothing: false,
+            peak_extraction: true,
+            peak_threshold: None,
+            extra_args: Vec::new(),
+            check_image: false,
+            backend: GlareBackend::default(),
+            cross_check: false,
+        }
+    }
+}
+
+/**
+ * A set of images processed as one scene of a batch, wherever the images are stored (e.g. a
+ * bracket set found by group_brackets in a folder holding many brackets)
+ *
+ * @field name - Name of the scene, used for its temp directory and output file names
+ * @field images - Paths to the images of the bracket
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BracketGroup {
+    pub name: String,
+    pub images: Vec<String>,
+}
+
+/**
+ * Program merging the LDR exposures into an HDR image
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeBackend {
+    // hdrgen (the bundled sidecar, or the one in hdrgen_path)
+    #[default]
+    Hdrgen,
+    // The built-in merge, for platforms hdrgen is not available on. Needs a response function.
+    Native,
+}
+
+/**
+ * What happens when the images of a scene fail the bracket checks (see image_metadata.rs)
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BracketCheck {
+    // The bracket is not checked
+    Off,
+    // The problems are reported as a warning and the scene is processed anyway
+    #[default]
+    Warn,
+    // The scene fails before its images are merged
+    Fail,
+}
+
+/**
+ * Parameters of a pipeline run
+ *
+ * @field radiance_path - Directory containing the Radiance binaries (empty to search PATH)
+ * @field hdrgen_path - Directory containing hdrgen (empty to use the bundled sidecar)
+ * @field dcraw_emu_path - Directory containing dcraw_emu (empty to use the bundled sidecar)
+ * @field output_path - Directory for the final HDR images. Temp dir is created within it.
+ * @field input_images - Paths to the input images, or to the input directories if batch processing
+ * @field bracket_groups - Image sets processed as the scenes of a batch, instead of input_images
+ * @field recursive - Whether every leaf directory with images below the input directories is
+ *                    processed as a scene, with the outputs in a mirror of the input tree
+ * @field scene_filter - Inputs (scene directories or group names) of the scenes to process, all
+ *                       scenes if empty. Set when the failed scenes of a run are run again.
+ * @field response_function - Camera response function (.rsp)
+ * @field fisheye_correction_cal - Fisheye correction calibration file (.cal)
+ * @field vignetting_correction_cal - Vignetting correction calibration file (.cal)
+ * @field photometric_adjustment_cal - Photometric adjustment calibration file (.cal)
+ * @field neutral_density_cal - Neutral density adjustment calibration file (.cal)
+ * @field diameter - The fisheye view diameter in pixels
+ * @field xleft - The x-coordinate of the bottom left corner of the circumscribed square
+ *                of the fisheye view (in pixels)
+ * @field ydown - The y-coordinate of the bottom left corner of the circumscribed square
+ *                of the fisheye view, measured up from the bottom of the image like the
+ *                pcompos offsets (in pixels). Every stage uses this convention: the circle
+ *                center is at row height - (ydown + diameter / 2) counted from the top.
+ * @field xdim - The x-dimensional resolution to resize the HDR image to (in pixels)
+ * @field ydim - The y-dimensional resolution to resize the HDR image to (in pixels)
+ * @field vertical_angle - The vertical fov of the fisheye view, in degrees
+ * @field horizontal_angle - The horizontal fov of the fisheye view, in degrees
+ * @field luminance - Arguments for the falsecolor luminance map
+ * @field evalglare - Arguments for the evalglare glare analysis
+ * @field filter_images - Whether to filter out LDR images that don't contribute to the HDR image
+ * @field merge_backend - Program merging the LDR images into the HDR image
+ * @field align_exposures - Whether the LDR images are aligned before they are merged, for
+ *                         handheld or bumped brackets
+ * @field bracket_check - What happens when the images of a scene mix ISO or aperture, repeat an
+ *                        exposure, or have gaps larger than max_ev_step
+ * @field max_ev_step - Largest allowed difference between consecutive exposures, in EV
+ * @field check_fisheye_circle - Whether diameter, xleft and ydown are checked against the
+ *                               fisheye circle detected in the images of each scene
+ * @field stages - Ordered description of the stages to run (the standard order if omitted)
+ * @field workers - Number of scenes processed at the same time when batch processing
+ * @field continue_on_error - Whether a batch goes on with the other scenes when a scene fails
+ * @field use_cache - Whether stages whose inputs and parameters did not change since an earlier
+ *                    run are restored from the stage cache instead of running again
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineParams {
+    #[serde(default)]
+    pub radiance_path: PathBuf,
+    #[serde(default)]
+    pub hdrgen_path: PathBuf,
+    #[serde(default)]
+    pub dcraw_emu_path: PathBuf,
+    pub output_path: PathBuf,
+    #[serde(default)]
+    pub input_images: Vec<String>,
+    #[serde(default)]
+    pub bracket_groups: Vec<BracketGroup>,
+    #[serde(default)]
+    pub recursive: bool,
+    #[serde(default)]
+    pub scene_filter: Vec<String>,
+    pub response_function: Option<PathBuf>,
+    pub fisheye_correction_cal: Option<PathBuf>,
+    pub vignetting_correction_cal: Option<PathBuf>,
+    pub photometric_adjustment_cal: Option<PathBuf>,
+    pub neutral_density_cal: Option<PathBuf>,
+    pub diameter: u32,
+    pub xleft: i64,
+    pub ydown: i64,
+    pub xdim: u32,
+    pub ydim: u32,
+    pub vertical_angle: f64,
+    pub horizontal_angle: f64,
+    #[serde(default)]
+    pub luminance: LuminanceArgs,
+    #[serde(default)]
+    pub evalglare: EvalglareArgs,
+    #[serde(default)]
+    pub filter_images: bool,
+    #[serde(default)]
+    pub merge_backend: MergeBackend,
+    #[serde(default)]
+    pub align_exposures: bool,
+    #[serde(default)]
+    pub bracket_check: BracketCheck,
+    #[serde(default = "default_max_ev_step")]
+    pub max_ev_step: f64,
+    #[serde(default)]
+    pub check_fisheye_circle: bool,
+    #[serde(default)]
+    pub stages: Option<Vec<StageEntry>>,
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    #[serde(default)]
+    pub continue_on_error: bool,
+    #[serde(default = "default_use_cache")]
+    pub use_cache: bool,
+}
+
+impl PipelineParams {
+    /**
+     * Checks the parameters before the pipeline runs. Returns every problem found, so the
+     * user can fix them all at once.
+     *
+     * Checks value ranges, that the crop square lies inside the source images, that the
+     * response function, calibration files and tool directories exist, and that the stages
+     * can run in the described order.
+     *
+     * @returns Ok if the parameters are valid, otherwise a list of error messages
+     */
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = Vec::new();
+
+        if self.input_images.is_empty() && self.bracket_groups.is_empty() {
+            errors.push("No input images were provided.".into());
+        }
+        check_bracket_groups(&self.bracket_groups, &mut errors);
+        if self.recursive {
+            for input in &self.input_images {
+                if !Path::new(input).is_dir() {
+                    errors.push(format!(
+                        "Recursive discovery requires input directories ({} is not one).",
+                        input
+                    ));
+                }
+            }
+        }
+        if self.workers == 0 || self.workers > MAX_WORKERS {
+            errors.push(format!(
+                "The number of workers must be between 1 and {} (got {}).",
+                MAX_WORKERS, self.workers
+            ));
+        }
+
+        if !(self.max_ev_step > 0.0 && self.max_ev_step.is_finite()) {
+            errors.push(format!(
+                "The largest EV step between exposures must be greater than 0 (got {}).",
+                self.max_ev_step
+            ));
+        }
+
+        // Fisheye view
+        if self.diameter == 0 {
+            errors.push("The fisheye view diameter must be greater than 0.".into());
+        }
+        if self.xleft < 0 {
+            errors.push(format!("xleft must not be negative (got {}).", self.xleft));
+        }
+        if self.ydown < 0 {
+            errors.push(format!("ydown must not be negative (got {}).", self.ydown));
+        }
+
+        // Target resolution
+        for (name, value) in [("xdim", self.xdim), ("ydim", self.ydim)] {
+            if value == 0 || value > MAX_TARGET_RESOLUTION {
+                errors.push(format!(
+                    "{} must be between 1 and {} pixels (got {}).",
+                    name, MAX_TARGET_RESOLUTION, value
+                ));
+            }
+        }
+
+        // View angles
+        for (name, value) in [
+            ("Vertical view angle", self.vertical_angle),
+            ("Horizontal view angle", self.horizontal_angle),
+        ] {
+            if !(value > 0.0 && value <= MAX_VIEW_ANGLE) {
+                errors.push(format!(
+                    "{} must be greater than 0 and at most {} degrees (got {}).",
+                    name, MAX_VIEW_ANGLE, value
+                ));
+            }
+        }
+
+        // Falsecolor settings
+        if let Some(scale_limit) = self.luminance.scale_limit {
+            if !(scale_limit > 0.0 && scale_limit.is_finite()) {
+                errors.push(format!(
+                    "The falsecolor scale limit must be greater than 0 (got {}).",
+                    scale_limit
+                ));
+            }
+        }
+        if self.luminance.scale_levels == Some(0) {
+            errors.push("The number of falsecolor scale levels must be greater than 0.".into());
+        }
+        if self.luminance.legend_width == Some(0) || self.luminance.legend_height == Some(0) {
+            errors.push("The falsecolor legend dimensions must be greater than 0.".into());
+        }
+
+        // Evalglare settings
+        if let Some(threshold) = self.evalglare.threshold {
+            if !(threshold > 0.0 && threshold.is_finite()) {
+                errors.push(format!(
+                    "The evalglare glare source threshold must be greater than 0 (got {}).",
+                    threshold
+                ));
+            }
+        }
+        if let Some(task_area) = &self.evalglare.task_area {
+            if !(task_area.angle > 0.0 && task_area.angle <= MAX_VIEW_ANGLE) {
+                errors.push(format!(
+                    "The evalglare task zone angle must be greater than 0 and at most {} degrees (got {}).",
+                    MAX_VIEW_ANGLE, task_area.angle
+                ));
+            }
+            if !(task_area.x >= 0.0 && task_area.y >= 0.0) {
+                errors.push(format!(
+                    "The evalglare task zone center must not be negative (got {}, {}).",
+                    task_area.x, task_area.y
+                ));
+            }
+        }
+        if let Some(peak_threshold) = self.evalglare.peak_threshold {
+            if !self.evalglare.peak_extraction {
+                errors.push(
+                    "The evalglare peak threshold can't be set when peak extraction is disabled."
+                        .into(),
+                );
+            } else if !(peak_threshold > 0.0 && peak_threshold.is_finite()) {
+                errors.push(format!(
+                    "The evalglare peak threshold must be greater than 0 (got {}).",
+                    peak_threshold
+                ));
+            }
+        }
+
+        // Response function and calibration files
+        let calibration_files = [
+            ("Camera response function", &self.response_function),
+            ("Fisheye correction file", &self.fisheye_correction_cal),
+            (
+                "Vignetting correction file",
+                &self.vignetting_correction_cal,
+            ),
+            (
+                "Photometric adjustment file",
+                &self.photometric_adjustment_cal,
+            ),
+            ("Neutral density file", &self.neutral_density_cal),
+        ];
+        for (name, path) in calibration_files {
+            if let Some(path) = path {
+                check_calibration_file(name, path, &mut errors);
+            }
+        }
+
+        // The native merge linearizes the pixel values with the response function, it does not
+        // recover one from the images like hdrgen
+        if self.merge_backend == MergeBackend::Native && self.response_function.is_none() {
+            errors.push(
+                "The native exposure merge requires a camera response function (.rsp).".into(),
+            );
+        }
+
+        // Tool directories (empty means the default location is used)
+        for (name, path) in [
+            ("Radiance", &self.radiance_path),
+            ("hdrgen", &self.hdrgen_path),
+            ("dcraw_emu", &self.dcraw_emu_path),
+        ] {
+            if !path.as_os_str().is_empty() && !path.is_dir() {
+                errors.push(format!(
+                    "{} directory does not exist: {}",
+                    name,
+                    path.display()
+                ));
+            }
+        }
+
+        // Pipeline description
+        if let Err(stage_errors) = build_stages(self) {
+            errors.extend(stage_errors);
+        }
+
+        // Crop square must lie inside the source images
+        if self.diameter > 0 && self.xleft >= 0 && self.ydown >= 0 {
+            if let Some((path, width, height)) = self.first_image_dimensions() {
+                let right = self.xleft + self.diameter as i64;
+                let top = self.ydown + self.diameter as i64;
+                if right > width as i64 || top > height as i64 {
+                    errors.push(format!(
+                        "The fisheye crop square ({}x{} at xleft {}, ydown {}) falls outside the source image {} ({}x{}).",
+                        self.diameter,
+                        self.diameter,
+                        self.xleft,
+                        self.ydown,
+                        path.display(),
+                        width,
+                        height
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Returns the dimensions of the first input image that can be read without conversion
+    // (JPG or TIFF). Raw images are skipped, their size is only known after dcraw_emu runs.
+    fn first_image_dimensions(&self) -> Option<(PathBuf, u32, u32)> {
+        if let Some(group) = self.bracket_groups.first() {
+            return first_readable_dimensions(group.images.iter().map(PathBuf::from).collect());
+        }
+
+        let first_input = PathBuf::from(self.input_images.first()?);
+        let candidates: Vec<PathBuf> = if first_input.is_dir() {
+            super::get_images_from_dir(&first_input.display().to_string())
+                .ok()?
+                .into_iter()
+                .map(PathBuf::from)
+                .collect()
+        } else {
+            vec![first_input]
+        };
+
+        first_readable_dimensions(candidates)
+    }
+}
+
+// Returns the dimensions of the first image that is a JPG or TIFF
+fn first_readable_dimensions(candidates: Vec<PathBuf>) -> Option<(PathBuf, u32, u32)> {
+    candidates.into_iter().find_map(|path| {
+        let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+        if !["jpg", "jpeg", "tif", "tiff"]
+            .iter()
+            .any(|e| extension == *e)
+        {
+            return None;
+        }
+        image::image_dimensions(&path)
+            .ok()
+            .map(|(width, height)| (path, width, height))
+    })
+}
+
+// Scenes are processed one after another unless a worker count is given
+fn default_workers() -> usize {
+    1
+}
+
+// Brackets may have up to 3 EV between consecutive exposures unless set otherwise
+fn default_max_ev_step() -> f64 {
+    DEFAULT_MAX_EV_STEP
+}
+
+// Stage outputs are cached unless disabled
+fn default_use_cache() -> bool {
+    true
+}
+
+// Checks that a response function or calibration file exists
+fn check_calibration_file(name: &str, path: &Path, errors: &mut Vec<String>) {
+    if !path.is_file() {
+        errors.push(format!("{} not found: {}", name, path.display()));
+    }
+}
+
+// Checks that every bracket group has images and a unique name that can be used as a file name
+fn check_bracket_groups(groups: &[BracketGroup], errors: &mut Vec<String>) {
+    let mut names: HashSet<&str> = HashSet::new();
+    for group in groups {
+        if group.name.is_empty() || group.name.contains(['/', '\\']) || group.name == ".." {
+            errors.push(format!(
+                "Invalid bracket group name '{}', names are used as file names.",
+                group.name
+            ));
+        } else if !names.insert(&group.name) {
+            errors.push(format!(
+                "Bracket group names must be unique ('{}' is used more than once).",
+                group.name
+            ));
+        }
+        if group.images.is_empty() {
+            errors.push(format!("Bracket group '{}' has no images.", group.name));
+        }
+    }
+}
@@ -12,7 +12,7 @@ use std::fs::File;
 use std::process::Command;
 use std::process::Stdio;
 
-use super::ConfigSettings;
+use super::{run_command, ConfigSettings, PipelineError};
 
 /**
  * Applies photometric adjustments to an HDR image using Radiance's pcomb utility.
@@ -37,7 +37,7 @@ pub fn photometric_adjustment(
     input_file: String,
     output_file: String,
     photometric_adjustment: String,
-) -> Result<String, String> {
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!("photometric_adjustment() was called with parameters:\n\t photometric_adjustment: {photometric_adjustment}");
     }
@@ -65,11 +65,12 @@ pub fn photometric_adjustment(
     command.stdout(stdio);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: photometric_adjustment: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command(
+        "photometric_adjustment",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
 
     if DEBUG {
         println!(
@@ -86,6 +87,10 @@ pub fn photometric_adjustment(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'pcomb' (photometric adjustment) failed.".into())
+        Err(PipelineError::command_failed(
+            "photometric_adjustment",
+            &command,
+            &output,
+        ))
     }
 }
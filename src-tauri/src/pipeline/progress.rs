@@ -0,0 +1,291 @@
+/**
+ * Module for tracking and reporting the progress of a pipeline run.
+ *
+ * Progress is reported per stage as a PipelineProgress event, which carries the stage name,
+ * the position of the current scene in the batch, per-stage timings and an estimate of the
+ * remaining time. The percentage covers the whole run (all scenes of a batch), so it does not
+ * restart for each directory, and stays correct when scenes are processed in parallel.
+ */
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::{FrameOffset, GlareMetrics, PipelineError, PipelineHost, Scene};
+
+/**
+ * What a progress event reports
+ */
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProgressEvent {
+    // The run has started, no stage has run yet
+    Started,
+    // A stage has started on a scene
+    StageStarted,
+    // A stage has finished on a scene
+    StageFinished,
+    // All stages of a scene have run
+    SceneFinished,
+    // A scene has failed, its remaining stages will not run
+    SceneFailed,
+    // A scene has problems that do not stop it, e.g. a bracket failing the bracket checks
+    Warning,
+    // All scenes have been processed
+    Finished,
+}
+
+/**
+ * Progress of a pipeline run, sent to the frontend as the "pipeline-progress" event.
+ * When scenes are processed in parallel, events of different scenes are interleaved.
+ *
+ * @field event - What happened
+ * @field percent - Percentage of the stages of the whole run that have completed (0 to 100)
+ * @field scene_index - Position of the scene in the batch, starting at 1 (0 if no scene)
+ * @field scene_count - Number of scenes in the run (1 unless batch processing)
+ * @field scene_name - Name of the scene (input directory name, empty for a single scene)
+ * @field stage - Name of the stage, if any
+ * @field stage_index - Position of the stage in the pipeline, starting at 1 (0 if no stage)
+ * @field stage_count - Number of stages run for each scene
+ * @field stage_elapsed_secs - Time the stage took, for StageFinished events
+ * @field cached - Whether the stage's outputs were restored from the stage cache instead of
+ *                 running it, for StageFinished events
+ * @field elapsed_secs - Time since the run started
+ * @field eta_secs - Estimated time until the run finishes, once a stage has completed
+ * @field error - Why the scene failed, for SceneFailed events
+ * @field warnings - The problems found, for Warning events
+ * @field glare_metrics - The glare metrics of the scene, for SceneFinished events (None if
+ *                       evalglare did not run)
+ * @field frame_offsets - The shift applied to each LDR image when aligning the exposures, for
+ *                        SceneFinished events (empty if they were not aligned, or the merged
+ *                        image was restored from the stage cache)
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineProgress {
+    pub event: ProgressEvent,
+    pub percent: i32,
+    pub scene_index: usize,
+    pub scene_count: usize,
+    pub scene_name: String,
+    pub stage: Option<String>,
+    pub stage_index: usize,
+    pub stage_count: usize,
+    pub stage_elapsed_secs: Option<f64>,
+    pub cached: bool,
+    pub elapsed_secs: f64,
+    pub eta_secs: Option<f64>,
+    pub error: Option<PipelineError>,
+    pub warnings: Vec<String>,
+    pub glare_metrics: Option<GlareMetrics>,
+    pub frame_offsets: Vec<FrameOffset>,
+}
+
+/**
+ * Keeps track of the completed stages of a run and emits progress events to the host.
+ * Can be shared between the workers processing scenes in parallel.
+ */
+pub struct ProgressTracker<'a> {
+    host: &'a dyn PipelineHost,
+    start: Instant,
+    scene_count: usize,
+    stage_count: usize,
+    completed_steps: AtomicUsize,
+    completed_stages: Vec<AtomicUsize>, // completed stages of each scene
+}
+
+impl<'a> ProgressTracker<'a> {
+    /**
+     * Creates a tracker for a run and emits the Started event
+     *
+     * @param host - Host the events are emitted to
+     * @param scene_count - Number of scenes (image sets) in the run
+     * @param stage_count - Number of stages run for each scene
+     */
+    pub fn start(
+        host: &'a dyn PipelineHost,
+        scene_count: usize,
+        stage_count: usize,
+    ) -> Result<ProgressTracker<'a>, String> {
+        let tracker = ProgressTracker {
+            host,
+            start: Instant::now(),
+            scene_count,
+            stage_count,
+            completed_steps: AtomicUsize::new(0),
+            completed_stages: (0..scene_count).map(|_| AtomicUsize::new(0)).collect(),
+        };
+        tracker
+            .host
+            .emit_progress(&tracker.progress(ProgressEvent::Started))?;
+        Ok(tracker)
+    }
+
+    /**
+     * Emits a StageStarted event
+     *
+     * @param scene - The scene the stage runs on
+     * @param stage_index - Index of the stage in the pipeline, starting at 0
+     * @param stage - Name of the stage
+     */
+    pub fn stage_started(
+        &self,
+        scene: &Scene,
+        stage_index: usize,
+        stage: &str,
+    ) -> Result<(), String> {
+        let mut progress = self.scene_progress(ProgressEvent::StageStarted, scene);
+        progress.stage = Some(stage.to_string());
+        progress.stage_index = stage_index + 1;
+        self.host.emit_progress(&progress)
+    }
+
+    /**
+     * Records a completed stage and emits a StageFinished event
+     *
+     * @param scene - The scene the stage ran on
+     * @param stage_index - Index of the stage in the pipeline, starting at 0
+     * @param stage - Name of the stage
+     * @param stage_elapsed - Time the stage took
+     * @param cached - Whether the outputs were restored from the stage cache
+     */
+    pub fn stage_finished(
+        &self,
+        scene: &Scene,
+        stage_index: usize,
+        stage: &str,
+        stage_elapsed: Duration,
+        cached: bool,
+    ) -> Result<(), String> {
+        self.completed_steps.fetch_add(1, Ordering::SeqCst);
+        if let Some(completed) = self.completed_stages.get(scene.index) {
+            completed.fetch_add(1, Ordering::SeqCst);
+        }
+        let mut progress = self.scene_progress(ProgressEvent::StageFinished, scene);
+        progress.stage = Some(stage.to_string());
+        progress.stage_index = stage_index + 1;
+        progress.stage_elapsed_secs = Some(stage_elapsed.as_secs_f64());
+        progress.cached = cached;
+        self.host.emit_progress(&progress)
+    }
+
+    /**
+     * Emits a SceneFinished event
+     *
+     * @param scene - The scene whose stages have all run
+     * @param glare_metrics - The glare metrics of the scene, if evalglare ran
+     * @param frame_offsets - The shifts applied when aligning the exposures
+     */
+    pub fn scene_finished(
+        &self,
+        scene: &Scene,
+        glare_metrics: Option<&GlareMetrics>,
+        frame_offsets: &[FrameOffset],
+    ) -> Result<(), String> {
+        let mut progress = self.scene_progress(ProgressEvent::SceneFinished, scene);
+        progress.glare_metrics = glare_metrics.cloned();
+        progress.frame_offsets = frame_offsets.to_vec();
+        self.host.emit_progress(&progress)
+    }
+
+    /**
+     * Emits a SceneFailed event. The stages the scene will not run no longer count towards
+     * the percentage, so that it still reaches 100 at the end of the run.
+     *
+     * @param scene_index - Index of the scene in the run, starting at 0
+     * @param scene_name - Name of the scene
+     * @param error - Why the scene failed
+     */
+    pub fn scene_failed(
+        &self,
+        scene_index: usize,
+        scene_name: &str,
+        error: &PipelineError,
+    ) -> Result<(), String> {
+        let completed_stages = self
+            .completed_stages
+            .get(scene_index)
+            .map_or(0, |completed| {
+                completed.swap(self.stage_count, Ordering::SeqCst)
+            });
+        self.completed_steps.fetch_add(
+            self.stage_count.saturating_sub(completed_stages),
+            Ordering::SeqCst,
+        );
+        let mut progress = self.progress(ProgressEvent::SceneFailed);
+        progress.scene_index = scene_index + 1;
+        progress.scene_name = scene_name.to_string();
+        progress.error = Some(error.clone());
+        self.host.emit_progress(&progress)
+    }
+
+    /**
+     * Emits a Warning event
+     *
+     * @param scene - The scene the warnings are about
+     * @param warnings - The problems found
+     */
+    pub fn warning(&self, scene: &Scene, warnings: Vec<String>) -> Result<(), String> {
+        let mut progress = self.scene_progress(ProgressEvent::Warning, scene);
+        progress.warnings = warnings;
+        self.host.emit_progress(&progress)
+    }
+
+    /**
+     * Emits the Finished event (100%)
+     */
+    pub fn finish(&self) -> Result<(), String> {
+        self.completed_steps
+            .store(self.total_steps(), Ordering::SeqCst);
+        self.host
+            .emit_progress(&self.progress(ProgressEvent::Finished))
+    }
+
+    fn total_steps(&self) -> usize {
+        self.scene_count * self.stage_count
+    }
+
+    // Creates an event for a scene, without stage information
+    fn scene_progress(&self, event: ProgressEvent, scene: &Scene) -> PipelineProgress {
+        let mut progress = self.progress(event);
+        progress.scene_index = scene.index + 1;
+        progress.scene_name = scene.name.clone();
+        progress
+    }
+
+    // Creates an event with the overall progress of the run
+    fn progress(&self, event: ProgressEvent) -> PipelineProgress {
+        let total_steps = self.total_steps().max(1);
+        let completed_steps = self.completed_steps.load(Ordering::SeqCst).min(total_steps);
+        let elapsed_secs = self.start.elapsed().as_secs_f64();
+
+        // Estimate the remaining time from the average time per completed stage so far
+        let eta_secs = if completed_steps > 0 {
+            let remaining_steps = total_steps - completed_steps;
+            Some(elapsed_secs / completed_steps as f64 * remaining_steps as f64)
+        } else {
+            None
+        };
+
+        PipelineProgress {
+            event,
+            percent: (completed_steps * 100 / total_steps) as i32,
+            scene_index: 0,
+            scene_count: self.scene_count,
+            scene_name: String::new(),
+            stage: None,
+            stage_index: 0,
+            stage_count: self.stage_count,
+            stage_elapsed_secs: None,
+            cached: false,
+            elapsed_secs,
+            eta_secs,
+            error: None,
+            warnings: Vec::new(),
+            glare_metrics: None,
+            frame_offsets: Vec::new(),
+        }
+    }
+}
@@ -12,7 +12,7 @@ use std::{
     process::{Command, Stdio},
 };
 
-use super::ConfigSettings;
+use super::{run_command, ConfigSettings, PipelineError};
 
 /**
  * Applies projection adjustment for the fisheye lens to an HDR image using Radiance's pcomb utility.
@@ -32,7 +32,7 @@ pub fn projection_adjustment(
     input_file: String,
     output_file: String,
     fisheye_correction_cal: String,
-) -> Result<String, String> {
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!("projection_adjustment() was called with parameters:");
         println!("\tfisheye_correction_cal: {fisheye_correction_cal}");
@@ -56,11 +56,12 @@ pub fn projection_adjustment(
     command.stdout(stdio);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: projection_adjustment: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command(
+        "projection_adjustment",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
 
     if DEBUG {
         println!(
@@ -75,6 +76,10 @@ pub fn projection_adjustment(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'pcomb' (projection adjustment) failed.".into())
+        Err(PipelineError::command_failed(
+            "projection_adjustment",
+            &command,
+            &output,
+        ))
     }
 }
@@ -0,0 +1,85 @@
+/**
+ * Module for running an extra Radiance command as a pipeline step.
+ *
+ * Labs can insert their own steps into the pipeline description (e.g. an additional pcomb
+ * correction with a lab-specific .cal file, or a pfilt pass). The command is run from the
+ * Radiance directory with the current HDR image as its last argument, and its standard output
+ * becomes the next HDR image.
+ */
+use crate::pipeline::DEBUG;
+use std::fs::File;
+use std::process::Command;
+use std::process::Stdio;
+
+use super::{run_command, ConfigSettings, PipelineError};
+
+/**
+ * Runs a Radiance program on an HDR image and saves its output
+ *
+ * @param config_settings - Contains configuration settings including path to Radiance and temp directory
+ * @param program - Name of the Radiance program to run (e.g. "pcomb")
+ * @param args - Arguments passed to the program before the input file
+ * @param input_file - The path to the input HDR image (must be in .hdr format)
+ * @param output_file - The path and filename where the resulting HDR image will be saved
+ *
+ * @returns Result<String, String> - On success, returns the path to the output file.
+ *                                  On failure, returns an error message.
+ */
+pub fn radiance_command(
+    config_settings: &ConfigSettings,
+    program: &str,
+    args: &[String],
+    input_file: String,
+    output_file: String,
+) -> Result<String, PipelineError> {
+    if DEBUG {
+        println!("radiance_command() was called with parameters:");
+        println!("\tprogram: {program}");
+        println!("\targs: {:?}", args);
+    }
+
+    // Create a new command for the program
+    let mut command = Command::new(config_settings.radiance_path.join(program));
+
+    // Add the user's arguments followed by the input image
+    command.args(args);
+    command.arg(input_file.as_str());
+
+    // Direct command's output to specifed output file
+    let file_result = File::create(&output_file);
+    if file_result.is_err() {
+        return Err(format!(
+            "pipeline: radiance_command: failed to create output file for '{}' command.",
+            program
+        )
+        .into());
+    }
+
+    let file = file_result.unwrap(); // Can safely unwrap result w/o panicking after checking for Err
+    command.stdout(Stdio::from(file));
+
+    // Run the command
+    let output = run_command(
+        "radiance_command",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
+
+    if DEBUG {
+        println!("\n'{}' command exit status: {:?}\n", program, status);
+    }
+
+    // Return a Result object to indicate whether command was successful
+    if status.success() {
+        // On success, return output path of HDR image
+        Ok(output_file)
+    } else {
+        // On error, return an error message
+        Err(PipelineError::command_failed(
+            "radiance_command",
+            &command,
+            &output,
+        ))
+    }
+}
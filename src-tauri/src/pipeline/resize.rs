@@ -11,7 +11,7 @@ use std::fs::File;
 use std::process::Command;
 use std::process::Stdio;
 
-use super::ConfigSettings;
+use super::{run_command, ConfigSettings, PipelineError};
 
 /**
  * Resizes an HDR image to the target x and y resolution using Radiance's pfilt utility.
@@ -31,9 +31,9 @@ pub fn resize(
     config_settings: &ConfigSettings,
     input_file: String,
     output_file: String,
-    xdim: String,
-    ydim: String,
-) -> Result<String, String> {
+    xdim: u32,
+    ydim: u32,
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!("resize() was called with parameters:");
         println!("\txdim: {xdim}");
@@ -47,9 +47,9 @@ pub fn resize(
     command.args([
         "-1",
         "-x",
-        xdim.as_str(),
+        xdim.to_string().as_str(),
         "-y",
-        ydim.as_str(),
+        ydim.to_string().as_str(),
         input_file.as_str(),
     ]);
 
@@ -67,11 +67,8 @@ pub fn resize(
     command.stdout(stdio);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: resize: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command("resize", &mut command, &config_settings.cancel_token)?;
+    let status = output.status;
 
     if DEBUG {
         println!("\nResize command exit status: {:?}\n", status);
@@ -83,6 +80,6 @@ pub fn resize(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'pfilt' (resizing) failed.".into())
+        Err(PipelineError::command_failed("resize", &command, &output))
     }
 }
@@ -0,0 +1,334 @@
+/**
+ * Module reading camera response functions (.rsp files).
+ *
+ * A .rsp file, as written by hdrgen, holds one line per channel (red, green, blue). Each line
+ * gives the order N of a polynomial followed by its N + 1 coefficients, highest power first
+ * (see example/response_function.rsp). The polynomial maps a pixel value normalized to 0..1 to
+ * the relative scene radiance, with f(1) = 1.
+ *
+ * Curves can be sampled for plotting, which also checks that they increase and flags channels
+ * that diverge from the others, as these usually point to a bad fit.
+ */
+use std::{fs, path::Path};
+
+use serde::Serialize;
+
+// Number of color channels described by a response function
+pub const CHANNEL_COUNT: usize = 3;
+
+// Number of pixel values the curves are checked at for monotonicity
+const MONOTONICITY_SAMPLES: usize = 256;
+
+// A channel diverges when its curve is further than this (as a fraction of full scale) from the
+// median of the three channels at any pixel value
+pub const DIVERGENCE_THRESHOLD: f64 = 0.1;
+
+// Names of the channels, in file order
+const CHANNEL_NAMES: [&str; CHANNEL_COUNT] = ["red", "green", "blue"];
+
+/**
+ * Sampled response curve of one channel
+ *
+ * @field channel - Name of the channel (red, green or blue)
+ * @field values - Relative radiance at each sampled pixel value
+ * @field increasing - Whether the curve increases over the whole 0..1 range
+ * @field max_deviation - Largest distance from the median of the three channels
+ * @field diverges - Whether max_deviation is above DIVERGENCE_THRESHOLD
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCurve {
+    pub channel: String,
+    pub values: Vec<f64>,
+    pub increasing: bool,
+    pub max_deviation: f64,
+    pub diverges: bool,
+}
+
+/**
+ * Response curves sampled at evenly spaced pixel values
+ *
+ * @field pixel_values - The sampled pixel values, from 0 to 1
+ * @field channels - Curves of the red, green and blue channels
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCurves {
+    pub pixel_values: Vec<f64>,
+    pub channels: Vec<ResponseCurve>,
+}
+
+/**
+ * Per-channel response curves of a camera
+ *
+ * @field channels - Polynomial coefficients of the red, green and blue channels, highest power
+ *                   first
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseFunction {
+    pub channels: Vec<Vec<f64>>,
+}
+
+impl ResponseFunction {
+    /**
+     * Reads a response function from a .rsp file
+     *
+     * @param path - Path to the .rsp file
+     */
+    pub fn read(path: &Path) -> Result<ResponseFunction, String> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Failed to read camera response function {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        ResponseFunction::parse(&contents)
+            .map_err(|e| format!("Invalid camera response function {}: {}", path.display(), e))
+    }
+
+    /**
+     * Parses the contents of a .rsp file
+     *
+     * @param contents - One line per channel: the polynomial order followed by its coefficients
+     */
+    pub fn parse(contents: &str) -> Result<ResponseFunction, String> {
+        let mut channels: Vec<Vec<f64>> = Vec::new();
+
+        for (line_index, line) in contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+        {
+            let values: Vec<&str> = line.split_whitespace().collect();
+            let order: usize = values[0].parse().map_err(|_| {
+                format!(
+                    "line {}: the polynomial order must be a whole number (got '{}')",
+                    line_index + 1,
+                    values[0]
+                )
+            })?;
+            // Checked, as the order comes from the file and order + 1 could overflow
+            if order.checked_add(1) != Some(values.len() - 1) {
+                return Err(format!(
+                    "line {}: a polynomial of order {} needs one coefficient more than its order (got {})",
+                    line_index + 1,
+                    order,
+                    values.len() - 1
+                ));
+            }
+
+            let coefficients = values[1..]
+                .iter()
+                .map(|value| {
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|coefficient| coefficient.is_finite())
+                        .ok_or_else(|| {
+                            format!("line {}: invalid coefficient '{}'", line_index + 1, value)
+                        })
+                })
+                .collect::<Result<Vec<f64>, String>>()?;
+            channels.push(coefficients);
+        }
+
+        if channels.len() != CHANNEL_COUNT {
+            return Err(format!(
+                "expected one line for each of the {} channels (got {})",
+                CHANNEL_COUNT,
+                channels.len()
+            ));
+        }
+
+        Ok(ResponseFunction { channels })
+    }
+
+    /**
+     * Returns the relative radiance of a pixel value
+     *
+     * @param channel - Index of the channel (0 red, 1 green, 2 blue)
+     * @param value - Pixel value, normalized to 0..1
+     */
+    pub fn evaluate(&self, channel: usize, value: f64) -> f64 {
+        // Horner's method, coefficients are stored highest power first
+        self.channels[channel]
+            .iter()
+            .fold(0.0, |result, coefficient| result * value + coefficient)
+    }
+
+    /**
+     * Returns whether the curve of a channel increases over the whole 0..1 range, i.e. whether
+     * brighter pixel values always mean more light
+     *
+     * @param channel - Index of the channel (0 red, 1 green, 2 blue)
+     */
+    pub fn is_increasing(&self, channel: usize) -> bool {
+        (0..MONOTONICITY_SAMPLES).all(|i| {
+            let value = i as f64 / MONOTONICITY_SAMPLES as f64;
+            let next = (i + 1) as f64 / MONOTONICITY_SAMPLES as f64;
+            self.evaluate(channel, next) > self.evaluate(channel, value)
+        })
+    }
+
+    /**
+     * Samples the curves at evenly spaced pixel values, checking their monotonicity and
+     * divergence
+     *
+     * @param sample_count - Number of pixel values to sample, including 0 and 1 (at least 2)
+     */
+    pub fn sample(&self, sample_count: usize) -> ResponseCurves {
+        let pixel_values: Vec<f64> = (0..sample_count)
+            .map(|i| i as f64 / (sample_count - 1) as f64)
+            .collect();
+        let values: Vec<Vec<f64>> = (0..CHANNEL_COUNT)
+            .map(|channel| {
+                pixel_values
+                    .iter()
+                    .map(|value| self.evaluate(channel, *value))
+                    .collect()
+            })
+            .collect();
+
+        // Distance of each channel from the median of the channels, sampled more finely than
+        // the returned curves so that narrow bumps are not missed
+        let mut max_deviations = [0.0_f64; CHANNEL_COUNT];
+        for i in 0..=MONOTONICITY_SAMPLES {
+            let value = i as f64 / MONOTONICITY_SAMPLES as f64;
+            let mut radiances: [f64; CHANNEL_COUNT] =
+                std::array::from_fn(|channel| self.evaluate(channel, value));
+            let channel_radiances = radiances;
+            radiances.sort_by(|a, b| a.total_cmp(b));
+            let median = radiances[CHANNEL_COUNT / 2];
+            for (max_deviation, radiance) in max_deviations.iter_mut().zip(channel_radiances) {
+                *max_deviation = max_deviation.max((radiance - median).abs());
+            }
+        }
+
+        let channels = values
+            .into_iter()
+            .enumerate()
+            .map(|(channel, values)| ResponseCurve {
+                channel: CHANNEL_NAMES[channel].to_string(),
+                values,
+                increasing: self.is_increasing(channel),
+                max_deviation: max_deviations[channel],
+                diverges: max_deviations[channel] > DIVERGENCE_THRESHOLD,
+            })
+            .collect();
+
+        ResponseCurves {
+            pixel_values,
+            channels,
+        }
+    }
+
+    /**
+     * Writes the response function to a .rsp file
+     *
+     * @param path - Path of the .rsp file to create or overwrite
+     */
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let contents: String = self
+            .channels
+            .iter()
+            .map(|coefficients| {
+                let values: Vec<String> = coefficients.iter().map(|c| c.to_string()).collect();
+                format!("{} {}\n", coefficients.len() - 1, values.join(" "))
+            })
+            .collect();
+        fs::write(path, contents).map_err(|e| {
+            format!(
+                "Failed to write camera response function {}: {}",
+                path.display(),
+                e
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Response function written by hdrgen, shipped as an example
+    const EXAMPLE_RSP: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../example/response_function.rsp"
+    );
+
+    #[test]
+    fn reads_example_file() {
+        let response_function = ResponseFunction::read(Path::new(EXAMPLE_RSP)).unwrap();
+        assert_eq!(response_function.channels.len(), CHANNEL_COUNT);
+        assert!(response_function
+            .channels
+            .iter()
+            .all(|coefficients| coefficients.len() == 5));
+        assert_eq!(response_function.channels[0][0], 2.48363666666666);
+        assert_eq!(response_function.channels[2][4], 0.00287970333333048);
+    }
+
+    #[test]
+    fn evaluates_polynomials_highest_power_first() {
+        let response_function = ResponseFunction::parse("2 1 0 0\n1 2 0\n0 0.5\n").unwrap();
+        assert_eq!(response_function.evaluate(0, 0.5), 0.25);
+        assert_eq!(response_function.evaluate(1, 0.5), 1.0);
+        assert_eq!(response_function.evaluate(2, 0.5), 0.5);
+
+        // hdrgen normalizes its curves so that f(1) = 1, up to the rounding of the coefficients
+        let response_function = ResponseFunction::read(Path::new(EXAMPLE_RSP)).unwrap();
+        for channel in 0..CHANNEL_COUNT {
+            assert!((response_function.evaluate(channel, 1.0) - 1.0).abs() < 1e-5);
+            assert!(response_function.is_increasing(channel));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let errors = [
+            ("x 1 0\n1 1 0\n1 1 0\n", "whole number"),
+            ("2 1 0\n1 1 0\n1 1 0\n", "order 2"),
+            ("1 1 nan\n1 1 0\n1 1 0\n", "invalid coefficient"),
+            ("1 1 0\n1 1 0\n", "got 2"),
+        ];
+        for (contents, expected) in errors {
+            let error = ResponseFunction::parse(contents).unwrap_err();
+            assert!(error.contains(expected), "{}", error);
+        }
+
+        // An order of usize::MAX must not overflow when counting its coefficients
+        let contents = format!("{} 1 0\n1 1 0\n1 1 0\n", usize::MAX);
+        assert!(ResponseFunction::parse(&contents).is_err());
+    }
+
+    #[test]
+    fn flags_diverging_channels() {
+        let response_function = ResponseFunction::read(Path::new(EXAMPLE_RSP)).unwrap();
+        let curves = response_function.sample(11);
+        assert_eq!(curves.pixel_values.len(), 11);
+        assert!(curves.channels.iter().all(|curve| !curve.diverges));
+
+        // Blue replaced by a linear curve, far from the gamma-like red and green curves
+        let mut response_function = response_function;
+        response_function.channels[2] = vec![1.0, 0.0];
+        let curves = response_function.sample(11);
+        let diverging: Vec<&str> = curves
+            .channels
+            .iter()
+            .filter(|curve| curve.diverges)
+            .map(|curve| curve.channel.as_str())
+            .collect();
+        assert_eq!(diverging, vec!["blue"]);
+        assert!(curves.channels[2].max_deviation > DIVERGENCE_THRESHOLD);
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let path = std::env::temp_dir().join(format!("response_{}.rsp", std::process::id()));
+        let response_function = ResponseFunction::read(Path::new(EXAMPLE_RSP)).unwrap();
+        response_function.write(&path).unwrap();
+        assert_eq!(ResponseFunction::read(&path).unwrap(), response_function);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
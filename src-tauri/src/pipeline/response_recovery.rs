@@ -0,0 +1,452 @@
+/**
+ * Module recovering the response function of a camera from bracket sets, without hdrgen.
+ *
+ * Follows Mitsunaga and Nayar ("Radiometric Self Calibration", CVPR 1999): the response of
+ * each channel is a polynomial f with f(1) = 1, and a pixel seen in two exposures of the same
+ * scene must satisfy f(M_short) = R * f(M_long), where R is the ratio of the exposures
+ * (t * ISO / N^2 from EXIF, see relative_exposures()). The coefficients are the least-squares
+ * solution of these equations over pixels sampled on a grid of every image pair with
+ * consecutive exposures. Exposure ratios are kept at their EXIF values rather than refined,
+ * which avoids the exponential ambiguity of the self-calibration.
+ */
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::{
+    image_metadata::{read_metadata, relative_exposures},
+    response_function::{ResponseFunction, CHANNEL_COUNT},
+};
+
+// Highest polynomial order tried when the order is not given
+pub const MAX_ORDER: usize = 6;
+
+// Pixels are sampled on a grid of this many columns and rows in every image
+const SAMPLE_GRID: u32 = 64;
+
+// Samples darker or brighter than this (normalized to 0..1) in either exposure are clipped or
+// buried in noise, and left out of the fit
+const MIN_SAMPLE_VALUE: f32 = 0.02;
+const MAX_SAMPLE_VALUE: f32 = 0.98;
+
+// Image pairs whose exposure ratio is above this carry no information about the response
+const MAX_EXPOSURE_RATIO: f64 = 0.95;
+
+// When the order is not given, the lowest order whose residual is within this factor of the
+// best residual is used, as higher orders always fit the samples a little closer
+const ORDER_TOLERANCE: f64 = 1.05;
+
+/**
+ * Fit of the response curve of one channel
+ *
+ * @field order - Order of the fitted polynomial
+ * @field rms_residual - Root mean square of f(M_short) - R * f(M_long) over the samples
+ * @field max_residual - Largest absolute residual over the samples
+ * @field sample_count - Number of sample pairs the curve was fitted to
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelFit {
+    pub order: usize,
+    pub rms_residual: f64,
+    pub max_residual: f64,
+    pub sample_count: usize,
+}
+
+/**
+ * Result of a response function recovery
+ *
+ * @field response_function - The recovered curves
+ * @field channels - Fit of the red, green and blue channels
+ */
+#[derive(Clone, Debug)]
+pub struct ResponseRecovery {
+    pub response_function: ResponseFunction,
+    pub channels: Vec<ChannelFit>,
+}
+
+// A pixel sampled in two exposures of a bracket: its values in the shorter and the longer
+// exposure, and the ratio of their exposures
+struct SamplePair {
+    short: [f32; CHANNEL_COUNT],
+    long: [f32; CHANNEL_COUNT],
+    ratio: f64,
+}
+
+/**
+ * Recovers the response function of a camera from bracket sets of JPG or TIFF images
+ *
+ * @param bracket_sets - Paths to the images of each bracket set. Every set holds exposures of
+ *                       the same scene, taken from a tripod.
+ * @param order - Order of the polynomials, or None to pick one from 1 to MAX_ORDER
+ */
+pub fn recover_response(
+    bracket_sets: &[Vec<String>],
+    order: Option<usize>,
+) -> Result<ResponseRecovery, String> {
+    if let Some(order) = order {
+        if order == 0 || order > MAX_ORDER {
+            return Err(format!(
+                "The polynomial order must be between 1 and {} (got {}).",
+                MAX_ORDER, order
+            ));
+        }
+    }
+
+    let mut pairs: Vec<SamplePair> = Vec::new();
+    for bracket_set in bracket_sets {
+        pairs.extend(sample_bracket_set(bracket_set)?);
+    }
+
+    let mut response_function = ResponseFunction {
+        channels: Vec::new(),
+    };
+    let mut channels: Vec<ChannelFit> = Vec::new();
+    for channel in 0..CHANNEL_COUNT {
+        // Only samples that are well exposed in both images constrain the curve
+        let samples: Vec<(f64, f64, f64)> = pairs
+            .iter()
+            .filter(|pair| {
+                [pair.short[channel], pair.long[channel]]
+                    .iter()
+                    .all(|value| (MIN_SAMPLE_VALUE..=MAX_SAMPLE_VALUE).contains(value))
+            })
+            .map(|pair| {
+                (
+                    pair.short[channel] as f64,
+                    pair.long[channel] as f64,
+                    pair.ratio,
+                )
+            })
+            .collect();
+
+        let (coefficients, fit) = fit_channel(&samples, order).ok_or(format!(
+            "Could not fit a monotonic response curve to the {} channel from {} samples. Use brackets with more exposures, or with larger well-exposed areas.",
+            ["red", "green", "blue"][channel],
+            samples.len()
+        ))?;
+        response_function.channels.push(coefficients);
+        channels.push(fit);
+    }
+
+    Ok(ResponseRecovery {
+        response_function,
+        channels,
+    })
+}
+
+// Samples the images of a bracket set on a grid, and pairs the samples of images with
+// consecutive exposures
+fn sample_bracket_set(images: &[String]) -> Result<Vec<SamplePair>, String> {
+    if images.len() < 2 {
+        return Err(format!(
+            "A bracket set needs at least two exposures (got {}).",
+            images.len()
+        ));
+    }
+
+    let metadata = images
+        .iter()
+        .map(|image_path| read_metadata(Path::new(image_path)))
+        .collect::<Result<Vec<_>, String>>()?;
+    let image_exposures = relative_exposures(&metadata)?;
+
+    let mut exposures: Vec<(f64, Vec<[f32; CHANNEL_COUNT]>)> = Vec::new();
+    let mut dimensions: Option<(u32, u32)> = None;
+    for (image_path, exposure) in images.iter().zip(image_exposures) {
+        let image = image::open(image_path)
+            .map_err(|e| format!("Failed to open image {}: {}", image_path, e))?
+            .into_rgb32f();
+        if dimensions.is_some_and(|expected| expected != image.dimensions()) {
+            return Err(format!(
+                "Image {} does not have the same size as the other images of its bracket set.",
+                image_path
+            ));
+        }
+        dimensions = Some(image.dimensions());
+
+        // Sample the centers of the grid cells
+        let (width, height) = image.dimensions();
+        let mut samples: Vec<[f32; CHANNEL_COUNT]> = Vec::new();
+        for row in 0..SAMPLE_GRID {
+            for column in 0..SAMPLE_GRID {
+                let x = ((2 * column + 1) as u64 * width as u64 / (2 * SAMPLE_GRID) as u64) as u32;
+                let y = ((2 * row + 1) as u64 * height as u64 / (2 * SAMPLE_GRID) as u64) as u32;
+                samples.push(image.get_pixel(x, y).0);
+            }
+        }
+        exposures.push((exposure, samples));
+    }
+
+    exposures.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut pairs: Vec<SamplePair> = Vec::new();
+    for exposure_pair in exposures.windows(2) {
+        let (short_exposure, short_samples) = &exposure_pair[0];
+        let (long_exposure, long_samples) = &exposure_pair[1];
+        let ratio = short_exposure / long_exposure;
+        if ratio > MAX_EXPOSURE_RATIO {
+            continue;
+        }
+        pairs.extend(
+            short_samples
+                .iter()
+                .zip(long_samples)
+                .map(|(short, long)| SamplePair {
+                    short: *short,
+                    long: *long,
+                    ratio,
+                }),
+        );
+    }
+    Ok(pairs)
+}
+
+// Fits the response polynomial of one channel to (short value, long value, exposure ratio)
+// samples. Returns the coefficients, highest power first, and the fit statistics, or None if
+// no monotonic curve fits.
+fn fit_channel(
+    samples: &[(f64, f64, f64)],
+    order: Option<usize>,
+) -> Option<(Vec<f64>, ChannelFit)> {
+    let orders = match order {
+        Some(order) => order..=order,
+        None => 1..=MAX_ORDER,
+    };
+
+    let fits: Vec<(Vec<f64>, ChannelFit)> = orders
+        .filter_map(|order| fit_order(samples, order))
+        .filter(|(coefficients, _)| {
+            ResponseFunction {
+                channels: vec![coefficients.clone()],
+            }
+            .is_increasing(0)
+        })
+        .collect();
+
+    let best_residual = fits
+        .iter()
+        .map(|(_, fit)| fit.rms_residual)
+        .min_by(|a, b| a.total_cmp(b))?;
+    fits.into_iter()
+        .find(|(_, fit)| fit.rms_residual <= best_residual * ORDER_TOLERANCE)
+}
+
+// Least-squares fit of a polynomial of the given order with f(1) = 1
+fn fit_order(samples: &[(f64, f64, f64)], order: usize) -> Option<(Vec<f64>, ChannelFit)> {
+    // Need more equations than unknowns
+    if samples.len() <= order {
+        return None;
+    }
+
+    // With c_k the coefficient of M^k and d_k = M_short^k - R * M_long^k, each sample gives
+    // sum(c_k * d_k) = 0. Substituting c_order = 1 - sum(c_k, k < order) leaves the unknowns
+    // c_0 .. c_(order - 1) with rows a_k = d_k - d_order and right-hand side -d_order.
+    let row = |&(short, long, ratio): &(f64, f64, f64)| -> (Vec<f64>, f64) {
+        let d: Vec<f64> = (0..=order)
+            .map(|k| short.powi(k as i32) - ratio * long.powi(k as i32))
+            .collect();
+        ((0..order).map(|k| d[k] - d[order]).collect(), -d[order])
+    };
+
+    // Normal equations: (A^T A) c = A^T b
+    let mut normal = vec![vec![0.0; order + 1]; order];
+    for sample in samples {
+        let (a, b) = row(sample);
+        for i in 0..order {
+            for j in 0..order {
+                normal[i][j] += a[i] * a[j];
+            }
+            normal[i][order] += a[i] * b;
+        }
+    }
+    let lower = solve(normal)?;
+
+    // Coefficients by increasing power, then stored highest power first like .rsp files
+    let mut coefficients = lower.clone();
+    coefficients.push(1.0 - lower.iter().sum::<f64>());
+    let response = ResponseFunction {
+        channels: vec![coefficients.iter().rev().cloned().collect()],
+    };
+
+    let residuals: Vec<f64> = samples
+        .iter()
+        .map(|&(short, long, ratio)| {
+            response.evaluate(0, short) - ratio * response.evaluate(0, long)
+        })
+        .collect();
+    let rms_residual =
+        (residuals.iter().map(|r| r * r).sum::<f64>() / residuals.len() as f64).sqrt();
+    let max_residual = residuals.iter().fold(0.0_f64, |max, r| max.max(r.abs()));
+
+    Some((
+        response.channels.into_iter().next()?,
+        ChannelFit {
+            order,
+            rms_residual,
+            max_residual,
+            sample_count: samples.len(),
+        },
+    ))
+}
+
+// Solves a linear system given as an augmented matrix, with Gaussian elimination and partial
+// pivoting. Returns None if the system is singular.
+fn solve(mut matrix: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let size = matrix.len();
+    for column in 0..size {
+        let pivot = (column..size).max_by(|a, b| {
+            matrix[*a][column]
+                .abs()
+                .total_cmp(&matrix[*b][column].abs())
+        })?;
+        if matrix[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+
+        for row in column + 1..size {
+            let factor = matrix[row][column] / matrix[column][column];
+            let (upper, lower) = matrix.split_at_mut(row);
+            for (target, source) in lower[0][column..].iter_mut().zip(&upper[column][column..]) {
+                *target -= factor * source;
+            }
+        }
+    }
+
+    let mut solution = vec![0.0; size];
+    for row in (0..size).rev() {
+        let sum: f64 = (row + 1..size).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (matrix[row][size] - sum) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use image::{DynamicImage, ImageBuffer, Rgb};
+
+    use super::*;
+    use crate::pipeline::{alignment::write_aligned, image_metadata::ImageMetadata};
+
+    // Response used to simulate the camera, highest power first: f(M) = 0.5 M^3 + 0.3 M^2 + 0.2 M
+    const RESPONSE: [f64; 4] = [0.5, 0.3, 0.2, 0.0];
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Pixel value recording the given light (1 saturates), by bisection of the response
+    fn pixel_value(light: f64) -> u16 {
+        let response = ResponseFunction {
+            channels: vec![RESPONSE.to_vec()],
+        };
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..40 {
+            let middle = (low + high) / 2.0;
+            if response.evaluate(0, middle) < light {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        (low * u16::MAX as f64).round() as u16
+    }
+
+    // Writes an exposure of a scene whose light grows from left to right and top to bottom, as a
+    // 16-bit TIFF with the given EXIF exposure time and ISO
+    fn write_exposure(
+        dir: &Path,
+        size: u32,
+        exposure_time: f64,
+        iso: u32,
+        exposure: f64,
+    ) -> String {
+        let frame = ImageBuffer::from_fn(size, size, |x, y| {
+            let position = (y * size + x) as f64 / (size * size) as f64;
+            let light = 0.005 * 300_f64.powf(position);
+            Rgb([pixel_value((light * exposure).min(1.0)); 3])
+        });
+        let metadata = ImageMetadata {
+            exposure_time: Some(exposure_time),
+            iso: Some(iso),
+            ..Default::default()
+        };
+        let path = dir.join(format!("exposure_{}_{}_{}.tiff", size, exposure_time, iso));
+        write_aligned(&DynamicImage::ImageRgb16(frame), Some(&metadata), &path).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn recovers_known_polynomial_from_bracket_with_mixed_iso() {
+        let dir = test_dir("response_recovery");
+        // Exposures of 1/8, 1/4, 1/2 and 1, two of them reached by raising the ISO
+        let bracket_sets = vec![[(0.125, 100, 0.125), (0.125, 200, 0.25)]
+            .into_iter()
+            .chain([(0.25, 200, 0.5), (0.25, 400, 1.0)])
+            .map(|(time, iso, exposure)| write_exposure(&dir, 128, time, iso, exposure))
+            .collect::<Vec<String>>()];
+
+        let recovery = recover_response(&bracket_sets, Some(3)).unwrap();
+        for coefficients in &recovery.response_function.channels {
+            assert_eq!(coefficients.len(), 4);
+            for (coefficient, expected) in coefficients.iter().zip(RESPONSE) {
+                assert!(
+                    (coefficient - expected).abs() < 0.01,
+                    "{:?} instead of {:?}",
+                    coefficients,
+                    RESPONSE
+                );
+            }
+        }
+
+        // Whatever the order picked, f(1) = 1 and the curve follows the simulated one
+        let recovery = recover_response(&bracket_sets, None).unwrap();
+        let simulated = ResponseFunction {
+            channels: vec![RESPONSE.to_vec()],
+        };
+        for channel in 0..CHANNEL_COUNT {
+            let response_function = &recovery.response_function;
+            assert!((response_function.evaluate(channel, 1.0) - 1.0).abs() < 1e-9);
+            for i in 1..10 {
+                let value = i as f64 / 10.0;
+                let difference =
+                    response_function.evaluate(channel, value) - simulated.evaluate(0, value);
+                assert!(difference.abs() < 0.01, "{} at {}", difference, value);
+            }
+            assert!(recovery.channels[channel].rms_residual < 1e-3);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_single_exposures_and_mismatched_sizes() {
+        let dir = test_dir("response_recovery_errors");
+        let short = write_exposure(&dir, 64, 0.125, 100, 0.125);
+        let error = recover_response(&[vec![short.clone()]], None).unwrap_err();
+        assert!(error.contains("at least two exposures"), "{}", error);
+
+        let long = write_exposure(&dir, 32, 0.5, 100, 0.5);
+        let error = recover_response(&[vec![short, long]], None).unwrap_err();
+        assert!(error.contains("does not have the same size"), "{}", error);
+
+        let error = recover_response(&[], Some(MAX_ORDER + 1)).unwrap_err();
+        assert!(error.contains("between 1 and"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn solves_linear_systems() {
+        // y = 2 and x + y = 3 (augmented matrix), whose first pivot is zero, then x + y = 3 and
+        // 2x - y = 0: both have x = 1, y = 2
+        let solution = solve(vec![vec![0.0, 1.0, 2.0], vec![1.0, 1.0, 3.0]]).unwrap();
+        assert!((solution[0] - 1.0).abs() < 1e-12 && (solution[1] - 2.0).abs() < 1e-12);
+        let solution = solve(vec![vec![1.0, 1.0, 3.0], vec![2.0, -1.0, 0.0]]).unwrap();
+        assert!((solution[0] - 1.0).abs() < 1e-12 && (solution[1] - 2.0).abs() < 1e-12);
+
+        assert_eq!(solve(vec![vec![1.0, 2.0, 3.0], vec![2.0, 4.0, 6.0]]), None);
+    }
+}
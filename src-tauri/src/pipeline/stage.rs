@@ -0,0 +1,918 @@
+/**
+ * Module describing the pipeline as an ordered list of stages.
+ *
+ * Every step of the pipeline (merging exposures, cropping, applying a calibration file, ...)
+ * implements the Stage trait. Stages exchange artifacts (the LDR input images, the current HDR
+ * image, the evalglare metrics and the falsecolor map) instead of hard-coded file names, so a
+ * pipeline description can list them in any order, leave some out, or insert extra Radiance
+ * commands, as long as each stage's inputs are produced by an earlier stage.
+ *
+ * When a run does not provide a description, default_stages() reproduces the standard order:
+ * merge -> nullify -> crop -> resize -> projection -> vignetting -> ND -> photometric ->
+ * evalglare -> header editing -> falsecolor.
+ */
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    crop::crop,
+    evalglare::{evalglare, find_evalglare, GlareMetrics},
+    falsecolor::falsecolor,
+    header_editing::header_editing,
+    merge_exposures::merge_exposures,
+    native_glare::{compare_glare, compute_glare},
+    neutral_density::neutral_density,
+    nullify_exposure_value::nullify_exposure_value,
+    photometric_adjustment::photometric_adjustment,
+    projection_adjustment::projection_adjustment,
+    radiance_command::radiance_command,
+    resize::resize,
+    stage_cache::CacheInputs,
+    vignetting_effect_correction::vignetting_effect_correction,
+    ConfigSettings, FrameOffset, GlareBackend, PipelineError, PipelineHost, PipelineParams, DEBUG,
+};
+use crate::radiance_hdr::{read_hdr, write_hdr};
+
+// Images with a fisheye diameter up to this size (in pixels) are not resized
+const RESIZE_THRESHOLD: u32 = 1000;
+
+/**
+ * Data passed from one stage to the next
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Artifact {
+    // The LDR images of the scene
+    LdrImages,
+    // The current HDR image. Stages that modify the image replace it with their output.
+    HdrImage,
+    // The glare metrics computed by evalglare for the current HDR image
+    GlareMetrics,
+    // The falsecolor luminance map
+    FalsecolorImage,
+    // The evalglare check image showing the detected glare sources
+    GlareCheckImage,
+}
+
+/**
+ * Artifacts of one image set, filled in as the stages run
+ */
+#[derive(Debug, Clone, Default)]
+pub struct StageData {
+    pub ldr_images: Vec<String>,
+    pub hdr_image: Option<PathBuf>,
+    pub glare_metrics: Option<GlareMetrics>,
+    pub falsecolor_image: Option<PathBuf>,
+    pub glare_check_image: Option<PathBuf>,
+    pub frame_offsets: Vec<FrameOffset>, // found when aligning the LDR images (empty if not)
+    pub warnings: Vec<String>,           // problems found by the last stage, reported after it ran
+}
+
+impl StageData {
+    /**
+     * Creates the data for an image set before any stage has run
+     *
+     * @param ldr_images - Paths to the LDR images of the scene
+     */
+    pub fn new(ldr_images: Vec<String>) -> StageData {
+        StageData {
+            ldr_images,
+            ..Default::default()
+        }
+    }
+
+    // Returns the current HDR image as a string path, or an error naming the stage that needed it
+    fn hdr_image_path(&self, stage: &str) -> Result<String, String> {
+        match &self.hdr_image {
+            Some(path) => Ok(path.display().to_string()),
+            None => Err(format!(
+                "pipeline: {}: no HDR image was produced by an earlier stage.",
+                stage
+            )),
+        }
+    }
+}
+
+/**
+ * Everything a stage can read while running, shared by all stages of an image set
+ */
+pub struct StageContext<'a> {
+    pub host: &'a dyn PipelineHost,
+    pub config_settings: &'a ConfigSettings,
+    pub params: &'a PipelineParams,
+}
+
+impl StageContext<'_> {
+    // Returns the path of a file in the temp directory of the current image set
+    pub(super) fn temp_file(&self, file_name: &str) -> String {
+        self.config_settings
+            .temp_path
+            .join(file_name)
+            .display()
+            .to_string()
+    }
+}
+
+/**
+ * A step of the pipeline. Shared between the workers when scenes are processed in parallel.
+ */
+pub trait Stage: Send + Sync {
+    /**
+     * Name of the stage, used in progress and error messages. Unique within a pipeline.
+     */
+    fn name(&self) -> &str;
+
+    /**
+     * Artifacts that must have been produced by earlier stages
+     */
+    fn inputs(&self) -> &[Artifact];
+
+    /**
+     * Artifacts the stage produces (or replaces)
+     */
+    fn outputs(&self) -> &[Artifact];
+
+    /**
+     * Runs the stage on one image set
+     *
+     * @param context - Host, configuration settings and parameters of the run
+     * @param data - Artifacts produced so far. The stage stores its outputs here.
+     */
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError>;
+
+    /**
+     * Describes what the stage's outputs depend on besides its input artifacts, for the stage
+     * cache. Returns None if the outputs must not be cached (the stage always runs).
+     *
+     * @param context - Host, configuration settings and parameters of the run
+     */
+    fn cache_inputs(&self, _context: &StageContext) -> Option<CacheInputs> {
+        None
+    }
+}
+
+/**
+ * A stage as listed in a pipeline description, e.g. `{ "stage": "neutralDensity" }` or
+ * `{ "stage": "radianceCommand", "name": "extra_filter", "program": "pfilt", "args": ["-1"] }`
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "stage", rename_all = "camelCase")]
+pub enum StageKind {
+    MergeExposures,
+    NullifyExposureValue,
+    Crop,
+    // Only runs when the fisheye diameter is larger than 1000 pixels
+    Resize,
+    // Calibration stages only run when their calibration file is set
+    ProjectionAdjustment,
+    VignettingCorrection,
+    NeutralDensity,
+    PhotometricAdjustment,
+    Evalglare,
+    HeaderEditing,
+    Falsecolor,
+    // An extra Radiance program applied to the current HDR image
+    #[serde(rename_all = "camelCase")]
+    RadianceCommand {
+        name: String,
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+/**
+ * An entry of a pipeline description
+ *
+ * @field kind - The stage to run
+ * @field enabled - Whether the stage runs (true if omitted)
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StageEntry {
+    #[serde(flatten)]
+    pub kind: StageKind,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/**
+ * Returns the standard pipeline description
+ */
+pub fn default_stages() -> Vec<StageEntry> {
+    [
+        StageKind::MergeExposures,
+        StageKind::NullifyExposureValue,
+        StageKind::Crop,
+        StageKind::Resize,
+        StageKind::ProjectionAdjustment,
+        StageKind::VignettingCorrection,
+        StageKind::NeutralDensity,
+        StageKind::PhotometricAdjustment,
+        StageKind::Evalglare,
+        StageKind::HeaderEditing,
+        StageKind::Falsecolor,
+    ]
+    .into_iter()
+    .map(|kind| StageEntry {
+        kind,
+        enabled: true,
+    })
+    .collect()
+}
+
+/**
+ * Builds the stages of a run from its pipeline description (or the default one), leaving out
+ * disabled stages, calibration stages without a calibration file, and resizing of small images.
+ *
+ * Checks that stage names are unique, that extra Radiance commands name a program of the
+ * Radiance directory (not a path), that every stage's inputs are produced by an earlier stage,
+ * and that the pipeline ends with an HDR image.
+ *
+ * @param params - Parameters of the run
+ * @returns The stages in the order they run, or a list of error messages
+ */
+pub fn build_stages(params: &PipelineParams) -> Result<Vec<Box<dyn Stage>>, Vec<String>> {
+    let description = match &params.stages {
+        Some(stages) => stages.clone(),
+        None => default_stages(),
+    };
+
+    let mut errors: Vec<String> = Vec::new();
+    let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+    for entry in description.into_iter().filter(|entry| entry.enabled) {
+        let stage: Option<Box<dyn Stage>> = match entry.kind {
+            StageKind::MergeExposures => Some(Box::new(MergeExposuresStage)),
+            StageKind::NullifyExposureValue => Some(Box::new(NullifyExposureValueStage)),
+            StageKind::Crop => Some(Box::new(CropStage)),
+            StageKind::Resize => {
+                // Check diameter instead of ydim or xdim in case user wanted image smaller than 1000
+                if params.diameter > RESIZE_THRESHOLD {
+                    Some(Box::new(ResizeStage))
+                } else {
+                    None
+                }
+            }
+            StageKind::ProjectionAdjustment => CalibrationStage::boxed(
+                "projection_adjustment",
+                &params.fisheye_correction_cal,
+                projection_adjustment,
+            ),
+            StageKind::VignettingCorrection => CalibrationStage::boxed(
+                "vignetting_correction",
+                &params.vignetting_correction_cal,
+                vignetting_effect_correction,
+            ),
+            StageKind::NeutralDensity => CalibrationStage::boxed(
+                "neutral_density",
+                &params.neutral_density_cal,
+                neutral_density,
+            ),
+            StageKind::PhotometricAdjustment => CalibrationStage::boxed(
+                "photometric_adjustment",
+                &params.photometric_adjustment_cal,
+                photometric_adjustment,
+            ),
+            StageKind::Evalglare => Some(Box::new(EvalglareStage {
+                check_image: params.evalglare.check_image,
+            })),
+            StageKind::HeaderEditing => Some(Box::new(HeaderEditingStage)),
+            StageKind::Falsecolor => Some(Box::new(FalsecolorStage)),
+            StageKind::RadianceCommand {
+                name,
+                program,
+                args,
+            } => {
+                // The program is joined to the Radiance path, which a path must not escape
+                if !is_program_name(&program) {
+                    errors.push(format!(
+                        "Pipeline stage '{}' must name a program of the Radiance directory, without a path (got '{}').",
+                        name, program
+                    ));
+                }
+                Some(Box::new(RadianceCommandStage {
+                    name,
+                    program,
+                    args,
+                }))
+            }
+        };
+        stages.extend(stage);
+    }
+
+    errors.extend(check_stages(&stages));
+    if errors.is_empty() {
+        Ok(stages)
+    } else {
+        Err(errors)
+    }
+}
+
+// Whether a program of a Radiance command is a plain file name, e.g. "pfilt" but not
+// "../bin/pfilt" or "/usr/bin/pfilt"
+fn is_program_name(program: &str) -> bool {
+    !program.is_empty() && !program.contains("..") && !program.contains(['/', '\\', ':'])
+}
+
+// Checks that the stages can run in the given order
+fn check_stages(stages: &[Box<dyn Stage>]) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+
+    let mut names: HashSet<&str> = HashSet::new();
+    let mut available: HashSet<Artifact> = HashSet::from([Artifact::LdrImages]);
+    for stage in stages {
+        // Names are used for the stage's file in the temp directory
+        let valid_name = !stage.name().is_empty()
+            && stage
+                .name()
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            errors.push(format!(
+                "Pipeline stage name '{}' must only contain letters, digits, '_' and '-'.",
+                stage.name()
+            ));
+        } else if !names.insert(stage.name()) {
+            errors.push(format!(
+                "Pipeline stage '{}' is listed more than once.",
+                stage.name()
+            ));
+        }
+
+        for input in stage.inputs() {
+            if !available.contains(input) {
+                errors.push(format!(
+                    "Pipeline stage '{}' needs {:?}, which no earlier stage produces.",
+                    stage.name(),
+                    input
+                ));
+            }
+        }
+        available.extend(stage.outputs().iter().copied());
+    }
+
+    if !available.contains(&Artifact::HdrImage) {
+        errors.push("The pipeline does not produce an HDR image.".into());
+    }
+
+    errors
+}
+
+// Merges the LDR images into an HDR image
+struct MergeExposuresStage;
+
+impl Stage for MergeExposuresStage {
+    fn name(&self) -> &str {
+        "merge_exposures"
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::LdrImages]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let params = context.params;
+        let output_file = context.temp_file("merge_exposures.hdr");
+        let (_, frame_offsets) = merge_exposures(
+            context.host,
+            context.config_settings,
+            data.ldr_images.clone(),
+            params.response_function.as_deref(),
+            output_file.clone(),
+            params.diameter,
+            params.xleft,
+            params.ydown,
+            params.xdim,
+            params.ydim,
+            params.filter_images,
+            params.merge_backend,
+            params.align_exposures,
+        )?;
+        data.hdr_image = Some(output_file.into());
+        data.frame_offsets = frame_offsets;
+        Ok(())
+    }
+
+    fn cache_inputs(&self, context: &StageContext) -> Option<CacheInputs> {
+        let params = context.params;
+        Some(CacheInputs {
+            params: format!(
+                "diameter={} xleft={} ydown={} xdim={} ydim={} filter_images={} merge_backend={:?} align_exposures={} hdrgen={} dcraw_emu={}",
+                params.diameter,
+                params.xleft,
+                params.ydown,
+                params.xdim,
+                params.ydim,
+                params.filter_images,
+                params.merge_backend,
+                params.align_exposures,
+                params.hdrgen_path.display(),
+                params.dcraw_emu_path.display()
+            ),
+            files: params.response_function.iter().cloned().collect(),
+        })
+    }
+}
+
+// Nullifies the exposure value of the HDR image
+struct NullifyExposureValueStage;
+
+impl Stage for NullifyExposureValueStage {
+    fn name(&self) -> &str {
+        "nullify_exposure_value"
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let output_file = context.temp_file("nullify_exposure_value.hdr");
+        nullify_exposure_value(
+            context.config_settings,
+            data.hdr_image_path(self.name())?,
+            output_file.clone(),
+        )?;
+        data.hdr_image = Some(output_file.into());
+        Ok(())
+    }
+
+    fn cache_inputs(&self, _context: &StageContext) -> Option<CacheInputs> {
+        Some(CacheInputs::default())
+    }
+}
+
+// Crops the HDR image to a square fitting the fisheye view
+struct CropStage;
+
+impl Stage for CropStage {
+    fn name(&self) -> &str {
+        "crop"
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let output_file = context.temp_file("crop.hdr");
+        crop(
+            context.config_settings,
+            data.hdr_image_path(self.name())?,
+            output_file.clone(),
+            context.params.diameter,
+            context.params.xleft,
+            context.params.ydown,
+        )?;
+        data.hdr_image = Some(output_file.into());
+        Ok(())
+    }
+
+    fn cache_inputs(&self, context: &StageContext) -> Option<CacheInputs> {
+        Some(CacheInputs {
+            params: format!(
+                "diameter={} xleft={} ydown={}",
+                context.params.diameter, context.params.xleft, context.params.ydown
+            ),
+            files: Vec::new(),
+        })
+    }
+}
+
+// Resizes the HDR image to the target resolution
+struct ResizeStage;
+
+impl Stage for ResizeStage {
+    fn name(&self) -> &str {
+        "resize"
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let output_file = context.temp_file("resize.hdr");
+        resize(
+            context.config_settings,
+            data.hdr_image_path(self.name())?,
+            output_file.clone(),
+            context.params.xdim,
+            context.params.ydim,
+        )?;
+        data.hdr_image = Some(output_file.into());
+        Ok(())
+    }
+
+    fn cache_inputs(&self, context: &StageContext) -> Option<CacheInputs> {
+        Some(CacheInputs {
+            params: format!("xdim={} ydim={}", context.params.xdim, context.params.ydim),
+            files: Vec::new(),
+        })
+    }
+}
+
+// Signature shared by the stages applying a calibration file to the HDR image
+type CalibrationFn = fn(&ConfigSettings, String, String, String) -> Result<String, PipelineError>;
+
+// Applies a calibration file (projection, vignetting, neutral density or photometric)
+struct CalibrationStage {
+    name: &'static str,
+    calibration_file: PathBuf,
+    apply: CalibrationFn,
+}
+
+impl CalibrationStage {
+    // Creates the stage, or None if no calibration file was provided (the stage is skipped)
+    fn boxed(
+        name: &'static str,
+        calibration_file: &Option<PathBuf>,
+        apply: CalibrationFn,
+    ) -> Option<Box<dyn Stage>> {
+        let calibration_file = calibration_file.clone()?;
+        Some(Box::new(CalibrationStage {
+            name,
+            calibration_file,
+            apply,
+        }))
+    }
+}
+
+impl Stage for CalibrationStage {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let output_file = context.temp_file(&format!("{}.hdr", self.name));
+        (self.apply)(
+            context.config_settings,
+            data.hdr_image_path(self.name())?,
+            output_file.clone(),
+            self.calibration_file.display().to_string(),
+        )?;
+        data.hdr_image = Some(output_file.into());
+        Ok(())
+    }
+
+    fn cache_inputs(&self, _context: &StageContext) -> Option<CacheInputs> {
+        Some(CacheInputs {
+            params: String::new(),
+            files: vec![self.calibration_file.clone()],
+        })
+    }
+}
+
+// Runs evalglare on the HDR image, and writes the check image of the glare sources if enabled
+struct EvalglareStage {
+    check_image: bool,
+}
+
+impl Stage for EvalglareStage {
+    fn name(&self) -> &str {
+        "evalglare"
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        if self.check_image {
+            &[Artifact::GlareMetrics, Artifact::GlareCheckImage]
+        } else {
+            &[Artifact::GlareMetrics]
+        }
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let args = &context.params.evalglare;
+        let input_file = data.hdr_image_path(self.name())?;
+        let check_file = self
+            .check_image
+            .then(|| context.temp_file("evalglare_check.hdr"));
+
+        let evalglare_path = evalglare_path(context);
+        let glare_metrics = if let Some(evalglare_path) = &evalglare_path {
+            if DEBUG {
+                println!("Glare metrics: running {}", evalglare_path.display());
+            }
+            let glare_metrics = evalglare(
+                context.config_settings,
+                input_file.clone(),
+                context.params.vertical_angle,
+                context.params.horizontal_angle,
+                args,
+                check_file.as_deref().map(Path::new),
+            )?;
+            if args.cross_check {
+                let image = read_hdr(Path::new(&input_file))?;
+                let glare = compute_glare(
+                    &image,
+                    context.params.vertical_angle,
+                    context.params.horizontal_angle,
+                    args,
+                )?;
+                data.warnings
+                    .extend(compare_glare(&glare.metrics, &glare_metrics));
+            }
+            glare_metrics
+        } else {
+            if args.backend == GlareBackend::Evalglare {
+                let location = if context.config_settings.radiance_path.as_os_str().is_empty() {
+                    "on PATH".to_string()
+                } else {
+                    format!("in {}", context.config_settings.radiance_path.display())
+                };
+                data.warnings.push(format!(
+                    "evalglare was not found {}, the glare metrics were computed by the built-in implementation.",
+                    location
+                ));
+            }
+            if DEBUG {
+                println!("Glare metrics: using the built-in computation");
+            }
+            let image = read_hdr(Path::new(&input_file))?;
+            let glare = compute_glare(
+                &image,
+                context.params.vertical_angle,
+                context.params.horizontal_angle,
+                args,
+            )?;
+            if let Some(check_file) = &check_file {
+                write_hdr(Path::new(check_file), &glare.check_image(&image))?;
+            }
+            glare.metrics
+        };
+        data.glare_metrics = Some(glare_metrics);
+
+        if let Some(check_file) = check_file {
+            if !Path::new(&check_file).is_file() {
+                return Err("pipeline: evalglare: no check image was written.".into());
+            }
+            data.glare_check_image = Some(check_file.into());
+        }
+        Ok(())
+    }
+
+    fn cache_inputs(&self, context: &StageContext) -> Option<CacheInputs> {
+        // The built-in fallback is not cached, so that it runs again (with its warning) until
+        // evalglare is installed, instead of being restored in place of evalglare's metrics
+        let evalglare_path = evalglare_path(context);
+        if context.params.evalglare.backend == GlareBackend::Evalglare && evalglare_path.is_none() {
+            return None;
+        }
+        let backend =
+            evalglare_path.map_or("native".to_string(), |path| path.display().to_string());
+
+        let mut inputs = view_angles(context);
+        inputs.params = format!(
+            "{} {:?} backend={}",
+            inputs.params, context.params.evalglare, backend
+        );
+        Some(inputs)
+    }
+}
+
+// Writes the view and glare metrics into the header of the HDR image
+struct HeaderEditingStage;
+
+impl Stage for HeaderEditingStage {
+    fn name(&self) -> &str {
+        "header_editing"
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage, Artifact::GlareMetrics]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let glare_metrics = data.glare_metrics.as_ref().ok_or(
+            "pipeline: header_editing: no glare metrics were produced by an earlier stage.",
+        )?;
+        let output_file = context.temp_file("header_editing.hdr");
+        header_editing(
+            context.config_settings,
+            data.hdr_image_path(self.name())?,
+            output_file.clone(),
+            context.params.vertical_angle,
+            context.params.horizontal_angle,
+            glare_metrics,
+        )?;
+        data.hdr_image = Some(output_file.into());
+        Ok(())
+    }
+
+    fn cache_inputs(&self, context: &StageContext) -> Option<CacheInputs> {
+        Some(view_angles(context))
+    }
+}
+
+// Creates the falsecolor luminance map of the HDR image
+struct FalsecolorStage;
+
+impl Stage for FalsecolorStage {
+    fn name(&self) -> &str {
+        "falsecolor"
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::FalsecolorImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let output_file = context.temp_file("falsecolor_output.hdr");
+        falsecolor(
+            context.config_settings,
+            data.hdr_image_path(self.name())?,
+            output_file.clone(),
+            &context.params.luminance,
+        )?;
+        data.falsecolor_image = Some(output_file.into());
+        Ok(())
+    }
+
+    fn cache_inputs(&self, context: &StageContext) -> Option<CacheInputs> {
+        Some(CacheInputs {
+            params: format!("{:?}", context.params.luminance),
+            files: Vec::new(),
+        })
+    }
+}
+
+// Runs an extra Radiance program on the HDR image
+struct RadianceCommandStage {
+    name: String,
+    program: String,
+    args: Vec<String>,
+}
+
+impl Stage for RadianceCommandStage {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn inputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn outputs(&self) -> &[Artifact] {
+        &[Artifact::HdrImage]
+    }
+
+    fn run(&self, context: &StageContext, data: &mut StageData) -> Result<(), PipelineError> {
+        let output_file = context.temp_file(&format!("{}.hdr", self.name));
+        radiance_command(
+            context.config_settings,
+            &self.program,
+            &self.args,
+            data.hdr_image_path(self.name())?,
+            output_file.clone(),
+        )?;
+        data.hdr_image = Some(output_file.into());
+        Ok(())
+    }
+
+    fn cache_inputs(&self, _context: &StageContext) -> Option<CacheInputs> {
+        // Arguments naming files (e.g. "-f lab.cal") are hashed by content
+        Some(CacheInputs {
+            params: format!("{} {:?}", self.program, self.args),
+            files: self
+                .args
+                .iter()
+                .map(PathBuf::from)
+                .filter(|path| path.is_file())
+                .collect(),
+        })
+    }
+}
+
+// Path of the evalglare command computing the glare metrics, None for the built-in computation.
+// evalglare is looked up like the other Radiance commands (on PATH when the Radiance directory is
+// empty), and the built-in computation is only used if it is not installed.
+fn evalglare_path(context: &StageContext) -> Option<PathBuf> {
+    match context.params.evalglare.backend {
+        GlareBackend::Native => None,
+        GlareBackend::Evalglare => find_evalglare(&context.config_settings.radiance_path),
+    }
+}
+
+// Cache inputs of the stages using the view angles of the fisheye view
+fn view_angles(context: &StageContext) -> CacheInputs {
+    CacheInputs {
+        params: format!(
+            "vertical_angle={} horizontal_angle={}",
+            context.params.vertical_angle, context.params.horizontal_angle
+        ),
+        files: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parameters of a run with the given pipeline description
+    fn run_params(stages: serde_json::Value) -> PipelineParams {
+        serde_json::from_value(serde_json::json!({
+            "outputPath": "/output",
+            "inputImages": ["/input/a"],
+            "diameter": 100,
+            "xleft": 0,
+            "ydown": 0,
+            "xdim": 100,
+            "ydim": 100,
+            "verticalAngle": 180.0,
+            "horizontalAngle": 180.0,
+            "stages": stages,
+        }))
+        .unwrap()
+    }
+
+    // Errors of a pipeline merging the exposures and running a Radiance program
+    fn program_errors(program: &str) -> Vec<String> {
+        let params = run_params(serde_json::json!([
+            { "stage": "mergeExposures" },
+            { "stage": "radianceCommand", "name": "extra_filter", "program": program },
+        ]));
+        build_stages(&params).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn radiance_commands_name_a_program_of_the_radiance_directory() {
+        assert_eq!(program_errors("pfilt"), Vec::<String>::new());
+        assert_eq!(program_errors("pcomb.exe"), Vec::<String>::new());
+        for program in [
+            "",
+            "/usr/bin/rm",
+            "../../bin/sh",
+            "..",
+            "bin/pfilt",
+            "..\\cmd.exe",
+            "C:\\Windows\\cmd.exe",
+        ] {
+            let errors = program_errors(program);
+            assert_eq!(errors.len(), 1, "{:?}: {:?}", program, errors);
+            assert!(errors[0].starts_with("Pipeline stage 'extra_filter' must name a program"));
+        }
+    }
+
+    #[test]
+    fn stages_run_in_order() {
+        let params = run_params(serde_json::json!([
+            { "stage": "mergeExposures" },
+            { "stage": "falsecolor" },
+            { "stage": "radianceCommand", "name": "extra_filter", "program": "pfilt" },
+            { "stage": "headerEditing", "enabled": false },
+        ]));
+        let names: Vec<String> = build_stages(&params)
+            .unwrap()
+            .iter()
+            .map(|stage| stage.name().to_string())
+            .collect();
+        assert_eq!(names, vec!["merge_exposures", "falsecolor", "extra_filter"]);
+
+        let params = run_params(serde_json::json!([{ "stage": "falsecolor" }]));
+        assert!(build_stages(&params).is_err());
+    }
+}
@@ -0,0 +1,602 @@
+/**
+ * Module for caching the outputs of pipeline stages between runs.
+ *
+ * Like the image cache used for dcraw_emu conversions, entries are content addressed with
+ * blake3. The key of a stage combines the key of its input (the key of the stage before it, or
+ * the hash of the LDR images for the first stage), the stage's parameters and the hash of the
+ * files it reads (response function, .cal files). Re-running a batch where only the falsecolor
+ * settings changed therefore restores the merged and calibrated HDR images from the cache and
+ * only runs falsecolor again.
+ *
+ * Every entry holds full-resolution HDR images, so the cache is kept under a size limit (set in
+ * the settings page, next to the image cache's) by evicting the least recently used entries
+ * after each store. Restoring an entry records its access.
+ */
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::image_cache::{compute_hash_for_file, evict_entries, touch, CacheEntry};
+
+use super::{Artifact, ConfigSettings, Stage, StageContext, StageData};
+
+// Version of the cache layout, part of every key so that old entries are ignored when it changes
+const CACHE_VERSION: &str = "stage_cache_v2";
+
+// File names of the artifacts within a cache entry
+const HDR_IMAGE_FILE: &str = "hdr_image.hdr";
+const FALSECOLOR_IMAGE_FILE: &str = "falsecolor_image.hdr";
+const GLARE_METRICS_FILE: &str = "glare_metrics.json";
+const GLARE_CHECK_IMAGE_FILE: &str = "glare_check_image.hdr";
+
+// Marks entries being written, which are not complete yet
+const PARTIAL_MARKER: &str = ".partial-";
+
+/**
+ * Size limit of the stage cache when none was configured (10 GiB)
+ */
+pub const DEFAULT_STAGE_CACHE_MAX_SIZE_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+/**
+ * What the output of a stage depends on besides its input artifacts
+ *
+ * @field params - The parameters of the stage, formatted as a string
+ * @field files - Files the stage reads (response function, calibration files). Their contents
+ *                are hashed, so editing a .cal file in place invalidates the entry.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct CacheInputs {
+    pub params: String,
+    pub files: Vec<PathBuf>,
+}
+
+/**
+ * Directory holding the cached stage outputs, one subdirectory per key
+ */
+#[derive(Debug, Clone)]
+pub struct StageCache {
+    dir: PathBuf,
+    max_size_bytes: u64,
+}
+
+impl StageCache {
+    /**
+     * Opens the cache in the OS temporary directory, next to the image cache
+     *
+     * @param max_size_bytes - Size the cache is kept under by evicting least recently used entries
+     */
+    pub fn open(max_size_bytes: u64) -> Result<StageCache, String> {
+        Ok(StageCache {
+            dir: stage_cache_dir()?,
+            max_size_bytes,
+        })
+    }
+
+    /**
+     * Returns the key of the LDR images of a scene, the input of the first stage
+     *
+     * @param ldr_images - Paths to the LDR images, in the order they are passed to the pipeline
+     */
+    pub fn input_key(&self, ldr_images: &[String]) -> Result<String, String> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(CACHE_VERSION.as_bytes());
+        for image in ldr_images {
+            hasher.update(compute_hash_for_file(Path::new(image), "ldr_image")?.as_bytes());
+        }
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /**
+     * Returns the key of the output of a stage
+     *
+     * @param input_key - Key of the stage's input (the key of the previous stage)
+     * @param stage - Name of the stage
+     * @param inputs - Parameters and files of the stage
+     * @param config_settings - Configuration of the run (tool paths are part of the key)
+     */
+    pub fn stage_key(
+        &self,
+        input_key: &str,
+        stage: &str,
+        inputs: &CacheInputs,
+        config_settings: &ConfigSettings,
+    ) -> Result<String, String> {
+        let mut hasher = blake3::Hasher::new();
+        for part in [
+            CACHE_VERSION,
+            input_key,
+            stage,
+            &inputs.params,
+            &config_settings.radiance_path.to_string_lossy(),
+        ] {
+            // Separate the parts so that e.g. ("ab", "c") and ("a", "bc") differ
+            hasher.update(part.as_bytes());
+            hasher.update(&[0]);
+        }
+        for file in &inputs.files {
+            hasher.update(compute_hash_for_file(file, stage)?.as_bytes());
+        }
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /**
+     * Restores the cached outputs of a stage into the temp directory of the scene.
+     * Returns false (and changes nothing) if there is no complete entry for the key.
+     *
+     * @param key - Key of the stage's output
+     * @param stage - The stage
+     * @param context - Context of the scene, whose temp directory receives the files
+     * @param data - Artifacts of the scene, updated with the restored outputs
+     */
+    pub fn restore(
+        &self,
+        key: &str,
+        stage: &dyn Stage,
+        context: &StageContext,
+        data: &mut StageData,
+    ) -> bool {
+        let entry = self.dir.join(key);
+        if !entry.is_dir() {
+            return false;
+        }
+
+        let mut restored = data.clone();
+        for output in stage.outputs() {
+            let ok = match output {
+                Artifact::HdrImage => {
+                    let path = context.temp_file(&format!("{}.hdr", stage.name()));
+                    let ok = fs::copy(entry.join(HDR_IMAGE_FILE), &path).is_ok();
+                    restored.hdr_image = Some(path.into());
+                    ok
+                }
+                Artifact::FalsecolorImage => {
+                    let path = context.temp_file(&format!("{}_fc.hdr", stage.name()));
+                    let ok = fs::copy(entry.join(FALSECOLOR_IMAGE_FILE), &path).is_ok();
+                    restored.falsecolor_image = Some(path.into());
+                    ok
+                }
+                Artifact::GlareCheckImage => {
+                    let path = context.temp_file(&format!("{}_check.hdr", stage.name()));
+                    let ok = fs::copy(entry.join(GLARE_CHECK_IMAGE_FILE), &path).is_ok();
+                    restored.glare_check_image = Some(path.into());
+                    ok
+                }
+                Artifact::GlareMetrics => {
+                    match fs::read_to_string(entry.join(GLARE_METRICS_FILE))
+                        .ok()
+                        .and_then(|json| serde_json::from_str(&json).ok())
+                    {
+                        Some(metrics) => {
+                            restored.glare_metrics = Some(metrics);
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                // Stages replacing the LDR images are never stored
+                Artifact::LdrImages => false,
+            };
+            if !ok {
+                return false;
+            }
+        }
+
+        // record the access for least recently used eviction
+        if let Ok(files) = fs::read_dir(&entry) {
+            for file in files.flatten() {
+                touch(&file.path());
+            }
+        }
+
+        *data = restored;
+        true
+    }
+
+    /**
+     * Stores the outputs of a stage that just ran. Stages whose outputs cannot be stored
+     * (LDR images) are skipped.
+     *
+     * @param key - Key of the stage's output
+     * @param stage - The stage
+     * @param data - Artifacts of the scene after the stage ran
+     */
+    pub fn store(&self, key: &str, stage: &dyn Stage, data: &StageData) -> Result<(), String> {
+        let entry = self.dir.join(key);
+        if entry.is_dir() || stage.outputs().contains(&Artifact::LdrImages) {
+            return Ok(());
+        }
+
+        // Write the entry under a temporary name and rename it when complete, so that a scene
+        // processed in parallel never restores a partial entry
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let partial = self.dir.join(format!(
+            "{}{}{}-{}",
+            key,
+            PARTIAL_MARKER,
+            std::process::id(),
+            nanos
+        ));
+        let result = write_entry(&partial, stage, data)
+            .and_then(|_| fs::rename(&partial, &entry).map_err(|e| e.to_string()));
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&partial);
+        }
+        result
+            .map_err(|e| format!("Error storing '{}' in the stage cache: {}", stage.name(), e))?;
+
+        // make room for the new entry
+        let entries = list_entries(&self.dir)?;
+        evict_entries(entries, self.max_size_bytes, Some(&entry), remove_entry);
+        Ok(())
+    }
+}
+
+/**
+ * Returns the directory of the stage cache, creating it if needed
+ */
+pub fn stage_cache_dir() -> Result<PathBuf, String> {
+    let dir = env::temp_dir()
+        .join(env!("CARGO_PKG_NAME"))
+        .join("stage_cache");
+    if fs::create_dir_all(&dir).is_err() {
+        return Err("Couldn't create stage cache directory".to_string());
+    }
+    Ok(dir)
+}
+
+/**
+ * Returns the total size and the number of entries of the stage cache
+ */
+pub fn stage_cache_stats() -> Result<(u64, usize), String> {
+    let entries = list_entries(&stage_cache_dir()?)?;
+    Ok((entries.iter().map(|entry| entry.size).sum(), entries.len()))
+}
+
+/**
+ * Removes the least recently used entries of the stage cache until it is no larger than
+ * max_size_bytes. Returns the number of removed entries.
+ */
+pub fn evict_stage_cache(max_size_bytes: u64) -> Result<usize, String> {
+    let entries = list_entries(&stage_cache_dir()?)?;
+    Ok(evict_entries(entries, max_size_bytes, None, remove_entry))
+}
+
+/**
+ * Removes every entry of the stage cache. Returns the number of removed entries.
+ */
+pub fn clear_stage_cache() -> Result<usize, String> {
+    let entries = list_entries(&stage_cache_dir()?)?;
+    for entry in &entries {
+        remove_entry(&entry.path);
+    }
+    Ok(entries.len())
+}
+
+// Lists the complete entries of the cache, with the size of their files and the last time one
+// of them was used
+fn list_entries(dir: &Path) -> Result<Vec<CacheEntry>, String> {
+    let read_dir = fs::read_dir(dir).map_err(|_| "Error reading stage cache directory")?;
+
+    let mut entries: Vec<CacheEntry> = Vec::new();
+    for dir_entry in read_dir.flatten() {
+        let path = dir_entry.path();
+        let partial = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().contains(PARTIAL_MARKER));
+        if partial || !path.is_dir() {
+            continue;
+        }
+        let (mut size, mut last_access) = (0, SystemTime::UNIX_EPOCH);
+        for meta in fs::read_dir(&path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|file| file.metadata().ok())
+        {
+            size += meta.len();
+            let accessed = meta.accessed().or_else(|_| meta.modified());
+            last_access = last_access.max(accessed.unwrap_or(SystemTime::UNIX_EPOCH));
+        }
+        entries.push(CacheEntry {
+            path,
+            size,
+            last_access,
+        });
+    }
+    Ok(entries)
+}
+
+// Removes an entry of the cache
+fn remove_entry(entry: &Path) {
+    let _ = fs::remove_dir_all(entry);
+}
+
+// Writes the output artifacts of a stage into a cache entry directory
+fn write_entry(dir: &Path, stage: &dyn Stage, data: &StageData) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    for output in stage.outputs() {
+        match output {
+            Artifact::HdrImage => {
+                let path = data.hdr_image.as_ref().ok_or("no HDR image")?;
+                fs::copy(path, dir.join(HDR_IMAGE_FILE)).map_err(|e| e.to_string())?;
+            }
+            Artifact::FalsecolorImage => {
+                let path = data
+                    .falsecolor_image
+                    .as_ref()
+                    .ok_or("no falsecolor image")?;
+                fs::copy(path, dir.join(FALSECOLOR_IMAGE_FILE)).map_err(|e| e.to_string())?;
+            }
+            Artifact::GlareCheckImage => {
+                let path = data
+                    .glare_check_image
+                    .as_ref()
+                    .ok_or("no glare check image")?;
+                fs::copy(path, dir.join(GLARE_CHECK_IMAGE_FILE)).map_err(|e| e.to_string())?;
+            }
+            Artifact::GlareMetrics => {
+                let metrics = data.glare_metrics.as_ref().ok_or("no glare metrics")?;
+                let json = serde_json::to_string(metrics).map_err(|e| e.to_string())?;
+                fs::write(dir.join(GLARE_METRICS_FILE), json).map_err(|e| e.to_string())?;
+            }
+            Artifact::LdrImages => return Err("LDR images are not cached".into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, time::Duration};
+
+    use super::*;
+    use crate::pipeline::{
+        CancellationToken, GlareMetrics, PipelineError, PipelineHost, PipelineParams,
+        PipelineProgress,
+    };
+
+    struct TestHost;
+
+    impl PipelineHost for TestHost {
+        fn emit_progress(&self, _progress: &PipelineProgress) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn sidecar_command(&self, name: &str) -> Result<Command, String> {
+            Ok(Command::new(name))
+        }
+    }
+
+    // Stage producing an HDR image and glare metrics, which is never run
+    struct TestStage;
+
+    impl Stage for TestStage {
+        fn name(&self) -> &str {
+            "test_stage"
+        }
+
+        fn inputs(&self) -> &[Artifact] {
+            &[]
+        }
+
+        fn outputs(&self) -> &[Artifact] {
+            &[Artifact::HdrImage, Artifact::GlareMetrics]
+        }
+
+        fn run(&self, _context: &StageContext, _data: &mut StageData) -> Result<(), PipelineError> {
+            Ok(())
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config_settings(temp_path: &Path) -> ConfigSettings {
+        ConfigSettings {
+            radiance_path: PathBuf::from("/usr/local/radiance/bin"),
+            hdrgen_path: PathBuf::new(),
+            dcraw_emu_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            temp_path: temp_path.to_path_buf(),
+            cancel_token: CancellationToken::new(),
+            stage_cache: None,
+        }
+    }
+
+    fn params() -> PipelineParams {
+        serde_json::from_value(serde_json::json!({
+            "outputPath": "/output",
+            "diameter": 100,
+            "xleft": 0,
+            "ydown": 0,
+            "xdim": 100,
+            "ydim": 100,
+            "verticalAngle": 180.0,
+            "horizontalAngle": 180.0,
+        }))
+        .unwrap()
+    }
+
+    // Names of the files and directories in a directory
+    fn dir_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn keys_change_with_upstream_inputs() {
+        let dir = test_dir("stage_cache_keys");
+        let cache = StageCache {
+            dir: dir.join("cache"),
+            max_size_bytes: u64::MAX,
+        };
+        let config_settings = config_settings(&dir);
+        let image = dir.join("IMG_0001.JPG");
+        fs::write(&image, "LDR image").unwrap();
+        let cal = dir.join("fisheye.cal");
+        fs::write(&cal, "fisheye = 1;").unwrap();
+        let cal_files = vec![cal.clone()];
+
+        let input_key = cache.input_key(&[image.display().to_string()]).unwrap();
+        let key = |input_key: &str, stage: &str, params: &str, files: &[PathBuf]| {
+            let inputs = CacheInputs {
+                params: params.into(),
+                files: files.to_vec(),
+            };
+            cache
+                .stage_key(input_key, stage, &inputs, &config_settings)
+                .unwrap()
+        };
+
+        // Keys of two stages, the second chained on the first as in the pipeline
+        let first = key(&input_key, "crop", "xleft=0", &[]);
+        let second = key(&first, "fisheye", "", &cal_files);
+        assert_eq!(first, key(&input_key, "crop", "xleft=0", &[]));
+        assert_eq!(second, key(&first, "fisheye", "", &cal_files));
+
+        // Changing the parameters of the first stage changes the key of the second
+        let changed_first = key(&input_key, "crop", "xleft=1", &[]);
+        assert_ne!(changed_first, first);
+        assert_ne!(key(&changed_first, "fisheye", "", &cal_files), second);
+
+        // As does editing a file the second stage reads, or changing the LDR images
+        fs::write(&cal, "fisheye = 2;").unwrap();
+        assert_ne!(key(&first, "fisheye", "", &cal_files), second);
+        fs::write(&image, "other LDR image").unwrap();
+        assert_ne!(
+            cache.input_key(&[image.display().to_string()]).unwrap(),
+            input_key
+        );
+
+        // Parts are separated, so moving text between the stage name and parameters matters
+        assert_ne!(
+            key(&input_key, "crop", "x", &[]),
+            key(&input_key, "cro", "px", &[])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stored_outputs_are_restored() {
+        let dir = test_dir("stage_cache_restore");
+        let cache = StageCache {
+            dir: dir.join("cache"),
+            max_size_bytes: u64::MAX,
+        };
+        fs::create_dir_all(&cache.dir).unwrap();
+        let config_settings = config_settings(&dir);
+        let params = params();
+        let context = StageContext {
+            host: &TestHost,
+            config_settings: &config_settings,
+            params: &params,
+        };
+
+        let hdr_image = dir.join("merged.hdr");
+        fs::write(&hdr_image, "HDR image").unwrap();
+        let mut data = StageData::new(Vec::new());
+        data.hdr_image = Some(hdr_image);
+        data.glare_metrics = Some(GlareMetrics {
+            dgp: Some(0.4),
+            vertical_illuminance: 3000.0,
+            ..Default::default()
+        });
+        cache.store("key", &TestStage, &data).unwrap();
+        assert_eq!(dir_names(&cache.dir), vec!["key"]);
+
+        let mut restored = StageData::new(Vec::new());
+        assert!(cache.restore("key", &TestStage, &context, &mut restored));
+        assert_eq!(restored.glare_metrics, data.glare_metrics);
+        let restored_image = restored.hdr_image.unwrap();
+        assert_eq!(restored_image, dir.join("test_stage.hdr"));
+        assert_eq!(fs::read_to_string(restored_image).unwrap(), "HDR image");
+
+        let mut missing = StageData::new(Vec::new());
+        assert!(!cache.restore("other_key", &TestStage, &context, &mut missing));
+        assert_eq!(missing.hdr_image, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn partial_entries_are_never_visible() {
+        let dir = test_dir("stage_cache_partial");
+        let cache = StageCache {
+            dir: dir.join("cache"),
+            max_size_bytes: u64::MAX,
+        };
+        fs::create_dir_all(&cache.dir).unwrap();
+        let config_settings = config_settings(&dir);
+        let params = params();
+        let context = StageContext {
+            host: &TestHost,
+            config_settings: &config_settings,
+            params: &params,
+        };
+
+        // An entry still being written by another worker is not restored or listed
+        let partial = cache.dir.join(format!("key{}1-2", PARTIAL_MARKER));
+        fs::create_dir_all(&partial).unwrap();
+        fs::write(partial.join(HDR_IMAGE_FILE), "HDR image").unwrap();
+        let mut data = StageData::new(Vec::new());
+        assert!(!cache.restore("key", &TestStage, &context, &mut data));
+        assert!(list_entries(&cache.dir).unwrap().is_empty());
+        fs::remove_dir_all(&partial).unwrap();
+
+        // A store that fails half way (the glare metrics are missing, after the HDR image was
+        // copied) leaves neither the entry nor its partial directory
+        let hdr_image = dir.join("merged.hdr");
+        fs::write(&hdr_image, "HDR image").unwrap();
+        data.hdr_image = Some(hdr_image);
+        assert!(cache.store("key", &TestStage, &data).is_err());
+        assert!(dir_names(&cache.dir).is_empty());
+        assert!(!cache.restore("key", &TestStage, &context, &mut data));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn store_evicts_older_entries() {
+        let dir = test_dir("stage_cache_evict");
+        let cache = StageCache {
+            dir: dir.join("cache"),
+            max_size_bytes: 40,
+        };
+        let old_entry = cache.dir.join("old_key");
+        fs::create_dir_all(&old_entry).unwrap();
+        fs::write(old_entry.join(HDR_IMAGE_FILE), [0; 30]).unwrap();
+        let old_time = SystemTime::now() - Duration::from_secs(3600);
+        let file = fs::File::options()
+            .write(true)
+            .open(old_entry.join(HDR_IMAGE_FILE))
+            .unwrap();
+        file.set_times(
+            fs::FileTimes::new()
+                .set_accessed(old_time)
+                .set_modified(old_time),
+        )
+        .unwrap();
+
+        // The new entry is larger than the limit on its own, but is kept
+        let hdr_image = dir.join("merged.hdr");
+        fs::write(&hdr_image, [0; 50]).unwrap();
+        let mut data = StageData::new(Vec::new());
+        data.hdr_image = Some(hdr_image);
+        data.glare_metrics = Some(GlareMetrics::default());
+        cache.store("new_key", &TestStage, &data).unwrap();
+        assert_eq!(dir_names(&cache.dir), vec!["new_key"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
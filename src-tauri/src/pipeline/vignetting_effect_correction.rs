@@ -13,7 +13,7 @@ use std::{
     process::{Command, Stdio},
 };
 
-use super::ConfigSettings;
+use super::{run_command, ConfigSettings, PipelineError};
 
 /**
  * Corrects for the vignetting effect of an HDR image using Radiance's pcomb utility.
@@ -35,7 +35,7 @@ pub fn vignetting_effect_correction(
     input_file: String,
     output_file: String,
     vignetting_correction_cal: String,
-) -> Result<String, String> {
+) -> Result<String, PipelineError> {
     if DEBUG {
         println!("vignetting_effect_correction() was called with parameters:");
         println!("\tvignetting_correction_cal: {vignetting_correction_cal}");
@@ -63,11 +63,12 @@ pub fn vignetting_effect_correction(
     command.stdout(stdio);
 
     // Run the command
-    let status_result = command.status();
-    if status_result.is_err() {
-        return Err("pipeline: vignetting_effect_correction: failed to start command.".into());
-    }
-    let status = status_result.unwrap();
+    let output = run_command(
+        "vignetting_correction",
+        &mut command,
+        &config_settings.cancel_token,
+    )?;
+    let status = output.status;
 
     if DEBUG {
         println!(
@@ -82,6 +83,10 @@ pub fn vignetting_effect_correction(
         Ok(output_file.into())
     } else {
         // On error, return an error message
-        Err("PIPELINE ERROR: command 'pcomb' (vignetting effect correction) failed.".into())
+        Err(PipelineError::command_failed(
+            "vignetting_correction",
+            &command,
+            &output,
+        ))
     }
 }
@@ -0,0 +1,273 @@
+// Parsing and serialization of the Radiance picture header.
+//
+// A Radiance header is a sequence of text lines that starts with a "#?RADIANCE" magic line and
+// ends with an empty line. Lines of the form "KEY=value" are variables (FORMAT, EXPOSURE, VIEW,
+// or custom keys such as EVALGLARE), every other line is a command that was applied to the
+// picture (e.g. "pcomb -f vignetting.cal"). The header is followed by the resolution string.
+
+// Format string used for RGBE pictures
+pub const RGBE_FORMAT: &str = "32-bit_rle_rgbe";
+
+/**
+ * A single line of the header, either a "KEY=value" variable or a free-form command line
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderLine {
+    Variable { key: String, value: String },
+    Command(String),
+}
+
+/**
+ * The text header of a Radiance picture, kept in file order so it can be written back unchanged
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrHeader {
+    pub lines: Vec<HeaderLine>,
+}
+
+impl Default for HdrHeader {
+    fn default() -> Self {
+        HdrHeader {
+            lines: vec![HeaderLine::Variable {
+                key: "FORMAT".to_string(),
+                value: RGBE_FORMAT.to_string(),
+            }],
+        }
+    }
+}
+
+impl HdrHeader {
+    /**
+     * Parses the header text (without the magic line and the terminating empty line)
+     *
+     * @param text - The header lines, separated by '\n'
+     * @returns The parsed header
+     */
+    pub fn parse(text: &str) -> HdrHeader {
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            // getinfo prints header lines indented with a tab, so ignore leading whitespace
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() {
+                continue;
+            }
+            match trimmed.split_once('=') {
+                Some((key, value)) if is_variable_name(key) => lines.push(HeaderLine::Variable {
+                    key: key.to_string(),
+                    value: value.trim().to_string(),
+                }),
+                _ => lines.push(HeaderLine::Command(trimmed.to_string())),
+            }
+        }
+        HdrHeader { lines }
+    }
+
+    /**
+     * Returns the value of the last occurrence of a variable, if present
+     *
+     * @param key - The variable name without the '=' (e.g. "EVALGLARE")
+     */
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().rev().find_map(|line| match line {
+            HeaderLine::Variable { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /**
+     * Returns the values of every occurrence of a variable, in file order
+     *
+     * @param key - The variable name without the '='
+     */
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.lines.iter().filter_map(move |line| match line {
+            HeaderLine::Variable { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /**
+     * Replaces every occurrence of a variable with a single "KEY=value" line.
+     * The new line takes the place of the first occurrence, or is appended if the key is new.
+     *
+     * @param key - The variable name without the '='
+     * @param value - The new value
+     */
+    pub fn set(&mut self, key: &str, value: &str) {
+        let new_line = HeaderLine::Variable {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let first = self
+            .lines
+            .iter()
+            .position(|line| matches!(line, HeaderLine::Variable { key: k, .. } if k == key));
+        match first {
+            Some(index) => {
+                self.lines[index] = new_line;
+                let mut i = index + 1;
+                while i < self.lines.len() {
+                    if matches!(&self.lines[i], HeaderLine::Variable { key: k, .. } if k == key) {
+                        self.lines.remove(i);
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            None => self.lines.push(new_line),
+        }
+    }
+
+    /**
+     * Appends a "KEY=value" line without touching existing occurrences of the key
+     */
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.lines.push(HeaderLine::Variable {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    /**
+     * Appends a command line (e.g. the name and arguments of a processing step)
+     */
+    pub fn push_command(&mut self, command: &str) {
+        self.lines.push(HeaderLine::Command(command.to_string()));
+    }
+
+    /**
+     * Returns the FORMAT variable, if present
+     */
+    pub fn format(&self) -> Option<&str> {
+        self.get("FORMAT")
+    }
+
+    /**
+     * Returns the cumulative exposure of the picture. Radiance multiplies successive
+     * EXPOSURE lines together; a picture without EXPOSURE lines has an exposure of 1.
+     */
+    pub fn exposure(&self) -> f64 {
+        self.get_all("EXPOSURE")
+            .filter_map(|value| value.parse::<f64>().ok())
+            .product()
+    }
+
+    /**
+     * Returns the view options of the picture. Successive VIEW lines accumulate,
+     * so they are joined in file order.
+     */
+    pub fn view(&self) -> Option<String> {
+        let views: Vec<&str> = self.get_all("VIEW").collect();
+        if views.is_empty() {
+            None
+        } else {
+            Some(views.join(" "))
+        }
+    }
+
+    /**
+     * Serializes the header, including the magic line and the terminating empty line
+     */
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("#?RADIANCE\n");
+        for line in &self.lines {
+            match line {
+                HeaderLine::Variable { key, value } => {
+                    text.push_str(&format!("{}={}\n", key, value));
+                }
+                HeaderLine::Command(command) => {
+                    text.push_str(command);
+                    text.push('\n');
+                }
+            }
+        }
+        text.push('\n');
+        text
+    }
+}
+
+// Radiance variable names are a single word, e.g. "FORMAT" or "EVALGLARE"
+fn is_variable_name(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_variables_and_commands() {
+        let header = HdrHeader::parse(
+            "pcomb -s 0.5 scene.hdr\nFORMAT=32-bit_rle_rgbe\nEXPOSURE= 5.000000e-01\n\
+             \tVIEW= -vta -vv 180\npcomb -f vignetting.cal\n",
+        );
+        assert_eq!(
+            header.lines,
+            vec![
+                HeaderLine::Command("pcomb -s 0.5 scene.hdr".into()),
+                HeaderLine::Variable {
+                    key: "FORMAT".into(),
+                    value: RGBE_FORMAT.into(),
+                },
+                HeaderLine::Variable {
+                    key: "EXPOSURE".into(),
+                    value: "5.000000e-01".into(),
+                },
+                HeaderLine::Variable {
+                    key: "VIEW".into(),
+                    value: "-vta -vv 180".into(),
+                },
+                HeaderLine::Command("pcomb -f vignetting.cal".into()),
+            ]
+        );
+        assert_eq!(header.format(), Some(RGBE_FORMAT));
+    }
+
+    #[test]
+    fn lines_with_spaces_before_equals_are_commands() {
+        let header = HdrHeader::parse("pcomb -e lo=0.5\n");
+        assert_eq!(
+            header.lines,
+            vec![HeaderLine::Command("pcomb -e lo=0.5".into())]
+        );
+    }
+
+    #[test]
+    fn exposures_multiply_and_views_accumulate() {
+        let header = HdrHeader::parse("EXPOSURE=2\nVIEW=-vta\nEXPOSURE=0.25\nVIEW=-vv 180\n");
+        assert_eq!(header.exposure(), 0.5);
+        assert_eq!(header.view().as_deref(), Some("-vta -vv 180"));
+
+        let empty = HdrHeader::parse("");
+        assert_eq!(empty.exposure(), 1.0);
+        assert_eq!(empty.view(), None);
+    }
+
+    #[test]
+    fn set_replaces_every_occurrence() {
+        let mut header = HdrHeader::parse("EXPOSURE=2\nSOFTWARE=x\nEXPOSURE=3\n");
+        header.set("EXPOSURE", "1");
+        assert_eq!(header.get_all("EXPOSURE").collect::<Vec<_>>(), vec!["1"]);
+        assert_eq!(header.lines.len(), 2);
+
+        header.set("EVALGLARE", "dgp");
+        assert_eq!(header.get("EVALGLARE"), Some("dgp"));
+        assert_eq!(header.lines.len(), 3);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut header = HdrHeader::default();
+        header.push("EXPOSURE", "0.5");
+        header.push_command("pcomb -f fisheye_corr.cal");
+        let text = header.to_text();
+        assert!(text.starts_with("#?RADIANCE\n"));
+        assert!(text.ends_with("\n\n"));
+
+        let body = text.strip_prefix("#?RADIANCE\n").unwrap();
+        assert_eq!(HdrHeader::parse(body), header);
+    }
+}
@@ -0,0 +1,344 @@
+/**
+ * Module for reading and writing Radiance RGBE (.hdr) pictures without a Radiance install.
+ *
+ * Pictures are decoded into floating-point RGB buffers together with their header, so that
+ * pipeline stages, previews and analysis code can work on pixels in-process. Both flat and
+ * run-length encoded scanlines are supported when reading; pictures are always written with
+ * the adaptive run-length encoding used by Radiance itself.
+ */
+mod header;
+mod rgbe;
+
+pub use header::{HdrHeader, HeaderLine, RGBE_FORMAT};
+pub use rgbe::{rgb_to_rgbe, rgbe_to_rgb};
+
+use std::{fs, path::Path};
+
+/**
+ * A decoded Radiance picture. Pixels are stored row by row from the top of the image,
+ * as the raw values found in the file (divide by `header.exposure()` to undo EXPOSURE).
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub header: HdrHeader,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    /**
+     * Creates a black picture with a default header
+     */
+    pub fn new(width: usize, height: usize) -> HdrImage {
+        HdrImage {
+            header: HdrHeader::default(),
+            width,
+            height,
+            pixels: vec![[0.0, 0.0, 0.0]; width * height],
+        }
+    }
+
+    /**
+     * Returns the pixel at column x, row y (row 0 is the top of the image)
+     */
+    pub fn get_pixel(&self, x: usize, y: usize) -> [f32; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    /**
+     * Sets the pixel at column x, row y (row 0 is the top of the image)
+     */
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [f32; 3]) {
+        self.pixels[y * self.width + x] = rgb;
+    }
+
+    /**
+     * Decodes a picture from the contents of a .hdr file
+     *
+     * @param data - The bytes of the file
+     * @returns Result containing the decoded picture or an error message
+     */
+    pub fn from_bytes(data: &[u8]) -> Result<HdrImage, String> {
+        let (header, mut pos) = parse_header(data)?;
+
+        if let Some(format) = header.format() {
+            if format != RGBE_FORMAT {
+                return Err(format!(
+                    "radiance_hdr: unsupported picture format '{}'.",
+                    format
+                ));
+            }
+        }
+
+        // Resolution string, e.g. "-Y 1000 +X 1000"
+        let line_end = data[pos..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or("radiance_hdr: missing resolution string.")?;
+        let resolution = String::from_utf8_lossy(&data[pos..pos + line_end]).to_string();
+        pos += line_end + 1;
+        let (width, height, bottom_up) = parse_resolution(&resolution)?;
+
+        let mut pixels = vec![[0.0, 0.0, 0.0]; width * height];
+        let mut scanline = vec![[0u8; 4]; width];
+        for row in 0..height {
+            rgbe::read_scanline(data, &mut pos, &mut scanline)?;
+
+            // Store rows from the top of the image regardless of the file orientation
+            let y = if bottom_up { height - 1 - row } else { row };
+            for (pixel, rgbe) in pixels[y * width..(y + 1) * width].iter_mut().zip(&scanline) {
+                *pixel = rgbe_to_rgb(*rgbe);
+            }
+        }
+
+        Ok(HdrImage {
+            header,
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /**
+     * Encodes the picture into the contents of a .hdr file. A FORMAT line is added to the
+     * header if it is missing.
+     */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = self.header.clone();
+        if header.format().is_none() {
+            header.set("FORMAT", RGBE_FORMAT);
+        }
+
+        let mut out = header.to_text().into_bytes();
+        out.extend_from_slice(format!("-Y {} +X {}\n", self.height, self.width).as_bytes());
+
+        let mut scanline = vec![[0u8; 4]; self.width];
+        for row in self.pixels.chunks(self.width.max(1)).take(self.height) {
+            for (rgbe, pixel) in scanline.iter_mut().zip(row) {
+                *rgbe = rgb_to_rgbe(*pixel);
+            }
+            rgbe::write_scanline(&scanline, &mut out);
+        }
+        out
+    }
+}
+
+/**
+ * Reads and decodes a .hdr file
+ *
+ * @param path - Path of the .hdr file
+ * @returns Result containing the decoded picture or an error message
+ */
+pub fn read_hdr(path: &Path) -> Result<HdrImage, String> {
+    let data = fs::read(path)
+        .map_err(|e| format!("radiance_hdr: failed to read {}: {}", path.display(), e))?;
+    HdrImage::from_bytes(&data).map_err(|e| format!("{} ({})", e, path.display()))
+}
+
+/**
+ * Reads only the header of a .hdr file, without decoding the pixels
+ *
+ * @param path - Path of the .hdr file
+ * @returns Result containing the header or an error message
+ */
+pub fn read_hdr_header(path: &Path) -> Result<HdrHeader, String> {
+    let data = fs::read(path)
+        .map_err(|e| format!("radiance_hdr: failed to read {}: {}", path.display(), e))?;
+    parse_header(&data)
+        .map(|(header, _)| header)
+        .map_err(|e| format!("{} ({})", e, path.display()))
+}
+
+/**
+ * Encodes and writes a picture to a .hdr file
+ *
+ * @param path - Path of the .hdr file to create or overwrite
+ * @param image - The picture to write
+ */
+pub fn write_hdr(path: &Path, image: &HdrImage) -> Result<(), String> {
+    if image.pixels.len() != image.width * image.height {
+        return Err("radiance_hdr: pixel buffer does not match image dimensions.".into());
+    }
+    fs::write(path, image.to_bytes())
+        .map_err(|e| format!("radiance_hdr: failed to write {}: {}", path.display(), e))
+}
+
+// Parses the magic line and header, returning the header and the offset of the resolution string
+fn parse_header(data: &[u8]) -> Result<(HdrHeader, usize), String> {
+    if !data.starts_with(b"#?") {
+        return Err("radiance_hdr: not a Radiance picture (missing '#?' magic line).".into());
+    }
+
+    // The header ends at the first empty line
+    let end = data
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .ok_or("radiance_hdr: unterminated header.")?;
+    let text = String::from_utf8_lossy(&data[..end]);
+
+    // Skip the magic line itself
+    let body = text.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+    Ok((HdrHeader::parse(body), end + 2))
+}
+
+// Parses the resolution string, returning (width, height, rows stored bottom-up)
+fn parse_resolution(resolution: &str) -> Result<(usize, usize, bool), String> {
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    if fields.len() != 4 {
+        return Err(format!(
+            "radiance_hdr: bad resolution string '{}'.",
+            resolution
+        ));
+    }
+
+    let first = fields[1].parse::<usize>();
+    let second = fields[3].parse::<usize>();
+    if first.is_err() || second.is_err() {
+        return Err(format!(
+            "radiance_hdr: bad resolution string '{}'.",
+            resolution
+        ));
+    }
+
+    // Only orientations with rows along the Y axis are supported (standard "-Y h +X w")
+    match (fields[0], fields[2]) {
+        ("-Y", "+X") => Ok((second.unwrap(), first.unwrap(), false)),
+        ("+Y", "+X") => Ok((second.unwrap(), first.unwrap(), true)),
+        _ => Err(format!(
+            "radiance_hdr: unsupported picture orientation '{}'.",
+            resolution
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Picture in the layout Radiance writes: a header with a command line, FORMAT, EXPOSURE and
+    // VIEW lines, then 3 scanlines of 16 pixels encoded with the adaptive run-length encoding
+    // of fwritecolrs (runs, literal bytes and short runs in each component)
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/radiance_rle.hdr"
+    );
+
+    // Picture whose pixels survive the conversion to RGBE unchanged
+    fn test_image(width: usize, height: usize) -> HdrImage {
+        let mut image = HdrImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let rgbe = [
+                    128 + (x % 128) as u8,
+                    (y * 3) as u8,
+                    200,
+                    120 + (x / 16 % 20) as u8,
+                ];
+                image.set_pixel(x, y, rgbe_to_rgb(if x % 7 == 3 { [0; 4] } else { rgbe }));
+            }
+        }
+        image.header.set("EXPOSURE", "0.5");
+        image
+    }
+
+    #[test]
+    fn decodes_fixture() {
+        let image = read_hdr(Path::new(FIXTURE)).unwrap();
+        assert_eq!((image.width, image.height), (16, 3));
+        assert_eq!(image.header.format(), Some(RGBE_FORMAT));
+        assert_eq!(image.header.exposure(), 0.5);
+        assert_eq!(image.header.view().as_deref(), Some("-vta -vv 180 -vh 180"));
+        assert_eq!(
+            image.header.lines[0],
+            HeaderLine::Command("pcomb -s 0.5 scene.hdr".into())
+        );
+
+        assert_eq!(
+            image.get_pixel(0, 0),
+            [200.5 / 256.0, 180.5 / 256.0, 160.5 / 256.0]
+        );
+        assert_eq!(
+            image.get_pixel(5, 2),
+            [200.5 / 64.0, 180.5 / 64.0, 160.5 / 64.0]
+        );
+        assert_eq!(
+            image.get_pixel(6, 1),
+            [176.5 / 64.0, 101.5 / 64.0, 56.5 / 64.0]
+        );
+        assert_eq!(image.get_pixel(12, 1), [0.0, 0.0, 0.0]);
+        assert_eq!(image.get_pixel(15, 2), [4088.0, 4088.0, 4088.0]);
+    }
+
+    #[test]
+    fn encodes_fixture_pixels_as_radiance() {
+        // Re-encoding the pixels gives the same bytes as the fixture
+        let data = fs::read(FIXTURE).unwrap();
+        let encoded = HdrImage::from_bytes(&data).unwrap().to_bytes();
+        let resolution = b"-Y 3 +X 16\n";
+        let pixels = |bytes: &[u8]| {
+            let start = bytes
+                .windows(resolution.len())
+                .position(|w| w == resolution)
+                .unwrap();
+            bytes[start..].to_vec()
+        };
+        assert_eq!(pixels(&encoded), pixels(&data));
+    }
+
+    #[test]
+    fn round_trips_every_width() {
+        // Flat (short and wide scanlines) and adaptive run-length encodings
+        for width in [1, 7, 8, 300, 0x7fff, 40000] {
+            let image = test_image(width, 2);
+            let decoded = HdrImage::from_bytes(&image.to_bytes()).unwrap();
+            assert_eq!(decoded, image, "width {}", width);
+        }
+    }
+
+    #[test]
+    fn reads_bottom_up_pictures() {
+        let image = test_image(8, 2);
+        let mut data = image.header.to_text().into_bytes();
+        data.extend_from_slice(b"+Y 2 +X 8\n");
+        for y in [1, 0] {
+            for x in 0..8 {
+                data.extend_from_slice(&rgb_to_rgbe(image.get_pixel(x, y)));
+            }
+        }
+        assert_eq!(HdrImage::from_bytes(&data).unwrap().pixels, image.pixels);
+    }
+
+    #[test]
+    fn header_ends_at_first_empty_line() {
+        let data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=2\n\n-Y 1 +X 1\n";
+        let (header, pos) = parse_header(data).unwrap();
+        assert_eq!(header.exposure(), 2.0);
+        assert_eq!(header.lines.len(), 2);
+        assert_eq!(&data[pos..], b"-Y 1 +X 1\n");
+    }
+
+    #[test]
+    fn truncated_pictures_are_errors() {
+        let data = test_image(300, 4).to_bytes();
+        let header_end = data.windows(2).position(|w| w == b"\n\n").unwrap();
+
+        // Unterminated header, missing resolution string, missing and partial scanlines
+        for len in [header_end, header_end + 2, header_end + 8, data.len() - 1] {
+            assert!(
+                HdrImage::from_bytes(&data[..len]).is_err(),
+                "length {}",
+                len
+            );
+        }
+        assert!(parse_header(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n").is_err());
+        assert!(parse_header(b"P6\n1 1\n255\n").is_err());
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let data = b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0";
+        assert!(HdrImage::from_bytes(data).is_err());
+        assert!(HdrImage::from_bytes(b"#?RADIANCE\n\n-Y 1 -X 1\n\0\0\0\0").is_err());
+    }
+}
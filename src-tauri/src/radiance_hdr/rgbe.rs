@@ -0,0 +1,391 @@
+// Conversion between RGBE pixels and floating-point colors, and the scanline encodings
+// used by Radiance pictures.
+//
+// Scanlines are stored either flat (4 bytes per pixel, optionally with the old-style
+// run-length encoding that repeats the previous pixel), or with the adaptive run-length
+// encoding introduced in Radiance 2.0, where each of the four components is encoded separately.
+
+// Scanlines outside this range of widths cannot use the adaptive run-length encoding
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+
+// Shortest run worth encoding as a run instead of literal bytes
+const MIN_RUN: usize = 4;
+
+/**
+ * Converts an RGBE pixel to a floating-point RGB color (same rounding as Radiance's colr_color)
+ */
+pub fn rgbe_to_rgb(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0, 0.0, 0.0];
+    }
+    let f = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    [
+        ((rgbe[0] as f64 + 0.5) * f) as f32,
+        ((rgbe[1] as f64 + 0.5) * f) as f32,
+        ((rgbe[2] as f64 + 0.5) * f) as f32,
+    ]
+}
+
+/**
+ * Converts a floating-point RGB color to an RGBE pixel (same rounding as Radiance's setcolr).
+ * Negative components are clamped to zero.
+ */
+pub fn rgb_to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let r = rgb[0].max(0.0) as f64;
+    let g = rgb[1].max(0.0) as f64;
+    let b = rgb[2].max(0.0) as f64;
+    let max = r.max(g).max(b);
+    if max <= 1e-32 || !max.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // frexp: max = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let mut exponent = max.log2().floor() as i32 + 1;
+    let mut mantissa = max / 2f64.powi(exponent);
+    if mantissa >= 1.0 {
+        mantissa /= 2.0;
+        exponent += 1;
+    } else if mantissa < 0.5 {
+        mantissa *= 2.0;
+        exponent -= 1;
+    }
+    if exponent < -128 {
+        return [0, 0, 0, 0];
+    }
+    let exponent = exponent.min(127);
+
+    let scale = mantissa * 256.0 / max;
+    [
+        (r * scale).min(255.0) as u8,
+        (g * scale).min(255.0) as u8,
+        (b * scale).min(255.0) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+/**
+ * Decodes one scanline of RGBE pixels starting at `pos`, advancing `pos` past it.
+ * Handles flat, old-style run-length and adaptive run-length encoded scanlines.
+ *
+ * @param data - The picture data following the resolution string
+ * @param pos - Offset of the scanline in `data`, updated to the start of the next scanline
+ * @param scanline - Destination buffer, its length is the scanline width
+ */
+pub fn read_scanline(data: &[u8], pos: &mut usize, scanline: &mut [[u8; 4]]) -> Result<(), String> {
+    let width = scanline.len();
+    if width == 0 {
+        return Ok(());
+    }
+
+    if (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) && data.len() >= *pos + 4 {
+        let marker = &data[*pos..*pos + 4];
+        if marker[0] == 2 && marker[1] == 2 && marker[2] & 0x80 == 0 {
+            let encoded_width = ((marker[2] as usize) << 8) | marker[3] as usize;
+            if encoded_width != width {
+                return Err("radiance_hdr: scanline length does not match image width.".into());
+            }
+            *pos += 4;
+            return read_adaptive_rle_scanline(data, pos, scanline);
+        }
+    }
+
+    read_flat_scanline(data, pos, scanline)
+}
+
+// Reads a scanline where each component is run-length encoded separately
+fn read_adaptive_rle_scanline(
+    data: &[u8],
+    pos: &mut usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<(), String> {
+    let width = scanline.len();
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data
+                .get(*pos)
+                .ok_or("radiance_hdr: unexpected end of file in scanline.")?
+                as usize;
+            *pos += 1;
+
+            if count > 128 {
+                // Run of the same byte
+                let run = count - 128;
+                if x + run > width {
+                    return Err("radiance_hdr: run-length overruns scanline.".into());
+                }
+                let value = *data
+                    .get(*pos)
+                    .ok_or("radiance_hdr: unexpected end of file in scanline.")?;
+                *pos += 1;
+                for pixel in &mut scanline[x..x + run] {
+                    pixel[component] = value;
+                }
+                x += run;
+            } else {
+                // Literal bytes
+                if count == 0 || x + count > width {
+                    return Err("radiance_hdr: bad scanline data.".into());
+                }
+                if *pos + count > data.len() {
+                    return Err("radiance_hdr: unexpected end of file in scanline.".into());
+                }
+                for (pixel, value) in scanline[x..x + count]
+                    .iter_mut()
+                    .zip(&data[*pos..*pos + count])
+                {
+                    pixel[component] = *value;
+                }
+                *pos += count;
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads a flat scanline, expanding old-style runs (1,1,1,count) of the previous pixel
+fn read_flat_scanline(
+    data: &[u8],
+    pos: &mut usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<(), String> {
+    let width = scanline.len();
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        if *pos + 4 > data.len() {
+            return Err("radiance_hdr: unexpected end of file in scanline.".into());
+        }
+        let pixel = [data[*pos], data[*pos + 1], data[*pos + 2], data[*pos + 3]];
+        *pos += 4;
+
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+            if x == 0 {
+                return Err("radiance_hdr: run-length at start of scanline.".into());
+            }
+            let run = (pixel[3] as usize) << shift;
+            if x + run > width {
+                return Err("radiance_hdr: run-length overruns scanline.".into());
+            }
+            let previous = scanline[x - 1];
+            for p in &mut scanline[x..x + run] {
+                *p = previous;
+            }
+            x += run;
+            shift += 8;
+        } else {
+            scanline[x] = pixel;
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Encodes one scanline of RGBE pixels, using the adaptive run-length encoding when the
+ * width allows it and flat pixels otherwise
+ *
+ * @param scanline - The pixels of the scanline
+ * @param out - Buffer the encoded bytes are appended to
+ */
+pub fn write_scanline(scanline: &[[u8; 4]], out: &mut Vec<u8>) {
+    let width = scanline.len();
+    if !(MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) {
+        for pixel in scanline {
+            out.extend_from_slice(pixel);
+        }
+        return;
+    }
+
+    out.extend_from_slice(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+    let mut component_bytes = vec![0u8; width];
+    for component in 0..4 {
+        for (byte, pixel) in component_bytes.iter_mut().zip(scanline) {
+            *byte = pixel[component];
+        }
+        write_rle_component(&component_bytes, out);
+    }
+}
+
+// Run-length encodes one component of a scanline (same scheme as Radiance's fwritecolrs)
+fn write_rle_component(data: &[u8], out: &mut Vec<u8>) {
+    let len = data.len();
+    let mut cur = 0;
+    while cur < len {
+        // Find the next run that is long enough to be worth encoding
+        let mut begin_run = cur;
+        let mut run_count = 0;
+        let mut old_run_count = 0;
+        while run_count < MIN_RUN && begin_run < len {
+            begin_run += run_count;
+            old_run_count = run_count;
+            run_count = 1;
+            while begin_run + run_count < len
+                && run_count < 127
+                && data[begin_run] == data[begin_run + run_count]
+            {
+                run_count += 1;
+            }
+        }
+
+        // A short run directly before the long run is cheaper as a run than as literals
+        if old_run_count > 1 && old_run_count == begin_run - cur {
+            out.push((128 + old_run_count) as u8);
+            out.push(data[cur]);
+            cur = begin_run;
+        }
+
+        // Literal bytes up to the start of the run
+        while cur < begin_run {
+            let count = (begin_run - cur).min(128);
+            out.push(count as u8);
+            out.extend_from_slice(&data[cur..cur + count]);
+            cur += count;
+        }
+
+        // The run itself
+        if run_count >= MIN_RUN {
+            out.push((128 + run_count) as u8);
+            out.push(data[begin_run]);
+            cur += run_count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pixels with a run of equal pixels, a stretch of different ones and a black pixel. The
+    // largest component is at least 128 (as setcolr writes it), so the pixels survive a
+    // conversion to floating-point and back.
+    fn test_scanline(width: usize) -> Vec<[u8; 4]> {
+        (0..width)
+            .map(|x| match x % 40 {
+                0..=19 => [200, 150, 100, 130],
+                20 => [0, 0, 0, 0],
+                i => [128 + i as u8, (i * 7) as u8, (x % 256) as u8, 120 + i as u8],
+            })
+            .collect()
+    }
+
+    // Encodes a scanline, decodes it again and checks that the whole encoding was read
+    fn round_trip(scanline: &[[u8; 4]]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        write_scanline(scanline, &mut encoded);
+
+        let mut decoded = vec![[0u8; 4]; scanline.len()];
+        let mut pos = 0;
+        read_scanline(&encoded, &mut pos, &mut decoded).unwrap();
+        assert_eq!(decoded, scanline);
+        assert_eq!(pos, encoded.len());
+        encoded
+    }
+
+    #[test]
+    fn adaptive_rle_round_trip() {
+        let scanline = test_scanline(300);
+        let encoded = round_trip(&scanline);
+        assert_eq!(encoded[..4], [2, 2, 1, 44]);
+        assert!(encoded.len() < 300 * 4);
+    }
+
+    #[test]
+    fn adaptive_rle_splits_long_runs() {
+        // Runs are at most 127 bytes long
+        let scanline = vec![[10, 20, 30, 128]; 1000];
+        let encoded = round_trip(&scanline);
+        assert_eq!(encoded[4..6], [128 + 127, 10]);
+    }
+
+    #[test]
+    fn short_scanline_is_flat() {
+        let scanline = test_scanline(MIN_RLE_WIDTH - 1);
+        let encoded = round_trip(&scanline);
+        assert_eq!(encoded, scanline.concat());
+    }
+
+    #[test]
+    fn wide_scanline_is_flat() {
+        let scanline = test_scanline(MAX_RLE_WIDTH + 1);
+        let encoded = round_trip(&scanline);
+        assert_eq!(encoded, scanline.concat());
+
+        let scanline = test_scanline(40000);
+        assert_eq!(round_trip(&scanline).len(), 40000 * 4);
+    }
+
+    #[test]
+    fn flat_round_trip() {
+        // Flat pixels are also accepted when the width allows the adaptive encoding
+        let scanline = test_scanline(64);
+        let encoded = scanline.concat();
+        let mut decoded = vec![[0u8; 4]; 64];
+        let mut pos = 0;
+        read_scanline(&encoded, &mut pos, &mut decoded).unwrap();
+        assert_eq!(decoded, scanline);
+        assert_eq!(pos, encoded.len());
+    }
+
+    #[test]
+    fn old_rle_scanline() {
+        // A pixel, a run of 2 copies, then a run of 1 << 8 copies (successive run markers
+        // are shifted by 8 bits), then another pixel
+        let encoded = [
+            [10, 20, 30, 128],
+            [1, 1, 1, 2],
+            [1, 1, 1, 1],
+            [40, 50, 60, 129],
+        ]
+        .concat();
+        let mut decoded = vec![[0u8; 4]; 260];
+        let mut pos = 0;
+        read_scanline(&encoded, &mut pos, &mut decoded).unwrap();
+        assert!(decoded[..259].iter().all(|p| *p == [10, 20, 30, 128]));
+        assert_eq!(decoded[259], [40, 50, 60, 129]);
+        assert_eq!(pos, encoded.len());
+    }
+
+    #[test]
+    fn old_rle_run_at_start_is_an_error() {
+        let encoded = [[1, 1, 1, 4], [10, 20, 30, 128]].concat();
+        let mut decoded = vec![[0u8; 4]; 5];
+        assert!(read_scanline(&encoded, &mut 0, &mut decoded).is_err());
+    }
+
+    #[test]
+    fn truncated_scanline_is_an_error() {
+        let mut encoded = Vec::new();
+        write_scanline(&test_scanline(300), &mut encoded);
+        for len in [3, 5, encoded.len() / 2, encoded.len() - 1] {
+            let mut decoded = vec![[0u8; 4]; 300];
+            assert!(read_scanline(&encoded[..len], &mut 0, &mut decoded).is_err());
+        }
+    }
+
+    #[test]
+    fn mismatched_width_is_an_error() {
+        let mut encoded = Vec::new();
+        write_scanline(&test_scanline(300), &mut encoded);
+        let mut decoded = vec![[0u8; 4]; 299];
+        assert!(read_scanline(&encoded, &mut 0, &mut decoded).is_err());
+    }
+
+    #[test]
+    fn rgbe_conversions() {
+        assert_eq!(rgbe_to_rgb([0, 0, 0, 0]), [0.0, 0.0, 0.0]);
+        assert_eq!(
+            rgbe_to_rgb([128, 64, 32, 129]),
+            [128.5 / 128.0, 64.5 / 128.0, 32.5 / 128.0]
+        );
+        assert_eq!(rgb_to_rgbe([1.0, 0.5, 0.25]), [128, 64, 32, 129]);
+        assert_eq!(rgb_to_rgbe([-1.0, 0.0, 0.0]), [0, 0, 0, 0]);
+        assert_eq!(rgb_to_rgbe([0.0, 0.0, 0.0]), [0, 0, 0, 0]);
+        for rgbe in test_scanline(40) {
+            assert_eq!(rgb_to_rgbe(rgbe_to_rgb(rgbe)), rgbe);
+        }
+    }
+}
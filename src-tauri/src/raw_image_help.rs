@@ -1,6 +1,7 @@
 use std::path::Path;
 
 use crate::image_cache::ensure_tiff_for_raw;
+use crate::pipeline::CancellationToken;
 
 // converts raw image(s) into .tiff image(s) with caching. Returns temp cache paths.
 #[tauri::command]
@@ -20,7 +21,9 @@ pub async fn convert_raw_img(
                 None
             },
             Path::new(&p),
-        )?;
+            &CancellationToken::new(),
+        )
+        .map_err(|error| error.to_string())?;
         outputs.push(output.display().to_string());
     }
 
@@ -0,0 +1,56 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::pipeline::{get_images_from_dir, recover_response, ChannelFit};
+
+/**
+ * Result of the recover_response_function command
+ *
+ * @field output_path - Path of the written .rsp file
+ * @field channels - Fit of the red, green and blue response curves
+ */
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseRecoveryReport {
+    output_path: String,
+    channels: Vec<ChannelFit>,
+}
+
+// Recovers the response function of a camera from one or more bracket sets, and writes it to a
+// .rsp file in the format hdrgen writes, so that it can be used in a configuration instead of
+// running hdrgen separately. Returns the fit residuals of each channel.
+// bracket_sets:
+//      The images of each bracket set (JPG or TIFF, with exposure times in their EXIF metadata),
+//      or a single directory containing them
+// output_path:
+//      Path of the .rsp file to write
+// order:
+//      Order of the response polynomials (chosen from the fit residuals if omitted)
+#[tauri::command]
+pub async fn recover_response_function(
+    bracket_sets: Vec<Vec<String>>,
+    output_path: String,
+    order: Option<usize>,
+) -> Result<ResponseRecoveryReport, String> {
+    if bracket_sets.is_empty() {
+        return Err("No bracket sets were provided.".into());
+    }
+
+    // Expand bracket sets given as a directory
+    let bracket_sets = bracket_sets
+        .into_iter()
+        .map(|images| match images.as_slice() {
+            [dir] if Path::new(dir).is_dir() => get_images_from_dir(dir),
+            _ => Ok(images),
+        })
+        .collect::<Result<Vec<Vec<String>>, String>>()?;
+
+    let recovery = recover_response(&bracket_sets, order)?;
+    recovery.response_function.write(Path::new(&output_path))?;
+
+    Ok(ResponseRecoveryReport {
+        output_path,
+        channels: recovery.channels,
+    })
+}
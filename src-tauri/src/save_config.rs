@@ -7,6 +7,8 @@ use serde::Serialize;
 use serde_json::to_string;
 use tauri::Manager;
 
+use crate::pipeline::EvalglareArgs;
+
 #[derive(Serialize)]
 struct Config {
     name: String,
@@ -25,11 +27,13 @@ struct Config {
     scale_label: String,
     scale_levels: String,
     legend_dimensions: String,
+    evalglare: EvalglareArgs,
 }
 
 // Saves a configuration, which includes view settings, response function, and calibration files.
 // Configuration is saved to "{app_config_dir}/configurations/{config_name}".
 // Writes a JSON file containing relative paths of the copied files, the configuration name, and the view settings.
+// The evalglare options are optional, evalglare's defaults are saved when they are not given.
 #[tauri::command(rename_all = "snake_case")]
 pub async fn save_config(
     app_handle: tauri::AppHandle,
@@ -49,6 +53,7 @@ pub async fn save_config(
     scale_label: String,
     scale_levels: String,
     legend_dimensions: String,
+    evalglare: Option<EvalglareArgs>,
 ) -> Result<String, String> {
     let mut config = Config {
         name,
@@ -67,6 +72,7 @@ pub async fn save_config(
         scale_label,
         scale_levels,
         legend_dimensions,
+        evalglare: evalglare.unwrap_or_default(),
     };
 
     // Retrieved part of this code from https://github.com/tauri-apps/tauri/discussions/5557
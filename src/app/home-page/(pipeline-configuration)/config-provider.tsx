@@ -23,6 +23,10 @@ export type pipelineConfig = {
 	outputSettings: {
 		targetRes: number | null;
 		filterIrrelevantSrcImages: boolean;
+		nativeMerge: boolean;
+		alignExposures: boolean;
+		checkLensMask: boolean;
+		glareCheckImage: boolean;
 	};
 };
 
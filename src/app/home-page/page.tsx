@@ -43,13 +43,19 @@ import { useMotionValue, useTransform } from "framer-motion";
 import { useMotionValueFormState } from "@/lib/useMotionValueFormState";
 import { LensMaskInput } from "./lens-mask-input";
 import { invoke } from "@tauri-apps/api/core";
+import { save } from "@tauri-apps/plugin-dialog";
 import { useSettingsStore } from "../stores/settings-store";
 import { Checkbox } from "@/components/ui/checkbox";
 import { Label } from "@/components/ui/label";
 import { Button } from "@/components/ui/button";
 import { FieldContainerAccordionTrigger } from "@/components/ui/field-accordion-trigger";
 import { PipelineStatus } from "./pipeline-status";
+import { ResponseCurvePreview } from "./response-curve-preview";
 import { toast } from "sonner";
+import {
+	describePipelineError,
+	parsePipelineError,
+} from "@/lib/pipeline-error";
 
 import { create } from "zustand";
 
@@ -76,6 +82,10 @@ const useGlobalPipelineConfig = create<
 	outputSettings: {
 		targetRes: null,
 		filterIrrelevantSrcImages: true,
+		nativeMerge: false,
+		alignExposures: false,
+		checkLensMask: false,
+		glareCheckImage: false,
 	},
 
 	set,
@@ -106,6 +116,40 @@ export default function Home() {
 
 	const inputSets = watch("inputSets");
 
+	/**
+	 * Recovers a camera response function from the selected image sets, writes it to a .rsp
+	 * file chosen by the user, and uses it as the camera response
+	 */
+	const recoverResponse = async () => {
+		const outputPath = await save({
+			title: "Save camera response function",
+			filters: [{ name: "Camera response files", extensions: ["rsp"] }],
+		});
+		if (!outputPath) return;
+
+		invoke<{
+			outputPath: string;
+			channels: { order: number; rmsResidual: number; sampleCount: number }[];
+		}>("recover_response_function", {
+			bracketSets: inputSets.map((set) => set.files),
+			outputPath,
+		})
+			.then((report) => {
+				setValue("cameraResponseLocation", report.outputPath);
+				toast.success("Camera response function recovered.", {
+					description: report.channels
+						.map(
+							(fit, i) =>
+								`${["Red", "Green", "Blue"][i]}: order ${fit.order}, RMS residual ${fit.rmsResidual.toExponential(2)} (${fit.sampleCount} samples)`
+						)
+						.join("\n"),
+				});
+			})
+			.catch((error) =>
+				toast.error("Could not recover the camera response function: " + error)
+			);
+	};
+
 	const maskPreviewImage = useMemo(() => {
 		return inputSets?.[0]?.files?.[0];
 	}, [inputSets]);
@@ -142,6 +186,41 @@ export default function Home() {
 		return () => unsub();
 	}, [radius, setValue]);
 
+	/**
+	 * Finds the fisheye circle in the brightest image of the first image set, and uses it as
+	 * the lens mask
+	 */
+	const detectLensMask = () => {
+		invoke<{
+			image: string;
+			diameter: number;
+			xCenter: number;
+			yCenter: number;
+			confidence: number;
+		}>("detect_fisheye_circle", {
+			dcraw: settings.dcrawEmuPath,
+			images: inputSets[0].files,
+		})
+			.then((detection) => {
+				const radius = detection.diameter / 2;
+				centerX.set(detection.xCenter);
+				centerY.set(detection.yCenter);
+				radiusAjusterCenterX.set(detection.xCenter + radius);
+				radiusAjusterCenterY.set(detection.yCenter);
+				setValue("lensMask.radius", radius);
+
+				const description = `Found in ${detection.image} (confidence ${detection.confidence.toFixed(2)}).`;
+				if (detection.confidence < 0.6) {
+					toast.warning("The detected lens mask may be wrong.", {
+						description,
+					});
+				} else {
+					toast.success("Lens mask detected.", { description });
+				}
+			})
+			.catch((error) => toast.error("Could not detect the lens mask: " + error));
+	};
+
 	const [progressVisible, setProgressVisible] = useState(false);
 
 	return (
@@ -156,7 +235,8 @@ export default function Home() {
 						const targetRes = data.outputSettings.targetRes!;
 						const verticalAngle = data.fisheyeView.verticalViewDegrees!;
 						const horizontalAngle = data.fisheyeView.horizontalViewDegrees!;
-						const imageSet = data.inputSets[0]!; // TODO: implement batch processing
+						// Several image sets (e.g. the brackets of a split folder) are run as a batch
+						const batch = data.inputSets.length > 1;
 						const params = {
 							// Paths to external tools
 							radiancePath: settings.radiancePath,
@@ -164,34 +244,55 @@ export default function Home() {
 							dcrawEmuPath: settings.dcrawEmuPath,
 							outputPath: settings.outputPath,
 
-							// Input images and correction files
-							inputImages: imageSet.files,
-							responseFunction: data.cameraResponseLocation ?? "",
-							fisheyeCorrectionCal: data.correctionFiles.fisheye ?? "",
-							vignettingCorrectionCal: data.correctionFiles.vignetting ?? "",
+							// Input images and correction files (null when not provided)
+							inputImages: batch ? [] : data.inputSets[0]!.files,
+							bracketGroups: batch
+								? data.inputSets.map((set) => ({
+										name: set.name,
+										images: set.files,
+									}))
+								: [],
+							responseFunction: data.cameraResponseLocation || null,
+							fisheyeCorrectionCal: data.correctionFiles.fisheye || null,
+							vignettingCorrectionCal: data.correctionFiles.vignetting || null,
 							photometricAdjustmentCal:
-								data.correctionFiles.calibrationFactor ?? "",
-							neutralDensityCal: data.correctionFiles.neutralDensity ?? "",
-							// todo: refactor the backend to accept proper numerical types instead of icky strings that will be coerced later.
-							diameter: String(Math.round(data.lensMask.radius * 2)),
-							xleft: String(Math.round(data.lensMask.x - data.lensMask.radius)),
-							ydown: String(Math.round(data.lensMask.y - data.lensMask.radius)),
-							xdim: String(targetRes),
-							ydim: String(targetRes),
-							verticalAngle: String(verticalAngle),
-							horizontalAngle: String(horizontalAngle),
-							// todo: remove these from this form completely when we get to refactoring the backend. These should only be exposed on the image viewer, where they are relevant
-							scaleLimit: "",
-							scaleLabel: "",
-							scaleLevels: "",
-							legendDimensions: "",
+								data.correctionFiles.calibrationFactor || null,
+							neutralDensityCal: data.correctionFiles.neutralDensity || null,
+							diameter: Math.round(data.lensMask.radius * 2),
+							xleft: Math.round(data.lensMask.x - data.lensMask.radius),
+							ydown: Math.round(data.lensMask.y - data.lensMask.radius),
+							xdim: targetRes,
+							ydim: targetRes,
+							verticalAngle: verticalAngle,
+							horizontalAngle: horizontalAngle,
+							// todo: falsecolor settings (luminance) should only be exposed on the image viewer, where they are relevant; falsecolor defaults are used for now
 							filterImages: data.outputSettings.filterIrrelevantSrcImages,
+							mergeBackend: data.outputSettings.nativeMerge ? "native" : "hdrgen",
+							alignExposures: data.outputSettings.alignExposures,
+							checkFisheyeCircle: data.outputSettings.checkLensMask,
+							// other evalglare options use evalglare's defaults; they can be set in saved configurations
+							evalglare: { checkImage: data.outputSettings.glareCheckImage },
 						};
 						console.log("pipeline params", params);
-						const invokePromise = invoke<string>("pipeline", params).catch(
-							(error) => {
+						const invokePromise = invoke<string>("pipeline", { params }).catch(
+							(rawError) => {
 								setProgressVisible(false);
-								toast.error("Error generating HDR image: " + error);
+								const error = parsePipelineError(rawError);
+								if (error.kind === "cancelled") {
+									toast.info("HDR image generation was cancelled.");
+								} else {
+									// Show the failed command and its stderr so users can see why it failed
+									const { summary, details } = describePipelineError(error);
+									toast.error("Error generating HDR image: " + summary, {
+										description: details && (
+											<pre className="max-h-48 overflow-auto whitespace-pre-wrap text-xs">
+												{details}
+											</pre>
+										),
+										duration: details ? Infinity : undefined,
+										closeButton: true,
+									});
+								}
 							}
 						);
 						console.log("invokePromise", invokePromise);
@@ -226,6 +327,8 @@ export default function Home() {
 									"lensMask.x",
 									"lensMask.y",
 									"outputSettings.filterIrrelevantSrcImages",
+									"outputSettings.nativeMerge",
+									"outputSettings.alignExposures",
 								]}
 							>
 								HDR Generation
@@ -262,6 +365,60 @@ export default function Home() {
 										to finish the generation process.
 									</TooltipContent>
 								</Tooltip>
+								<Tooltip>
+									<TooltipTrigger asChild>
+										<div className="flex items-center gap-2">
+											<Controller
+												name="outputSettings.nativeMerge"
+												control={control}
+												render={({ field }) => (
+													<Checkbox
+														checked={field.value ?? false}
+														onCheckedChange={(checked) =>
+															field.onChange(Boolean(checked))
+														}
+														onBlur={field.onBlur}
+														ref={field.ref}
+													/>
+												)}
+											/>
+											<Label>Merge exposures without hdrgen</Label>
+										</div>
+									</TooltipTrigger>
+									<TooltipContent className="max-w-xs">
+										Merges the LDR images with the built-in merge instead of
+										hdrgen, for platforms hdrgen is not available on. Requires a
+										camera response file, and exposure times in the EXIF
+										metadata of the images.
+									</TooltipContent>
+								</Tooltip>
+								<Tooltip>
+									<TooltipTrigger asChild>
+										<div className="flex items-center gap-2">
+											<Controller
+												name="outputSettings.alignExposures"
+												control={control}
+												render={({ field }) => (
+													<Checkbox
+														checked={field.value ?? false}
+														onCheckedChange={(checked) =>
+															field.onChange(Boolean(checked))
+														}
+														onBlur={field.onBlur}
+														ref={field.ref}
+													/>
+												)}
+											/>
+											<Label>Align exposures</Label>
+										</div>
+									</TooltipTrigger>
+									<TooltipContent className="max-w-xs">
+										Aligns the LDR images before merging them, for handheld or
+										bumped brackets that would give a ghosted HDR image. Only
+										shifts are corrected, not rotations. Pixels outside the
+										fisheye view are ignored.
+									</TooltipContent>
+								</Tooltip>
 								<div className="flex flex-col gap-2">
 									<Tooltip>
 										<TooltipTrigger asChild>
@@ -278,7 +435,8 @@ export default function Home() {
 									</Tooltip>
 									<FileInput
 										// disabled={true}
-										disabled={inputSets?.every((set) =>
+										// the native merge needs a response function for raw images too
+										disabled={!formValues.outputSettings?.nativeMerge && inputSets?.every((set) =>
 											set.files.every((file) => {
 												const fileextension = file
 													.split(".")
@@ -296,6 +454,17 @@ export default function Home() {
 										]}
 										rules={{ required: "Camera response file is required" }}
 									/>
+									<Button
+										type="button"
+										variant="outline"
+										disabled={!inputSets?.length}
+										onClick={recoverResponse}
+									>
+										Recover from image sets
+									</Button>
+									<ResponseCurvePreview
+										path={formValues.cameraResponseLocation}
+									/>
 								</div>
 							</AccordionContent>
 						</AccordionItem>
@@ -308,6 +477,7 @@ export default function Home() {
 									"lensMask.y",
 									"outputSettings.targetRes",
 									"outputSettings.filterIrrelevantSrcImages",
+									"outputSettings.checkLensMask",
 								]}
 							>
 								Cropping and Resizing
@@ -360,7 +530,40 @@ export default function Home() {
 										radiusAjusterCenterY={radiusAjusterCenterY}
 										register={register}
 									/>
+									<Button
+										type="button"
+										variant="outline"
+										disabled={!inputSets?.[0]?.files?.length}
+										onClick={detectLensMask}
+									>
+										Detect from images
+									</Button>
 								</div>
+								<Tooltip>
+									<TooltipTrigger asChild>
+										<div className="flex items-center gap-2">
+											<Controller
+												name="outputSettings.checkLensMask"
+												control={control}
+												render={({ field }) => (
+													<Checkbox
+														checked={field.value ?? false}
+														onCheckedChange={(checked) =>
+															field.onChange(Boolean(checked))
+														}
+														onBlur={field.onBlur}
+														ref={field.ref}
+													/>
+												)}
+											/>
+											<Label>Check lens mask against the images</Label>
+										</div>
+									</TooltipTrigger>
+									<TooltipContent className="max-w-xs">
+										Detects the fisheye circle in the images of each scene, and
+										warns when the lens mask differs from it.
+									</TooltipContent>
+								</Tooltip>
 							</AccordionContent>
 						</AccordionItem>
 						<AccordionItem value="item-correction-fisheye" className="px-4">
@@ -459,6 +662,7 @@ export default function Home() {
 									"outputSettings.targetRes",
 									"fisheyeView.verticalViewDegrees",
 									"fisheyeView.horizontalViewDegrees",
+									"outputSettings.glareCheckImage",
 								]}
 							>
 								Output Header Editing
@@ -518,6 +722,31 @@ export default function Home() {
 										/>
 									</FieldContent>
 								</Field>
+								<Tooltip>
+									<TooltipTrigger asChild>
+										<div className="flex items-center gap-2">
+											<Controller
+												name="outputSettings.glareCheckImage"
+												control={control}
+												render={({ field }) => (
+													<Checkbox
+														checked={field.value ?? false}
+														onCheckedChange={(checked) =>
+															field.onChange(Boolean(checked))
+														}
+														onBlur={field.onBlur}
+														ref={field.ref}
+													/>
+												)}
+											/>
+											<Label>Save glare check image</Label>
+										</div>
+									</TooltipTrigger>
+									<TooltipContent className="max-w-xs">
+										Saves the evalglare check image, which shows the pixels
+										detected as glare sources, next to the final HDR image.
+									</TooltipContent>
+								</Tooltip>
 							</AccordionContent>
 						</AccordionItem>
 					</Accordion>
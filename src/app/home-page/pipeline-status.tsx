@@ -3,9 +3,83 @@
 
 import React, { useEffect, useMemo, useState } from "react";
 import { listen } from "@tauri-apps/api/event";
+import { invoke } from "@tauri-apps/api/core";
 import { Progress } from "@/components/ui/progress";
 import z from "zod";
 import { Button } from "@/components/ui/button";
+import { describePipelineError, sceneErrorSchema } from "@/lib/pipeline-error";
+import { toast } from "sonner";
+
+// Glare metrics parsed from the evalglare output (see pipeline/evalglare.rs). Only the vertical
+// illuminance is always present.
+export const glareMetricsSchema = z.object({
+	dgp: z.number().nullable(),
+	averageLuminance: z.number().nullable(),
+	verticalIlluminance: z.number(),
+	backgroundLuminance: z.number().nullable(),
+	dgi: z.number().nullable(),
+	ugr: z.number().nullable(),
+	vcp: z.number().nullable(),
+	cgi: z.number().nullable(),
+	other: z.record(z.string(), z.number()),
+	glareSources: z.array(
+		z.object({
+			pixels: z.number(),
+			x: z.number(),
+			y: z.number(),
+			luminance: z.number(),
+			solidAngle: z.number(),
+			positionIndex: z.number(),
+			verticalIlluminance: z.number().nullable(),
+		})
+	),
+});
+
+// Progress event emitted by the backend for each pipeline stage (see pipeline/progress.rs)
+const pipelineProgressSchema = z.object({
+	event: z.enum([
+		"started",
+		"stageStarted",
+		"stageFinished",
+		"sceneFinished",
+		"sceneFailed",
+		"warning",
+		"finished",
+	]),
+	percent: z.number(),
+	sceneIndex: z.number(),
+	sceneCount: z.number(),
+	sceneName: z.string(),
+	stage: z.string().nullable(),
+	stageIndex: z.number(),
+	stageCount: z.number(),
+	stageElapsedSecs: z.number().nullable(),
+	cached: z.boolean(),
+	elapsedSecs: z.number(),
+	etaSecs: z.number().nullable(),
+	error: sceneErrorSchema.nullable(),
+	warnings: z.array(z.string()),
+	glareMetrics: glareMetricsSchema.nullable(),
+	// Shifts applied to the LDR images when their exposures were aligned (see pipeline/alignment.rs)
+	frameOffsets: z.array(
+		z.object({
+			image: z.string(),
+			alignedImage: z.string(),
+			xOffset: z.number(),
+			yOffset: z.number(),
+		})
+	),
+});
+
+// Formats a duration in seconds as e.g. "1m 05s"
+function formatDuration(secs: number) {
+	const total = Math.round(secs);
+	const minutes = Math.floor(total / 60);
+	const seconds = total % 60;
+	return minutes > 0
+		? `${minutes}m ${seconds.toString().padStart(2, "0")}s`
+		: `${seconds}s`;
+}
 
 export function PipelineStatus({
 	onFinishAcknowledgment,
@@ -13,14 +87,45 @@ export function PipelineStatus({
 	onFinishAcknowledgment: () => void;
 }) {
 	const [progress, setProgress] = useState<number>(0);
-	// TODO: implement status texts from the backend
 	const [statusText, setStatusText] = useState<string>("");
 
 	useEffect(() => {
 		const unlistenPromise = listen(
 			"pipeline-progress",
 			(event: { payload: unknown }) => {
-				setProgress(z.number().parse(event.payload));
+				const update = pipelineProgressSchema.parse(event.payload);
+				setProgress(update.percent);
+
+				if (update.event === "sceneFailed" && update.error) {
+					// Other scenes of the batch may still be running
+					const { summary } = describePipelineError(update.error);
+					setStatusText(`Scene ${update.sceneName} failed – ${summary}`);
+				} else if (update.event === "warning") {
+					// e.g. a bracket mixing ISO values, the scene is processed anyway
+					toast.warning(
+						update.sceneName
+							? `Scene ${update.sceneName}: check the exposure bracket`
+							: "Check the exposure bracket",
+						{ description: update.warnings.join("\n") }
+					);
+				} else if (update.event === "sceneFinished" && update.glareMetrics) {
+					// e.g. "Scene 7/32 – DGP 0.31, Ev 3120 lx"
+					const { dgp, verticalIlluminance } = update.glareMetrics;
+					let text = `Scene ${update.sceneIndex}/${update.sceneCount} –`;
+					if (dgp !== null) {
+						text += ` DGP ${dgp.toFixed(2)},`;
+					}
+					setStatusText(`${text} Ev ${Math.round(verticalIlluminance)} lx`);
+				} else if (update.event === "finished") {
+					setStatusText(`Done in ${formatDuration(update.elapsedSecs)}`);
+				} else if (update.stage) {
+					// e.g. "Scene 7/32 – vignetting correction"
+					let text = `Scene ${update.sceneIndex}/${update.sceneCount} – ${update.stage.replaceAll("_", " ")}`;
+					if (update.etaSecs !== null) {
+						text += ` (about ${formatDuration(update.etaSecs)} left)`;
+					}
+					setStatusText(text);
+				}
 			}
 		);
 
@@ -36,9 +141,22 @@ export function PipelineStatus({
 			)}
 			<div className="text-xs text-muted-foreground">{progress}%</div>
 			<Progress value={progress} />
-			<Button onClick={onFinishAcknowledgment} disabled={progress !== 100}>
-				OK
-			</Button>
+			{progress !== 100 ? (
+				<Button
+					type="button"
+					variant="outline"
+					onClick={() => {
+						// The pending pipeline invocation rejects once the run has stopped
+						invoke("cancel_pipeline").catch((error) =>
+							console.error("Failed to cancel pipeline", error)
+						);
+					}}
+				>
+					Cancel
+				</Button>
+			) : (
+				<Button onClick={onFinishAcknowledgment}>OK</Button>
+			)}
 		</div>
 	);
 }
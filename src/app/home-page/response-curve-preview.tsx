@@ -0,0 +1,103 @@
+"use client";
+
+import React, { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import z from "zod";
+
+// Sampled response curves returned by inspect_response_function (see pipeline/response_function.rs)
+const responseCurvesSchema = z.object({
+	pixelValues: z.array(z.number()),
+	channels: z.array(
+		z.object({
+			channel: z.string(),
+			values: z.array(z.number()),
+			increasing: z.boolean(),
+			maxDeviation: z.number(),
+			diverges: z.boolean(),
+		})
+	),
+});
+type ResponseCurves = z.infer<typeof responseCurvesSchema>;
+
+const CHANNEL_COLORS: Record<string, string> = {
+	red: "#dc2626",
+	green: "#16a34a",
+	blue: "#2563eb",
+};
+
+// Size of the plot, in SVG units
+const PLOT_SIZE = 100;
+
+/**
+ * Plots the red, green and blue curves of a camera response function, and warns about
+ * channels that do not increase or that diverge from the others
+ *
+ * @param path - Path to the .rsp file, nothing is shown if null
+ */
+export function ResponseCurvePreview({ path }: { path: string | null }) {
+	const [curves, setCurves] = useState<ResponseCurves | null>(null);
+	const [error, setError] = useState<string>("");
+
+	useEffect(() => {
+		setCurves(null);
+		setError("");
+		if (!path) return;
+
+		invoke("inspect_response_function", { path })
+			.then((result) => setCurves(responseCurvesSchema.parse(result)))
+			.catch((error) => setError(String(error)));
+	}, [path]);
+
+	if (error) return <div className="text-sm text-destructive">{error}</div>;
+	if (!curves) return null;
+
+	// Scale the plot to the largest radiance, curves are normalized to 1 at full scale
+	const maxValue = Math.max(
+		1,
+		...curves.channels.flatMap((curve) => curve.values)
+	);
+	const warnings = curves.channels.flatMap((curve) => [
+		...(curve.increasing
+			? []
+			: [`The ${curve.channel} curve does not increase over the whole range.`]),
+		...(curve.diverges
+			? [
+					`The ${curve.channel} curve diverges from the other channels (by up to ${(curve.maxDeviation * 100).toFixed(0)}% of full scale).`,
+				]
+			: []),
+	]);
+
+	return (
+		<div className="flex flex-col gap-1">
+			<svg
+				viewBox={`0 0 ${PLOT_SIZE} ${PLOT_SIZE}`}
+				className="w-40 h-40 border border-input bg-background"
+				preserveAspectRatio="none"
+			>
+				{curves.channels.map((curve) => (
+					<polyline
+						key={curve.channel}
+						fill="none"
+						stroke={CHANNEL_COLORS[curve.channel]}
+						strokeWidth={1}
+						vectorEffect="non-scaling-stroke"
+						points={curve.values
+							.map(
+								(value, i) =>
+									`${curves.pixelValues[i] * PLOT_SIZE},${PLOT_SIZE - (Math.max(value, 0) / maxValue) * PLOT_SIZE}`
+							)
+							.join(" ")}
+					/>
+				))}
+			</svg>
+			<div className="text-xs text-muted-foreground">
+				Relative radiance by pixel value
+			</div>
+			{warnings.map((warning) => (
+				<div key={warning} className="text-sm text-destructive">
+					{warning}
+				</div>
+			))}
+		</div>
+	);
+}
@@ -15,6 +15,24 @@ import { invoke } from "@tauri-apps/api/core";
 import { basename } from "@tauri-apps/api/path";
 import { open } from "@tauri-apps/plugin-dialog";
 
+/**
+ * Luminance statistics over the fisheye view of an image, as returned by the
+ * luminance_statistics command (values in cd/m²)
+ */
+type LuminanceStatistics = {
+  pixelCount: number;
+  min: number;
+  max: number;
+  mean: number;
+  median: number;
+  percentiles: { percentile: number; luminance: number }[];
+  solidAngleWeightedMean: number | null;
+  view: string | null;
+  histogram: { lower: number; upper: number; count: number }[];
+  nonPositiveCount: number;
+  warnings: string[];
+};
+
 /**
  * Main Image Viewer component
  *
@@ -30,6 +48,9 @@ export default function ImageViewer() {
   const [selectedImages, setSelectedImages] = useState<string[]>([]);
   const [evalglares, setEvalglares] = useState<(string | null)[]>([]);
   const [imageFullPaths, setImageFullPaths] = useState<string[]>([]);
+  const [statistics, setStatistics] = useState<
+    Record<string, LuminanceStatistics>
+  >({});
 
   // Helper to sort images by filename
   function sortImages(
@@ -144,6 +165,23 @@ export default function ImageViewer() {
       setError("Failed to open ximage");
     });
   }
+  /**
+   * Computes the luminance statistics of an HDR image over its fisheye view
+   *
+   * @param imagePath - Full path to the HDR image file
+   */
+  async function loadStatistics(imagePath: string) {
+    await invoke<LuminanceStatistics>("luminance_statistics", {
+      filePath: imagePath,
+    })
+      .then((result) =>
+        setStatistics((prev) => ({ ...prev, [imagePath]: result }))
+      )
+      .catch((error: unknown) => {
+        console.log(error);
+        setError("Failed to compute luminance statistics");
+      });
+  }
   /**
    * Retrieves all HDR image files from a directory
    *
@@ -211,6 +249,8 @@ export default function ImageViewer() {
             {selectedImages.map((image, index) => {
               const imageName = image.split("/").pop();
               const evalGlare = evalglares[index];
+              const fullPath = imageFullPaths[index] ?? image;
+              const stats = statistics[fullPath];
               return (
                 <div
                   key={index}
@@ -231,6 +271,33 @@ export default function ImageViewer() {
                       Evalglare:{" "}
                       {evalGlare ? `${evalGlare} lx` : "Not present"}
                     </p>
+                    {stats ? (
+                      <>
+                        <p className="text-sm">
+                          Luminance (cd/m²): mean {stats.mean.toFixed(1)}, median{" "}
+                          {stats.median.toFixed(1)}, min {stats.min.toFixed(1)},
+                          max {stats.max.toFixed(0)}
+                          {stats.solidAngleWeightedMean != null &&
+                            `, solid-angle-weighted mean ${stats.solidAngleWeightedMean.toFixed(1)}`}
+                        </p>
+                        {stats.warnings.map((warning) => (
+                          <p key={warning} className="text-sm text-destructive">
+                            {warning}
+                          </p>
+                        ))}
+                      </>
+                    ) : (
+                      <button
+                        type="button"
+                        className="text-sm underline"
+                        onClick={(event) => {
+                          event.stopPropagation();
+                          void loadStatistics(fullPath);
+                        }}
+                      >
+                        Luminance statistics
+                      </button>
+                    )}
                   </div>
                 </div>
               );
@@ -0,0 +1,160 @@
+/**
+ * Image cache section of the settings page.
+ *
+ * Shows the size and number of entries of the cache of raw images converted to TIFF, and lets
+ * users change its size limit, clear it, or validate its entries (corrupted entries are removed).
+ * The pipeline's stage cache (outputs of earlier runs) has its own size limit and is cleared
+ * together with the image cache.
+ */
+"use client";
+
+import React, { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+
+// Statistics returned by the image cache commands (see src-tauri/src/image_cache/mod.rs)
+interface CacheStats {
+  sizeBytes: number;
+  entryCount: number;
+  maxSizeBytes: number;
+  stageCacheSizeBytes: number;
+  stageCacheEntryCount: number;
+  stageCacheMaxSizeBytes: number;
+}
+
+const BYTES_PER_GB = 1024 * 1024 * 1024;
+
+// Formats a size in bytes as gigabytes, e.g. "2.35 GB"
+function formatGb(bytes: number) {
+  return `${(bytes / BYTES_PER_GB).toFixed(2)} GB`;
+}
+
+/**
+ * Image cache statistics and management
+ *
+ * @returns React component with the image cache settings
+ */
+export default function ImageCacheSettings() {
+  const [stats, setStats] = useState<CacheStats | null>(null);
+  const [limitGb, setLimitGb] = useState<string>("");
+  const [stageLimitGb, setStageLimitGb] = useState<string>("");
+  const [message, setMessage] = useState<string>("");
+
+  /**
+   * Reloads the cache statistics from the backend
+   */
+  const refresh = () => {
+    invoke<CacheStats>("get_image_cache_stats")
+      .then((stats) => {
+        setStats(stats);
+        setLimitGb((stats.maxSizeBytes / BYTES_PER_GB).toString());
+        setStageLimitGb((stats.stageCacheMaxSizeBytes / BYTES_PER_GB).toString());
+      })
+      .catch((error) => console.error(error));
+  };
+
+  useEffect(refresh, []);
+
+  /**
+   * Saves the size limits of both caches, evicting entries over them
+   */
+  const saveLimit = () => {
+    const gb = Number(limitGb);
+    const stageGb = Number(stageLimitGb);
+    if (!(gb > 0) || !(stageGb > 0)) {
+      setMessage("The size limits must be greater than 0.");
+      return;
+    }
+    invoke<CacheStats>("set_image_cache_limit", {
+      maxSizeBytes: Math.round(gb * BYTES_PER_GB),
+      stageCacheMaxSizeBytes: Math.round(stageGb * BYTES_PER_GB),
+    })
+      .then((stats) => {
+        setStats(stats);
+        setMessage("Size limits saved.");
+      })
+      .catch((error) => setMessage(String(error)));
+  };
+
+  const clearCache = () => {
+    invoke<number>("clear_image_cache")
+      .then((removed) => {
+        setMessage(`Removed ${removed} cache entries.`);
+        refresh();
+      })
+      .catch((error) => setMessage(String(error)));
+  };
+
+  const validateCache = () => {
+    setMessage("Validating cached images...");
+    invoke<{ checked: number; removed: string[] }>("validate_image_cache")
+      .then((report) => {
+        setMessage(
+          `Checked ${report.checked} cached image(s), removed ${report.removed.length} corrupted.`
+        );
+        refresh();
+      })
+      .catch((error) => setMessage(String(error)));
+  };
+
+  return (
+    <div className="border border-gray-300 rounded-lg p-5">
+      <h2 className="text-xl font-bold mb-4 flex items-center">Image Cache</h2>
+      <div className="mb-4 text-sm">
+        {stats
+          ? `${stats.entryCount} converted image(s), ${formatGb(stats.sizeBytes)} of ${formatGb(stats.maxSizeBytes)}`
+          : "Loading..."}
+      </div>
+      <div className="mb-4 text-sm">
+        {stats &&
+          `Stage cache: ${stats.stageCacheEntryCount} cached output(s), ${formatGb(stats.stageCacheSizeBytes)} of ${formatGb(stats.stageCacheMaxSizeBytes)}`}
+      </div>
+      <label htmlFor="imageCacheLimit" className="font-semibold block mb-1">
+        Size limit (GB)
+      </label>
+      <div className="flex items-center gap-2 mb-4">
+        <input
+          id="imageCacheLimit"
+          type="number"
+          min="0"
+          step="0.5"
+          value={limitGb}
+          onChange={(event) => setLimitGb(event.currentTarget.value)}
+          className="grow border border-gray-400 rounded px-2 py-1"
+        />
+      </div>
+      <label htmlFor="stageCacheLimit" className="font-semibold block mb-1">
+        Stage cache size limit (GB)
+      </label>
+      <div className="flex items-center gap-2 mb-4">
+        <input
+          id="stageCacheLimit"
+          type="number"
+          min="0"
+          step="0.5"
+          value={stageLimitGb}
+          onChange={(event) => setStageLimitGb(event.currentTarget.value)}
+          className="grow border border-gray-400 rounded px-2 py-1"
+        />
+        <button
+          onClick={saveLimit}
+          className="bg-gray-300 hover:bg-gray-400 text-gray-700 font-semibold py-1 px-2 rounded"
+        >
+          Save
+        </button>
+        <button
+          onClick={validateCache}
+          className="bg-gray-300 hover:bg-gray-400 text-gray-700 font-semibold py-1 px-2 rounded"
+        >
+          Validate
+        </button>
+        <button
+          onClick={clearCache}
+          className="bg-gray-300 hover:bg-gray-400 text-gray-700 font-semibold py-1 px-2 rounded"
+        >
+          Clear
+        </button>
+      </div>
+      {message && <div className="text-sm">{message}</div>}
+    </div>
+  );
+}
@@ -4,6 +4,7 @@
  * This component allows users to configure application settings including:
  * - External utility paths (Radiance, hdrgen, dcraw_emu)
  * - Output file location
+ * - Image cache size limit
  * - User experience level
  * - Debug console access
  *
@@ -18,6 +19,7 @@ import { openPath } from "@tauri-apps/plugin-opener";
 import { invoke } from "@tauri-apps/api/core";
 import { getName, getTauriVersion, getVersion } from "@tauri-apps/api/app";
 import SettingsButtonBar from "./settings-button-bar";
+import ImageCacheSettings from "./image-cache-settings";
 
 const handleExternalLink = async (url: string) => {
   await openPath(url);
@@ -257,6 +259,8 @@ export default function SettingsPage() {
             ))}
           </div>
 
+          <ImageCacheSettings />
+
           {/* Right: Usability Preferences */}
           {/* <div className="border border-gray-300 rounded-lg p-5">
             <h2 className="text-xl font-bold mb-4 flex items-center">
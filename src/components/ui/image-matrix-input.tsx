@@ -26,6 +26,8 @@ import {
 import { DialogFilter, open } from "@tauri-apps/plugin-dialog";
 import { DirEntry, readDir, stat } from "@tauri-apps/plugin-fs";
 import { ImageSet, ImageSetPreview } from "./image-set-preview";
+import { invoke } from "@tauri-apps/api/core";
+import z from "zod";
 
 type FileMatrixFieldName<T extends FieldValues> = FieldPathByValue<
 	T,
@@ -47,6 +49,18 @@ const imageFilters: DialogFilter[] = [
 	{ name: "Images", extensions: imageFileExtensions },
 ];
 
+// Bracket sets proposed by the group_brackets command (see pipeline/bracket_grouping.rs)
+const bracketGroupingSchema = z.object({
+	groups: z.array(
+		z.object({
+			name: z.string(),
+			images: z.array(z.string()),
+			splitReason: z.string().nullable(),
+		})
+	),
+	warnings: z.array(z.string()),
+});
+
 type FullDirEntry = DirEntry & {
 	path: string;
 };
@@ -134,6 +148,42 @@ export function ImageMatrixInput<
 		});
 		if (selectedDirectories) onDrop(selectedDirectories);
 	}, [onDrop]);
+	// Splits a folder holding many brackets (e.g. a DCIM folder) into one image set per bracket,
+	// using the capture times and exposures of the images. The files are not moved.
+	const groupDirectoryIntoBrackets = useCallback(async () => {
+		const selectedDirectory = await open({
+			multiple: false,
+			directory: true,
+		});
+		if (!selectedDirectory) return;
+
+		try {
+			const grouping = bracketGroupingSchema.parse(
+				await invoke("group_brackets", { inputDir: selectedDirectory })
+			);
+			field.onChange([
+				...(value ?? []),
+				...grouping.groups.map((group) => ({
+					name: group.name,
+					files: group.images,
+				})),
+			]);
+			toast.success(
+				`Found ${grouping.groups.length} bracket set(s) in ${path.basename(selectedDirectory)}.`,
+				{
+					description: [
+						...grouping.groups
+							.filter((group) => group.splitReason)
+							.map((group) => `${group.name}: ${group.splitReason}`),
+						...grouping.warnings,
+					].join("\n"),
+				}
+			);
+		} catch (error) {
+			toast.error("Could not group the folder into brackets: " + error);
+		}
+	}, [field, value]);
+
 	return (
 		<Field className={className} data-invalid={fieldState.invalid}>
 			<FieldContent className="flex flex-col gap-0 divide-y overflow-y-auto">
@@ -183,6 +233,9 @@ export function ImageMatrixInput<
 							Create multiple...
 							{/* <ContextMenuShortcut>⌘]</ContextMenuShortcut> */}
 						</ContextMenuItem>
+						<ContextMenuItem onClick={groupDirectoryIntoBrackets}>
+							Split a folder into brackets...
+						</ContextMenuItem>
 					</ContextMenuContent>
 				</ContextMenu>
 			</FieldContent>
@@ -0,0 +1,116 @@
+import z from "zod";
+
+// Options shared by every pipeline error
+const errorOptions = [
+	z.object({ kind: z.literal("invalidParams"), errors: z.array(z.string()) }),
+	z.object({
+		kind: z.literal("invalidBracket"),
+		problems: z.array(z.string()),
+	}),
+	z.object({
+		kind: z.literal("commandNotStarted"),
+		stage: z.string(),
+		command: z.string(),
+		message: z.string(),
+	}),
+	z.object({
+		kind: z.literal("commandFailed"),
+		stage: z.string(),
+		command: z.string(),
+		exitCode: z.number().nullable(),
+		stderr: z.string(),
+		stdout: z.string(),
+	}),
+	z.object({ kind: z.literal("cancelled") }),
+	z.object({
+		kind: z.literal("other"),
+		stage: z.string().nullable(),
+		message: z.string(),
+	}),
+] as const;
+
+// Error of one scene of a batch, reported by "sceneFailed" errors and progress events
+export const sceneErrorSchema = z.discriminatedUnion("kind", [...errorOptions]);
+
+// Error returned by the pipeline command (see src-tauri/src/pipeline/error.rs)
+export const pipelineErrorSchema = z.discriminatedUnion("kind", [
+	...errorOptions,
+	z.object({
+		kind: z.literal("sceneFailed"),
+		scene: z.string(),
+		sceneIndex: z.number(),
+		error: sceneErrorSchema,
+	}),
+	z.object({
+		kind: z.literal("scenesFailed"),
+		failedCount: z.number(),
+		sceneCount: z.number(),
+		manifest: z.string(),
+	}),
+]);
+
+export type PipelineError = z.infer<typeof pipelineErrorSchema>;
+
+// Parses an error rejected by the pipeline command, falling back to a generic error
+export function parsePipelineError(error: unknown): PipelineError {
+	const parsed = pipelineErrorSchema.safeParse(error);
+	if (parsed.success) {
+		return parsed.data;
+	}
+	return { kind: "other", stage: null, message: String(error) };
+}
+
+// Returns a short summary of the error and, for failed commands, the details to show below it
+export function describePipelineError(error: PipelineError): {
+	summary: string;
+	details?: string;
+} {
+	switch (error.kind) {
+		case "invalidParams":
+			return {
+				summary: "Invalid pipeline parameters",
+				details: error.errors.join("\n"),
+			};
+		case "invalidBracket":
+			return {
+				summary: "Invalid exposure bracket",
+				details: error.problems.join("\n"),
+			};
+		case "commandNotStarted":
+			return {
+				summary: `Stage "${error.stage}" could not start its command: ${error.message}`,
+				details: error.command,
+			};
+		case "commandFailed": {
+			const exit =
+				error.exitCode === null
+					? "was terminated"
+					: `failed with exit code ${error.exitCode}`;
+			const output = error.stderr.trim() || error.stdout.trim();
+			return {
+				summary: `Stage "${error.stage}" ${exit}`,
+				details: output ? `${error.command}\n\n${output}` : error.command,
+			};
+		}
+		case "sceneFailed": {
+			const cause = describePipelineError(error.error);
+			return {
+				summary: `Scene "${error.scene}": ${cause.summary}`,
+				details: cause.details,
+			};
+		}
+		case "scenesFailed":
+			return {
+				summary: `${error.failedCount} of ${error.sceneCount} scenes failed`,
+				details: `See the batch manifest for the error of each scene:\n${error.manifest}`,
+			};
+		case "cancelled":
+			return { summary: "The pipeline was cancelled." };
+		case "other":
+			return {
+				summary: error.stage
+					? `Stage "${error.stage}": ${error.message}`
+					: error.message,
+			};
+	}
+}